@@ -0,0 +1,811 @@
+//! This module contains the argument parsers for the `render` and `node`
+//! subcommands.
+
+use crate::{
+    generator::{
+        args::{
+            Averaging, BoundaryTracing, CpuKernel, Formula, InteriorChecks, InteriorColoring,
+            Multisampling, Precision, Smoothing, DEFAULT_RADIUS,
+        },
+        color::{Relief, Shading},
+        remote::{protocol::MAX_VIEW_PIXELS, DEFAULT_NODE_PORT},
+        trap::OrbitTrap,
+        view::View,
+        FractalOpts, PixelFormat,
+    },
+    storage::CHUNK_SIZE_POWERS,
+};
+use num_complex::Complex64;
+use std::{
+    net::{Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+};
+
+/// Usage text printed by `render --help` or when the arguments are invalid.
+pub const RENDER_USAGE: &str = r"Usage: fractal-rs-2 render [OPTIONS] --output <FILE>
+
+Renders a fractal to a PNG file without opening a window.
+
+Options:
+    -o, --output <FILE>           PNG file to write (required)
+        --bit-depth <BITS>        Bits per channel of the PNG: 8 | 16 [default: 8]
+    -W, --width <PIXELS>          Image width [default: 1024]
+    -H, --height <PIXELS>         Image height [default: 1024]
+        --plane-width <WIDTH>     Width of the complex plane shown [default: 3.0]
+        --center <RE,IM>          Center of the complex plane shown [default: 0,0]
+        --julia <RE,IM>           Render the Julia set for this c instead of the Mandelbrot set
+    -i, --iterations <COUNT>      Maximum iteration count [default: 200]
+    -f, --formula <FORMULA>       z^<integer> | z^<real> (the `+ c` is implied) | burning-ship |
+                                  tricorn | perpendicular-burning-ship | celtic |
+                                  <expression in z and c, e.g. 'sin(z)*c'> [default: z^2]
+        --radius <RADIUS>         Escape radius [default: 4]
+        --smoothing <SMOOTHING>   none | linear | logarithmic(<radius>, <max power>)
+                                  [default: logarithmic(<escape radius>, <formula exponent>)]
+        --multisampling <MS>      none | four(<offset>) | linear(<axial points>) |
+                                  adaptive(<max samples>, <threshold>) [default: linear(16)]
+        --precision <PRECISION>   single | double [default: the lowest precision the view needs]
+        --interior-checks <CHECKS>
+                                  Shortcuts for points inside the set: none | bulbs | periodicity |
+                                  all [default: all]
+        --boundary-tracing <MODE> off | exact | always (fill rectangles with uniform borders instead
+                                  of generating them; CPU generators only) [default: off]
+        --cpu-kernel <KERNEL>     scalar | simd4 | simd8 (how many pixels the CPU generator
+                                  iterates at once) [default: scalar]
+        --distance-estimation     Estimate each pixel's distance to the set (not for expressions)
+        --boundary-lines <PIXELS> Draw the boundary of the set this thick, using distance
+                                  estimation [default: 0, no lines]
+        --distance-fade <PIXELS>  Darken pixels within about this distance of the set, using
+                                  distance estimation [default: 0, no fading]
+        --histogram-equalization  Spread the colors of the palette evenly over the values of the
+                                  whole image instead of using the palette's density
+        --relief <SOURCE>         Light the fractal as a surface whose height comes from: value |
+                                  distance (needs --distance-estimation) [default: no relief]
+        --light-angle <DEGREES>   Direction the relief's light comes from, counter-clockwise from
+                                  the right [default: 45]
+        --light-height <DEGREES>  Height of the relief's light above the image [default: 45]
+        --specular <AMOUNT>       Brightness of the relief's highlights [default: 0.5]
+        --relief-depth <DEPTH>    How steep the relief is drawn [default: 1]
+        --averaging <AVERAGE>     Color escaped points by an average over their orbits:
+                                  stripe(<density>) | tia | curvature (works best with a large
+                                  --radius) [default: none, the smoothed iteration count]
+        --orbit-trap <TRAP>       Color escaped points by how close their orbits come to a shape:
+                                  point(<re>, <im>) | line(<re>, <im>, <angle>) |
+                                  cross(<re>, <im>, <angle>) | circle(<re>, <im>, <radius>) |
+                                  image(<re>, <im>, <size>) [default: no trap]
+        --trap-image <FILE>       PNG image for an image trap (required for image traps)
+        --interior-coloring <MODE>
+                                  none | final-magnitude | min-magnitude | period | distance |
+                                  atom-domain (distance only applies to Mandelbrot sets of
+                                  z^n + c) [default: none, a black interior]
+        --palette <NAME|FILE>     Palette saved in the palettes config dir, or a palette file
+                                  [default: classic hue-cycling colors]
+        --interior-palette <NAME|FILE>
+                                  Palette for the interior coloring, like --palette
+                                  [default: classic hue-cycling colors]
+    -g, --generator <TYPE>        cpu | gpu | perturbation | hybrid [default: from general.ron]
+        --chunk-size-power <N>    Generate in chunks of 2^N x 2^N pixels, with N from 4 to 13
+                                  [default: from general.ron]
+        --node <HOST:PORT>        Generate on the render node at HOST:PORT instead of locally
+                                  (see `fractal-rs-2 node --help`)
+        --resumable               Journal completed chunks next to the output, so an interrupted
+                                  render resumes where it stopped when run again
+    -h, --help                    Print this help";
+
+/// Usage text printed by `node --help` or when the arguments are invalid.
+pub const NODE_USAGE: &str = r"Usage: fractal-rs-2 node [OPTIONS]
+
+Runs a render node that generates fractals for other fractal-rs-2 processes
+connecting over TCP.
+
+Options:
+    -l, --listen <ADDR:PORT>      Address to accept connections on. Clients are not
+                                  authenticated, so only listen on other interfaces, like
+                                  0.0.0.0:7117, on trusted networks [default: 127.0.0.1:7117]
+    -g, --generator <TYPE>        cpu | gpu | perturbation | hybrid [default: from general.ron]
+        --max-view-pixels <PIXELS>
+                                  Most pixels a single requested view may have
+                                  [default and maximum: 16777216]
+    -h, --help                    Print this help";
+
+/// Which kind of generator the `render` subcommand should use.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RenderGeneratorType {
+    Cpu,
+    Gpu,
+    Perturbation,
+    Hybrid,
+}
+
+impl FromStr for RenderGeneratorType {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "cpu" => Ok(RenderGeneratorType::Cpu),
+            "gpu" => Ok(RenderGeneratorType::Gpu),
+            "perturbation" => Ok(RenderGeneratorType::Perturbation),
+            "hybrid" => Ok(RenderGeneratorType::Hybrid),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The parsed arguments of the `render` subcommand.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderArgs {
+    pub output: PathBuf,
+    pub format: PixelFormat,
+    pub width: usize,
+    pub height: usize,
+    pub plane_width: f64,
+    pub center: Complex64,
+    pub julia: Option<Complex64>,
+    pub iterations: u32,
+    pub formula: Formula,
+    pub radius: f32,
+    /// `None` means use the logarithmic smoothing appropriate for the formula.
+    pub smoothing: Option<Smoothing>,
+    pub multisampling: Multisampling,
+    /// `None` means use the lowest precision able to render the view.
+    pub precision: Option<Precision>,
+    pub interior_checks: InteriorChecks,
+    pub boundary_tracing: BoundaryTracing,
+    pub cpu_kernel: CpuKernel,
+    pub distance_estimation: bool,
+    pub shading: Shading,
+    pub histogram_equalization: bool,
+    pub averaging: Averaging,
+    /// `None` means color by iteration count instead of using an orbit trap.
+    /// Image traps are parsed without their image.
+    pub orbit_trap: Option<OrbitTrap>,
+    /// The image to load into an image trap.
+    pub trap_image: Option<PathBuf>,
+    pub interior_coloring: InteriorColoring,
+    /// The name or path of the palette to use. `None` means use the classic
+    /// hue-cycling colors.
+    pub palette: Option<String>,
+    /// The name or path of the palette to color the interior with. `None`
+    /// means use the classic hue-cycling colors.
+    pub interior_palette: Option<String>,
+    /// `None` means use the generator type from the general config.
+    pub generator: Option<RenderGeneratorType>,
+    /// `None` means use the chunk size from the general config.
+    pub chunk_size_power: Option<usize>,
+    /// The address of the render node to generate on. `None` means generate
+    /// locally.
+    pub node: Option<String>,
+    /// Whether to keep a journal that lets this render be resumed.
+    pub resumable: bool,
+}
+
+impl RenderArgs {
+    /// Parses the arguments following the `render` subcommand.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<RenderArgs, ArgsError> {
+        let mut output = None;
+        let mut width = 1024;
+        let mut height = 1024;
+        let mut plane_width = 3.0;
+        let mut center = Complex64 { re: 0.0, im: 0.0 };
+        let mut julia = None;
+        let mut iterations = 200;
+        let mut formula = Formula::default();
+        let mut radius = DEFAULT_RADIUS;
+        let mut smoothing = None;
+        let mut multisampling = Multisampling::Linear { axial_points: 16 };
+        let mut precision = None;
+        let mut interior_checks = InteriorChecks::default();
+        let mut boundary_tracing = BoundaryTracing::default();
+        let mut cpu_kernel = CpuKernel::default();
+        let mut distance_estimation = false;
+        let mut shading = Shading::default();
+        let mut histogram_equalization = false;
+        let mut relief_source = None;
+        let mut relief = Relief::default();
+        let mut averaging = Averaging::default();
+        let mut orbit_trap = None;
+        let mut trap_image = None;
+        let mut interior_coloring = InteriorColoring::default();
+        let mut palette = None;
+        let mut interior_palette = None;
+        let mut generator = None;
+        let mut chunk_size_power = None;
+        let mut node = None;
+        let mut resumable = false;
+        let mut format = PixelFormat::default();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // support both `--name value` and `--name=value`
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => {
+                    (name.to_string(), Some(value.to_string()))
+                },
+                _ => (arg, None),
+            };
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| ArgsError::MissingValue(name.clone()))
+            };
+
+            match name.as_str() {
+                "-o" | "--output" => output = Some(PathBuf::from(value()?)),
+                "--bit-depth" => format = parse_value(&name, value()?)?,
+                "-W" | "--width" => width = parse_value(&name, value()?)?,
+                "-H" | "--height" => height = parse_value(&name, value()?)?,
+                "--plane-width" => plane_width = parse_value(&name, value()?)?,
+                "--center" => center = parse_complex(&name, value()?)?,
+                "--julia" => julia = Some(parse_complex(&name, value()?)?),
+                "-i" | "--iterations" => iterations = parse_value(&name, value()?)?,
+                "-f" | "--formula" => formula = parse_value(&name, value()?)?,
+                "--radius" => radius = parse_value(&name, value()?)?,
+                "--smoothing" => smoothing = Some(parse_value(&name, value()?)?),
+                "--multisampling" => multisampling = parse_value(&name, value()?)?,
+                "--precision" => precision = Some(parse_value(&name, value()?)?),
+                "--interior-checks" => interior_checks = parse_value(&name, value()?)?,
+                "--boundary-tracing" => boundary_tracing = parse_value(&name, value()?)?,
+                "--cpu-kernel" => cpu_kernel = parse_value(&name, value()?)?,
+                "--distance-estimation" => distance_estimation = true,
+                "--boundary-lines" => shading.boundary_thickness = parse_value(&name, value()?)?,
+                "--distance-fade" => shading.distance_fade = parse_value(&name, value()?)?,
+                "--histogram-equalization" => histogram_equalization = true,
+                "--relief" => relief_source = Some(parse_value(&name, value()?)?),
+                "--light-angle" => relief.light_angle = parse_value(&name, value()?)?,
+                "--light-height" => relief.light_height = parse_value(&name, value()?)?,
+                "--specular" => relief.specular = parse_value(&name, value()?)?,
+                "--relief-depth" => relief.depth = parse_value(&name, value()?)?,
+                "--averaging" => averaging = parse_value(&name, value()?)?,
+                "--orbit-trap" => orbit_trap = Some(parse_value(&name, value()?)?),
+                "--trap-image" => trap_image = Some(PathBuf::from(value()?)),
+                "--interior-coloring" => interior_coloring = parse_value(&name, value()?)?,
+                "--palette" => palette = Some(value()?),
+                "--interior-palette" => interior_palette = Some(value()?),
+                "-g" | "--generator" => generator = Some(parse_value(&name, value()?)?),
+                "--chunk-size-power" => {
+                    let value = value()?;
+                    let power = parse_value(&name, value.clone())?;
+                    if !CHUNK_SIZE_POWERS.contains(&power) {
+                        return Err(ArgsError::InvalidValue { arg: name, value });
+                    }
+                    chunk_size_power = Some(power);
+                },
+                "--node" => node = Some(value()?),
+                "--resumable" => resumable = true,
+                _ => return Err(ArgsError::UnknownArgument(name)),
+            }
+        }
+
+        let output = output.ok_or(ArgsError::MissingArgument("--output"))?;
+        if width == 0 || height == 0 {
+            return Err(ArgsError::EmptyImage);
+        }
+        if matches!(orbit_trap, Some(OrbitTrap::Image { .. })) && trap_image.is_none() {
+            return Err(ArgsError::MissingArgument("--trap-image"));
+        }
+        shading.relief = relief_source.map(|source| Relief { source, ..relief });
+
+        Ok(RenderArgs {
+            output,
+            format,
+            width,
+            height,
+            plane_width,
+            center,
+            julia,
+            iterations,
+            formula,
+            radius,
+            smoothing,
+            multisampling,
+            precision,
+            interior_checks,
+            boundary_tracing,
+            cpu_kernel,
+            distance_estimation,
+            shading,
+            histogram_equalization,
+            averaging,
+            orbit_trap,
+            trap_image,
+            interior_coloring,
+            palette,
+            interior_palette,
+            generator,
+            chunk_size_power,
+            node,
+            resumable,
+        })
+    }
+
+    /// Gets the [`FractalOpts`] described by these arguments.
+    ///
+    /// The palettes and any trap image are left unset, because loading them
+    /// means reading a file.
+    pub fn opts(&self) -> FractalOpts {
+        FractalOpts {
+            mandelbrot: self.julia.is_none(),
+            formula: self.formula.clone(),
+            iterations: self.iterations,
+            smoothing: self
+                .smoothing
+                .unwrap_or_else(|| self.formula.logarithmic_smoothing(self.radius)),
+            multisampling: self.multisampling,
+            c: self.julia.unwrap_or(Complex64 { re: 0.0, im: 0.0 }),
+            radius_squared: self.radius * self.radius,
+            precision: self
+                .precision
+                .unwrap_or_else(|| Precision::required_for(&self.view())),
+            plane_start: None,
+            palette: None,
+            interior_palette: None,
+            shading: self.shading,
+            histogram_equalization: self.histogram_equalization,
+            interior_checks: self.interior_checks,
+            boundary_tracing: self.boundary_tracing,
+            cpu_kernel: self.cpu_kernel,
+            distance_estimation: self.distance_estimation,
+            orbit_trap: self.orbit_trap.clone(),
+            averaging: self.averaging,
+            interior_coloring: self.interior_coloring,
+        }
+    }
+
+    /// Gets the [`View`] of the whole output image described by these
+    /// arguments.
+    pub fn view(&self) -> View {
+        View::new_uniform(
+            self.width,
+            self.height,
+            self.plane_width,
+            self.center.re,
+            self.center.im,
+        )
+    }
+}
+
+/// The parsed arguments of the `node` subcommand.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeArgs {
+    pub listen: SocketAddr,
+    /// `None` means use the generator type from the general config.
+    pub generator: Option<RenderGeneratorType>,
+    /// The most pixels a view a client asks for may have.
+    pub max_view_pixels: usize,
+}
+
+impl NodeArgs {
+    /// Parses the arguments following the `node` subcommand.
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<NodeArgs, ArgsError> {
+        let mut listen = SocketAddr::from((Ipv4Addr::LOCALHOST, DEFAULT_NODE_PORT));
+        let mut generator = None;
+        let mut max_view_pixels = MAX_VIEW_PIXELS;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (name, inline_value) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => {
+                    (name.to_string(), Some(value.to_string()))
+                },
+                _ => (arg, None),
+            };
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| ArgsError::MissingValue(name.clone()))
+            };
+
+            match name.as_str() {
+                "-l" | "--listen" => listen = parse_value(&name, value()?)?,
+                "-g" | "--generator" => generator = Some(parse_value(&name, value()?)?),
+                "--max-view-pixels" => {
+                    let value = value()?;
+                    max_view_pixels = parse_value(&name, value.clone())?;
+                    if max_view_pixels == 0 || max_view_pixels > MAX_VIEW_PIXELS {
+                        return Err(ArgsError::InvalidValue { arg: name, value });
+                    }
+                },
+                _ => return Err(ArgsError::UnknownArgument(name)),
+            }
+        }
+
+        Ok(NodeArgs {
+            listen,
+            generator,
+            max_view_pixels,
+        })
+    }
+}
+
+/// Returned if the `render` or `node` subcommand's arguments could not be
+/// parsed.
+#[derive(Debug, Clone, Error)]
+pub enum ArgsError {
+    #[error("unknown argument '{0}'")]
+    UnknownArgument(String),
+    #[error("missing value for argument '{0}'")]
+    MissingValue(String),
+    #[error("invalid value '{value}' for argument '{arg}'")]
+    InvalidValue { arg: String, value: String },
+    #[error("missing required argument '{0}'")]
+    MissingArgument(&'static str),
+    #[error("image width and height must be greater than zero")]
+    EmptyImage,
+}
+
+fn parse_value<T: FromStr>(arg: &str, value: String) -> Result<T, ArgsError> {
+    value.parse().map_err(|_| ArgsError::InvalidValue {
+        arg: arg.to_string(),
+        value,
+    })
+}
+
+fn parse_complex(arg: &str, value: String) -> Result<Complex64, ArgsError> {
+    let parsed = value
+        .split_once(',')
+        .and_then(|(re, im)| Some((re.trim().parse().ok()?, im.trim().parse().ok()?)));
+
+    match parsed {
+        Some((re, im)) => Ok(Complex64 { re, im }),
+        None => Err(ArgsError::InvalidValue {
+            arg: arg.to_string(),
+            value,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::color::ReliefSource;
+
+    fn parse(args: &[&str]) -> Result<RenderArgs, ArgsError> {
+        RenderArgs::parse(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn defaults() {
+        let args = parse(&["-o", "out.png"]).unwrap();
+        assert_eq!(args.output, PathBuf::from("out.png"));
+        assert_eq!(args.width, 1024);
+        assert_eq!(args.height, 1024);
+        assert_eq!(args.generator, None);
+        assert_eq!(args.palette, None);
+        assert!(!args.resumable);
+        assert_eq!(args.format, PixelFormat::Rgba8);
+        assert!(args.opts().mandelbrot);
+        assert_eq!(args.opts().radius_squared, DEFAULT_RADIUS * DEFAULT_RADIUS);
+    }
+
+    #[test]
+    fn full_arguments() {
+        let args = parse(&[
+            "--output=julia.png",
+            "-W",
+            "640",
+            "-H",
+            "480",
+            "--center",
+            "-0.5,0.25",
+            "--julia",
+            "0.16611, 0.59419",
+            "--iterations",
+            "500",
+            "--smoothing",
+            "linear",
+            "--multisampling",
+            "four(0.25)",
+            "--palette",
+            "sunset",
+            "-g",
+            "CPU",
+            "--resumable",
+            "--bit-depth",
+            "16",
+        ])
+        .unwrap();
+
+        assert_eq!(args.output, PathBuf::from("julia.png"));
+        assert_eq!(args.width, 640);
+        assert_eq!(args.height, 480);
+        assert_eq!(args.center, Complex64 { re: -0.5, im: 0.25 });
+        assert_eq!(args.palette.as_deref(), Some("sunset"));
+        assert_eq!(args.generator, Some(RenderGeneratorType::Cpu));
+        assert!(args.resumable);
+        assert_eq!(args.format, PixelFormat::Rgba16);
+
+        let opts = args.opts();
+        assert!(!opts.mandelbrot);
+        assert_eq!(
+            opts.c,
+            Complex64 {
+                re: 0.16611,
+                im: 0.59419
+            }
+        );
+        assert_eq!(opts.iterations, 500);
+        assert_eq!(opts.formula, Formula::default());
+        assert_eq!(opts.smoothing, Smoothing::LinearIntersection);
+        assert_eq!(
+            opts.multisampling,
+            Multisampling::FourPoints { offset: 0.25 }
+        );
+
+        let view = args.view();
+        assert_eq!(view.image_width, 640);
+        assert_eq!(view.image_height, 480);
+    }
+
+    #[test]
+    fn formula() {
+        let opts = parse(&["-o", "out.png", "--formula", "z^3"])
+            .unwrap()
+            .opts();
+        assert_eq!(opts.formula, Formula::IntegerPower { exponent: 3 });
+        assert_eq!(
+            opts.smoothing,
+            Smoothing::from_logarithmic_distance(DEFAULT_RADIUS, 3.0)
+        );
+
+        let opts = parse(&["-o", "out.png", "-f", "z^-2.5"]).unwrap().opts();
+        assert_eq!(opts.formula, Formula::RealPower { exponent: -2.5 });
+        assert_eq!(opts.smoothing, Smoothing::None);
+
+        let opts = parse(&["-o", "out.png", "-f", "Burning Ship"])
+            .unwrap()
+            .opts();
+        assert_eq!(opts.formula, Formula::BurningShip);
+        let opts = parse(&["-o", "out.png", "-f", "mandelbar"]).unwrap().opts();
+        assert_eq!(opts.formula, Formula::Tricorn);
+        let opts = parse(&["-o", "out.png", "-f", "z^3 - 0.5*z + c"])
+            .unwrap()
+            .opts();
+        assert!(matches!(opts.formula, Formula::Expression(_)));
+        assert_eq!(
+            opts.smoothing,
+            Smoothing::from_logarithmic_distance(DEFAULT_RADIUS, 3.0)
+        );
+        assert!(matches!(
+            parse(&["-o", "out.png", "-f", "z^ * c"]),
+            Err(ArgsError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn precision() {
+        let opts = parse(&["-o", "out.png"]).unwrap().opts();
+        assert_eq!(opts.precision, Precision::Single);
+
+        let opts = parse(&[
+            "-o",
+            "out.png",
+            "--plane-width",
+            "1e-9",
+            "--center",
+            "-0.743643887037151,0.131825904205330",
+        ])
+        .unwrap()
+        .opts();
+        assert_eq!(opts.precision, Precision::Double);
+        assert_eq!(opts.c, Complex64 { re: 0.0, im: 0.0 });
+
+        let opts = parse(&["-o", "out.png", "--precision", "double"])
+            .unwrap()
+            .opts();
+        assert_eq!(opts.precision, Precision::Double);
+    }
+
+    #[test]
+    fn interior_checks() {
+        let opts = parse(&["-o", "out.png"]).unwrap().opts();
+        assert_eq!(opts.interior_checks, InteriorChecks::ALL);
+        assert_eq!(opts.interior_coloring, InteriorColoring::None);
+
+        let opts = parse(&[
+            "-o",
+            "out.png",
+            "--interior-checks",
+            "periodicity",
+            "--boundary-tracing",
+            "exact",
+            "--cpu-kernel",
+            "simd8",
+            "--distance-estimation",
+            "--boundary-lines=1.5",
+            "--histogram-equalization",
+            "--interior-coloring",
+            "atom-domain",
+        ])
+        .unwrap()
+        .opts();
+        assert_eq!(opts.interior_coloring, InteriorColoring::AtomDomain);
+        assert_eq!(opts.boundary_tracing, BoundaryTracing::Exact);
+        assert_eq!(opts.cpu_kernel, CpuKernel::Simd8);
+        assert!(opts.distance_estimation);
+        assert_eq!(opts.shading.boundary_thickness, 1.5);
+        assert_eq!(opts.shading.distance_fade, 0.0);
+        assert!(opts.histogram_equalization);
+        assert_eq!(opts.shading.relief, None);
+        assert_eq!(
+            opts.interior_checks,
+            InteriorChecks {
+                bulbs: false,
+                periodicity: true,
+            }
+        );
+    }
+
+    #[test]
+    fn orbit_traps() {
+        let opts = parse(&["-o", "out.png", "--orbit-trap", "circle(0, 0, 0.5)"])
+            .unwrap()
+            .opts();
+        assert_eq!(
+            opts.orbit_trap,
+            Some(OrbitTrap::Circle {
+                center: Complex64 { re: 0.0, im: 0.0 },
+                radius: 0.5,
+            })
+        );
+
+        let args = parse(&[
+            "-o",
+            "out.png",
+            "--orbit-trap=image(0, 0, 2)",
+            "--trap-image",
+            "trap.png",
+        ])
+        .unwrap();
+        assert_eq!(args.trap_image, Some(PathBuf::from("trap.png")));
+
+        assert!(matches!(
+            parse(&["-o", "out.png", "--orbit-trap", "image(0, 0, 2)"]),
+            Err(ArgsError::MissingArgument("--trap-image"))
+        ));
+        assert!(matches!(
+            parse(&["-o", "out.png", "--orbit-trap", "square(0, 0)"]),
+            Err(ArgsError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn relief() {
+        let opts = parse(&[
+            "-o",
+            "out.png",
+            "--light-angle",
+            "120",
+            "--relief",
+            "distance",
+            "--specular=0",
+        ])
+        .unwrap()
+        .opts();
+        assert_eq!(
+            opts.shading.relief,
+            Some(Relief {
+                source: ReliefSource::Distance,
+                light_angle: 120.0,
+                specular: 0.0,
+                ..Default::default()
+            })
+        );
+
+        assert!(matches!(
+            parse(&["-o", "out.png", "--relief", "slope"]),
+            Err(ArgsError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn averaging() {
+        let opts = parse(&["-o", "out.png"]).unwrap().opts();
+        assert_eq!(opts.averaging, Averaging::None);
+
+        let opts = parse(&["-o", "out.png", "--averaging", "stripe(4.5)"])
+            .unwrap()
+            .opts();
+        assert_eq!(opts.averaging, Averaging::Stripe { density: 4.5 });
+
+        let opts = parse(&["-o", "out.png", "--averaging=TIA"]).unwrap().opts();
+        assert_eq!(opts.averaging, Averaging::TriangleInequality);
+
+        assert!(matches!(
+            parse(&["-o", "out.png", "--averaging", "stripe"]),
+            Err(ArgsError::InvalidValue { .. })
+        ));
+    }
+
+    #[test]
+    fn missing_output() {
+        assert!(matches!(
+            parse(&["-W", "16"]),
+            Err(ArgsError::MissingArgument("--output"))
+        ));
+    }
+
+    #[test]
+    fn missing_value() {
+        assert!(matches!(
+            parse(&["-o", "out.png", "--width"]),
+            Err(ArgsError::MissingValue(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_values() {
+        assert!(matches!(
+            parse(&["-o", "out.png", "--multisampling", "linear(0)"]),
+            Err(ArgsError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            parse(&["-o", "out.png", "--center", "1.0"]),
+            Err(ArgsError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            parse(&["-o", "out.png", "-g", "tpu"]),
+            Err(ArgsError::InvalidValue { .. })
+        ));
+        for power in ["0", "3", "14", "64"] {
+            assert!(matches!(
+                parse(&["-o", "out.png", "--chunk-size-power", power]),
+                Err(ArgsError::InvalidValue { .. })
+            ));
+        }
+        assert_eq!(
+            parse(&["-o", "out.png", "--chunk-size-power", "13"])
+                .unwrap()
+                .chunk_size_power,
+            Some(13)
+        );
+    }
+
+    #[test]
+    fn unknown_argument() {
+        assert!(matches!(
+            parse(&["-o", "out.png", "--frobnicate"]),
+            Err(ArgsError::UnknownArgument(_))
+        ));
+    }
+
+    #[test]
+    fn node_arguments() {
+        let parse_node = |args: &[&str]| NodeArgs::parse(args.iter().map(|s| s.to_string()));
+
+        let defaults = parse_node(&[]).unwrap();
+        assert_eq!(defaults.listen.port(), DEFAULT_NODE_PORT);
+        assert!(defaults.listen.ip().is_loopback());
+        assert_eq!(defaults.generator, None);
+        assert_eq!(defaults.max_view_pixels, MAX_VIEW_PIXELS);
+
+        let args = parse_node(&["--listen=127.0.0.1:9000", "-g", "gpu"]).unwrap();
+        assert_eq!(args.listen, "127.0.0.1:9000".parse().unwrap());
+        assert_eq!(args.generator, Some(RenderGeneratorType::Gpu));
+
+        assert!(matches!(
+            parse_node(&["--listen", "localhost"]),
+            Err(ArgsError::InvalidValue { .. })
+        ));
+        assert_eq!(
+            parse_node(&["--max-view-pixels", "65536"])
+                .unwrap()
+                .max_view_pixels,
+            65536
+        );
+        for pixels in ["0", "16777217"] {
+            assert!(matches!(
+                parse_node(&["--max-view-pixels", pixels]),
+                Err(ArgsError::InvalidValue { .. })
+            ));
+        }
+        assert_eq!(
+            parse(&["-o", "out.png", "--node", "render-box:7117"])
+                .unwrap()
+                .node
+                .as_deref(),
+            Some("render-box:7117")
+        );
+    }
+}
@@ -0,0 +1,327 @@
+//! cli/mod.rs - This is where the command-line core application logic happens.
+//!
+//! This contains the headless `render` subcommand, which renders a single
+//! fractal image to a file without needing a window or a display, and the
+//! `node` subcommand, which generates fractals for other processes connecting
+//! over the network.
+
+use crate::{
+    cli::args::{NodeArgs, RenderArgs, RenderGeneratorType, NODE_USAGE, RENDER_USAGE},
+    generator::{
+        composite::CompositeFractalGeneratorFactory,
+        cpu::CpuFractalGeneratorFactory,
+        gpu::GpuFractalGeneratorFactory,
+        manager::GeneratorManager,
+        palette::Palette,
+        perturbation::PerturbationFractalGeneratorFactory,
+        remote::{node::bind_and_serve, RemoteFractalGeneratorFactory},
+        trap::{OrbitTrap, TrapImage},
+        FractalGeneratorFactory,
+    },
+    gpu::{
+        util::{backend::preferred_backends, get_desired_limits, print_adapter_info},
+        GPUContext, GPUContextType,
+    },
+    storage::{CfgFractalGeneratorType, CfgGeneral, CfgSingleton, CHUNK_SIZE_POWERS},
+    util::running_guard::RunningGuard,
+};
+use std::{
+    io::{stderr, Write},
+    process::exit,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::sleep,
+    time::Duration,
+};
+use tokio::{
+    runtime::{self, Runtime},
+    task::yield_now,
+};
+use wgpu::{
+    DeviceDescriptor, Instance, InstanceDescriptor, Maintain, PowerPreference,
+    RequestAdapterOptions, RequestDeviceError,
+};
+
+pub mod args;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Launches the application as a headless renderer, rendering a single image
+/// as described by `args`. These are the arguments following the `render`
+/// subcommand.
+///
+/// This exits with a non-zero status if the arguments were invalid or if an
+/// error occurred while generating or writing the image.
+pub fn start_render_application(args: Vec<String>) -> ! {
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", RENDER_USAGE);
+        exit(0);
+    }
+
+    let args = match RenderArgs::parse(args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("Error: {}\n\n{}", e, RENDER_USAGE);
+            exit(2);
+        },
+    };
+
+    match render(args) {
+        Ok(_) => exit(0),
+        Err(e) => {
+            error!("Error rendering fractal: {:?}", e);
+            eprintln!("\nError: {:#}", e);
+            exit(1);
+        },
+    }
+}
+
+/// Launches the application as a render node, generating fractals for other
+/// processes that connect to it as described by `args`. These are the
+/// arguments following the `node` subcommand.
+///
+/// This never returns on its own, but exits with a non-zero status if the
+/// arguments were invalid or if the node could not accept connections.
+pub fn start_node_application(args: Vec<String>) -> ! {
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", NODE_USAGE);
+        exit(0);
+    }
+
+    let args = match NodeArgs::parse(args) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("Error: {}\n\n{}", e, NODE_USAGE);
+            exit(2);
+        },
+    };
+
+    match serve_node(args) {
+        Ok(never) => never,
+        Err(e) => {
+            error!("Error running render node: {:?}", e);
+            eprintln!("\nError: {:#}", e);
+            exit(1);
+        },
+    }
+}
+
+fn serve_node(args: NodeArgs) -> anyhow::Result<!> {
+    info!("Creating runtime...");
+    let runtime = runtime::Builder::new_multi_thread().enable_all().build()?;
+
+    // The guard keeps the device poll task alive for as long as the node runs.
+    let (factory, _gpu_poll) = create_factory(&runtime, args.generator)?;
+    eprintln!("Starting render node on {}...", args.listen);
+    runtime
+        .block_on(bind_and_serve(args.listen, factory, args.max_view_pixels))
+        .map_err(Into::into)
+}
+
+fn render(args: RenderArgs) -> anyhow::Result<()> {
+    let general = CfgGeneral::read_clone();
+    let chunk_size_power = args
+        .chunk_size_power
+        .unwrap_or(general.fractal_chunk_size_power);
+    if !CHUNK_SIZE_POWERS.contains(&chunk_size_power) {
+        bail!(
+            "Chunk size power {} in general.ron is outside of {:?}",
+            chunk_size_power,
+            CHUNK_SIZE_POWERS
+        );
+    }
+    let chunk_size = 1 << chunk_size_power;
+
+    info!("Creating runtime...");
+    let runtime = runtime::Builder::new_multi_thread().enable_all().build()?;
+
+    // The guard keeps the device poll task alive until rendering has finished.
+    let (factory, _gpu_poll) = match &args.node {
+        Some(node) => {
+            let factory: Arc<dyn FractalGeneratorFactory + Send + Sync> =
+                Arc::new(RemoteFractalGeneratorFactory::new(node.clone()));
+            (factory, None)
+        },
+        None => create_factory(&runtime, args.generator)?,
+    };
+
+    let mut opts = args.opts();
+    if let Some(name) = &args.palette {
+        opts.palette = Some(
+            Palette::find(name).map_err(|e| anyhow!("Error loading palette '{}': {}", name, e))?,
+        );
+    }
+    if let Some(name) = &args.interior_palette {
+        opts.interior_palette = Some(
+            Palette::find(name)
+                .map_err(|e| anyhow!("Error loading interior palette '{}': {}", name, e))?,
+        );
+    }
+    if let (Some(OrbitTrap::Image { image, .. }), Some(path)) =
+        (&mut opts.orbit_trap, &args.trap_image)
+    {
+        *image = TrapImage::load(path)
+            .map_err(|e| anyhow!("Error loading trap image {:?}: {}", path, e))?;
+    }
+    let view = args.view();
+    let views: Vec<_> = view.subdivide_rectangles(chunk_size, chunk_size).collect();
+
+    info!(
+        "Rendering {}x{} image to {:?}...",
+        view.image_width, view.image_height, &args.output
+    );
+    let mut manager = GeneratorManager::new(runtime.handle().clone(), factory);
+    if !manager.factory_supports_precision(&opts) {
+        eprintln!(
+            "The generator can't iterate {:?} in {} precision, falling back to the CPU generator",
+            opts.formula,
+            opts.precision.name()
+        );
+    }
+    manager.start_to_image(
+        opts,
+        view,
+        views,
+        false,
+        args.output.clone(),
+        args.format,
+        args.resumable,
+    )?;
+
+    while manager.running() {
+        manager.poll()?;
+
+        eprint!(
+            "\rGenerating: {:>5.1}%  Writing: {:>5.1}%",
+            manager.progress() * 100.0,
+            manager.writer_progress() * 100.0
+        );
+        stderr().flush().ok();
+
+        sleep(POLL_INTERVAL);
+    }
+    eprintln!();
+
+    info!("Finished rendering {:?}", &args.output);
+
+    Ok(())
+}
+
+/// Creates a local generator factory of the given type, or of the type in the
+/// general config if there is none, along with the guard that keeps its GPU
+/// device, if any, polled.
+fn create_factory(
+    runtime: &Runtime,
+    generator_type: Option<RenderGeneratorType>,
+) -> anyhow::Result<(
+    Arc<dyn FractalGeneratorFactory + Send + Sync>,
+    Option<RunningGuard>,
+)> {
+    let generator_type =
+        generator_type.unwrap_or(match CfgGeneral::read().fractal_generator_type {
+            CfgFractalGeneratorType::Cpu => RenderGeneratorType::Cpu,
+            CfgFractalGeneratorType::Gpu | CfgFractalGeneratorType::GpuDedicated => {
+                RenderGeneratorType::Gpu
+            },
+            CfgFractalGeneratorType::Perturbation => RenderGeneratorType::Perturbation,
+            CfgFractalGeneratorType::Hybrid => RenderGeneratorType::Hybrid,
+        });
+
+    let (factory, gpu_poll): (Arc<dyn FractalGeneratorFactory + Send + Sync + 'static>, _) =
+        match generator_type {
+            RenderGeneratorType::Cpu => (
+                Arc::new(CpuFractalGeneratorFactory::new(num_cpus::get())),
+                None,
+            ),
+            RenderGeneratorType::Gpu => {
+                let (gpu, guard) = runtime.block_on(create_headless_gpu_context())?;
+                (Arc::new(GpuFractalGeneratorFactory::new(gpu)), Some(guard))
+            },
+            RenderGeneratorType::Perturbation => (
+                Arc::new(PerturbationFractalGeneratorFactory::new(num_cpus::get())),
+                None,
+            ),
+            RenderGeneratorType::Hybrid => {
+                let (gpu, guard) = runtime.block_on(create_headless_gpu_context())?;
+                (
+                    Arc::new(CompositeFractalGeneratorFactory::new(vec![
+                        Arc::new(CpuFractalGeneratorFactory::new(num_cpus::get())),
+                        Arc::new(GpuFractalGeneratorFactory::new(gpu)),
+                    ])),
+                    Some(guard),
+                )
+            },
+        };
+
+    Ok((factory, gpu_poll))
+}
+
+/// Creates a GPU context that is not associated with any surface, along with
+/// the task that polls its device.
+pub(crate) async fn create_headless_gpu_context(
+) -> Result<(GPUContext, RunningGuard), HeadlessGpuError> {
+    info!("Creating instance...");
+    let instance = Instance::new(InstanceDescriptor {
+        backends: preferred_backends(),
+        ..Default::default()
+    });
+
+    info!("Requesting adapter...");
+    let adapter = instance
+        .request_adapter(&RequestAdapterOptions {
+            power_preference: PowerPreference::HighPerformance,
+            force_fallback_adapter: false,
+            compatible_surface: None,
+        })
+        .await
+        .ok_or(HeadlessGpuError::RequestAdapterError)?;
+
+    print_adapter_info(&adapter);
+
+    info!("Requesting device...");
+    let limits = get_desired_limits(&adapter);
+    let (device, queue) = adapter
+        .request_device(
+            &DeviceDescriptor {
+                label: Some("Headless Device"),
+                features: Default::default(),
+                limits: limits.clone(),
+            },
+            None,
+        )
+        .await?;
+
+    let device = Arc::new(device);
+    let queue = Arc::new(queue);
+
+    info!("Creating device poll task...");
+    let poll_device = device.clone();
+    let status = Arc::new(AtomicBool::new(true));
+    let poll_status = status.clone();
+    tokio::spawn(async move {
+        while poll_status.load(Ordering::Acquire) {
+            poll_device.poll(Maintain::Poll);
+            yield_now().await;
+        }
+    });
+
+    Ok((
+        GPUContext {
+            device,
+            queue,
+            limits,
+            ty: GPUContextType::Dedicated,
+        },
+        RunningGuard::new(status),
+    ))
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum HeadlessGpuError {
+    #[error("Unable to retrieve a GPU adapter")]
+    RequestAdapterError,
+    #[error("Error requesting logical device")]
+    RequestDeviceError(#[from] RequestDeviceError),
+}
@@ -1,18 +1,167 @@
-use crate::generator::util::{build_four_points_offsets, build_linear_offsets};
+use crate::generator::{
+    expression::{Expression, ExpressionError},
+    util::{build_four_points_offsets, build_linear_offsets},
+    view::View,
+};
 use cgmath::Vector2;
 use regex::{Regex, RegexBuilder};
-use std::{num::ParseFloatError, str::FromStr};
+use std::{
+    num::{ParseFloatError, ParseIntError},
+    str::FromStr,
+};
 
 pub const DEFAULT_RADIUS: f32 = 4f32;
 pub const DEFAULT_RADIUS_SQUARED: f32 = DEFAULT_RADIUS * DEFAULT_RADIUS;
 
+/// How many distinct single-precision values a pixel must span before single
+/// precision is considered good enough to render it.
+const SINGLE_PRECISION_ULPS_PER_PIXEL: f64 = 16.0;
+
 lazy_static::lazy_static! {
 static ref SMOOTHING_REGEX: Regex = RegexBuilder::new(r"^logarithmic(distance)? *\( *(?P<radius>\d+(\.\d+)?|\.\d+) *, *(?P<max_power>\d+(\.\d+)?|\.\d+) *\)$").case_insensitive(true).build().unwrap();
+static ref STRIPE_REGEX: Regex = RegexBuilder::new(r"^stripe *\( *(?P<density>\d+(\.\d+)?|\.\d+) *\)$").case_insensitive(true).build().unwrap();
+static ref FOUR_POINTS_REGEX: Regex = RegexBuilder::new(r"^four(points)? *\( *(?P<offset>\d+(\.\d+)?|\.\d+) *\)$").case_insensitive(true).build().unwrap();
+static ref POWER_REGEX: Regex = RegexBuilder::new(r"^z *\^ *(?P<exponent>-?(\d+(\.\d*)?|\.\d+))$").case_insensitive(true).build().unwrap();
+static ref LINEAR_REGEX: Regex = RegexBuilder::new(r"^linear *\( *(?P<axial_points>\d+) *\)$").case_insensitive(true).build().unwrap();
+static ref ADAPTIVE_REGEX: Regex = RegexBuilder::new(r"^adaptive *\( *(?P<max_samples>\d+) *, *(?P<threshold>\d+(\.\d+)?|\.\d+) *\)$").case_insensitive(true).build().unwrap();
+}
+
+/// Represents the iterative function applied to `z` on every iteration.
+///
+/// The Mandelbrot and Julia/Fatou variants of every formula are selected by
+/// [`FractalOpts::mandelbrot`].
+///
+/// [`FractalOpts::mandelbrot`]: crate::generator::FractalOpts::mandelbrot
+#[derive(Debug, Clone, PartialOrd, PartialEq, Serialize, Deserialize)]
+pub enum Formula {
+    /// `z^exponent + c` for an integer exponent. An exponent of 2 gives the
+    /// classic Mandelbrot set.
+    IntegerPower { exponent: i32 },
+    /// `z^exponent + c` for a real exponent, using the principal branch of the
+    /// complex power.
+    RealPower { exponent: f32 },
+    /// `(|Re(z)| + i|Im(z)|)^2 + c`, the Burning Ship fractal.
+    BurningShip,
+    /// `conj(z)^2 + c`, also known as the Mandelbar set.
+    Tricorn,
+    /// `(Re(z) - i|Im(z)|)^2 + c`, the Perpendicular Burning Ship fractal.
+    PerpendicularBurningShip,
+    /// `|Re(z^2)| + i Im(z^2) + c`, the Celtic fractal.
+    Celtic,
+    /// A user-defined expression in `z` and `c`. Unlike the other formulas,
+    /// the `+ c` is not implied.
+    Expression(Expression),
+}
+
+impl Formula {
+    /// Gets the exponent of `z` in this formula.
+    pub fn exponent(&self) -> f32 {
+        match self {
+            Formula::IntegerPower { exponent } => *exponent as f32,
+            Formula::RealPower { exponent } => *exponent,
+            Formula::BurningShip
+            | Formula::Tricorn
+            | Formula::PerpendicularBurningShip
+            | Formula::Celtic => 2.0,
+            // expressions that aren't polynomial-like get no smoothing
+            Formula::Expression(expression) => expression.degree().unwrap_or(1.0),
+        }
+    }
+
+    /// Whether the Mandelbrot variant of this formula should start with
+    /// `z = c` instead of `z = 0`.
+    ///
+    /// Formulas with negative exponents are undefined at `z = 0`, so they skip
+    /// straight to the first iteration's result instead.
+    pub fn starts_at_c(&self) -> bool {
+        self.exponent() < 0.0
+    }
+
+    /// Whether distances can be estimated for this formula. Expressions don't
+    /// have a known derivative, so their pixels never get a distance estimate.
+    pub fn has_derivative(&self) -> bool {
+        !matches!(self, Formula::Expression(_))
+    }
+
+    /// Creates the logarithmic distance smoothing appropriate for this formula
+    /// and the given escape radius.
+    pub fn logarithmic_smoothing(&self, radius: f32) -> Smoothing {
+        Smoothing::from_logarithmic_distance(radius, self.exponent())
+    }
+}
+
+impl Default for Formula {
+    fn default() -> Self {
+        Formula::IntegerPower { exponent: 2 }
+    }
+}
+
+impl FromStr for Formula {
+    type Err = ParseFormulaError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s_lowercase = s.to_ascii_lowercase();
+        // names may be written with spaces, dashes or underscores between words
+        let name: String = s_lowercase
+            .chars()
+            .filter(|c| !matches!(c, ' ' | '-' | '_'))
+            .collect();
+
+        match name.as_str() {
+            "mandelbrot" => return Ok(Formula::default()),
+            "burningship" => return Ok(Formula::BurningShip),
+            "tricorn" | "mandelbar" => return Ok(Formula::Tricorn),
+            "perpendicularburningship" => return Ok(Formula::PerpendicularBurningShip),
+            "celtic" => return Ok(Formula::Celtic),
+            _ => {},
+        }
+
+        if let Some(captures) = POWER_REGEX.captures(&s_lowercase) {
+            let exponent = &captures["exponent"];
+            if exponent.contains('.') {
+                Ok(Formula::RealPower {
+                    exponent: exponent.parse::<f32>()?,
+                })
+            } else {
+                Ok(Formula::IntegerPower {
+                    exponent: exponent.parse::<i32>()?,
+                })
+            }
+        } else {
+            Ok(Formula::Expression(s.parse()?))
+        }
+    }
+}
+
+/// Returned if an error occurred while parsing a formula from a string.
+#[derive(Debug, Clone)]
+pub enum ParseFormulaError {
+    ParseFloatError(ParseFloatError),
+    ParseIntError(ParseIntError),
+    ExpressionError(ExpressionError),
+}
+
+impl From<ParseFloatError> for ParseFormulaError {
+    fn from(e: ParseFloatError) -> Self {
+        ParseFormulaError::ParseFloatError(e)
+    }
+}
+
+impl From<ParseIntError> for ParseFormulaError {
+    fn from(e: ParseIntError) -> Self {
+        ParseFormulaError::ParseIntError(e)
+    }
+}
+
+impl From<ExpressionError> for ParseFormulaError {
+    fn from(e: ExpressionError) -> Self {
+        ParseFormulaError::ExpressionError(e)
+    }
 }
 
 /// Represents an operation for smoothing an integer iteration count into a
 /// floating point value.
-#[derive(Debug, Copy, Clone, PartialOrd, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialOrd, PartialEq, Serialize, Deserialize)]
 pub enum Smoothing {
     None,
     LogarithmicDistance { divisor: f32, addend: f32 },
@@ -20,9 +169,26 @@ pub enum Smoothing {
 }
 
 impl Smoothing {
+    /// Gets the name of this smoothing as displayed to the user.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Smoothing::None => "None",
+            Smoothing::LogarithmicDistance { .. } => "Logarithmic",
+            Smoothing::LinearIntersection => "Linear Intersection",
+        }
+    }
+
     /// Creates a logarithmic distance smoothing from the given radius and max
     /// power.
+    ///
+    /// The max power is the exponent of `z` in the iterated formula. Only
+    /// formulas with a power greater than 1 escape exponentially, so any other
+    /// power results in no smoothing.
     pub fn from_logarithmic_distance(radius: f32, max_power: f32) -> Smoothing {
+        if max_power <= 1.0 {
+            return Smoothing::None;
+        }
+
         let divisor = max_power.ln();
         Smoothing::LogarithmicDistance {
             divisor,
@@ -65,9 +231,96 @@ impl From<ParseFloatError> for ParseSmoothingError {
     }
 }
 
+/// Averages are multiplied by this to get pixel values, so that they are in
+/// the same range as iteration counts and look reasonable with the same
+/// palettes.
+pub const AVERAGE_VALUE_SCALE: f32 = 32.0;
+
+/// Represents a statistic averaged over the orbit of each escaped point, which
+/// is used as its value instead of its smoothed iteration count.
+///
+/// Each average is interpolated between the averages with and without the
+/// last iteration using the fractional part of the smoothed iteration count,
+/// so that it is continuous across iteration bands. This works best with
+/// logarithmic smoothing and a large escape radius.
+#[derive(Debug, Copy, Clone, PartialOrd, PartialEq, Serialize, Deserialize)]
+pub enum Averaging {
+    /// Escaped points are given their smoothed iteration count.
+    None,
+    /// Averages `sin(density * arg(z)) / 2 + 1 / 2`, which forms stripes
+    /// radiating out from the set.
+    Stripe { density: f32 },
+    /// Averages where `|z|` lies between the smallest and largest values the
+    /// triangle inequality allows it to have given `c` and the previous value
+    /// of `z`.
+    TriangleInequality,
+    /// Averages the angle the orbit turns by at each iteration, divided by
+    /// pi.
+    Curvature,
+}
+
+impl Averaging {
+    /// Gets the name of this averaging as displayed to the user.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Averaging::None => "None",
+            Averaging::Stripe { .. } => "Stripe Average",
+            Averaging::TriangleInequality => "Triangle Inequality Average",
+            Averaging::Curvature => "Curvature Average",
+        }
+    }
+}
+
+impl Default for Averaging {
+    fn default() -> Self {
+        Averaging::None
+    }
+}
+
+impl FromStr for Averaging {
+    type Err = ParseAveragingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s_lowercase = s.to_ascii_lowercase();
+        if s_lowercase == "none" {
+            Ok(Averaging::None)
+        } else if s_lowercase == "tia" || s_lowercase == "triangle-inequality" {
+            Ok(Averaging::TriangleInequality)
+        } else if s_lowercase == "curvature" {
+            Ok(Averaging::Curvature)
+        } else if let Some(captures) = STRIPE_REGEX.captures(&s_lowercase) {
+            Ok(Averaging::Stripe {
+                density: captures["density"].parse::<f32>()?,
+            })
+        } else {
+            Err(ParseAveragingError::NotAveraging)
+        }
+    }
+}
+
+/// Returned if an error occurred while parsing an averaging from a string.
+#[derive(Debug, Clone)]
+pub enum ParseAveragingError {
+    NotAveraging,
+    ParseFloatError(ParseFloatError),
+}
+
+impl From<ParseFloatError> for ParseAveragingError {
+    fn from(e: ParseFloatError) -> Self {
+        ParseAveragingError::ParseFloatError(e)
+    }
+}
+
 /// Represents an image multisampling function.
+///
+/// The samples of a pixel are averaged into a single value before it is
+/// colored, rather than being colored separately and averaged. Where a pixel's
+/// samples straddle a point where the palette wraps around, like the brightness
+/// of the classic colors every 16 iterations, their average is colored with
+/// whatever lies between them rather than a blend of the two colors, so bands
+/// of color keep aliased edges.
 #[allow(dead_code)]
-#[derive(Copy, Clone, Debug, PartialOrd, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialOrd, PartialEq, Serialize, Deserialize)]
 pub enum Multisampling {
     None,
     /// Samples the fractal at four points within the pixel. Each point is
@@ -83,6 +336,19 @@ pub enum Multisampling {
         /// The number of points per axis.
         axial_points: u32,
     },
+    /// Samples each pixel once, then samples the pixels whose values differ
+    /// from one of their neighbors' by more than `threshold` again using a
+    /// linear grid of up to `max_samples` points. This only spends time on
+    /// edges and other detailed regions.
+    Adaptive {
+        /// The maximum number of samples per pixel. This is rounded down to a
+        /// square number so the samples form a grid.
+        max_samples: u32,
+        /// How far apart two neighboring pixels' smoothed iteration counts
+        /// have to be for them to be sampled again. Pixels inside the set are
+        /// always considered different from pixels outside of it.
+        threshold: f32,
+    },
 }
 
 impl Multisampling {
@@ -91,14 +357,407 @@ impl Multisampling {
             Multisampling::None => 1,
             Multisampling::FourPoints { .. } => 4,
             Multisampling::Linear { axial_points } => *axial_points * *axial_points,
+            Multisampling::Adaptive { max_samples, .. } => {
+                let axial_points = adaptive_axial_points(*max_samples);
+                axial_points * axial_points
+            },
         }
     }
 
+    /// Gets the sub-pixel offsets of every sample taken of a pixel. For
+    /// adaptive multisampling, these are the samples taken of the pixels that
+    /// are sampled again.
     pub fn offsets(&self) -> Vec<Vector2<f32>> {
         match self {
             Multisampling::None => vec![Vector2 { x: 0.5, y: 0.5 }],
             Multisampling::FourPoints { offset } => build_four_points_offsets(*offset),
             Multisampling::Linear { axial_points } => build_linear_offsets(*axial_points),
+            Multisampling::Adaptive { max_samples, .. } => {
+                build_linear_offsets(adaptive_axial_points(*max_samples))
+            },
+        }
+    }
+
+    /// Gets the contrast threshold above which pixels are sampled again, if
+    /// this is adaptive multisampling.
+    pub fn adaptive_threshold(&self) -> Option<f32> {
+        match self {
+            Multisampling::Adaptive { threshold, .. } => Some(*threshold),
+            _ => None,
+        }
+    }
+}
+
+fn adaptive_axial_points(max_samples: u32) -> u32 {
+    ((max_samples as f64).sqrt().floor() as u32).max(1)
+}
+
+impl FromStr for Multisampling {
+    type Err = ParseMultisamplingError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s_lowercase = s.to_ascii_lowercase();
+        if s_lowercase == "none" {
+            Ok(Multisampling::None)
+        } else if let Some(captures) = FOUR_POINTS_REGEX.captures(&s_lowercase) {
+            Ok(Multisampling::FourPoints {
+                offset: captures["offset"].parse::<f32>()?,
+            })
+        } else if let Some(captures) = LINEAR_REGEX.captures(&s_lowercase) {
+            let axial_points = captures["axial_points"].parse::<u32>()?;
+            if axial_points == 0 {
+                Err(ParseMultisamplingError::NotMultisampling)
+            } else {
+                Ok(Multisampling::Linear { axial_points })
+            }
+        } else if let Some(captures) = ADAPTIVE_REGEX.captures(&s_lowercase) {
+            let max_samples = captures["max_samples"].parse::<u32>()?;
+            if max_samples == 0 {
+                Err(ParseMultisamplingError::NotMultisampling)
+            } else {
+                Ok(Multisampling::Adaptive {
+                    max_samples,
+                    threshold: captures["threshold"].parse::<f32>()?,
+                })
+            }
+        } else {
+            Err(ParseMultisamplingError::NotMultisampling)
+        }
+    }
+}
+
+/// Returned if an error occurred while parsing a multisampling function from a
+/// string.
+#[derive(Debug, Clone)]
+pub enum ParseMultisamplingError {
+    NotMultisampling,
+    ParseFloatError(ParseFloatError),
+    ParseIntError(ParseIntError),
+}
+
+impl From<ParseFloatError> for ParseMultisamplingError {
+    fn from(e: ParseFloatError) -> Self {
+        ParseMultisamplingError::ParseFloatError(e)
+    }
+}
+
+impl From<ParseIntError> for ParseMultisamplingError {
+    fn from(e: ParseIntError) -> Self {
+        ParseMultisamplingError::ParseIntError(e)
+    }
+}
+
+/// The floating-point precision fractals are iterated in.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Precision {
+    /// `f32`, supported by every generator.
+    Single,
+    /// `f64` on the CPU. The GPU generator emulates it with pairs of `f32`s
+    /// for the formulas that allow it, and the perturbation generator
+    /// iterates its deltas in it.
+    Double,
+}
+
+impl Precision {
+    /// Gets the lowest precision able to tell neighboring pixels in `view`
+    /// apart.
+    ///
+    /// Values of `z` stay around the magnitude of the escape radius while
+    /// iterating, so the plane coordinates are never considered to be smaller
+    /// than 1.
+    pub fn required_for(view: &View) -> Precision {
+        let plane_end_x = view.plane_start_x + view.image_width as f64 * view.image_scale_x;
+        let plane_end_y = view.plane_start_y + view.image_height as f64 * view.image_scale_y;
+        let magnitude = [
+            view.plane_start_x,
+            view.plane_start_y,
+            plane_end_x,
+            plane_end_y,
+        ]
+        .into_iter()
+        .fold(1.0f64, |max, value| max.max(value.abs()));
+        let pixel_size = view.image_scale_x.abs().min(view.image_scale_y.abs());
+
+        if pixel_size < magnitude * f32::EPSILON as f64 * SINGLE_PRECISION_ULPS_PER_PIXEL {
+            Precision::Double
+        } else {
+            Precision::Single
+        }
+    }
+
+    /// Gets the name of this precision as displayed to the user.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Precision::Single => "Single (f32)",
+            Precision::Double => "Double (f64)",
+        }
+    }
+}
+
+impl Default for Precision {
+    fn default() -> Self {
+        Precision::Single
+    }
+}
+
+impl FromStr for Precision {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "single" | "f32" => Ok(Precision::Single),
+            "double" | "f64" => Ok(Precision::Double),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Shortcuts for finding points inside the set without iterating them all the
+/// way to the maximum iteration count.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct InteriorChecks {
+    /// Skips points inside the main cardioid and the period-2 bulb. This only
+    /// applies to the Mandelbrot set of `z^2 + c`.
+    pub bulbs: bool,
+    /// Stops iterating a point once `z` comes back to a value it had before,
+    /// using Brent's cycle detection.
+    pub periodicity: bool,
+}
+
+impl InteriorChecks {
+    pub const NONE: InteriorChecks = InteriorChecks {
+        bulbs: false,
+        periodicity: false,
+    };
+    pub const ALL: InteriorChecks = InteriorChecks {
+        bulbs: true,
+        periodicity: true,
+    };
+}
+
+impl Default for InteriorChecks {
+    fn default() -> Self {
+        InteriorChecks::ALL
+    }
+}
+
+impl FromStr for InteriorChecks {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(InteriorChecks::NONE),
+            "bulbs" => Ok(InteriorChecks {
+                bulbs: true,
+                periodicity: false,
+            }),
+            "periodicity" => Ok(InteriorChecks {
+                bulbs: false,
+                periodicity: true,
+            }),
+            "all" => Ok(InteriorChecks::ALL),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Whether the CPU generator uses Mariani-Silver boundary tracing, which only
+/// generates the borders of rectangles and fills in the ones whose borders are
+/// uniform.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum BoundaryTracing {
+    /// Every pixel is generated.
+    Off,
+    /// Rectangles are only filled when that can't change the image.
+    /// Rectangles inside the set are always filled, while rectangles of
+    /// escaped pixels are only filled when neither smoothing nor
+    /// multisampling is used, as those make pixels differ from their borders.
+    /// Nothing is filled while the inside of the set is colored, or for
+    /// formulas other than `z^n + c` with `n >= 2`, whose sets can have
+    /// escaping islands that a border doesn't show.
+    Exact,
+    /// Every rectangle with a uniform border is filled. This is the fastest,
+    /// but can leave visible rectangles when smoothing or multisampling.
+    Always,
+}
+
+impl BoundaryTracing {
+    /// Gets the name of this boundary tracing mode as displayed to the user.
+    pub fn name(&self) -> &'static str {
+        match self {
+            BoundaryTracing::Off => "Off",
+            BoundaryTracing::Exact => "Exact",
+            BoundaryTracing::Always => "Always",
+        }
+    }
+}
+
+impl Default for BoundaryTracing {
+    fn default() -> Self {
+        BoundaryTracing::Off
+    }
+}
+
+impl FromStr for BoundaryTracing {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(BoundaryTracing::Off),
+            "exact" => Ok(BoundaryTracing::Exact),
+            "always" => Ok(BoundaryTracing::Always),
+            _ => Err(()),
         }
     }
 }
+
+/// How the CPU generator iterates the fractal.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum CpuKernel {
+    /// Each location is iterated on its own.
+    Scalar,
+    /// Four locations are iterated in lockstep using SIMD.
+    Simd4,
+    /// Eight locations are iterated in lockstep using SIMD.
+    Simd8,
+}
+
+impl CpuKernel {
+    /// Gets the name of this kernel as displayed to the user.
+    pub fn name(&self) -> &'static str {
+        match self {
+            CpuKernel::Scalar => "Scalar",
+            CpuKernel::Simd4 => "SIMD x4",
+            CpuKernel::Simd8 => "SIMD x8",
+        }
+    }
+}
+
+impl Default for CpuKernel {
+    fn default() -> Self {
+        CpuKernel::Scalar
+    }
+}
+
+impl FromStr for CpuKernel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "scalar" => Ok(CpuKernel::Scalar),
+            "simd4" => Ok(CpuKernel::Simd4),
+            "simd8" => Ok(CpuKernel::Simd8),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Interior magnitudes are multiplied by this to get pixel values, so that
+/// they are in the same range as iteration counts and look reasonable with the
+/// same palettes.
+pub const INTERIOR_MAGNITUDE_SCALE: f32 = 32.0;
+
+/// How close `z` has to come back to itself for its orbit to be considered
+/// periodic by interior coloring, as a squared distance. This is much looser
+/// than periodicity checking, because orbits are only ever approaching their
+/// cycles.
+pub const INTERIOR_PERIOD_TOLERANCE: f32 = 1e-8;
+
+/// How points inside the set are colored.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum InteriorColoring {
+    /// Points inside the set are black.
+    None,
+    /// Points are colored by the magnitude of `z` after the last iteration.
+    FinalMagnitude,
+    /// Points are colored by the iteration where `z` came closest to 0.
+    MinMagnitudeIteration,
+    /// Points are colored by the period of the cycle their orbit is attracted
+    /// to.
+    Period,
+    /// Points are colored by their estimated distance to the boundary of the
+    /// set, in pixels. This only applies to Mandelbrot sets of `z^n + c`.
+    Distance,
+    /// Points are colored by their atom domain, which is the iteration where
+    /// `z` came closest to 0, shaded by how much closer it came than at any
+    /// earlier iteration.
+    AtomDomain,
+}
+
+impl InteriorColoring {
+    /// Gets the name of this interior coloring mode as displayed to the user.
+    pub fn name(&self) -> &'static str {
+        match self {
+            InteriorColoring::None => "None",
+            InteriorColoring::FinalMagnitude => "Final |z|",
+            InteriorColoring::MinMagnitudeIteration => "Min |z| Iteration",
+            InteriorColoring::Period => "Period",
+            InteriorColoring::Distance => "Distance",
+            InteriorColoring::AtomDomain => "Atom Domain",
+        }
+    }
+}
+
+impl Default for InteriorColoring {
+    fn default() -> Self {
+        InteriorColoring::None
+    }
+}
+
+impl FromStr for InteriorColoring {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(InteriorColoring::None),
+            "final-magnitude" => Ok(InteriorColoring::FinalMagnitude),
+            "min-magnitude" => Ok(InteriorColoring::MinMagnitudeIteration),
+            "period" => Ok(InteriorColoring::Period),
+            "distance" => Ok(InteriorColoring::Distance),
+            "atom-domain" => Ok(InteriorColoring::AtomDomain),
+            _ => Err(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn required_precision() {
+        assert_eq!(
+            Precision::required_for(&View::new_centered_uniform(1024, 1024, 3.0)),
+            Precision::Single
+        );
+        assert_eq!(
+            Precision::required_for(&View::new_uniform(1024, 1024, 1e-5, -0.75, 0.1)),
+            Precision::Double
+        );
+        // far away from the origin, a wider plane is needed for single precision
+        assert_eq!(
+            Precision::required_for(&View::new_uniform(1024, 1024, 0.01, 0.0, 0.0)),
+            Precision::Single
+        );
+        assert_eq!(
+            Precision::required_for(&View::new_uniform(1024, 1024, 0.01, 1000.0, 0.0)),
+            Precision::Double
+        );
+    }
+
+    #[test]
+    fn adaptive_multisampling() {
+        let multisampling: Multisampling = "adaptive(20, 0.5)".parse().unwrap();
+        assert_eq!(
+            multisampling,
+            Multisampling::Adaptive {
+                max_samples: 20,
+                threshold: 0.5
+            }
+        );
+        assert_eq!(multisampling.sample_count(), 16);
+        assert_eq!(multisampling.offsets(), build_linear_offsets(4));
+        assert_eq!(multisampling.adaptive_threshold(), Some(0.5));
+        assert!("adaptive(0, 0.5)".parse::<Multisampling>().is_err());
+    }
+}
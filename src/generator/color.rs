@@ -1,5 +1,235 @@
-use cgmath::Vector4;
-use std::mem::transmute;
+use crate::generator::{palette::Palette, PixelValue, NO_DISTANCE};
+use cgmath::{InnerSpace, Vector2, Vector3, Vector4, Zero};
+use std::{mem::transmute, str::FromStr};
+
+/// How much of a palette color is left on the parts of a relief facing away
+/// from the light.
+pub const RELIEF_AMBIENT: f32 = 0.25;
+
+/// How tight the specular highlights of a relief are.
+pub const RELIEF_SHININESS: f32 = 32.0;
+
+/// Describes how the estimated distance from each pixel to the set shades the
+/// fractal, and how the fractal is lit as a relief. Pixels whose distance was
+/// not estimated are never shaded.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Shading {
+    /// Pixels closer to the set than this many pixels are drawn as black
+    /// boundary lines. 0 draws no lines.
+    pub boundary_thickness: f32,
+    /// How many pixels away from the set escaped pixels fade in from black
+    /// over. 0 disables the fading.
+    pub distance_fade: f32,
+    /// How escaped pixels are lit as a surface, or `None` to leave them flat.
+    #[serde(default)]
+    pub relief: Option<Relief>,
+}
+
+impl Shading {
+    /// Gets how bright a pixel at the given distance from the set is, between
+    /// 0 and 1.
+    ///
+    /// This is mirrored by `shading_brightness` in
+    /// `recolor_fragment_shader.wgsl.liquid`.
+    pub fn brightness(&self, distance: f32) -> f32 {
+        let mut brightness = 1.0;
+
+        if self.boundary_thickness > 0.0 {
+            // lines get a single pixel of anti-aliasing
+            brightness *= (distance - self.boundary_thickness + 0.5).clamp(0.0, 1.0);
+        }
+
+        if self.distance_fade > 0.0 {
+            brightness *= 1.0 - (-distance / self.distance_fade).exp();
+        }
+
+        brightness
+    }
+}
+
+/// Lights the escaped pixels of the fractal as a surface whose height comes
+/// from their values, using Lambert diffuse and Blinn-Phong specular lighting.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Relief {
+    /// What the height of the surface comes from.
+    pub source: ReliefSource,
+    /// The direction the light comes from, in degrees counter-clockwise from
+    /// the right of the image.
+    pub light_angle: f32,
+    /// How high the light is above the image, in degrees. 90 lights the image
+    /// from straight above.
+    pub light_height: f32,
+    /// How bright the specular highlights are. 0 disables them.
+    pub specular: f32,
+    /// How much steeper than its heights the surface is drawn.
+    pub depth: f32,
+}
+
+impl Default for Relief {
+    fn default() -> Self {
+        Relief {
+            source: ReliefSource::Value,
+            light_angle: 45.0,
+            light_height: 45.0,
+            specular: 0.5,
+            depth: 1.0,
+        }
+    }
+}
+
+impl Relief {
+    /// Gets the unit vector pointing towards the light, in pixel coordinates
+    /// where `y` points down the image and `z` points out of it.
+    pub fn light_direction(&self) -> Vector3<f32> {
+        let (angle_sin, angle_cos) = self.light_angle.to_radians().sin_cos();
+        let (height_sin, height_cos) = self.light_height.to_radians().sin_cos();
+        Vector3::new(height_cos * angle_cos, -height_cos * angle_sin, height_sin)
+    }
+
+    /// Gets the height of the surface at a pixel, or `None` if the pixel is
+    /// inside the set and so has no surface.
+    ///
+    /// This is mirrored by `relief_height_at` in
+    /// `recolor_fragment_shader.wgsl.liquid`.
+    pub fn height(&self, value: &PixelValue) -> Option<f32> {
+        if value.coverage <= 0.0 {
+            return None;
+        }
+
+        Some(match self.source {
+            // closer to the set is higher, like with values
+            ReliefSource::Distance if value.distance != NO_DISTANCE => -(1.0 + value.distance).ln(),
+            _ => value.value,
+        })
+    }
+
+    /// Gets the slope of the surface at `(x, y)` in a grid of values `width`
+    /// pixels wide, from the heights of its neighbors. Pixels without a
+    /// surface are flat.
+    pub fn slope(&self, values: &[PixelValue], width: usize, x: usize, y: usize) -> Vector2<f32> {
+        let height = values.len() / width;
+        let height_at = |x: usize, y: usize| self.height(&values[y * width + x]);
+        let center = match height_at(x, y) {
+            Some(center) => center,
+            None => return Vector2::zero(),
+        };
+
+        let derivative = |before: Option<f32>, after: Option<f32>| match (before, after) {
+            (Some(before), Some(after)) => (after - before) / 2.0,
+            (Some(before), None) => center - before,
+            (None, Some(after)) => after - center,
+            (None, None) => 0.0,
+        };
+        Vector2::new(
+            derivative(
+                (x > 0).then(|| height_at(x - 1, y)).flatten(),
+                (x + 1 < width).then(|| height_at(x + 1, y)).flatten(),
+            ),
+            derivative(
+                (y > 0).then(|| height_at(x, y - 1)).flatten(),
+                (y + 1 < height).then(|| height_at(x, y + 1)).flatten(),
+            ),
+        )
+    }
+
+    /// Gets how much of the palette color is lit and how bright the specular
+    /// highlight is at a pixel with the given slope.
+    ///
+    /// This is mirrored by `relief_lighting` in
+    /// `recolor_fragment_shader.wgsl.liquid`.
+    pub fn lighting(&self, slope: Vector2<f32>) -> (f32, f32) {
+        let normal = Vector3::new(-self.depth * slope.x, -self.depth * slope.y, 1.0).normalize();
+        let light = self.light_direction();
+        let diffuse = normal.dot(light).max(0.0);
+        let half_vector = (light + Vector3::unit_z()).normalize();
+        let specular = self.specular * normal.dot(half_vector).max(0.0).powf(RELIEF_SHININESS);
+
+        (RELIEF_AMBIENT + (1.0 - RELIEF_AMBIENT) * diffuse, specular)
+    }
+}
+
+/// What the height of a [`Relief`] comes from.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum ReliefSource {
+    /// The smoothed iteration counts, or whatever else the values are.
+    Value,
+    /// The estimated distances to the set, where they were estimated, and the
+    /// values elsewhere.
+    Distance,
+}
+
+impl ReliefSource {
+    /// Gets the name of this source as displayed to the user.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReliefSource::Value => "Value",
+            ReliefSource::Distance => "Distance Estimate",
+        }
+    }
+}
+
+impl FromStr for ReliefSource {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "value" => Ok(ReliefSource::Value),
+            "distance" => Ok(ReliefSource::Distance),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Colors a generated pixel value with the given palettes, or with the classic
+/// hue-cycling colors where there are none, and shading. Escaped values use
+/// `palette`, while interior values use `interior_palette`. `slope` is the
+/// slope of the pixel's relief, which is only used if the shading has one.
+/// The palettes' stops must be sorted by position.
+///
+/// This is mirrored by the GPU recolor shader.
+pub fn color_value(
+    value: &PixelValue,
+    slope: Vector2<f32>,
+    palette: Option<&Palette>,
+    interior_palette: Option<&Palette>,
+    shading: &Shading,
+) -> Vector4<f32> {
+    let mut color = palette_color(palette, value.value);
+    if let Some(relief) = &shading.relief {
+        let (diffuse, specular) = relief.lighting(slope);
+        color = color * diffuse + Vector4::new(specular, specular, specular, 0.0);
+    }
+
+    // pixels partially inside the set fade towards black
+    let brightness = value.coverage * shading.brightness(value.distance);
+    color *= brightness;
+
+    // and then towards their interior color, if they have one
+    if value.interior >= 0.0 {
+        color += palette_color(interior_palette, value.interior) * (1.0 - value.coverage);
+    }
+
+    Vector4 {
+        x: color.x,
+        y: color.y,
+        z: color.z,
+        w: 1.0,
+    }
+}
+
+/// Gets the color of a value in the given palette, or in the classic colors if
+/// there is none.
+fn palette_color(palette: Option<&Palette>, value: f32) -> Vector4<f32> {
+    match palette {
+        Some(palette) => palette.color(value),
+        None => Vector4::<f32>::from_hsba(
+            value * 3.3f32 / 256f32 % 1f32,
+            1f32,
+            value * 16f32 / 256f32 % 1f32,
+            1f32,
+        ),
+    }
+}
 
 /// Trait for any color that can be created by converting HSBA values into RGBA
 /// values.
@@ -95,3 +325,111 @@ impl From<RGBA8Color> for [u8; 4] {
         unsafe { transmute(c) }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{view::View, PixelFormat, ValueBlock, NO_INTERIOR};
+
+    #[test]
+    fn value_blocks_match_the_classic_colors() {
+        // how the CPU generator colored each sample before values were colored
+        // separately, averaging the colors of a pixel's samples
+        let iterations = 200.0;
+        let sample_color = |value: f32| {
+            if value < iterations {
+                Vector4::<f32>::from_hsba(
+                    value * 3.3f32 / 256f32 % 1f32,
+                    1f32,
+                    value * 16f32 / 256f32 % 1f32,
+                    1f32,
+                )
+            } else {
+                Vector4::new(0.0, 0.0, 0.0, 1.0)
+            }
+        };
+
+        // (value, samples that escaped out of 4)
+        let pixels = [(37.5, 4), (37.5, 3), (123.25, 1), (0.0, 0)];
+        let mut expected = vec![];
+        let mut values = vec![];
+        for (value, escaped) in pixels {
+            let mut color = Vector4::zero();
+            for sample in 0..4 {
+                let sample = if sample < escaped { value } else { iterations };
+                color += sample_color(sample) / 4.0;
+            }
+            let color: RGBA8Color = color.into();
+            let color: [u8; 4] = color.into();
+            expected.extend_from_slice(&color);
+
+            values.push(PixelValue {
+                value,
+                coverage: escaped as f32 / 4.0,
+                distance: NO_DISTANCE,
+                interior: NO_INTERIOR,
+            });
+        }
+
+        let block = ValueBlock {
+            view: View::new_centered_uniform(4, 1, 1.0),
+            values: values.into_boxed_slice(),
+        };
+        let colored = block.color_with_neighbors(
+            None,
+            None,
+            None,
+            None,
+            &Shading::default(),
+            PixelFormat::Rgba8,
+        );
+        assert_eq!(&*colored.image, expected.as_slice());
+    }
+
+    #[test]
+    fn relief_faces_the_light() {
+        let relief = Relief {
+            light_angle: 0.0,
+            light_height: 30.0,
+            specular: 0.0,
+            ..Default::default()
+        };
+
+        // a ramp rising to the right faces away from a light on the right
+        let values: Vec<_> = (0..9)
+            .map(|i| PixelValue {
+                value: (i % 3) as f32,
+                coverage: 1.0,
+                distance: NO_DISTANCE,
+                interior: NO_INTERIOR,
+            })
+            .collect();
+        let slope = relief.slope(&values, 3, 1, 1);
+        assert_eq!(slope, Vector2::new(1.0, 0.0));
+        assert_eq!(relief.slope(&values, 3, 0, 0), Vector2::new(1.0, 0.0));
+
+        let (flat, _) = relief.lighting(Vector2::zero());
+        let (away, _) = relief.lighting(slope);
+        let (towards, _) = relief.lighting(-slope);
+        assert!(away < flat && flat < towards);
+        assert!(away >= RELIEF_AMBIENT);
+    }
+
+    #[test]
+    fn relief_skips_the_interior() {
+        let relief = Relief::default();
+        let mut values = [PixelValue {
+            value: 4.0,
+            coverage: 1.0,
+            distance: NO_DISTANCE,
+            interior: NO_INTERIOR,
+        }; 3];
+        values[0] = PixelValue::default();
+        assert_eq!(relief.height(&values[0]), None);
+
+        // the pixel next to the interior only uses its other neighbor
+        values[2].value = 6.0;
+        assert_eq!(relief.slope(&values, 3, 1, 0), Vector2::new(2.0, 0.0));
+        assert_eq!(relief.slope(&values, 3, 0, 0), Vector2::zero());
+    }
+}
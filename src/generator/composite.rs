@@ -1,26 +1,203 @@
-// TODO: Rewrite this.
-// Note: https://doc.rust-lang.org/std/collections/struct.BinaryHeap.html
-// Note: https://docs.rs/futures/0.3.12/futures/future/fn.join_all.html
+//! This module contains a fractal generator that splits its work across
+//! several other generators, such as a CPU thread pool and a GPU.
+//!
+//! Views are handed out to each child generator in proportion to how many
+//! pixels per second it generated during previous generations. Until a child
+//! has been measured, it is assumed to be as fast as the average of the
+//! children that have been, or all children are assumed to be equally fast if
+//! none have.
 
 use crate::{
-    generator::{view::View, FractalGenerator, FractalGeneratorInstance, PixelBlock},
+    generator::{
+        view::View, FractalGenerator, FractalGeneratorFactory, FractalGeneratorInstance,
+        FractalOpts, ValueBlock,
+    },
     gpu::GPUContext,
 };
 use futures::{future::BoxFuture, FutureExt};
-use std::sync::Arc;
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Instant,
+};
 use tokio::sync::mpsc::Sender;
 use wgpu::{Texture, TextureView};
 
+/// How much each new throughput measurement counts compared to the previous
+/// ones.
+const THROUGHPUT_SMOOTHING: f64 = 0.5;
+
+/// The measured pixels per second of each of a composite factory's children,
+/// by child index.
+type Throughputs = Arc<Mutex<Vec<Option<f64>>>>;
+
+type StartFuture = BoxFuture<'static, anyhow::Result<Box<dyn FractalGeneratorInstance + Send>>>;
+
+pub struct CompositeFractalGeneratorFactory {
+    factories: Vec<Arc<dyn FractalGeneratorFactory + Send + Sync>>,
+    throughputs: Throughputs,
+}
+
+impl CompositeFractalGeneratorFactory {
+    pub fn new(
+        factories: Vec<Arc<dyn FractalGeneratorFactory + Send + Sync>>,
+    ) -> CompositeFractalGeneratorFactory {
+        let throughputs = Arc::new(Mutex::new(vec![None; factories.len()]));
+        CompositeFractalGeneratorFactory {
+            factories,
+            throughputs,
+        }
+    }
+}
+
+impl FractalGeneratorFactory for CompositeFractalGeneratorFactory {
+    fn create_generator(
+        &self,
+        opts: FractalOpts,
+    ) -> BoxFuture<'static, anyhow::Result<Box<dyn FractalGenerator + Send + 'static>>> {
+        // children that can't generate in the requested precision are left out
+        let futs: Vec<_> = self
+            .factories
+            .iter()
+            .enumerate()
+            .filter(|(_, factory)| factory.supports_precision(&opts))
+            .map(|(index, factory)| (index, factory.create_generator(opts.clone())))
+            .collect();
+        let throughputs = self.throughputs.clone();
+
+        async move {
+            ensure!(
+                !futs.is_empty(),
+                "No child generator supports {:?} precision",
+                opts.precision
+            );
+
+            let mut children = vec![];
+            for (index, fut) in futs {
+                children.push(ChildGenerator {
+                    index,
+                    generator: fut.await?,
+                });
+            }
+
+            let boxed: Box<dyn FractalGenerator + Send> = Box::new(CompositeFractalGenerator {
+                children,
+                throughputs,
+            });
+            Ok(boxed)
+        }
+        .boxed()
+    }
+
+    fn supports_precision(&self, opts: &FractalOpts) -> bool {
+        self.factories
+            .iter()
+            .any(|factory| factory.supports_precision(opts))
+    }
+
+    fn uses_plane_start(&self) -> bool {
+        self.factories
+            .iter()
+            .any(|factory| factory.uses_plane_start())
+    }
+}
+
 pub struct CompositeFractalGenerator {
-    generators: Vec<Box<dyn FractalGenerator + Send + Sync>>,
+    children: Vec<ChildGenerator>,
+    throughputs: Throughputs,
+}
+
+struct ChildGenerator {
+    /// This child's index in the factory's throughputs.
+    index: usize,
+    generator: Box<dyn FractalGenerator + Send>,
+}
+
+impl CompositeFractalGenerator {
+    /// Splits `views` between the children, returning each child's index with
+    /// its views. Children that were given no views are left out.
+    fn split_views(&self, views: &[View]) -> Vec<(usize, Vec<View>)> {
+        let weights = {
+            let throughputs = self.throughputs.lock().unwrap();
+            child_weights(
+                &self
+                    .children
+                    .iter()
+                    .map(|child| throughputs[child.index])
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        assign_views(views, &weights)
+            .into_iter()
+            .enumerate()
+            .filter(|(_, views)| !views.is_empty())
+            .collect()
+    }
+
+    /// Starts each child with its share of `views` using `start`, cancelling
+    /// the children that have already started if any of them fail to start.
+    fn start_children(
+        &self,
+        views: &[View],
+        start: impl Fn(&(dyn FractalGenerator + Send), &[View]) -> StartFuture,
+    ) -> StartFuture {
+        let futs: Vec<_> = self
+            .split_views(views)
+            .into_iter()
+            .map(|(child, views)| {
+                let child = &self.children[child];
+                (
+                    child.index,
+                    pixel_count(&views),
+                    start(child.generator.as_ref(), &views),
+                )
+            })
+            .collect();
+        let throughputs = self.throughputs.clone();
+
+        async move {
+            let start_time = Instant::now();
+            let mut children: Vec<ChildInstance> = vec![];
+
+            for (index, pixels, fut) in futs {
+                match fut.await {
+                    Ok(instance) => children.push(ChildInstance {
+                        index,
+                        pixels,
+                        instance,
+                        finished: Arc::new(AtomicBool::new(false)),
+                    }),
+                    Err(e) => {
+                        for child in children.iter() {
+                            child.instance.cancel();
+                        }
+                        return Err(e);
+                    },
+                }
+            }
+
+            let boxed: Box<dyn FractalGeneratorInstance + Send> =
+                Box::new(CompositeFractalGeneratorInstance {
+                    children,
+                    throughputs,
+                    start_time,
+                    canceled: Arc::new(AtomicBool::new(false)),
+                });
+            Ok(boxed)
+        }
+        .boxed()
+    }
 }
 
 impl FractalGenerator for CompositeFractalGenerator {
     fn min_views_hint(&self) -> BoxFuture<'static, anyhow::Result<usize>> {
         let futs: Vec<_> = self
-            .generators
+            .children
             .iter()
-            .map(|gen| gen.min_views_hint())
+            .map(|child| child.generator.min_views_hint())
             .collect();
 
         async move {
@@ -35,21 +212,331 @@ impl FractalGenerator for CompositeFractalGenerator {
 
     fn start_generation_to_cpu(
         &self,
-        _views: &[View],
-        _sender: Sender<anyhow::Result<PixelBlock>>,
+        views: &[View],
+        sender: Sender<anyhow::Result<ValueBlock>>,
     ) -> BoxFuture<'static, anyhow::Result<Box<dyn FractalGeneratorInstance + Send + 'static>>>
     {
-        unimplemented!()
+        // every child sends its blocks to a clone of the same sender
+        self.start_children(views, |generator, views| {
+            generator.start_generation_to_cpu(views, sender.clone())
+        })
     }
 
     fn start_generation_to_gpu(
         &self,
-        _views: &[View],
-        _present: GPUContext,
-        _texture: Arc<Texture>,
-        _texture_view: Arc<TextureView>,
+        views: &[View],
+        present: GPUContext,
+        texture: Arc<Texture>,
+        texture_view: Arc<TextureView>,
     ) -> BoxFuture<'static, anyhow::Result<Box<dyn FractalGeneratorInstance + Send + 'static>>>
     {
-        unimplemented!()
+        // every child writes its views to different parts of the same texture
+        self.start_children(views, |generator, views| {
+            generator.start_generation_to_gpu(
+                views,
+                present.clone(),
+                texture.clone(),
+                texture_view.clone(),
+            )
+        })
+    }
+}
+
+struct CompositeFractalGeneratorInstance {
+    children: Vec<ChildInstance>,
+    throughputs: Throughputs,
+    start_time: Instant,
+    canceled: Arc<AtomicBool>,
+}
+
+struct ChildInstance {
+    /// This child's index in the factory's throughputs.
+    index: usize,
+    /// The number of pixels this child was given to generate.
+    pixels: usize,
+    instance: Box<dyn FractalGeneratorInstance + Send>,
+    /// Whether this child has been seen to finish.
+    finished: Arc<AtomicBool>,
+}
+
+impl FractalGeneratorInstance for CompositeFractalGeneratorInstance {
+    fn cancel(&self) {
+        self.canceled.store(true, Ordering::Release);
+        for child in self.children.iter() {
+            child.instance.cancel();
+        }
+    }
+
+    fn progress(&self) -> BoxFuture<'static, anyhow::Result<f32>> {
+        let futs: Vec<_> = self
+            .children
+            .iter()
+            .map(|child| (child.pixels, child.instance.progress()))
+            .collect();
+        let total: usize = self.children.iter().map(|child| child.pixels).sum();
+
+        async move {
+            // each child's progress counts for the share of the pixels it was given
+            let mut progress = 0.0;
+            for (pixels, fut) in futs {
+                progress += fut.await? * pixels as f32;
+            }
+            Ok(progress / total.max(1) as f32)
+        }
+        .boxed()
+    }
+
+    fn running(&self) -> BoxFuture<'static, anyhow::Result<bool>> {
+        let futs: Vec<_> = self
+            .children
+            .iter()
+            .map(|child| {
+                (
+                    child.index,
+                    child.pixels,
+                    child.finished.clone(),
+                    child.instance.running(),
+                )
+            })
+            .collect();
+        let throughputs = self.throughputs.clone();
+        let start_time = self.start_time;
+        let canceled = self.canceled.clone();
+
+        async move {
+            let mut running = false;
+            for (index, pixels, finished, fut) in futs {
+                if fut.await? {
+                    running = true;
+                } else if !finished.swap(true, Ordering::AcqRel)
+                    && !canceled.load(Ordering::Acquire)
+                {
+                    // canceled children would make their throughput look lower than it is
+                    let seconds = start_time.elapsed().as_secs_f64().max(f64::EPSILON);
+                    record_throughput(&throughputs, index, pixels as f64 / seconds);
+                }
+            }
+            Ok(running)
+        }
+        .boxed()
+    }
+}
+
+fn pixel_count(views: &[View]) -> usize {
+    views
+        .iter()
+        .map(|view| view.image_width * view.image_height)
+        .sum()
+}
+
+fn record_throughput(throughputs: &Throughputs, index: usize, measured: f64) {
+    let mut throughputs = throughputs.lock().unwrap();
+    let throughput = &mut throughputs[index];
+    *throughput = Some(match *throughput {
+        Some(previous) => previous + (measured - previous) * THROUGHPUT_SMOOTHING,
+        None => measured,
+    });
+    info!(
+        "Child generator {} generated {:.0} pixels per second",
+        index, measured
+    );
+}
+
+/// Gets the relative speed of each child from its measured throughput, if any.
+fn child_weights(throughputs: &[Option<f64>]) -> Vec<f64> {
+    let measured: Vec<_> = throughputs.iter().flatten().copied().collect();
+    let default = if measured.is_empty() {
+        1.0
+    } else {
+        measured.iter().sum::<f64>() / measured.len() as f64
+    };
+
+    throughputs
+        .iter()
+        .map(|throughput| throughput.unwrap_or(default).max(f64::EPSILON))
+        .collect()
+}
+
+/// Assigns each view to the child that would finish it soonest given the views
+/// it has already been assigned and its weight. Views stay in their original
+/// order, so each child still works through the image from top to bottom.
+fn assign_views(views: &[View], weights: &[f64]) -> Vec<Vec<View>> {
+    let mut assigned = vec![vec![]; weights.len()];
+    let mut pixels = vec![0usize; weights.len()];
+
+    for view in views {
+        let view_pixels = view.image_width * view.image_height;
+        let child = (0..weights.len())
+            .min_by(|&a, &b| {
+                let finish_a = (pixels[a] + view_pixels) as f64 / weights[a];
+                let finish_b = (pixels[b] + view_pixels) as f64 / weights[b];
+                finish_a.total_cmp(&finish_b)
+            })
+            .expect("A composite generator needs at least one child");
+
+        pixels[child] += view_pixels;
+        assigned[child].push(*view);
+    }
+
+    assigned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{cpu::CpuFractalGeneratorFactory, PixelValue};
+    use std::collections::HashSet;
+    use tokio::{runtime::Runtime, sync::mpsc, task::yield_now};
+
+    fn two_cpus() -> CompositeFractalGeneratorFactory {
+        CompositeFractalGeneratorFactory::new(vec![
+            Arc::new(CpuFractalGeneratorFactory::new(1)),
+            Arc::new(CpuFractalGeneratorFactory::new(1)),
+        ])
+    }
+
+    fn test_opts(iterations: u32) -> FractalOpts {
+        FractalOpts {
+            iterations,
+            ..FractalOpts::test_base()
+        }
+    }
+
+    /// Generates `views` to the CPU, returning the blocks in the order they
+    /// were received along with the finished instance.
+    async fn generate(
+        generator: &(dyn FractalGenerator + Send),
+        views: &[View],
+    ) -> (
+        Vec<(View, Box<[PixelValue]>)>,
+        Box<dyn FractalGeneratorInstance + Send>,
+    ) {
+        let (sender, mut receiver) = mpsc::channel(views.len());
+        let instance = generator
+            .start_generation_to_cpu(views, sender)
+            .await
+            .unwrap();
+
+        // the channel closes once every child has finished
+        let mut blocks = vec![];
+        while let Some(block) = receiver.recv().await {
+            let block = block.unwrap();
+            blocks.push((block.view, block.values));
+        }
+        (blocks, instance)
+    }
+
+    fn runtime() -> Runtime {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn matches_single_generator() {
+        runtime().block_on(async {
+            let composite = two_cpus();
+            let views: Vec<_> = View::new_centered_uniform(64, 64, 3.0)
+                .subdivide_rectangles(16, 16)
+                .collect();
+
+            let generator = composite.create_generator(test_opts(100)).await.unwrap();
+            let (mut blocks, instance) = generate(generator.as_ref(), &views).await;
+
+            // every view arrives exactly once
+            blocks.sort_by_key(|(view, _)| (view.image_y, view.image_x));
+            let received: Vec<_> = blocks.iter().map(|(view, _)| *view).collect();
+            assert_eq!(received, views);
+
+            let single = CpuFractalGeneratorFactory::new(1)
+                .create_generator(test_opts(100))
+                .await
+                .unwrap();
+            let (mut expected, _) = generate(single.as_ref(), &views).await;
+            expected.sort_by_key(|(view, _)| (view.image_y, view.image_x));
+            assert_eq!(blocks, expected);
+
+            while instance.running().await.unwrap() {
+                yield_now().await;
+            }
+            assert_eq!(instance.progress().await.unwrap(), 1.0);
+
+            // both children generated some of the views and were measured
+            let throughputs = composite.throughputs.lock().unwrap().clone();
+            assert!(throughputs.iter().all(Option::is_some), "{:?}", throughputs);
+        });
+    }
+
+    #[test]
+    fn cancel_stops_every_child() {
+        runtime().block_on(async {
+            let composite = two_cpus();
+            let views: Vec<_> = View::new_centered_uniform(256, 256, 3.0)
+                .subdivide_rectangles(16, 16)
+                .collect();
+
+            let generator = composite.create_generator(test_opts(10000)).await.unwrap();
+            let (sender, mut receiver) = mpsc::channel(views.len());
+            let instance = generator
+                .start_generation_to_cpu(&views, sender)
+                .await
+                .unwrap();
+            instance.cancel();
+            while instance.running().await.unwrap() {
+                yield_now().await;
+            }
+            assert!(instance.progress().await.unwrap() < 1.0);
+
+            // Equally fast children are given alternating views, so each child
+            // stopping early leaves some of both its views and the other's
+            // missing.
+            let mut received = HashSet::new();
+            while let Some(block) = receiver.recv().await {
+                let view = block.unwrap().view;
+                received.insert((view.image_x, view.image_y));
+            }
+            for child in 0..2 {
+                assert!(views
+                    .iter()
+                    .skip(child)
+                    .step_by(2)
+                    .any(|view| !received.contains(&(view.image_x, view.image_y))));
+            }
+
+            // canceled children aren't measured
+            let throughputs = composite.throughputs.lock().unwrap().clone();
+            assert_eq!(throughputs, vec![None, None]);
+        });
+    }
+
+    #[test]
+    fn weights_default_to_measured_average() {
+        assert_eq!(child_weights(&[None, None]), vec![1.0, 1.0]);
+        assert_eq!(
+            child_weights(&[Some(100.0), None, Some(300.0)]),
+            vec![100.0, 200.0, 300.0]
+        );
+    }
+
+    #[test]
+    fn views_assigned_by_weight() {
+        let views: Vec<_> = View::new_centered_uniform(64, 64, 3.0)
+            .subdivide_rectangles(16, 16)
+            .collect();
+
+        let assigned = assign_views(&views, &[3.0, 1.0]);
+        assert_eq!(assigned[0].len(), 12);
+        assert_eq!(assigned[1].len(), 4);
+
+        // every view is assigned exactly once, in order
+        let mut all: Vec<_> = assigned.concat();
+        all.sort_by_key(|view| (view.image_y, view.image_x));
+        assert_eq!(all, views);
+        for child in assigned {
+            assert!(child.windows(2).all(
+                |pair| (pair[0].image_y, pair[0].image_x) < (pair[1].image_y, pair[1].image_x)
+            ));
+        }
     }
 }
@@ -1,20 +1,23 @@
 use crate::{
     generator::{
-        color::RGBA8Color, cpu::opts::CpuFractalOpts, view::View, FractalGenerator,
-        FractalGeneratorFactory, FractalGeneratorInstance, FractalOpts, PixelBlock,
-        BYTES_PER_PIXEL,
+        args::{BoundaryTracing, Formula, InteriorColoring, Multisampling, Smoothing},
+        cpu::opts::CpuFractalOpts,
+        view::View,
+        FractalGenerator, FractalGeneratorFactory, FractalGeneratorInstance, FractalOpts,
+        PixelValue, ValueBlock, BYTES_PER_VALUE,
     },
     gpu::{GPUContext, GPUContextType},
     util::{display_duration, result::ResultExt, running_guard::RunningGuard},
 };
-use cgmath::Vector4;
+use bytemuck::cast_slice;
+use cgmath::Vector2;
 use chrono::Utc;
 use futures::{
     future::{ready, BoxFuture},
     FutureExt,
 };
 use rayon::{
-    iter::{IndexedParallelIterator, ParallelIterator},
+    iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator},
     slice::ParallelSliceMut,
     ThreadPool, ThreadPoolBuilder,
 };
@@ -29,6 +32,7 @@ use wgpu::{
 };
 
 pub mod opts;
+mod simd;
 
 pub struct CpuFractalGeneratorFactory {
     thread_count: usize,
@@ -54,6 +58,10 @@ impl FractalGeneratorFactory for CpuFractalGeneratorFactory {
         })
         .boxed()
     }
+
+    fn supports_precision(&self, _opts: &FractalOpts) -> bool {
+        true
+    }
 }
 
 pub struct CpuFractalGenerator {
@@ -80,15 +88,25 @@ impl FractalGenerator for CpuFractalGenerator {
     fn start_generation_to_cpu(
         &self,
         views: &[View],
-        sender: Sender<anyhow::Result<PixelBlock>>,
+        sender: Sender<anyhow::Result<ValueBlock>>,
     ) -> BoxFuture<'static, anyhow::Result<Box<dyn FractalGeneratorInstance + Send + 'static>>>
     {
         let thread_pool = self.thread_pool.clone();
         let views = views.to_vec();
         let opts = self.opts.clone();
         async move {
+            let multisampling = opts.multisampling;
+            let fill = BoundaryFill::for_opts(&opts);
             let boxed: Box<dyn FractalGeneratorInstance + Send> = Box::new(
-                CpuFractalGeneratorInstance::start(thread_pool, views, sender, opts).await,
+                CpuFractalGeneratorInstance::start(
+                    thread_pool,
+                    views,
+                    sender,
+                    opts,
+                    multisampling,
+                    fill,
+                )
+                .await,
             );
             Ok(boxed)
         }
@@ -113,19 +131,32 @@ impl FractalGenerator for CpuFractalGenerator {
         let views = views.to_vec();
         let opts = self.opts.clone();
         async move {
-            let sink = GpuPixelBlockSink {
+            let sink = GpuValueBlockSink {
                 queue: present.queue,
                 texture,
             };
-            let boxed: Box<dyn FractalGeneratorInstance + Send> =
-                Box::new(CpuFractalGeneratorInstance::start(thread_pool, views, sink, opts).await);
+            let multisampling = opts.multisampling;
+            let fill = BoundaryFill::for_opts(&opts);
+            let boxed: Box<dyn FractalGeneratorInstance + Send> = Box::new(
+                CpuFractalGeneratorInstance::start(
+                    thread_pool,
+                    views,
+                    sink,
+                    opts,
+                    multisampling,
+                    fill,
+                )
+                .await,
+            );
             Ok(boxed)
         }
         .boxed()
     }
 }
 
-struct CpuFractalGeneratorInstance {
+/// A running CPU fractal generator. This is also used by other generators that
+/// iterate on the CPU with their own [`CpuFractalOpts`].
+pub(crate) struct CpuFractalGeneratorInstance {
     view_count: usize,
     completed: Arc<AtomicUsize>,
     running: Arc<AtomicBool>,
@@ -133,11 +164,21 @@ struct CpuFractalGeneratorInstance {
 }
 
 impl CpuFractalGeneratorInstance {
-    async fn start<S: PixelBlockSink + Send + Sync + 'static>(
+    /// Starts generating `views` on `thread_pool` using `opts` to generate each
+    /// sample, taking samples as described by `multisampling`.
+    ///
+    /// If `fill` is set, boundary tracing is used to avoid generating the
+    /// insides of rectangles with uniform borders.
+    pub(crate) async fn start<
+        O: CpuFractalOpts + Send + Sync + 'static,
+        S: ValueBlockSink + Send + Sync + 'static,
+    >(
         thread_pool: Arc<ThreadPool>,
         views: Vec<View>,
         sink: S,
-        opts: FractalOpts,
+        opts: O,
+        multisampling: Multisampling,
+        fill: Option<BoundaryFill>,
     ) -> CpuFractalGeneratorInstance {
         info!("Starting new CPU fractal generator...");
         let view_count = views.len();
@@ -148,10 +189,15 @@ impl CpuFractalGeneratorInstance {
         let async_running = running.clone();
         let async_canceled = canceled.clone();
 
-        let sample_count = opts.multisampling.sample_count();
-        let sample_count_f32 = sample_count as f32;
-        let sample_count = sample_count as usize;
-        let offsets = Arc::new(opts.multisampling.offsets());
+        // adaptive multisampling starts out with a single sample per pixel
+        let threshold = multisampling.adaptive_threshold();
+        let offsets = Arc::new(multisampling.offsets());
+        let initial_offsets = if threshold.is_some() {
+            Arc::new(Multisampling::None.offsets())
+        } else {
+            offsets.clone()
+        };
+        let opts = Arc::new(opts);
 
         tokio::spawn(async move {
             let _running_guard = RunningGuard::new(async_running);
@@ -165,49 +211,26 @@ impl CpuFractalGeneratorInstance {
 
                 let spawn_thread_pool = thread_pool.clone();
                 let spawn_offsets = offsets.clone();
+                let spawn_initial_offsets = initial_offsets.clone();
+                let spawn_opts = opts.clone();
                 let spawn_completed = async_completed.clone();
                 let spawn_canceled = async_canceled.clone();
 
                 let res = task::spawn_blocking(move || {
-                    let mut image =
-                        vec![0u8; view.image_width * view.image_height * BYTES_PER_PIXEL];
+                    let mut values =
+                        vec![PixelValue::default(); view.image_width * view.image_height];
 
                     let res = spawn_thread_pool.install(|| {
-                        image
-                            .par_chunks_exact_mut(BYTES_PER_PIXEL)
-                            .enumerate()
-                            .try_for_each(|(index, pixel)| {
-                                let x = index % view.image_width;
-                                let y = index / view.image_width;
-
-                                if spawn_canceled.load(Ordering::Acquire) {
-                                    info!("Received cancel signal.");
-                                    return Err(());
-                                }
-
-                                let mut color = Vector4 {
-                                    x: 0.0,
-                                    y: 0.0,
-                                    z: 0.0,
-                                    w: 0.0,
-                                };
-
-                                for i in 0..sample_count {
-                                    let offset = spawn_offsets[i];
-                                    color += opts.gen_pixel(
-                                        view,
-                                        x as f32 + offset.x,
-                                        y as f32 + offset.y,
-                                    ) / sample_count_f32;
-                                }
-
-                                let color: RGBA8Color = color.into();
-                                let color: [u8; 4] = color.into();
-
-                                pixel.copy_from_slice(&color);
-
-                                Ok(())
-                            })
+                        gen_view(
+                            &mut values,
+                            view,
+                            spawn_opts.as_ref(),
+                            &spawn_initial_offsets,
+                            &spawn_offsets,
+                            threshold,
+                            &spawn_canceled,
+                            fill,
+                        )
                     });
 
                     if res.is_err() {
@@ -221,16 +244,16 @@ impl CpuFractalGeneratorInstance {
                         display_duration(start_time);
                     }
 
-                    Some(image.into_boxed_slice())
+                    Some(values.into_boxed_slice())
                 })
                 .await
                 .on_err(|e| warn!("JoinError in CPU generator: {:?}", e))
                 .flatten();
 
-                if let Some(image) = res {
-                    if let Err(e) = sink.accept(PixelBlock { view, image }).await {
+                if let Some(values) = res {
+                    if let Err(e) = sink.accept(ValueBlock { view, values }).await {
                         warn!(
-                            "Error while submitting pixel block in CPU generator: {:?}",
+                            "Error while submitting value block in CPU generator: {:?}",
                             e
                         );
                         return;
@@ -250,6 +273,414 @@ impl CpuFractalGeneratorInstance {
     }
 }
 
+/// How many pixels [`gen_pixels`] hands to [`CpuFractalOpts::gen_pixel_batch`]
+/// at once, so that kernels can iterate several of them in lockstep.
+const PIXEL_BATCH_SIZE: usize = 64;
+
+/// Generates every pixel of a view. With an adaptive `threshold`, the pixels
+/// are first generated with `initial_offsets` and only the ones that differ
+/// from their neighbors are generated again with every one of `offsets`.
+fn gen_view<O: CpuFractalOpts + Sync>(
+    values: &mut [PixelValue],
+    view: View,
+    opts: &O,
+    initial_offsets: &[Vector2<f32>],
+    offsets: &[Vector2<f32>],
+    threshold: Option<f32>,
+    canceled: &AtomicBool,
+    fill: Option<BoundaryFill>,
+) -> Result<(), ()> {
+    if let Some(fill) = fill {
+        trace_pixels(values, view, opts, initial_offsets, canceled, fill)?;
+    } else {
+        gen_pixels(values, view, opts, initial_offsets, canceled, |_| true)?;
+    }
+
+    if let Some(threshold) = threshold {
+        let apron = Apron::generate(view, opts, initial_offsets, canceled)?;
+        let mask = contrast_mask(
+            values,
+            view.image_width,
+            view.image_height,
+            &apron,
+            threshold,
+        );
+        gen_pixels(values, view, opts, offsets, canceled, |index| mask[index])?;
+    }
+
+    Ok(())
+}
+
+/// Generates the pixels of `values` whose indices pass `filter`, stopping early
+/// if `canceled` is set.
+fn gen_pixels<O: CpuFractalOpts + Sync>(
+    values: &mut [PixelValue],
+    view: View,
+    opts: &O,
+    offsets: &[Vector2<f32>],
+    canceled: &AtomicBool,
+    filter: impl Fn(usize) -> bool + Sync,
+) -> Result<(), ()> {
+    values
+        .par_chunks_mut(PIXEL_BATCH_SIZE)
+        .enumerate()
+        .try_for_each(|(batch_index, batch)| {
+            if canceled.load(Ordering::Acquire) {
+                info!("Received cancel signal.");
+                return Err(());
+            }
+
+            let start = batch_index * PIXEL_BATCH_SIZE;
+            let indices: Vec<_> = (start..start + batch.len())
+                .filter(|&index| filter(index))
+                .collect();
+            if indices.is_empty() {
+                return Ok(());
+            }
+
+            let pixels: Vec<_> = indices
+                .iter()
+                .map(|&index| (index % view.image_width, index / view.image_width))
+                .collect();
+            let mut generated = vec![PixelValue::default(); pixels.len()];
+            opts.gen_pixel_batch(view, &pixels, offsets, &mut generated);
+
+            for (index, value) in indices.into_iter().zip(generated) {
+                batch[index - start] = value;
+            }
+
+            Ok(())
+        })
+}
+
+/// The size of the tiles that boundary tracing splits each view into, so that
+/// the tiles can be traced in parallel.
+const TRACING_TILE_SIZE: usize = 32;
+
+/// Rectangles with a side at most this long are not subdivided any further by
+/// boundary tracing, but have all their pixels generated.
+const TRACING_MIN_SIZE: usize = 4;
+
+/// Which rectangles with uniform borders boundary tracing fills in.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub(crate) enum BoundaryFill {
+    /// Only rectangles bordered by points inside the set are filled.
+    Interior,
+    /// Every rectangle with a uniform border is filled.
+    Uniform,
+}
+
+impl BoundaryFill {
+    /// Gets how boundary tracing fills rectangles for the given options, or
+    /// `None` if boundary tracing is off.
+    pub(crate) fn for_opts(opts: &FractalOpts) -> Option<BoundaryFill> {
+        match opts.boundary_tracing {
+            BoundaryTracing::Off => None,
+            // Filling is only exact when neither the set nor its complement can have
+            // islands inside a rectangle, which is only known for the polynomials
+            // z^n + c. Other formulas can have escaping islands inside a border of
+            // points in the set.
+            BoundaryTracing::Exact if !matches!(opts.formula, Formula::IntegerPower { exponent } if exponent >= 2) => {
+                None
+            },
+            // colored interiors vary even inside a border of points inside the set
+            BoundaryTracing::Exact if opts.interior_coloring != InteriorColoring::None => None,
+            BoundaryTracing::Exact => {
+                // smoothed, multisampled, trapped or averaged escaped pixels vary even
+                // inside a uniform border
+                if opts.smoothing == Smoothing::None
+                    && opts.multisampling == Multisampling::None
+                    && opts.orbit_trap.is_none()
+                    && !opts.averages_orbits()
+                {
+                    Some(BoundaryFill::Uniform)
+                } else {
+                    Some(BoundaryFill::Interior)
+                }
+            },
+            BoundaryTracing::Always => Some(BoundaryFill::Uniform),
+        }
+    }
+
+    fn fills(&self, value: &PixelValue) -> bool {
+        match self {
+            BoundaryFill::Interior => value.coverage == 0.0,
+            BoundaryFill::Uniform => true,
+        }
+    }
+}
+
+/// Generates the pixels of `values` using Mariani-Silver boundary tracing,
+/// stopping early if `canceled` is set.
+///
+/// The view is split into tiles which are traced in parallel. Each tile is
+/// recursively subdivided, only generating the borders of each rectangle and
+/// filling in the rectangles whose borders are uniform, as allowed by `fill`.
+fn trace_pixels<O: CpuFractalOpts + Sync>(
+    values: &mut [PixelValue],
+    view: View,
+    opts: &O,
+    offsets: &[Vector2<f32>],
+    canceled: &AtomicBool,
+    fill: BoundaryFill,
+) -> Result<(), ()> {
+    let tiles: Vec<_> = (0..view.image_height)
+        .step_by(TRACING_TILE_SIZE)
+        .flat_map(|y| {
+            (0..view.image_width)
+                .step_by(TRACING_TILE_SIZE)
+                .map(move |x| Rect {
+                    x,
+                    y,
+                    width: TRACING_TILE_SIZE.min(view.image_width - x),
+                    height: TRACING_TILE_SIZE.min(view.image_height - y),
+                })
+        })
+        .collect();
+
+    let traced = tiles
+        .par_iter()
+        .map(|&tile| {
+            let mut tracer = Tracer {
+                view,
+                opts,
+                offsets,
+                fill,
+                tile,
+                values: vec![None; tile.width * tile.height],
+            };
+            tracer.trace(tile, canceled)?;
+            Ok((tile, tracer.values))
+        })
+        .collect::<Result<Vec<_>, ()>>()?;
+
+    for (tile, tile_values) in traced {
+        for (row, row_values) in tile_values.chunks_exact(tile.width).enumerate() {
+            let start = (tile.y + row) * view.image_width + tile.x;
+            for (pixel, value) in values[start..start + tile.width].iter_mut().zip(row_values) {
+                *pixel = value.expect("Boundary tracing left a pixel ungenerated (this is a bug)");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A rectangle of pixels within a view.
+#[derive(Debug, Copy, Clone)]
+struct Rect {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+/// Traces a single tile, remembering every pixel generated or filled so far.
+struct Tracer<'a, O> {
+    view: View,
+    opts: &'a O,
+    offsets: &'a [Vector2<f32>],
+    fill: BoundaryFill,
+    tile: Rect,
+    values: Vec<Option<PixelValue>>,
+}
+
+impl<'a, O: CpuFractalOpts> Tracer<'a, O> {
+    fn trace(&mut self, rect: Rect, canceled: &AtomicBool) -> Result<(), ()> {
+        if canceled.load(Ordering::Acquire) {
+            info!("Received cancel signal.");
+            return Err(());
+        }
+
+        if rect.width <= TRACING_MIN_SIZE || rect.height <= TRACING_MIN_SIZE {
+            for y in rect.y..rect.y + rect.height {
+                for x in rect.x..rect.x + rect.width {
+                    self.get(x, y);
+                }
+            }
+            return Ok(());
+        }
+
+        let right = rect.x + rect.width - 1;
+        let bottom = rect.y + rect.height - 1;
+        let border = (rect.x..=right)
+            .flat_map(|x| [(x, rect.y), (x, bottom)])
+            .chain((rect.y + 1..bottom).flat_map(|y| [(rect.x, y), (right, y)]));
+
+        let first = self.get(rect.x, rect.y);
+        let mut uniform = true;
+        for (x, y) in border {
+            if self.get(x, y) != first {
+                uniform = false;
+                break;
+            }
+        }
+
+        if uniform && self.fill.fills(&first) {
+            for y in rect.y + 1..bottom {
+                for x in rect.x + 1..right {
+                    let index = self.index(x, y);
+                    self.values[index] = Some(first);
+                }
+            }
+            return Ok(());
+        }
+
+        // the halves share the line they are split along, so its pixels are
+        // only generated once
+        if rect.width >= rect.height {
+            let middle = rect.x + rect.width / 2;
+            self.trace(
+                Rect {
+                    width: middle - rect.x + 1,
+                    ..rect
+                },
+                canceled,
+            )?;
+            self.trace(
+                Rect {
+                    x: middle,
+                    width: rect.x + rect.width - middle,
+                    ..rect
+                },
+                canceled,
+            )
+        } else {
+            let middle = rect.y + rect.height / 2;
+            self.trace(
+                Rect {
+                    height: middle - rect.y + 1,
+                    ..rect
+                },
+                canceled,
+            )?;
+            self.trace(
+                Rect {
+                    y: middle,
+                    height: rect.y + rect.height - middle,
+                    ..rect
+                },
+                canceled,
+            )
+        }
+    }
+
+    /// Gets the value of the pixel at `(x, y)` in the view, generating it if
+    /// it hasn't been yet.
+    fn get(&mut self, x: usize, y: usize) -> PixelValue {
+        let index = self.index(x, y);
+        match self.values[index] {
+            Some(value) => value,
+            None => {
+                let value = self.opts.gen_pixel(self.view, x, y, self.offsets);
+                self.values[index] = Some(value);
+                value
+            },
+        }
+    }
+
+    fn index(&self, x: usize, y: usize) -> usize {
+        (y - self.tile.y) * self.tile.width + x - self.tile.x
+    }
+}
+
+/// The initial values of the pixels just outside of a view, so that the pixels
+/// along its edges can be compared with the neighboring views' pixels without
+/// waiting for them.
+struct Apron {
+    top: Vec<PixelValue>,
+    bottom: Vec<PixelValue>,
+    left: Vec<PixelValue>,
+    right: Vec<PixelValue>,
+}
+
+impl Apron {
+    fn generate<O: CpuFractalOpts + Sync>(
+        view: View,
+        opts: &O,
+        offsets: &[Vector2<f32>],
+        canceled: &AtomicBool,
+    ) -> Result<Apron, ()> {
+        // The pixels are positioned relative to the view rather than by
+        // moving its plane start, which perturbation ignores.
+        let line = |pixels: Vec<(f64, f64)>| {
+            if canceled.load(Ordering::Acquire) {
+                info!("Received cancel signal.");
+                return Err(());
+            }
+
+            Ok(pixels
+                .par_iter()
+                .map(|&(x, y)| opts.gen_pixel_at(view, x, y, offsets))
+                .collect())
+        };
+
+        let (width, height) = (view.image_width as f64, view.image_height as f64);
+        let columns = || (0..view.image_width).map(|x| x as f64);
+        let rows = || (0..view.image_height).map(|y| y as f64);
+        Ok(Apron {
+            top: line(columns().map(|x| (x, -1.0)).collect())?,
+            bottom: line(columns().map(|x| (x, height)).collect())?,
+            left: line(rows().map(|y| (-1.0, y)).collect())?,
+            right: line(rows().map(|y| (width, y)).collect())?,
+        })
+    }
+}
+
+/// Finds the pixels that differ from at least one of their horizontal or
+/// vertical neighbors by more than `threshold`. Pixels along the edges of the
+/// block are compared with the pixels of the `apron` around it, so that the
+/// edges of neighboring blocks are refined like the rest of the image.
+///
+/// This mirrors `mask_main` in `fragment_shader_main.wgsl.liquid`.
+fn contrast_mask(
+    values: &[PixelValue],
+    width: usize,
+    height: usize,
+    apron: &Apron,
+    threshold: f32,
+) -> Vec<bool> {
+    let differs = |a: &PixelValue, b: &PixelValue| {
+        (a.coverage == 0.0) != (b.coverage == 0.0)
+            || (a.value - b.value).abs() > threshold
+            || (a.interior - b.interior).abs() > threshold
+    };
+
+    (0..width * height)
+        .map(|index| {
+            let x = index % width;
+            let y = index / width;
+            let value = &values[index];
+
+            let left = if x > 0 {
+                &values[index - 1]
+            } else {
+                &apron.left[y]
+            };
+            let right = if x + 1 < width {
+                &values[index + 1]
+            } else {
+                &apron.right[y]
+            };
+            let above = if y > 0 {
+                &values[index - width]
+            } else {
+                &apron.top[x]
+            };
+            let below = if y + 1 < height {
+                &values[index + width]
+            } else {
+                &apron.bottom[x]
+            };
+
+            differs(value, left)
+                || differs(value, right)
+                || differs(value, above)
+                || differs(value, below)
+        })
+        .collect()
+}
+
 impl FractalGeneratorInstance for CpuFractalGeneratorInstance {
     fn cancel(&self) {
         self.canceled.store(true, Ordering::Release);
@@ -273,50 +704,53 @@ pub enum CpuGenError {
     ThreadPoolBuildError(#[from] rayon::ThreadPoolBuildError),
 }
 
-trait PixelBlockSink {
+/// Structs implementing this trait receive the value blocks generated by a
+/// [`CpuFractalGeneratorInstance`].
+pub(crate) trait ValueBlockSink {
     type Error: std::fmt::Debug;
 
-    fn accept(&self, pixel_block: PixelBlock) -> BoxFuture<'_, Result<(), Self::Error>>;
+    fn accept(&self, value_block: ValueBlock) -> BoxFuture<'_, Result<(), Self::Error>>;
 }
 
-impl PixelBlockSink for Sender<anyhow::Result<PixelBlock>> {
-    type Error = tokio::sync::mpsc::error::SendError<anyhow::Result<PixelBlock>>;
+impl ValueBlockSink for Sender<anyhow::Result<ValueBlock>> {
+    type Error = tokio::sync::mpsc::error::SendError<anyhow::Result<ValueBlock>>;
 
-    fn accept(&self, pixel_block: PixelBlock) -> BoxFuture<'_, Result<(), Self::Error>> {
-        self.send(Ok(pixel_block)).boxed()
+    fn accept(&self, value_block: ValueBlock) -> BoxFuture<'_, Result<(), Self::Error>> {
+        self.send(Ok(value_block)).boxed()
     }
 }
 
+/// Writes value blocks directly into a GPU-side `Rgba32Float` texture.
 #[derive(Clone)]
-struct GpuPixelBlockSink {
-    queue: Arc<Queue>,
-    texture: Arc<Texture>,
+pub(crate) struct GpuValueBlockSink {
+    pub(crate) queue: Arc<Queue>,
+    pub(crate) texture: Arc<Texture>,
 }
 
-impl PixelBlockSink for GpuPixelBlockSink {
+impl ValueBlockSink for GpuValueBlockSink {
     type Error = ();
 
-    fn accept(&self, pixel_block: PixelBlock) -> BoxFuture<'_, Result<(), Self::Error>> {
+    fn accept(&self, value_block: ValueBlock) -> BoxFuture<'_, Result<(), Self::Error>> {
         self.queue.write_texture(
             ImageCopyTexture {
                 texture: &self.texture,
                 mip_level: 0,
                 origin: Origin3d {
-                    x: pixel_block.view.image_x as u32,
-                    y: pixel_block.view.image_y as u32,
+                    x: value_block.view.image_x as u32,
+                    y: value_block.view.image_y as u32,
                     z: 0,
                 },
                 aspect: TextureAspect::All,
             },
-            &pixel_block.image,
+            cast_slice(&value_block.values),
             ImageDataLayout {
                 offset: 0,
-                bytes_per_row: Some((pixel_block.view.image_width * BYTES_PER_PIXEL) as u32),
+                bytes_per_row: Some((value_block.view.image_width * BYTES_PER_VALUE) as u32),
                 rows_per_image: None,
             },
             Extent3d {
-                width: pixel_block.view.image_width as u32,
-                height: pixel_block.view.image_height as u32,
+                width: value_block.view.image_width as u32,
+                height: value_block.view.image_height as u32,
                 depth_or_array_layers: 1,
             },
         );
@@ -324,3 +758,207 @@ impl PixelBlockSink for GpuPixelBlockSink {
         ready(Ok(())).boxed()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{
+        args::Precision, perturbation::opts::PerturbationOpts, util::copy_region,
+    };
+    use bytemuck::cast_slice_mut;
+
+    fn tracing_opts(formula: Formula, smoothing: Smoothing) -> FractalOpts {
+        FractalOpts {
+            formula,
+            smoothing,
+            boundary_tracing: BoundaryTracing::Exact,
+            ..FractalOpts::test_base()
+        }
+    }
+
+    fn gen_with_and_without_tracing(
+        opts: &FractalOpts,
+        view: View,
+        fill: BoundaryFill,
+    ) -> (Vec<PixelValue>, Vec<PixelValue>) {
+        let offsets = Multisampling::None.offsets();
+        let canceled = AtomicBool::new(false);
+
+        let mut every_pixel = vec![PixelValue::default(); view.image_width * view.image_height];
+        gen_pixels(&mut every_pixel, view, opts, &offsets, &canceled, |_| true).unwrap();
+        let mut traced = vec![PixelValue::default(); view.image_width * view.image_height];
+        trace_pixels(&mut traced, view, opts, &offsets, &canceled, fill).unwrap();
+
+        (every_pixel, traced)
+    }
+
+    #[test]
+    fn exact_boundary_tracing_matches_every_pixel() {
+        // not a multiple of the tile size, so that the last tiles are partial
+        let view = View::new_centered_uniform(150, 100, 3.0);
+
+        // iteration counts are filled in along with the set
+        let opts = tracing_opts(Default::default(), Smoothing::None);
+        let fill = BoundaryFill::for_opts(&opts).unwrap();
+        assert_eq!(fill, BoundaryFill::Uniform);
+        let (every_pixel, traced) = gen_with_and_without_tracing(&opts, view, fill);
+        assert_eq!(every_pixel, traced);
+
+        // smoothed values are not, so only the set is filled in
+        let opts = tracing_opts(
+            Default::default(),
+            Smoothing::from_logarithmic_distance(4.0, 2.0),
+        );
+        let fill = BoundaryFill::for_opts(&opts).unwrap();
+        assert_eq!(fill, BoundaryFill::Interior);
+        let (every_pixel, traced) = gen_with_and_without_tracing(&opts, view, fill);
+        assert_eq!(every_pixel, traced);
+    }
+
+    #[test]
+    fn exact_boundary_tracing_skips_formulas_with_islands() {
+        // the bump makes the points around 0 escape, leaving an island in the
+        // middle of the main cardioid
+        let formula = "z^2 + c + 10 * exp(-1000 * abs(c)^2)".parse().unwrap();
+        let opts = tracing_opts(formula, Smoothing::None);
+        assert_eq!(BoundaryFill::for_opts(&opts), None);
+
+        // a single tile whose whole border is inside the set, so filling would
+        // lose the island
+        let view = View::new_uniform(32, 32, 0.4, -0.1, 0.0);
+        let (every_pixel, traced) =
+            gen_with_and_without_tracing(&opts, view, BoundaryFill::Interior);
+        let island = 16 * 32 + 24;
+        assert!(every_pixel[island].coverage > 0.0);
+        assert_eq!(traced[island].coverage, 0.0);
+    }
+
+    #[test]
+    fn contrast_mask_finds_edges() {
+        let escaped = |value| PixelValue {
+            value,
+            coverage: 1.0,
+            ..Default::default()
+        };
+        // a 4x2 block: a smooth gradient on the left, the set on the right
+        let values = [
+            escaped(1.0),
+            escaped(1.2),
+            escaped(1.4),
+            PixelValue::default(),
+            escaped(1.0),
+            escaped(1.2),
+            escaped(3.0),
+            PixelValue::default(),
+        ];
+
+        // an apron repeating the edges of the block doesn't add any contrast
+        let apron = Apron {
+            top: values[..4].to_vec(),
+            bottom: values[4..].to_vec(),
+            left: vec![values[0], values[4]],
+            right: vec![values[3], values[7]],
+        };
+        assert_eq!(
+            contrast_mask(&values, 4, 2, &apron, 0.5),
+            vec![false, false, true, true, false, true, true, true]
+        );
+
+        // but one that differs does
+        let apron = Apron {
+            left: vec![escaped(3.0), escaped(1.0)],
+            ..apron
+        };
+        assert_eq!(
+            contrast_mask(&values, 4, 2, &apron, 0.5),
+            vec![true, false, true, true, false, true, true, true]
+        );
+    }
+
+    /// Generates `parent` whole and split into `halves` with adaptive
+    /// multisampling, and checks that the stitched halves match the whole.
+    fn assert_refines_across_views<O: CpuFractalOpts + Sync>(
+        opts: &O,
+        multisampling: Multisampling,
+        parent: View,
+        halves: &[View],
+    ) {
+        let threshold = multisampling.adaptive_threshold();
+        let initial_offsets = Multisampling::None.offsets();
+        let offsets = multisampling.offsets();
+        let canceled = AtomicBool::new(false);
+        let gen = |view: View| {
+            let mut values = vec![PixelValue::default(); view.image_width * view.image_height];
+            gen_view(
+                &mut values,
+                view,
+                opts,
+                &initial_offsets,
+                &offsets,
+                threshold,
+                &canceled,
+                None,
+            )
+            .unwrap();
+            values
+        };
+
+        let whole = gen(parent);
+
+        let mut stitched = vec![PixelValue::default(); whole.len()];
+        for &half in halves {
+            let values = gen(half);
+            copy_region(
+                BYTES_PER_VALUE,
+                cast_slice(&values),
+                half.image_width,
+                0,
+                0,
+                cast_slice_mut(&mut stitched),
+                parent.image_width,
+                half.image_x,
+                half.image_y,
+                half.image_width,
+                half.image_height,
+            );
+        }
+
+        assert_eq!(stitched, whole);
+    }
+
+    #[test]
+    fn adaptive_multisampling_refines_across_views() {
+        let opts = FractalOpts {
+            multisampling: Multisampling::Adaptive {
+                max_samples: 4,
+                threshold: 0.5,
+            },
+            ..tracing_opts(
+                Default::default(),
+                Smoothing::from_logarithmic_distance(4.0, 2.0),
+            )
+        };
+
+        // the halves meet at the neck between the main cardioid and the
+        // largest bulb of the set. The scale and the edges of the views are exact in
+        // binary, so the pixels of both halves are at exactly the same points
+        // as the whole's.
+        let parent = View::new_uniform(32, 32, 1.0, -0.75, 0.0);
+        let halves: Vec<_> = parent.subdivide_rectangles(16, 32).collect();
+        assert_eq!(halves.len(), 2);
+        assert_refines_across_views(&opts, opts.multisampling, parent, &halves);
+
+        // perturbation positions pixels relative to the whole image rather than
+        // by the views' plane starts, and every view shares its reference.
+        let parent = View::new_uniform(32, 32, 1.0, -0.5, 0.5);
+        let halves: Vec<_> = parent.subdivide_rectangles(16, 32).collect();
+        let perturbation = PerturbationOpts::new(
+            FractalOpts {
+                precision: Precision::Double,
+                ..opts.clone()
+            },
+            &halves,
+        );
+        assert_refines_across_views(&perturbation, opts.multisampling, parent, &halves);
+    }
+}
@@ -1,104 +1,816 @@
-use crate::generator::{args::Smoothing, color::FromHSBA, view::View, FractalOpts};
-use cgmath::Vector4;
+use crate::generator::{
+    args::{
+        Averaging, Formula, InteriorColoring, Precision, Smoothing, AVERAGE_VALUE_SCALE,
+        INTERIOR_MAGNITUDE_SCALE, INTERIOR_PERIOD_TOLERANCE,
+    },
+    cpu::simd,
+    expression::{Function, Node},
+    trap::{OrbitTrap, TRAP_VALUE_SCALE},
+    view::View,
+    FractalOpts, PixelValue, NO_DISTANCE, NO_INTERIOR,
+};
+use cgmath::Vector2;
 use num_complex::Complex;
+use num_traits::{Float, NumCast};
 
-/// Structs implementing this trait can be used to generate pixel colors on a
+/// Structs implementing this trait can be used to generate pixel values on a
 /// CPU.
 pub trait CpuFractalOpts {
-    /// Generates a value between 0 and iterations corresponding to the smoothed
-    /// iteration count for that location on the complex plane.
-    fn gen_value(&self, loc: Complex<f32>) -> f32;
+    /// Gets the maximum number of iterations. Locations whose value reaches
+    /// this never escaped.
+    fn iterations(&self) -> u32;
 
-    /// Generates a color from a iteration count value.
-    fn gen_color(&self, value: f32) -> Vector4<f32>;
+    /// Generates a sample whose value is between 0 and iterations
+    /// corresponding to the smoothed iteration count for that location on the
+    /// complex plane.
+    fn gen_value(&self, loc: Complex<f64>) -> Sample;
 
-    /// Generates an iteration count value for a given pixel location and view.
-    fn gen_pixel_value(&self, view: View, x: f32, y: f32) -> f32 {
+    /// Generates a sample for a given pixel location and view.
+    fn gen_pixel_value(&self, view: View, x: f64, y: f64) -> Sample {
         self.gen_value(view.get_local_subpixel_plane_coordinates((x, y)))
     }
 
-    /// Generates a pixel color for a given pixel location and view.
-    fn gen_pixel(&self, view: View, x: f32, y: f32) -> Vector4<f32> {
-        self.gen_color(self.gen_pixel_value(view, x, y))
+    /// Generates the value of a pixel in a view by averaging the samples taken
+    /// at the given sub-pixel offsets.
+    fn gen_pixel(&self, view: View, x: usize, y: usize, offsets: &[Vector2<f32>]) -> PixelValue {
+        self.gen_pixel_at(view, x as f64, y as f64, offsets)
+    }
+
+    /// Generates the value of the pixel whose top-left corner is at the given
+    /// position relative to a view, like
+    /// [`gen_pixel`](CpuFractalOpts::gen_pixel) but also for pixels outside
+    /// of the view.
+    fn gen_pixel_at(&self, view: View, x: f64, y: f64, offsets: &[Vector2<f32>]) -> PixelValue {
+        average_samples(
+            self.iterations(),
+            offsets
+                .iter()
+                .map(|offset| self.gen_pixel_value(view, x + offset.x as f64, y + offset.y as f64)),
+            offsets.len(),
+            view.image_scale_x,
+        )
+    }
+
+    /// Generates the values of several pixels in a view at once, like calling
+    /// [`gen_pixel`](CpuFractalOpts::gen_pixel) for each of them. Kernels that
+    /// iterate several locations in lockstep override this.
+    fn gen_pixel_batch(
+        &self,
+        view: View,
+        pixels: &[(usize, usize)],
+        offsets: &[Vector2<f32>],
+        values: &mut [PixelValue],
+    ) {
+        for (&(x, y), value) in pixels.iter().zip(values) {
+            *value = self.gen_pixel(view, x, y, offsets);
+        }
+    }
+}
+
+/// The result of iterating a single location on the complex plane.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Sample {
+    /// The smoothed iteration count. This is the iteration limit for locations
+    /// that never escaped.
+    pub value: f32,
+    /// The estimated distance from the location to the boundary of the set in
+    /// units of the complex plane, if it escaped and distances are being
+    /// estimated, or if it is inside the set and colored by its distance.
+    pub distance: Option<f32>,
+    /// The interior value of a location inside the set, if it is colored by
+    /// anything other than its distance.
+    pub interior: Option<f32>,
+}
+
+impl Sample {
+    /// Creates a sample without a distance estimate or interior value.
+    pub fn new(value: f32) -> Sample {
+        Sample {
+            value,
+            distance: None,
+            interior: None,
+        }
+    }
+}
+
+/// Averages the samples of a pixel, only counting the ones that escaped,
+/// except for the interior value, which only counts the ones that didn't.
+/// `pixel_size` is the width of a pixel on the complex plane.
+fn average_samples(
+    iterations: u32,
+    samples: impl Iterator<Item = Sample>,
+    sample_count: usize,
+    pixel_size: f64,
+) -> PixelValue {
+    let iterations = iterations as f32;
+    let mut sum = 0.0;
+    let mut escaped = 0;
+    let mut distance: Option<f32> = None;
+    let mut interior_sum = 0.0;
+    let mut interior_count = 0;
+    let to_pixels = |distance: f32| (distance as f64 / pixel_size).min(NO_DISTANCE as f64) as f32;
+
+    for sample in samples {
+        if sample.value < iterations {
+            sum += sample.value;
+            escaped += 1;
+
+            if let Some(sample_distance) = sample.distance {
+                distance = Some(distance.map_or(sample_distance, |d| d.min(sample_distance)));
+            }
+        } else if let Some(interior) = sample.distance.map(to_pixels).or(sample.interior) {
+            interior_sum += interior;
+            interior_count += 1;
+        }
+    }
+
+    let interior = if interior_count == 0 {
+        NO_INTERIOR
+    } else {
+        interior_sum / interior_count as f32
+    };
+
+    if escaped == 0 {
+        PixelValue {
+            interior,
+            ..Default::default()
+        }
+    } else {
+        PixelValue {
+            value: sum / escaped as f32,
+            coverage: escaped as f32 / sample_count as f32,
+            distance: distance.map_or(NO_DISTANCE, to_pixels),
+            interior,
+        }
     }
 }
 
 impl CpuFractalOpts for FractalOpts {
-    fn gen_value(&self, loc: Complex<f32>) -> f32 {
-        let (mut z, c): (Complex<f32>, Complex<f32>) = if self.mandelbrot {
-            (Complex::<f32>::new(0f32, 0f32), loc)
+    fn iterations(&self) -> u32 {
+        self.iterations
+    }
+
+    fn gen_value(&self, loc: Complex<f64>) -> Sample {
+        match self.precision {
+            Precision::Single => gen_value_in::<f32>(self, cast_complex(loc)),
+            Precision::Double => gen_value_in::<f64>(self, loc),
+        }
+    }
+
+    fn gen_pixel_batch(
+        &self,
+        view: View,
+        pixels: &[(usize, usize)],
+        offsets: &[Vector2<f32>],
+        values: &mut [PixelValue],
+    ) {
+        let locs: Vec<_> = pixels
+            .iter()
+            .flat_map(|&(x, y)| {
+                offsets.iter().map(move |offset| {
+                    view.get_local_subpixel_plane_coordinates((
+                        x as f64 + offset.x as f64,
+                        y as f64 + offset.y as f64,
+                    ))
+                })
+            })
+            .collect();
+        let mut samples = vec![Sample::new(0.0); locs.len()];
+        simd::gen_values(self, &locs, &mut samples);
+
+        for (value, samples) in values.iter_mut().zip(samples.chunks_exact(offsets.len())) {
+            *value = average_samples(
+                self.iterations,
+                samples.iter().copied(),
+                offsets.len(),
+                view.image_scale_x,
+            );
+        }
+    }
+}
+
+/// Iterates the fractal at `loc` using `T` for all complex arithmetic.
+fn gen_value_in<T: Float>(opts: &FractalOpts, loc: Complex<T>) -> Sample {
+    if opts.checks_bulbs() && in_main_bulbs(loc) {
+        return Sample::new(opts.iterations as f32);
+    }
+
+    let (mut z, c): (Complex<T>, Complex<T>) = if opts.mandelbrot {
+        if opts.formula.starts_at_c() {
+            (loc, loc)
         } else {
-            (loc, self.c)
-        };
+            (Complex::<T>::new(T::zero(), T::zero()), loc)
+        }
+    } else {
+        (loc, cast_complex(opts.c))
+    };
+    let radius_squared: T = cast(opts.radius_squared);
+
+    // the derivative of z along the real axis of the plane, if distances are
+    // being estimated
+    let estimates_distance = opts.distance_estimation && opts.formula.has_derivative();
+    let mut dz = if opts.mandelbrot && !opts.formula.starts_at_c() {
+        Complex::<T>::new(T::zero(), T::zero())
+    } else {
+        Complex::<T>::new(T::one(), T::zero())
+    };
+    let dc = if opts.mandelbrot {
+        Complex::<T>::new(T::one(), T::zero())
+    } else {
+        Complex::<T>::new(T::zero(), T::zero())
+    };
+
+    let trap = opts.orbit_trap.as_ref().map(TrapMeasure::new);
+    let mut trap_distance = f32::INFINITY;
+
+    let mut average = AverageMeasure::new(opts, z, c);
+
+    let mut interior = InteriorMeasure::new(opts);
+
+    let mut z_prev = z;
+
+    let periodicity = opts.checks_periodicity();
+    let tolerance = T::epsilon() * T::epsilon();
+    let mut z_saved = z;
+
+    let mut n = 0;
+    while n < opts.iterations {
+        if z.norm_sqr() > radius_squared {
+            break;
+        }
+
+        z_prev = z;
+
+        if estimates_distance {
+            dz = opts.formula.apply_derivative(z, dz) + dc;
+        }
+        z = opts.formula.apply(z, c);
+
+        if let Some(trap) = &trap {
+            trap_distance = trap_distance.min(trap.distance(z));
+        }
+
+        if let Some(average) = &mut average {
+            average.record(z);
+        }
 
-        let mut z_prev = z;
+        n += 1;
 
-        let mut n = 0;
-        while n < self.iterations {
-            if z.norm_sqr() > self.radius_squared {
-                break;
+        if let Some(interior) = &mut interior {
+            interior.record(n, z);
+        }
+
+        if periodicity {
+            // z is caught in a cycle, so it will never escape
+            if (z - z_saved).norm_sqr() < tolerance {
+                return Sample::new(opts.iterations as f32);
             }
 
-            z_prev = z;
+            // Saving z at every power of two eventually finds cycles of any
+            // length (Brent's algorithm).
+            if n & (n - 1) == 0 {
+                z_saved = z;
+            }
+        }
+    }
 
-            z = z * z + c;
+    if n < opts.iterations {
+        let value = match (&trap, &average) {
+            (Some(trap), _) => trap.value(n, z, trap_distance),
+            (None, Some(average)) => average.value(&opts.smoothing, n, z, z_prev, radius_squared),
+            (None, None) => opts.smoothing.smooth(n, z, z_prev, radius_squared),
+        };
 
-            n += 1;
+        Sample {
+            value,
+            distance: estimates_distance.then(|| estimate_distance(z, dz)),
+            interior: None,
         }
+    } else if let Some(interior) = &interior {
+        interior.sample(z, c)
+    } else {
+        Sample::new(n as f32)
+    }
+}
 
-        if n < self.iterations {
-            self.smoothing.smooth(n, z, z_prev, self.radius_squared)
-        } else {
-            n as f32
+/// Estimates the distance from an escaped location to the set from the final
+/// `z` and its derivative. This mirrors `estimate_distance` in
+/// `fragment_shader_main.wgsl.liquid`.
+pub(crate) fn estimate_distance<T: Float>(z: Complex<T>, dz: Complex<T>) -> f32 {
+    let z_norm = z.norm();
+    let half: T = cast(0.5);
+    (half * z_norm * z_norm.ln() / dz.norm())
+        .to_f32()
+        .unwrap_or(f32::INFINITY)
+}
+
+/// Checks whether `c` is inside the main cardioid or the period-2 bulb of the
+/// Mandelbrot set of `z^2 + c`. This mirrors `in_main_bulbs` in
+/// `fragment_shader_main.wgsl.liquid`.
+pub(crate) fn in_main_bulbs<T: Float>(c: Complex<T>) -> bool {
+    let quarter: T = cast(0.25);
+    let y_squared = c.im * c.im;
+
+    let x = c.re - quarter;
+    let q = x * x + y_squared;
+    if q * (q + x) <= quarter * y_squared {
+        return true;
+    }
+
+    let x = c.re + T::one();
+    x * x + y_squared <= quarter * quarter
+}
+
+pub(crate) fn cast<T: NumCast, U: NumCast>(value: T) -> U {
+    U::from(value).expect("Float conversions never fail")
+}
+
+pub(crate) fn cast_complex<T: NumCast, U: NumCast>(value: Complex<T>) -> Complex<U> {
+    Complex::new(cast(value.re), cast(value.im))
+}
+
+/// Structs implementing this trait can be used as the iterative function on a
+/// CPU.
+pub trait CpuFormula {
+    /// Applies this formula to `z`, getting the next value of `z`.
+    fn apply<T: Float>(&self, z: Complex<T>, c: Complex<T>) -> Complex<T>;
+
+    /// Gets the derivative of the next value of `z`, excluding the derivative
+    /// of `c`, given `z` and its derivative `dz`. Formulas folding `z` fold
+    /// `dz` the same way. This is zero for formulas without
+    /// [`has_derivative`](Formula::has_derivative).
+    fn apply_derivative<T: Float>(&self, z: Complex<T>, dz: Complex<T>) -> Complex<T>;
+}
+
+impl CpuFormula for Formula {
+    fn apply<T: Float>(&self, z: Complex<T>, c: Complex<T>) -> Complex<T> {
+        match self {
+            Formula::IntegerPower { exponent: 2 } => z * z + c,
+            Formula::IntegerPower { exponent } => complex_powi(z, *exponent) + c,
+            Formula::RealPower { exponent } => complex_powf(z, cast(*exponent)) + c,
+            Formula::BurningShip => {
+                let a = Complex::<T>::new(z.re.abs(), z.im.abs());
+                a * a + c
+            },
+            Formula::Tricorn => {
+                let a = z.conj();
+                a * a + c
+            },
+            Formula::PerpendicularBurningShip => {
+                let a = Complex::<T>::new(z.re, -z.im.abs());
+                a * a + c
+            },
+            Formula::Celtic => {
+                let z2 = z * z;
+                Complex::<T>::new(z2.re.abs(), z2.im) + c
+            },
+            Formula::Expression(expression) => expression.root().eval(z, c),
+        }
+    }
+
+    fn apply_derivative<T: Float>(&self, z: Complex<T>, dz: Complex<T>) -> Complex<T> {
+        let two: T = cast(2.0);
+        match self {
+            Formula::IntegerPower { exponent: 2 } => z * dz * two,
+            Formula::IntegerPower { exponent } => {
+                complex_powi(z, *exponent - 1) * dz * cast::<_, T>(*exponent)
+            },
+            Formula::RealPower { exponent } => {
+                complex_powf(z, cast(*exponent - 1.0)) * dz * cast::<_, T>(*exponent)
+            },
+            Formula::BurningShip => {
+                let a = Complex::<T>::new(z.re.abs(), z.im.abs());
+                let da = Complex::<T>::new(flip(dz.re, z.re), flip(dz.im, z.im));
+                a * da * two
+            },
+            Formula::Tricorn => z.conj() * dz.conj() * two,
+            Formula::PerpendicularBurningShip => {
+                let a = Complex::<T>::new(z.re, -z.im.abs());
+                let da = Complex::<T>::new(dz.re, -flip(dz.im, z.im));
+                a * da * two
+            },
+            Formula::Celtic => {
+                let z2 = z * z;
+                let dz2 = z * dz * two;
+                Complex::<T>::new(flip(dz2.re, z2.re), dz2.im)
+            },
+            Formula::Expression(_) => Complex::<T>::new(T::zero(), T::zero()),
+        }
+    }
+}
+
+/// Negates `value` if `sign` is negative, which is the derivative of taking the
+/// absolute value of `sign`.
+fn flip<T: Float>(value: T, sign: T) -> T {
+    if sign < T::zero() {
+        -value
+    } else {
+        value
+    }
+}
+
+/// Structs implementing this trait are expression nodes that can be evaluated
+/// on a CPU.
+pub trait CpuExpression {
+    /// Evaluates this expression for the given values of `z` and `c`.
+    fn eval<T: Float>(&self, z: Complex<T>, c: Complex<T>) -> Complex<T>;
+}
+
+impl CpuExpression for Node {
+    fn eval<T: Float>(&self, z: Complex<T>, c: Complex<T>) -> Complex<T> {
+        match self {
+            Node::Z => z,
+            Node::C => c,
+            Node::Constant(value) => cast_complex(*value),
+            Node::Negate(a) => -a.eval(z, c),
+            Node::Add(a, b) => a.eval(z, c) + b.eval(z, c),
+            Node::Subtract(a, b) => a.eval(z, c) - b.eval(z, c),
+            Node::Multiply(a, b) => a.eval(z, c) * b.eval(z, c),
+            Node::Divide(a, b) => a.eval(z, c) / b.eval(z, c),
+            Node::IntegerPower(a, e) => complex_powi(a.eval(z, c), *e),
+            Node::RealPower(a, e) => complex_powf(a.eval(z, c), cast(*e)),
+            Node::ComplexPower(a, b) => complex_pow(a.eval(z, c), b.eval(z, c)),
+            Node::Function(function, a) => {
+                let a = a.eval(z, c);
+                match function {
+                    Function::Sin => a.sin(),
+                    Function::Cos => a.cos(),
+                    Function::Tan => a.tan(),
+                    Function::Sinh => a.sinh(),
+                    Function::Cosh => a.cosh(),
+                    Function::Tanh => a.tanh(),
+                    Function::Exp => a.exp(),
+                    Function::Ln => a.ln(),
+                    Function::Sqrt => a.sqrt(),
+                    Function::Abs => Complex::<T>::new(a.norm(), T::zero()),
+                    Function::Conj => a.conj(),
+                    Function::Re => Complex::<T>::new(a.re, T::zero()),
+                    Function::Im => Complex::<T>::new(a.im, T::zero()),
+                }
+            },
+        }
+    }
+}
+
+/// Raises `a` to an integer power by squaring. This mirrors `complex_powi` in
+/// `util/complex_f32.wgsl.liquid` so both generators get the same results.
+fn complex_powi<T: Float>(a: Complex<T>, n: i32) -> Complex<T> {
+    let mut base = a;
+    let mut result = Complex::<T>::new(T::one(), T::zero());
+    let mut e = n.unsigned_abs();
+
+    while e > 0 {
+        if e & 1 != 0 {
+            result = result * base;
+        }
+        e >>= 1;
+        if e > 0 {
+            base = base * base;
+        }
+    }
+
+    if n < 0 {
+        Complex::<T>::new(T::one(), T::zero()) / result
+    } else {
+        result
+    }
+}
+
+/// Raises `a` to a real power using the principal branch. This mirrors
+/// `complex_powf` in `util/complex_f32.wgsl.liquid`.
+fn complex_powf<T: Float>(a: Complex<T>, e: T) -> Complex<T> {
+    let half: T = cast(0.5);
+    let r = a.norm_sqr().powf(e * half);
+    let theta = a.im.atan2(a.re) * e;
+    Complex::<T>::new(r * theta.cos(), r * theta.sin())
+}
+
+/// Raises `a` to a complex power using the principal branch. This mirrors
+/// `complex_pow` in `util/complex_f32.wgsl.liquid`.
+fn complex_pow<T: Float>(a: Complex<T>, b: Complex<T>) -> Complex<T> {
+    if a.norm_sqr() == T::zero() {
+        return Complex::<T>::new(T::zero(), T::zero());
+    }
+    (b * a.ln()).exp()
+}
+
+/// An orbit trap prepared for measuring the distance between orbits and the
+/// trap on the CPU. This mirrors `trap_distance` in
+/// `fragment_shader_main.wgsl.liquid`.
+pub(crate) struct TrapMeasure<'a> {
+    trap: &'a OrbitTrap,
+    center: Complex<f32>,
+    direction: Complex<f32>,
+}
+
+impl<'a> TrapMeasure<'a> {
+    pub(crate) fn new(trap: &'a OrbitTrap) -> TrapMeasure<'a> {
+        TrapMeasure {
+            trap,
+            center: Complex::new(trap.center().re as f32, trap.center().im as f32),
+            direction: trap.direction(),
         }
     }
 
-    fn gen_color(&self, value: f32) -> Vector4<f32> {
-        if value < self.iterations as f32 {
-            Vector4::<f32>::from_hsba(
-                value * 3.3f32 / 256f32 % 1f32,
-                1f32,
-                value * 16f32 / 256f32 % 1f32,
-                1f32,
-            )
+    /// Gets the distance between `z` and the trap. This is measured in single
+    /// precision, like on the GPU.
+    pub(crate) fn distance<T: Float>(&self, z: Complex<T>) -> f32 {
+        let d = to_f32_complex(z) - self.center;
+        let dir = self.direction;
+
+        match self.trap {
+            OrbitTrap::Point { .. } => (d.re * d.re + d.im * d.im).sqrt(),
+            OrbitTrap::Line { .. } => (d.re * dir.im - d.im * dir.re).abs(),
+            OrbitTrap::Cross { .. } => (d.re * dir.im - d.im * dir.re)
+                .abs()
+                .min((d.re * dir.re + d.im * dir.im).abs()),
+            OrbitTrap::Circle { radius, .. } => ((d.re * d.re + d.im * d.im).sqrt() - radius).abs(),
+            OrbitTrap::Image { size, image, .. } => {
+                1.0 - image.coverage_at(d.re / size + 0.5, d.im / size + 0.5)
+            },
+        }
+    }
+
+    /// Gets the value of a location that escaped after `iterations`
+    /// iterations, ending at `z`, given the smallest distance between the
+    /// trap and the values of `z` after each iteration. Locations that escape
+    /// before the first iteration use their starting `z` instead.
+    pub(crate) fn value<T: Float>(
+        &self,
+        iterations: u32,
+        z: Complex<T>,
+        trap_distance: f32,
+    ) -> f32 {
+        if iterations == 0 {
+            self.distance(z) * TRAP_VALUE_SCALE
         } else {
-            Vector4::<f32> {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-                w: 1.0,
-            }
+            trap_distance * TRAP_VALUE_SCALE
+        }
+    }
+}
+
+/// Accumulates the orbit average of a location while it is iterated. This
+/// mirrors the averaging in `fragment_shader_main.wgsl.liquid`.
+pub(crate) struct AverageMeasure {
+    averaging: Averaging,
+    c: Complex<f32>,
+    /// The value of `z` after the previous iteration and the one before it.
+    previous: [Complex<f32>; 2],
+    /// How many values of `z` have been recorded, counting the starting one.
+    recorded: u32,
+    sum: f32,
+    count: u32,
+    /// The term added by the last iteration, if it added one.
+    last: Option<f32>,
+}
+
+impl AverageMeasure {
+    /// Creates a measure for the averaging in `opts` of the orbit starting at
+    /// `z`, or `None` if escaped locations aren't given averages.
+    pub(crate) fn new<T: Float>(
+        opts: &FractalOpts,
+        z: Complex<T>,
+        c: Complex<T>,
+    ) -> Option<AverageMeasure> {
+        opts.averages_orbits().then(|| AverageMeasure {
+            averaging: opts.averaging,
+            c: to_f32_complex(c),
+            previous: [to_f32_complex(z); 2],
+            recorded: 1,
+            sum: 0.0,
+            count: 0,
+            last: None,
+        })
+    }
+
+    /// Records the value of `z` after the next iteration. Averages are
+    /// measured in single precision, like on the GPU.
+    pub(crate) fn record<T: Float>(&mut self, z: Complex<T>) {
+        let z = to_f32_complex(z);
+        let [z_prev, z_prev_prev] = self.previous;
+
+        self.last = match self.averaging {
+            Averaging::None => None,
+            Averaging::Stripe { density } => Some(0.5 * (density * z.im.atan2(z.re)).sin() + 0.5),
+            Averaging::TriangleInequality => {
+                // z - c is what the formula did to the previous z
+                let applied = (z - self.c).norm();
+                let c_norm = self.c.norm();
+                let low = (applied - c_norm).abs();
+                let high = applied + c_norm;
+                (high > low).then(|| (z.norm() - low) / (high - low))
+            },
+            Averaging::Curvature => {
+                let step_z = z - z_prev;
+                let previous_step_z = z_prev - z_prev_prev;
+                (self.recorded >= 2 && previous_step_z.norm_sqr() > 0.0).then(|| {
+                    let turn = step_z * previous_step_z.conj();
+                    turn.im.atan2(turn.re).abs() / std::f32::consts::PI
+                })
+            },
+        };
+
+        if let Some(term) = self.last {
+            self.sum += term;
+            self.count += 1;
+        }
+        self.previous = [z, z_prev];
+        self.recorded += 1;
+    }
+
+    /// Gets the value of a location that escaped after `iterations`
+    /// iterations, ending at `z` after `z_prev`. The averages with and
+    /// without the last iteration are interpolated using `smoothing`.
+    pub(crate) fn value<T: Float>(
+        &self,
+        smoothing: &Smoothing,
+        iterations: u32,
+        z: Complex<T>,
+        z_prev: Complex<T>,
+        radius_squared: T,
+    ) -> f32 {
+        if self.count == 0 {
+            return 0.0;
+        }
+
+        let average = self.sum / self.count as f32;
+        let previous_average = match self.last {
+            Some(last) if self.count > 1 => (self.sum - last) / (self.count - 1) as f32,
+            _ => average,
+        };
+        let fraction = smoothing.fraction(iterations, z, z_prev, radius_squared);
+
+        (previous_average + (average - previous_average) * fraction) * AVERAGE_VALUE_SCALE
+    }
+}
+
+fn to_f32_complex<T: Float>(z: Complex<T>) -> Complex<f32> {
+    let to_f32 = |value: T| value.to_f64().unwrap_or(f64::NAN) as f32;
+    Complex::new(to_f32(z.re), to_f32(z.im))
+}
+
+/// Tracks what interior coloring needs to know about an orbit while it is
+/// iterated. This mirrors the interior coloring in
+/// `fragment_shader_main.wgsl.liquid`.
+pub(crate) struct InteriorMeasure<'a> {
+    opts: &'a FractalOpts,
+    /// The smallest squared magnitude of `z` so far.
+    min_norm_sqr: f32,
+    /// The smallest squared magnitude of `z` before it reached
+    /// `min_norm_sqr`.
+    previous_min_norm_sqr: f32,
+    /// The iteration `z` reached `min_norm_sqr` at.
+    min_iteration: u32,
+}
+
+impl<'a> InteriorMeasure<'a> {
+    /// Creates a measure for the interior coloring in `opts`, or `None` if
+    /// the inside of the set isn't colored.
+    pub(crate) fn new(opts: &'a FractalOpts) -> Option<InteriorMeasure<'a>> {
+        (opts.interior_coloring != InteriorColoring::None).then_some(InteriorMeasure {
+            opts,
+            min_norm_sqr: f32::INFINITY,
+            previous_min_norm_sqr: f32::INFINITY,
+            min_iteration: 0,
+        })
+    }
+
+    /// Records the value of `z` after iteration `n`.
+    pub(crate) fn record<T: Float>(&mut self, n: u32, z: Complex<T>) {
+        let norm_sqr = z.norm_sqr().to_f32().unwrap_or(f32::INFINITY);
+        if norm_sqr < self.min_norm_sqr {
+            self.previous_min_norm_sqr = self.min_norm_sqr;
+            self.min_norm_sqr = norm_sqr;
+            self.min_iteration = n;
+        }
+    }
+
+    /// Gets the sample of a location that never escaped, ending at `z`.
+    pub(crate) fn sample<T: Float>(&self, z: Complex<T>, c: Complex<T>) -> Sample {
+        let mut sample = Sample::new(self.opts.iterations as f32);
+
+        match self.opts.interior_coloring {
+            InteriorColoring::None => {},
+            InteriorColoring::FinalMagnitude => {
+                sample.interior = z
+                    .norm()
+                    .to_f32()
+                    .map(|norm| norm * INTERIOR_MAGNITUDE_SCALE);
+            },
+            InteriorColoring::MinMagnitudeIteration => {
+                sample.interior = Some(self.min_iteration as f32);
+            },
+            InteriorColoring::Period => {
+                sample.interior = find_period(self.opts, z, c).map(|period| period as f32);
+            },
+            InteriorColoring::Distance => match self.opts.formula {
+                Formula::IntegerPower { exponent } if self.opts.estimates_interior_distance() => {
+                    sample.distance = find_period(self.opts, z, c)
+                        .and_then(|period| estimate_interior_distance(exponent, z, c, period));
+                },
+                _ => {},
+            },
+            InteriorColoring::AtomDomain => {
+                let shade = (self.min_norm_sqr / self.previous_min_norm_sqr).sqrt();
+                sample.interior = Some(self.min_iteration as f32 + shade);
+            },
         }
+
+        sample
     }
 }
 
+/// Finds the period of the cycle that `z` has been attracted to, or `None` if
+/// it doesn't come back to itself within the iteration limit. This mirrors
+/// `find_period` in `fragment_shader_main.wgsl.liquid`.
+fn find_period<T: Float>(opts: &FractalOpts, z: Complex<T>, c: Complex<T>) -> Option<u32> {
+    let tolerance: T = cast(INTERIOR_PERIOD_TOLERANCE);
+    let mut w = z;
+    (1..=opts.iterations).find(|_| {
+        w = opts.formula.apply(w, c);
+        (w - z).norm_sqr() < tolerance
+    })
+}
+
+/// Estimates the distance from a location inside the Mandelbrot set of
+/// `z^exponent + c` to the boundary of the set, given a point `z` of the cycle
+/// of length `period` that its orbit is attracted to. Locations whose cycle
+/// isn't attracting have no estimate. This mirrors
+/// `estimate_interior_distance` in `fragment_shader_main.wgsl.liquid`.
+fn estimate_interior_distance<T: Float>(
+    exponent: i32,
+    z: Complex<T>,
+    c: Complex<T>,
+    period: u32,
+) -> Option<f32> {
+    let n: T = cast(exponent);
+    let one = Complex::<T>::new(T::one(), T::zero());
+    let zero = Complex::<T>::new(T::zero(), T::zero());
+
+    // the derivatives of the cycle with respect to its starting point and c
+    let mut z = z;
+    let (mut dz, mut dzdz, mut dc, mut dcdz) = (one, zero, zero, zero);
+    for _ in 0..period {
+        let df = complex_powi(z, exponent - 1) * n;
+        let ddf = complex_powi(z, exponent - 2) * (n * (n - T::one()));
+        dcdz = ddf * dc * dz + df * dcdz;
+        dc = df * dc + one;
+        dzdz = ddf * dz * dz + df * dzdz;
+        dz = df * dz;
+        z = complex_powi(z, exponent) + c;
+    }
+
+    if dz.norm_sqr() >= T::one() {
+        return None;
+    }
+
+    ((T::one() - dz.norm_sqr()) / (dcdz + dzdz * dc / (one - dz)).norm()).to_f32()
+}
+
 /// Structs implementing this trait can be used to smooth an integer iteration
 /// count into a floating-point value.
 pub trait CpuSmoothing {
     /// Smooths an integer iteration count based on the current and previous
     /// values of the complex number.
-    fn smooth(
+    fn smooth<T: Float>(
         &self,
         iterations: u32,
-        z_current: Complex<f32>,
-        z_previous: Complex<f32>,
-        radius_squared: f32,
+        z_current: Complex<T>,
+        z_previous: Complex<T>,
+        radius_squared: T,
     ) -> f32;
+
+    /// Gets how far the smoothed iteration count is past the iteration before
+    /// the last one, between 0 and 1. Orbit averages use this to interpolate
+    /// between the averages with and without the last iteration.
+    fn fraction<T: Float>(
+        &self,
+        iterations: u32,
+        z_current: Complex<T>,
+        z_previous: Complex<T>,
+        radius_squared: T,
+    ) -> f32 {
+        let smoothed = self.smooth(iterations, z_current, z_previous, radius_squared);
+        (smoothed - iterations as f32 + 1.0).clamp(0.0, 1.0)
+    }
 }
 
 impl CpuSmoothing for Smoothing {
-    fn smooth(
+    fn smooth<T: Float>(
         &self,
         iterations: u32,
-        z_current: Complex<f32>,
-        z_previous: Complex<f32>,
-        radius_squared: f32,
+        z_current: Complex<T>,
+        z_previous: Complex<T>,
+        radius_squared: T,
     ) -> f32 {
         match self {
             Smoothing::None => iterations as f32,
             Smoothing::LogarithmicDistance {
                 divisor, addend, ..
-            } => iterations as f32 - z_current.norm_sqr().ln().ln() / *divisor + *addend,
+            } => {
+                iterations as f32 - cast::<T, f32>(z_current.norm_sqr().ln().ln()) / *divisor
+                    + *addend
+            },
             Smoothing::LinearIntersection => {
                 if z_current == z_previous {
                     return iterations as f32;
@@ -120,9 +832,9 @@ impl CpuSmoothing for Smoothing {
                 let dy = by - ay;
 
                 iterations as f32
-                    - if dx.abs() > dy.abs() {
+                    - cast::<T, f32>(if dx.abs() > dy.abs() {
                         let m = dy / dx;
-                        let m_squared_1 = m * m + 1.0;
+                        let m_squared_1 = m * m + T::one();
                         let p = m * ax - ay;
 
                         (bx - if bx > ax {
@@ -132,7 +844,7 @@ impl CpuSmoothing for Smoothing {
                         }) / dx
                     } else {
                         let m = dx / dy;
-                        let m_squared_1 = m * m + 1.0;
+                        let m_squared_1 = m * m + T::one();
                         let p = m * ay - ax;
 
                         (by - if by > ay {
@@ -140,8 +852,470 @@ impl CpuSmoothing for Smoothing {
                         } else {
                             (m * p - (radius_squared * m_squared_1 - p * p).sqrt()) / m_squared_1
                         }) / dy
-                    }
+                    })
             },
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use super::*;
+    use crate::generator::{args::InteriorChecks, trap::TrapImage};
+    use test::Bencher;
+
+    const EPSILON: f32 = 1e-4;
+
+    fn assert_close(a: Complex<f32>, b: Complex<f32>) {
+        assert!((a - b).norm() < EPSILON, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn integer_powers() {
+        let z = Complex::<f32>::new(0.3, -0.7);
+        let c = Complex::<f32>::new(-0.1, 0.2);
+
+        assert_close(Formula::IntegerPower { exponent: 2 }.apply(z, c), z * z + c);
+        assert_close(
+            Formula::IntegerPower { exponent: 3 }.apply(z, c),
+            z * z * z + c,
+        );
+        assert_close(
+            Formula::IntegerPower { exponent: 0 }.apply(z, c),
+            Complex::new(1.0, 0.0) + c,
+        );
+        assert_close(
+            Formula::IntegerPower { exponent: -2 }.apply(z, c),
+            Complex::new(1.0, 0.0) / (z * z) + c,
+        );
+    }
+
+    #[test]
+    fn real_powers_match_integer_powers() {
+        let c = Complex::<f32>::new(0.25, 0.5);
+        for z in [
+            Complex::<f32>::new(0.3, -0.7),
+            Complex::<f32>::new(-1.5, 0.1),
+            Complex::<f32>::new(0.0, 2.0),
+        ] {
+            for exponent in [-3, -1, 1, 2, 5] {
+                assert_close(
+                    Formula::RealPower {
+                        exponent: exponent as f32,
+                    }
+                    .apply(z, c),
+                    Formula::IntegerPower { exponent }.apply(z, c),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn abs_and_conjugate_variants() {
+        let z = Complex::<f32>::new(0.3, -0.7);
+        let c = Complex::<f32>::new(-0.1, 0.2);
+
+        // x^2 - y^2 = -0.4, 2xy = -0.42
+        assert_close(
+            Formula::BurningShip.apply(z, c),
+            Complex::new(-0.4, 0.42) + c,
+        );
+        assert_close(Formula::Tricorn.apply(z, c), Complex::new(-0.4, 0.42) + c);
+        assert_close(
+            Formula::PerpendicularBurningShip.apply(z, c),
+            Complex::new(-0.4, -0.42) + c,
+        );
+        assert_close(Formula::Celtic.apply(z, c), Complex::new(0.4, -0.42) + c);
+    }
+
+    #[test]
+    fn expressions() {
+        let z = Complex::<f32>::new(0.3, -0.7);
+        let c = Complex::<f32>::new(-0.1, 0.2);
+        let apply = |s: &str| s.parse::<Formula>().unwrap().apply(z, c);
+
+        assert_close(apply("z^3 - 0.5*z + c"), z * z * z - 0.5 * z + c);
+        assert_close(apply("z*z + c"), Formula::default().apply(z, c));
+        assert_close(apply("sin(z)*c"), z.sin() * c);
+        assert_close(apply("conj(z)^2 + c"), Formula::Tricorn.apply(z, c));
+        assert_close(apply("z^(2 + 0*i) + c"), Formula::default().apply(z, c));
+        assert_close(apply("exp(ln(z))"), z);
+    }
+
+    #[test]
+    fn double_precision_resolves_deep_zooms() {
+        let view = View::new_uniform(16, 16, 1e-9, -0.743643887037151, 0.131825904205330);
+        let opts = FractalOpts {
+            iterations: 10000,
+            smoothing: Smoothing::LinearIntersection,
+            precision: Precision::required_for(&view),
+            ..FractalOpts::test_base()
+        };
+        assert_eq!(opts.precision, Precision::Double);
+
+        let row = |opts: &FractalOpts| -> Vec<f32> {
+            (0..16)
+                .map(|x| opts.gen_pixel_value(view, x as f64 + 0.5, 8.5).value)
+                .collect()
+        };
+
+        // in single precision, every pixel in the row maps to the same point
+        let single = row(&FractalOpts {
+            precision: Precision::Single,
+            ..opts.clone()
+        });
+        assert!(single.iter().all(|value| *value == single[0]));
+
+        let double = row(&opts);
+        assert!(double.iter().any(|value| *value != double[0]));
+    }
+
+    /// Options for the default view rendered by `fractal-rs-2 render`, but
+    /// with enough iterations for interior points to dominate.
+    fn default_view_opts(interior_checks: InteriorChecks) -> FractalOpts {
+        FractalOpts {
+            iterations: 1000,
+            interior_checks,
+            ..FractalOpts::test_base()
+        }
+    }
+
+    fn gen_default_view(opts: &FractalOpts) -> Vec<f32> {
+        let view = View::new_centered_uniform(128, 128, 3.0);
+        (0..view.image_width * view.image_height)
+            .map(|index| {
+                let x = (index % view.image_width) as f64 + 0.5;
+                let y = (index / view.image_width) as f64 + 0.5;
+                opts.gen_pixel_value(view, x, y).value
+            })
+            .collect()
+    }
+
+    #[test]
+    fn interior_checks_find_the_same_points() {
+        let checked = gen_default_view(&default_view_opts(InteriorChecks::ALL));
+        let unchecked = gen_default_view(&default_view_opts(InteriorChecks::NONE));
+        assert_eq!(checked, unchecked);
+
+        // the main cardioid and period-2 bulb
+        assert!(in_main_bulbs(Complex::<f64>::new(0.0, 0.0)));
+        assert!(in_main_bulbs(Complex::<f64>::new(-1.0, 0.2)));
+        assert!(!in_main_bulbs(Complex::<f64>::new(0.3, 0.0)));
+        assert!(!in_main_bulbs(Complex::<f64>::new(-0.75, 0.1)));
+    }
+
+    #[bench]
+    fn default_view_without_interior_checks(b: &mut Bencher) {
+        let opts = default_view_opts(InteriorChecks::NONE);
+        b.iter(|| gen_default_view(&opts));
+    }
+
+    #[bench]
+    fn default_view_with_interior_checks(b: &mut Bencher) {
+        let opts = default_view_opts(InteriorChecks::ALL);
+        b.iter(|| gen_default_view(&opts));
+    }
+
+    #[test]
+    fn distance_estimates_bound_the_distance_to_the_set() {
+        let opts = FractalOpts {
+            distance_estimation: true,
+            ..default_view_opts(InteriorChecks::ALL)
+        };
+
+        // the set ends at -2 on the real axis
+        for (loc, distance) in [(-2.01, 0.01), (-2.1, 0.1), (-2.5, 0.5)] {
+            for precision in [Precision::Single, Precision::Double] {
+                let opts = FractalOpts {
+                    precision,
+                    ..opts.clone()
+                };
+                let estimate = opts.gen_value(Complex::new(loc, 0.0)).distance.unwrap() as f64;
+                assert!(
+                    estimate > distance / 4.0 && estimate < distance * 2.0,
+                    "{}: estimated {}, expected about {}",
+                    loc,
+                    estimate,
+                    distance
+                );
+            }
+        }
+
+        // pixels are given the smallest distance of their samples, in pixels
+        let view = View::new_uniform(16, 16, 0.16, 0.38, 0.0);
+        let offsets = [Vector2::new(0.25, 0.5), Vector2::new(0.75, 0.5)];
+        let pixel = opts.gen_pixel(view, 0, 8, &offsets);
+        let sample = opts.gen_pixel_value(view, 0.25, 8.5).distance.unwrap();
+        assert!((pixel.distance - sample / 0.01).abs() < EPSILON);
+
+        // interior points and disabled estimation have no distance
+        assert_eq!(opts.gen_value(Complex::new(0.0, 0.0)).distance, None);
+        let opts = default_view_opts(InteriorChecks::ALL);
+        assert_eq!(opts.gen_value(Complex::new(0.3, 0.0)).distance, None);
+        assert_eq!(
+            opts.gen_pixel(view, 0, 8, &[Vector2::new(0.5, 0.5)])
+                .distance,
+            NO_DISTANCE
+        );
+    }
+
+    #[test]
+    fn orbit_traps_measure_the_closest_approach() {
+        // the orbit of 0.5 is 0.5, 0.75, 1.0625, 1.6289..., escaping after that
+        let c = Complex::new(0.5, 0.0);
+        for (trap, distance) in [
+            ("point(0, 0)", 0.5),
+            ("point(1, 0)", 0.0625),
+            ("circle(0, 0, 1)", 0.0625),
+            ("line(0, 0, 0)", 0.0),
+            ("line(0, 0.25, 0)", 0.25),
+            ("cross(0.8, 1, 0)", 0.05),
+        ] {
+            for precision in [Precision::Single, Precision::Double] {
+                let opts = FractalOpts {
+                    precision,
+                    orbit_trap: Some(trap.parse().unwrap()),
+                    ..default_view_opts(InteriorChecks::ALL)
+                };
+                let value = opts.gen_value(c).value;
+                assert!(
+                    (value - distance * TRAP_VALUE_SCALE).abs() < EPSILON,
+                    "{}: got {}, expected {}",
+                    trap,
+                    value,
+                    distance * TRAP_VALUE_SCALE
+                );
+            }
+        }
+
+        // images trap orbits passing over their bright pixels
+        let trap = OrbitTrap::Image {
+            center: Complex::new(0.75, 0.0),
+            size: 0.2,
+            image: TrapImage::from_coverage(2, 1, &[0.25, 1.0]),
+        };
+        let opts = FractalOpts {
+            orbit_trap: Some(trap),
+            ..default_view_opts(InteriorChecks::ALL)
+        };
+        assert_eq!(opts.gen_value(c).value, 0.0);
+
+        // interior points are unaffected
+        assert_eq!(
+            opts.gen_value(Complex::new(0.0, 0.0)).value,
+            default_view_opts(InteriorChecks::ALL)
+                .gen_value(Complex::new(0.0, 0.0))
+                .value
+        );
+    }
+
+    #[test]
+    fn orbit_averages() {
+        // the orbit of 0.5 never leaves the positive real axis, so it never turns
+        // and its magnitude is always as large as the triangle inequality allows
+        let c = Complex::new(0.5, 0.0);
+        for (averaging, average) in [
+            (Averaging::Stripe { density: 3.0 }, 0.5),
+            (Averaging::TriangleInequality, 1.0),
+            (Averaging::Curvature, 0.0),
+        ] {
+            for precision in [Precision::Single, Precision::Double] {
+                let opts = FractalOpts {
+                    precision,
+                    averaging,
+                    ..default_view_opts(InteriorChecks::ALL)
+                };
+                let value = opts.gen_value(c).value;
+                assert!(
+                    (value - average * AVERAGE_VALUE_SCALE).abs() < EPSILON,
+                    "{:?}: got {}, expected {}",
+                    averaging,
+                    value,
+                    average * AVERAGE_VALUE_SCALE
+                );
+            }
+        }
+
+        // averages are continuous across iteration bands, unlike unsmoothed
+        // iteration counts
+        let opts = FractalOpts {
+            smoothing: Smoothing::from_logarithmic_distance(1000.0, 2.0),
+            radius_squared: 1e6,
+            averaging: Averaging::Stripe { density: 3.0 },
+            ..default_view_opts(InteriorChecks::ALL)
+        };
+        let counts = FractalOpts {
+            smoothing: Smoothing::None,
+            averaging: Averaging::None,
+            ..opts.clone()
+        };
+        let locs: Vec<_> = (0..1000)
+            .map(|i| Complex::new(0.5 + i as f64 * 0.001, 0.5))
+            .collect();
+        let bands = locs
+            .windows(2)
+            .filter(|pair| counts.gen_value(pair[0]).value != counts.gen_value(pair[1]).value)
+            .count();
+        let escaped = |loc| counts.gen_value(loc).value < opts.iterations as f32;
+        let largest_jump = locs
+            .windows(2)
+            .filter(|pair| escaped(pair[0]) && escaped(pair[1]))
+            .map(|pair| (opts.gen_value(pair[0]).value - opts.gen_value(pair[1]).value).abs())
+            .fold(0.0, f32::max);
+        assert!(bands >= 2, "only crossed {} bands", bands);
+        assert!(
+            largest_jump < 0.01 * AVERAGE_VALUE_SCALE,
+            "jumped by {}",
+            largest_jump
+        );
+
+        // orbit traps take precedence over averages
+        let opts = FractalOpts {
+            orbit_trap: Some("point(0, 0)".parse().unwrap()),
+            ..opts
+        };
+        assert!((opts.gen_value(c).value - 0.5 * TRAP_VALUE_SCALE).abs() < EPSILON);
+    }
+
+    #[test]
+    fn interior_coloring_modes() {
+        let interior = |coloring, loc| {
+            let opts = FractalOpts {
+                interior_coloring: coloring,
+                ..default_view_opts(InteriorChecks::ALL)
+            };
+            let view = View::new_uniform(16, 16, 0.16, loc, 0.0);
+            opts.gen_pixel(view, 8, 8, &[Vector2::new(0.0, 0.0)])
+                .interior
+        };
+
+        // 0 stays at 0, while -1 cycles between -1 and 0
+        for (coloring, center, bulb) in [
+            (InteriorColoring::FinalMagnitude, 0.0, 0.0),
+            (InteriorColoring::MinMagnitudeIteration, 1.0, 2.0),
+            (InteriorColoring::Period, 1.0, 2.0),
+            (InteriorColoring::AtomDomain, 1.0, 2.0),
+        ] {
+            assert_eq!(interior(coloring, 0.0), center, "{:?}", coloring);
+            assert_eq!(interior(coloring, -1.0), bulb, "{:?}", coloring);
+        }
+
+        // the center of the main cardioid is 0.25 from its cusp, and the estimate
+        // is at most 4 times the distance, in pixels
+        let distance = interior(InteriorColoring::Distance, 0.0) * 0.01;
+        assert!(
+            (0.25..=1.0).contains(&distance),
+            "estimated {}, expected about 0.25",
+            distance
+        );
+        assert!((interior(InteriorColoring::Distance, -1.0) * 0.01 - 0.25).abs() < EPSILON);
+
+        // escaped pixels and the black interior have no interior value
+        assert_eq!(interior(InteriorColoring::Period, 0.5), NO_INTERIOR);
+        assert_eq!(interior(InteriorColoring::None, 0.0), NO_INTERIOR);
+    }
+
+    #[test]
+    fn real_power_principal_branch() {
+        // sqrt(-4) on the principal branch is 2i
+        assert_close(
+            complex_powf(Complex::new(-4.0, 0.0), 0.5),
+            Complex::new(0.0, 2.0),
+        );
+    }
+
+    #[test]
+    fn samples_average_into_pixel_values() {
+        let inside = Sample::new(100.0);
+        let interior = Sample {
+            interior: Some(0.5),
+            ..inside
+        };
+
+        // escaped values and interior values are averaged separately
+        let value = average_samples(
+            100,
+            [Sample::new(10.0), Sample::new(20.0), inside, interior].into_iter(),
+            4,
+            1.0,
+        );
+        assert_eq!(
+            value,
+            PixelValue {
+                value: 15.0,
+                coverage: 0.5,
+                distance: NO_DISTANCE,
+                interior: 0.5,
+            }
+        );
+
+        // the closest escaped sample's distance is kept, in pixels
+        let estimated = |value, distance| Sample {
+            distance: Some(distance),
+            ..Sample::new(value)
+        };
+        let value = average_samples(
+            100,
+            [estimated(10.0, 0.5), estimated(12.0, 0.25)].into_iter(),
+            2,
+            0.125,
+        );
+        assert_eq!(value.value, 11.0);
+        assert_eq!(value.coverage, 1.0);
+        assert_eq!(value.distance, 2.0);
+
+        // pixels without escaped samples are inside the set
+        let value = average_samples(100, [inside; 4].into_iter(), 4, 1.0);
+        assert_eq!(value, PixelValue::default());
+    }
+
+    /// Escapes everywhere to the right of the imaginary axis, with a value
+    /// that grows to the right.
+    struct HalfPlane;
+
+    impl CpuFractalOpts for HalfPlane {
+        fn iterations(&self) -> u32 {
+            100
+        }
+
+        fn gen_value(&self, loc: Complex<f64>) -> Sample {
+            if loc.re > 0.0 {
+                Sample::new(50.0 + loc.re as f32 * 10.0)
+            } else {
+                Sample::new(100.0)
+            }
+        }
+    }
+
+    #[test]
+    fn pixels_average_their_samples() {
+        // a single pixel straddling the imaginary axis
+        let view = View::new_uniform(1, 1, 1.0, 0.0, 0.0);
+        let offsets = [
+            Vector2::new(0.25, 0.25),
+            Vector2::new(0.75, 0.25),
+            Vector2::new(0.25, 0.75),
+            Vector2::new(0.75, 0.75),
+        ];
+        let samples: Vec<_> = offsets
+            .iter()
+            .map(|offset| HalfPlane.gen_pixel_value(view, offset.x as f64, offset.y as f64))
+            .collect();
+        let escaped: Vec<_> = samples
+            .iter()
+            .filter(|sample| sample.value < 100.0)
+            .map(|sample| sample.value)
+            .collect();
+        assert_eq!(escaped.len(), 2);
+
+        let value = HalfPlane.gen_pixel(view, 0, 0, &offsets);
+        assert_eq!(value.value, (escaped[0] + escaped[1]) / 2.0);
+        assert_eq!(value.coverage, 0.5);
+
+        let mut batch = [PixelValue::default()];
+        HalfPlane.gen_pixel_batch(view, &[(0, 0)], &offsets, &mut batch);
+        assert_eq!(batch[0], value);
+    }
+}
@@ -0,0 +1,315 @@
+//! This module contains the SIMD kernels of the CPU generator, which iterate
+//! several locations in lockstep using portable SIMD.
+//!
+//! Only the quadratic formulas are vectorized, and only while distances aren't
+//! being estimated, there is no orbit trap or average and the inside of the set
+//! isn't colored. Everything else is iterated one location at a time, like the
+//! scalar kernel does. Every vectorized operation mirrors `gen_value_in` in
+//! the [`opts`](super::opts) module exactly, so all kernels generate the same
+//! values.
+
+use crate::generator::{
+    args::{CpuKernel, Formula, InteriorColoring, Precision},
+    cpu::opts::{cast, cast_complex, in_main_bulbs, CpuFractalOpts, CpuSmoothing, Sample},
+    FractalOpts,
+};
+use num_complex::Complex;
+use std::simd::prelude::*;
+
+/// A formula the SIMD kernels can iterate.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum SimdFormula {
+    Square,
+    BurningShip,
+    Tricorn,
+    PerpendicularBurningShip,
+    Celtic,
+}
+
+impl SimdFormula {
+    fn from_formula(formula: &Formula) -> Option<SimdFormula> {
+        match formula {
+            Formula::IntegerPower { exponent: 2 } => Some(SimdFormula::Square),
+            Formula::BurningShip => Some(SimdFormula::BurningShip),
+            Formula::Tricorn => Some(SimdFormula::Tricorn),
+            Formula::PerpendicularBurningShip => Some(SimdFormula::PerpendicularBurningShip),
+            Formula::Celtic => Some(SimdFormula::Celtic),
+            _ => None,
+        }
+    }
+}
+
+/// Generates the sample of each location in `locs` into `values`, like calling
+/// [`CpuFractalOpts::gen_value`] for each of them, using the kernel selected
+/// by `opts`.
+pub(crate) fn gen_values(opts: &FractalOpts, locs: &[Complex<f64>], values: &mut [Sample]) {
+    let formula = match SimdFormula::from_formula(&opts.formula) {
+        Some(formula)
+            if opts.cpu_kernel != CpuKernel::Scalar
+                && !opts.distance_estimation
+                && opts.orbit_trap.is_none()
+                && !opts.averages_orbits()
+                && opts.interior_coloring == InteriorColoring::None =>
+        {
+            formula
+        },
+        _ => {
+            for (loc, value) in locs.iter().zip(values) {
+                *value = opts.gen_value(*loc);
+            }
+            return;
+        },
+    };
+
+    match (opts.cpu_kernel, opts.precision) {
+        (CpuKernel::Simd8, Precision::Single) => {
+            gen_lanes(locs, values, |lanes| iterate_f32x8(opts, formula, lanes))
+        },
+        (CpuKernel::Simd8, Precision::Double) => {
+            gen_lanes(locs, values, |lanes| iterate_f64x8(opts, formula, lanes))
+        },
+        (_, Precision::Single) => {
+            gen_lanes(locs, values, |lanes| iterate_f32x4(opts, formula, lanes))
+        },
+        (_, Precision::Double) => {
+            gen_lanes(locs, values, |lanes| iterate_f64x4(opts, formula, lanes))
+        },
+    }
+}
+
+/// Splits `locs` into groups of `N` lanes to be iterated by `iterate`. The
+/// last group is padded by repeating its last location.
+fn gen_lanes<const N: usize>(
+    locs: &[Complex<f64>],
+    values: &mut [Sample],
+    iterate: impl Fn(&[Complex<f64>; N]) -> [f32; N],
+) {
+    for (locs, values) in locs.chunks(N).zip(values.chunks_mut(N)) {
+        let lanes = std::array::from_fn(|lane| locs[lane.min(locs.len() - 1)]);
+        for (value, lane) in values.iter_mut().zip(iterate(&lanes)) {
+            *value = Sample::new(lane);
+        }
+    }
+}
+
+/// Defines a function iterating a group of locations in lockstep, using
+/// vectors of `$lanes` `$t`s and masks of type `$mask`.
+///
+/// This is a macro instead of a function generic over the lane count, because
+/// the bounds such a function needs differ between nightly versions.
+macro_rules! simd_iterate {
+    ($name:ident, $t:ty, $mask:ty, $lanes:literal) => {
+        fn $name(
+            opts: &FractalOpts,
+            formula: SimdFormula,
+            locs: &[Complex<f64>; $lanes],
+        ) -> [f32; $lanes] {
+            type V = Simd<$t, $lanes>;
+
+            let locs = locs.map(cast_complex::<f64, $t>);
+            let iterations = opts.iterations as f32;
+            let mut values = [iterations; $lanes];
+
+            // lanes that stop being active keep their value
+            let checks_bulbs = opts.checks_bulbs();
+            let mut active =
+                <$mask>::from_array(locs.map(|loc| !(checks_bulbs && in_main_bulbs(loc))));
+
+            // none of the quadratic formulas start at c
+            let loc_re = V::from_array(locs.map(|loc| loc.re));
+            let loc_im = V::from_array(locs.map(|loc| loc.im));
+            let (mut z_re, mut z_im, c_re, c_im) = if opts.mandelbrot {
+                (V::splat(0.0), V::splat(0.0), loc_re, loc_im)
+            } else {
+                let c: Complex<$t> = cast_complex(opts.c);
+                (loc_re, loc_im, V::splat(c.re), V::splat(c.im))
+            };
+            let scalar_radius_squared: $t = cast(opts.radius_squared);
+            let radius_squared = V::splat(scalar_radius_squared);
+
+            let mut z_prev_re = z_re;
+            let mut z_prev_im = z_im;
+
+            let periodicity = opts.checks_periodicity();
+            let tolerance = V::splat(<$t>::EPSILON * <$t>::EPSILON);
+            let mut z_saved_re = z_re;
+            let mut z_saved_im = z_im;
+
+            let mut n = 0;
+            while n < opts.iterations {
+                let escaped = active & (z_re * z_re + z_im * z_im).simd_gt(radius_squared);
+                if escaped.any() {
+                    let (z_re, z_im) = (z_re.to_array(), z_im.to_array());
+                    let (z_prev_re, z_prev_im) = (z_prev_re.to_array(), z_prev_im.to_array());
+                    for (lane, escaped) in escaped.to_array().into_iter().enumerate() {
+                        if escaped {
+                            values[lane] = opts.smoothing.smooth(
+                                n,
+                                Complex::new(z_re[lane], z_im[lane]),
+                                Complex::new(z_prev_re[lane], z_prev_im[lane]),
+                                scalar_radius_squared,
+                            );
+                        }
+                    }
+
+                    active &= !escaped;
+                    if !active.any() {
+                        break;
+                    }
+                }
+
+                z_prev_re = z_re;
+                z_prev_im = z_im;
+
+                let (a_re, a_im) = match formula {
+                    SimdFormula::Square | SimdFormula::Celtic => (z_re, z_im),
+                    SimdFormula::BurningShip => (z_re.abs(), z_im.abs()),
+                    SimdFormula::Tricorn => (z_re, -z_im),
+                    SimdFormula::PerpendicularBurningShip => (z_re, -z_im.abs()),
+                };
+                // a * a, in the same order as `Complex` multiplies
+                let square_re = a_re * a_re - a_im * a_im;
+                let square_im = a_re * a_im + a_im * a_re;
+                z_re = if formula == SimdFormula::Celtic {
+                    square_re.abs() + c_re
+                } else {
+                    square_re + c_re
+                };
+                z_im = square_im + c_im;
+
+                n += 1;
+
+                if periodicity {
+                    let d_re = z_re - z_saved_re;
+                    let d_im = z_im - z_saved_im;
+                    active &= !(d_re * d_re + d_im * d_im).simd_lt(tolerance);
+                    if !active.any() {
+                        break;
+                    }
+
+                    if n & (n - 1) == 0 {
+                        z_saved_re = z_re;
+                        z_saved_im = z_im;
+                    }
+                }
+            }
+
+            values
+        }
+    };
+}
+
+simd_iterate!(iterate_f32x4, f32, mask32x4, 4);
+simd_iterate!(iterate_f32x8, f32, mask32x8, 8);
+simd_iterate!(iterate_f64x4, f64, mask64x4, 4);
+simd_iterate!(iterate_f64x8, f64, mask64x8, 8);
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use super::*;
+    use crate::generator::{
+        args::{InteriorChecks, Smoothing},
+        view::View,
+    };
+    use test::Bencher;
+
+    fn test_opts(formula: Formula, smoothing: Smoothing, precision: Precision) -> FractalOpts {
+        FractalOpts {
+            formula,
+            iterations: 500,
+            smoothing,
+            precision,
+            interior_checks: InteriorChecks::ALL,
+            cpu_kernel: CpuKernel::Scalar,
+            ..FractalOpts::test_base()
+        }
+    }
+
+    /// Gets the locations of every pixel in a view of the whole fractal.
+    fn default_view_locs(size: usize) -> Vec<Complex<f64>> {
+        let view = View::new_centered_uniform(size, size, 3.0);
+        (0..size * size)
+            .map(|index| {
+                view.get_local_subpixel_plane_coordinates((
+                    (index % size) as f64 + 0.5,
+                    (index / size) as f64 + 0.5,
+                ))
+            })
+            .collect()
+    }
+
+    fn gen_with(opts: &FractalOpts, kernel: CpuKernel, locs: &[Complex<f64>]) -> Vec<Sample> {
+        let opts = FractalOpts {
+            cpu_kernel: kernel,
+            ..opts.clone()
+        };
+        let mut values = vec![Sample::new(0.0); locs.len()];
+        gen_values(&opts, locs, &mut values);
+        values
+    }
+
+    #[test]
+    fn simd_kernels_match_scalar_kernel() {
+        // not a multiple of the lane count, so that the last group is padded
+        let locs = default_view_locs(39);
+
+        for formula in [
+            Formula::default(),
+            Formula::BurningShip,
+            Formula::Tricorn,
+            Formula::PerpendicularBurningShip,
+            Formula::Celtic,
+        ] {
+            for smoothing in [
+                Smoothing::None,
+                Smoothing::LinearIntersection,
+                Smoothing::from_logarithmic_distance(2.0, 2.0),
+            ] {
+                for precision in [Precision::Single, Precision::Double] {
+                    let mut opts = test_opts(formula.clone(), smoothing, precision);
+                    let scalar = gen_with(&opts, CpuKernel::Scalar, &locs);
+                    assert_eq!(gen_with(&opts, CpuKernel::Simd4, &locs), scalar);
+                    assert_eq!(gen_with(&opts, CpuKernel::Simd8, &locs), scalar);
+
+                    opts.mandelbrot = false;
+                    opts.c = Complex::new(-0.8, 0.156);
+                    opts.interior_checks = InteriorChecks::NONE;
+                    let scalar = gen_with(&opts, CpuKernel::Scalar, &locs);
+                    assert_eq!(gen_with(&opts, CpuKernel::Simd4, &locs), scalar);
+                    assert_eq!(gen_with(&opts, CpuKernel::Simd8, &locs), scalar);
+                }
+            }
+        }
+    }
+
+    fn bench_kernel(b: &mut Bencher, kernel: CpuKernel) {
+        // without interior checks, the iteration itself dominates
+        let opts = FractalOpts {
+            interior_checks: InteriorChecks::NONE,
+            ..test_opts(
+                Formula::default(),
+                Smoothing::from_logarithmic_distance(2.0, 2.0),
+                Precision::Single,
+            )
+        };
+        let locs = default_view_locs(128);
+        b.iter(|| gen_with(&opts, kernel, &locs));
+    }
+
+    #[bench]
+    fn default_view_scalar(b: &mut Bencher) {
+        bench_kernel(b, CpuKernel::Scalar);
+    }
+
+    #[bench]
+    fn default_view_simd4(b: &mut Bencher) {
+        bench_kernel(b, CpuKernel::Simd4);
+    }
+
+    #[bench]
+    fn default_view_simd8(b: &mut Bencher) {
+        bench_kernel(b, CpuKernel::Simd8);
+    }
+}
@@ -0,0 +1,268 @@
+//! This module contains histogram equalization, which spreads the values of a
+//! generated fractal evenly over the palette by mapping each value through the
+//! distribution of all the values of its [`View`], instead of using a fixed
+//! palette density.
+//!
+//! The distribution is collected by a [`ValueDistribution`] as the blocks of
+//! the view are generated, and is turned into an [`Equalization`] that the
+//! recolor pass applies before looking values up in the palette. Only a fixed
+//! number of bins is kept, so views of any size can be equalized.
+//!
+//! [`View`]: crate::generator::view::View
+
+use crate::generator::{PixelValue, ValueBlock};
+
+/// How many values an [`Equalization`] samples the distribution at. This must
+/// be a multiple of 4, because the GPU recolor shader packs them into vectors.
+pub const EQUALIZATION_QUANTILES: usize = 256;
+
+/// Equalized values are spread between 0 and this, which is a single
+/// repetition of a palette with the default density.
+pub const EQUALIZED_VALUE_RANGE: f32 = 64.0;
+
+/// How many bins a [`ValueDistribution`] counts values in.
+pub const DISTRIBUTION_BINS: usize = 1 << 16;
+
+/// Counts the values of the escaped pixels of a view, one block at a time.
+///
+/// The bins are spaced logarithmically between 0 and the distribution's
+/// maximum value, so that the crowded low values are told apart as finely as
+/// the spread out high ones. Values outside of that range are counted in the
+/// first or last bin.
+#[derive(Debug, Clone)]
+pub struct ValueDistribution {
+    /// `ln(1 + max_value)`, which the last bin ends at.
+    log_range: f64,
+    bins: Box<[u64]>,
+    count: u64,
+    min: f32,
+    max: f32,
+}
+
+impl ValueDistribution {
+    /// Creates an empty distribution whose bins cover the values from 0 to
+    /// `max_value`, which is usually the iteration count of the fractal.
+    pub fn new(max_value: f32) -> ValueDistribution {
+        ValueDistribution {
+            log_range: (max_value.max(1.0) as f64).ln_1p(),
+            bins: vec![0; DISTRIBUTION_BINS].into_boxed_slice(),
+            count: 0,
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+        }
+    }
+
+    /// Adds the values of every pixel that at least partially escaped.
+    /// Interior values are colored by their own palette, so they are left out.
+    pub fn add(&mut self, values: &[PixelValue]) {
+        for value in values {
+            if value.coverage > 0.0 && value.value.is_finite() {
+                let bin = self.bin_position(value.value) as usize;
+                self.bins[bin.min(DISTRIBUTION_BINS - 1)] += 1;
+                self.count += 1;
+                self.min = self.min.min(value.value);
+                self.max = self.max.max(value.value);
+            }
+        }
+    }
+
+    /// Adds the values of a block.
+    pub fn add_block(&mut self, block: &ValueBlock) {
+        self.add(&block.values);
+    }
+
+    /// Gets the equalization of the collected values, or `None` if no pixel
+    /// escaped.
+    ///
+    /// The quantiles are interpolated between the edges of the bins they fall
+    /// in, as if the values in each bin were spread evenly across it.
+    pub fn equalization(&self) -> Option<Equalization> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let last = (self.count - 1) as f64;
+        let mut bin = 0;
+        // the number of values in the bins before `bin`
+        let mut before = 0;
+        let mut quantiles: Vec<_> = (0..EQUALIZATION_QUANTILES)
+            .map(|i| {
+                let rank = i as f64 * last / (EQUALIZATION_QUANTILES - 1) as f64;
+                while (before + self.bins[bin]) as f64 <= rank {
+                    before += self.bins[bin];
+                    bin += 1;
+                }
+
+                let fraction = (rank - before as f64 + 0.5) / self.bins[bin] as f64;
+                (self.bin_value(bin as f64 + fraction) as f32).clamp(self.min, self.max)
+            })
+            .collect();
+
+        // the ends are known exactly
+        quantiles[0] = self.min;
+        quantiles[EQUALIZATION_QUANTILES - 1] = self.max;
+
+        Some(Equalization { quantiles })
+    }
+
+    /// Gets the position of a value in the bins, which is its bin's index
+    /// plus how far through the bin it is.
+    fn bin_position(&self, value: f32) -> f64 {
+        (value.max(0.0) as f64).ln_1p() / self.log_range * DISTRIBUTION_BINS as f64
+    }
+
+    /// Gets the value at a position in the bins.
+    fn bin_value(&self, position: f64) -> f64 {
+        (position / DISTRIBUTION_BINS as f64 * self.log_range).exp_m1()
+    }
+}
+
+/// Maps values through the cumulative distribution of the values of a view,
+/// so that each part of the palette gets about the same number of pixels.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Equalization {
+    /// The values at evenly spaced fractions of the distribution, from the
+    /// smallest value to the largest one.
+    quantiles: Vec<f32>,
+}
+
+impl Equalization {
+    /// Gets the values at evenly spaced fractions of the distribution. There
+    /// are always [`EQUALIZATION_QUANTILES`] of them.
+    pub fn quantiles(&self) -> &[f32] {
+        &self.quantiles
+    }
+
+    /// Gets the equalized value of `value`, between 0 and
+    /// [`EQUALIZED_VALUE_RANGE`], interpolating between the quantiles it falls
+    /// between.
+    ///
+    /// This is mirrored by `equalize` in
+    /// `recolor_fragment_shader.wgsl.liquid`.
+    pub fn equalize(&self, value: f32) -> f32 {
+        let quantiles = &self.quantiles;
+        let last = quantiles.len() - 1;
+        if value <= quantiles[0] {
+            return 0.0;
+        }
+        if value >= quantiles[last] {
+            return EQUALIZED_VALUE_RANGE;
+        }
+
+        // the last quantile that is at most the value, which is never the last
+        let low = quantiles.partition_point(|&quantile| quantile <= value) - 1;
+        let (start, end) = (quantiles[low], quantiles[low + 1]);
+        let fraction = if end > start {
+            (value - start) / (end - start)
+        } else {
+            0.0
+        };
+
+        (low as f32 + fraction) / last as f32 * EQUALIZED_VALUE_RANGE
+    }
+
+    /// Equalizes the escaped values of a block in place.
+    pub fn apply(&self, block: &mut ValueBlock) {
+        for value in block.values.iter_mut() {
+            if value.coverage > 0.0 {
+                value.value = self.equalize(value.value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::NO_INTERIOR;
+
+    fn escaped(value: f32) -> PixelValue {
+        PixelValue {
+            value,
+            coverage: 1.0,
+            distance: 0.0,
+            interior: NO_INTERIOR,
+        }
+    }
+
+    #[test]
+    fn equalization_spreads_values_evenly() {
+        // most of the values are crowded at the low end
+        let values: Vec<_> = (0..1000)
+            .map(|i| escaped((i as f32 / 1000.0).powi(4) * 500.0))
+            .chain([PixelValue::default(); 100])
+            .collect();
+        let mut distribution = ValueDistribution::new(500.0);
+        distribution.add(&values);
+        let equalization = distribution.equalization().unwrap();
+        assert_eq!(equalization.quantiles().len(), EQUALIZATION_QUANTILES);
+
+        // the median value ends up in the middle of the range, ignoring the
+        // pixels inside the set
+        let median = (0.5f32).powi(4) * 500.0;
+        let equalized = equalization.equalize(median);
+        assert!(
+            (equalized - EQUALIZED_VALUE_RANGE / 2.0).abs() < 0.5,
+            "{}",
+            equalized
+        );
+        assert_eq!(equalization.equalize(-1.0), 0.0);
+        assert_eq!(equalization.equalize(1000.0), EQUALIZED_VALUE_RANGE);
+
+        // and the mapping keeps the order of the values
+        let mut previous = 0.0;
+        for value in values.iter().take(1000) {
+            let equalized = equalization.equalize(value.value);
+            assert!(equalized >= previous);
+            previous = equalized;
+        }
+    }
+
+    #[test]
+    fn equalization_of_nothing() {
+        let mut distribution = ValueDistribution::new(200.0);
+        distribution.add(&[PixelValue::default(); 4]);
+        assert_eq!(distribution.equalization(), None);
+
+        // a single value doesn't divide by zero
+        let mut distribution = ValueDistribution::new(200.0);
+        distribution.add(&[escaped(3.0); 4]);
+        let equalization = distribution.equalization().unwrap();
+        assert_eq!(equalization.equalize(3.0), 0.0);
+        assert_eq!(equalization.equalize(4.0), EQUALIZED_VALUE_RANGE);
+    }
+
+    #[test]
+    fn binned_equalization_matches_exact_quantiles() {
+        // crowded low values with a long tail
+        let values: Vec<_> = (0..100_000)
+            .map(|i| {
+                let fraction = i as f32 / 100_000.0;
+                escaped(fraction.powi(4) * 1000.0 + (i % 7) as f32 * 0.01)
+            })
+            .collect();
+        let mut distribution = ValueDistribution::new(1000.0);
+        for block in values.chunks(4096) {
+            distribution.add(block);
+        }
+        let binned = distribution.equalization().unwrap();
+
+        // the quantiles of every value, sorted
+        let mut sorted: Vec<_> = values.iter().map(|value| value.value).collect();
+        sorted.sort_unstable_by(f32::total_cmp);
+        let last = sorted.len() - 1;
+        let exact = Equalization {
+            quantiles: (0..EQUALIZATION_QUANTILES)
+                .map(|i| {
+                    sorted[(i * last + (EQUALIZATION_QUANTILES - 1) / 2)
+                        / (EQUALIZATION_QUANTILES - 1)]
+                })
+                .collect(),
+        };
+
+        for value in values.iter().step_by(37) {
+            let difference = (binned.equalize(value.value) - exact.equalize(value.value)).abs();
+            assert!(difference < 0.05, "{}: {}", value.value, difference);
+        }
+    }
+}
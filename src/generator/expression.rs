@@ -0,0 +1,668 @@
+//! This module contains user-defined formula expressions, like `z^3 - 0.5*z +
+//! c` or `sin(z)*c`.
+//!
+//! Expressions are parsed into an untyped syntax tree, which is then checked
+//! and resolved into a tree of [`Node`]s. The CPU generator interprets these
+//! nodes directly while the GPU generator emits them as WGSL.
+
+use num_complex::Complex32;
+use std::{
+    cmp::Ordering,
+    f32::consts::{E, PI},
+    fmt::{Display, Formatter},
+    iter::Peekable,
+    str::{CharIndices, FromStr},
+    sync::Arc,
+};
+
+/// A parsed and checked formula expression.
+///
+/// Expressions are serialized as their source text and compare equal when
+/// their source text is equal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Expression {
+    source: String,
+    root: Arc<Node>,
+}
+
+/// A checked expression node. Every node evaluates to a complex number.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    /// The current value of `z`.
+    Z,
+    /// The value of `c`.
+    C,
+    Constant(Complex32),
+    Negate(Box<Node>),
+    Add(Box<Node>, Box<Node>),
+    Subtract(Box<Node>, Box<Node>),
+    Multiply(Box<Node>, Box<Node>),
+    Divide(Box<Node>, Box<Node>),
+    /// A power with a constant integer exponent.
+    IntegerPower(Box<Node>, i32),
+    /// A power with a constant real exponent, using the principal branch.
+    RealPower(Box<Node>, f32),
+    /// A power with an arbitrary complex exponent, using the principal branch.
+    ComplexPower(Box<Node>, Box<Node>),
+    Function(Function, Box<Node>),
+}
+
+/// The functions that can be called from an expression.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, IntoStaticStr)]
+#[strum(serialize_all = "lowercase")]
+pub enum Function {
+    Sin,
+    Cos,
+    Tan,
+    Sinh,
+    Cosh,
+    Tanh,
+    Exp,
+    /// The natural logarithm, using the principal branch.
+    Ln,
+    /// The principal square root.
+    Sqrt,
+    /// The absolute value, as a complex number with no imaginary part.
+    Abs,
+    Conj,
+    /// The real part, as a complex number with no imaginary part.
+    Re,
+    /// The imaginary part, as a complex number with no imaginary part.
+    Im,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Function> {
+        match name {
+            "sin" => Some(Function::Sin),
+            "cos" => Some(Function::Cos),
+            "tan" => Some(Function::Tan),
+            "sinh" => Some(Function::Sinh),
+            "cosh" => Some(Function::Cosh),
+            "tanh" => Some(Function::Tanh),
+            "exp" => Some(Function::Exp),
+            "ln" | "log" => Some(Function::Ln),
+            "sqrt" => Some(Function::Sqrt),
+            "abs" => Some(Function::Abs),
+            "conj" => Some(Function::Conj),
+            "re" | "real" => Some(Function::Re),
+            "im" | "imag" => Some(Function::Im),
+            _ => None,
+        }
+    }
+
+    /// Gets the name of this function as written in expressions.
+    pub fn name(&self) -> &'static str {
+        self.into()
+    }
+}
+
+impl Expression {
+    /// Gets the source text this expression was parsed from.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Gets the root node of this expression.
+    pub fn root(&self) -> &Node {
+        &self.root
+    }
+
+    /// Estimates the degree of this expression in `z`, for choosing smoothing
+    /// and starting values. Returns `None` if the expression is not
+    /// polynomial-like in `z`, for example if it uses transcendental functions
+    /// of `z`.
+    pub fn degree(&self) -> Option<f32> {
+        self.root.degree()
+    }
+}
+
+impl Node {
+    fn degree(&self) -> Option<f32> {
+        match self {
+            Node::Z => Some(1.0),
+            Node::C | Node::Constant(_) => Some(0.0),
+            Node::Negate(a) => a.degree(),
+            // the term with the largest magnitude degree dominates, whether near
+            // infinity or near zero
+            Node::Add(a, b) | Node::Subtract(a, b) => {
+                let (a, b) = (a.degree()?, b.degree()?);
+                Some(if a.abs() >= b.abs() { a } else { b })
+            },
+            Node::Multiply(a, b) => Some(a.degree()? + b.degree()?),
+            Node::Divide(a, b) => Some(a.degree()? - b.degree()?),
+            Node::IntegerPower(a, e) => Some(a.degree()? * *e as f32),
+            Node::RealPower(a, e) => Some(a.degree()? * *e),
+            // anything else is only polynomial-like if it doesn't depend on z
+            Node::ComplexPower(..) | Node::Function(..) => self.is_constant().then_some(0.0),
+        }
+    }
+
+    /// Whether this node does not depend on `z`.
+    fn is_constant(&self) -> bool {
+        match self {
+            Node::Z => false,
+            Node::C | Node::Constant(_) => true,
+            Node::Negate(a)
+            | Node::IntegerPower(a, _)
+            | Node::RealPower(a, _)
+            | Node::Function(_, a) => a.is_constant(),
+            Node::Add(a, b)
+            | Node::Subtract(a, b)
+            | Node::Multiply(a, b)
+            | Node::Divide(a, b)
+            | Node::ComplexPower(a, b) => a.is_constant() && b.is_constant(),
+        }
+    }
+}
+
+impl PartialEq for Expression {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+
+impl PartialOrd for Expression {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.source.partial_cmp(&other.source)
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl FromStr for Expression {
+    type Err = ExpressionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser::new(s);
+        let ast = parser.parse_sum()?;
+        parser.expect_end()?;
+
+        Ok(Expression {
+            source: s.trim().to_string(),
+            root: Arc::new(ast.check()?),
+        })
+    }
+}
+
+impl TryFrom<String> for Expression {
+    type Error = ExpressionError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Expression> for String {
+    fn from(value: Expression) -> Self {
+        value.source
+    }
+}
+
+/// Returned if an expression could not be parsed or checked.
+///
+/// Positions are byte offsets into the expression's source text.
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ExpressionError {
+    #[error("unexpected character '{character}' at {position}")]
+    UnexpectedCharacter { position: usize, character: char },
+    #[error("unexpected '{found}' at {position}")]
+    UnexpectedToken { position: usize, found: String },
+    #[error("unexpected end of expression")]
+    UnexpectedEnd,
+    #[error("invalid number '{number}' at {position}")]
+    InvalidNumber { position: usize, number: String },
+    #[error("unknown variable '{name}' at {position}")]
+    UnknownVariable { position: usize, name: String },
+    #[error("unknown function '{name}' at {position}")]
+    UnknownFunction { position: usize, name: String },
+    #[error("function '{name}' at {position} takes 1 argument but {found} were given")]
+    WrongArgumentCount {
+        position: usize,
+        name: String,
+        found: usize,
+    },
+}
+
+//
+// Parsing
+//
+
+/// The untyped syntax tree produced by the parser, before names have been
+/// resolved.
+#[derive(Debug, Clone, PartialEq)]
+enum Ast {
+    Number(f32),
+    Variable {
+        position: usize,
+        name: String,
+    },
+    Call {
+        position: usize,
+        name: String,
+        args: Vec<Ast>,
+    },
+    Negate(Box<Ast>),
+    Binary(BinaryOp, Box<Ast>, Box<Ast>),
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Power,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(String),
+    Identifier(String),
+    Symbol(char),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(s) | Token::Identifier(s) => f.write_str(s),
+            Token::Symbol(c) => write!(f, "{}", c),
+        }
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    chars: Peekable<CharIndices<'a>>,
+    peeked: Option<(usize, Token)>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Parser<'a> {
+        Parser {
+            source,
+            chars: source.char_indices().peekable(),
+            peeked: None,
+        }
+    }
+
+    fn read_token(&mut self) -> Result<Option<(usize, Token)>, ExpressionError> {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+
+        let (start, first) = match self.chars.next() {
+            None => return Ok(None),
+            Some(next) => next,
+        };
+
+        let token = if first.is_ascii_digit() || first == '.' {
+            let mut end = start + 1;
+            let mut last = first;
+            while let Some((index, c)) = self.chars.next_if(|&(_, c)| {
+                c.is_ascii_digit()
+                    || c == '.'
+                    || c == 'e'
+                    || c == 'E'
+                    || ((c == '+' || c == '-') && (last == 'e' || last == 'E'))
+            }) {
+                end = index + c.len_utf8();
+                last = c;
+            }
+            Token::Number(self.source[start..end].to_string())
+        } else if first.is_alphabetic() || first == '_' {
+            let mut end = start + first.len_utf8();
+            while let Some((index, c)) = self
+                .chars
+                .next_if(|(_, c)| c.is_alphanumeric() || *c == '_')
+            {
+                end = index + c.len_utf8();
+            }
+            Token::Identifier(self.source[start..end].to_ascii_lowercase())
+        } else if "+-*/^(),".contains(first) {
+            Token::Symbol(first)
+        } else {
+            return Err(ExpressionError::UnexpectedCharacter {
+                position: start,
+                character: first,
+            });
+        };
+
+        Ok(Some((start, token)))
+    }
+
+    fn peek(&mut self) -> Result<Option<&(usize, Token)>, ExpressionError> {
+        if self.peeked.is_none() {
+            self.peeked = self.read_token()?;
+        }
+        Ok(self.peeked.as_ref())
+    }
+
+    fn next(&mut self) -> Result<Option<(usize, Token)>, ExpressionError> {
+        match self.peeked.take() {
+            Some(peeked) => Ok(Some(peeked)),
+            None => self.read_token(),
+        }
+    }
+
+    /// Consumes the next token if it is the given symbol.
+    fn next_if_symbol(&mut self, symbol: char) -> Result<bool, ExpressionError> {
+        if matches!(self.peek()?, Some((_, Token::Symbol(c))) if *c == symbol) {
+            self.peeked = None;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    fn expect_symbol(&mut self, symbol: char) -> Result<(), ExpressionError> {
+        match self.next()? {
+            Some((_, Token::Symbol(c))) if c == symbol => Ok(()),
+            Some((position, token)) => Err(ExpressionError::UnexpectedToken {
+                position,
+                found: token.to_string(),
+            }),
+            None => Err(ExpressionError::UnexpectedEnd),
+        }
+    }
+
+    fn expect_end(&mut self) -> Result<(), ExpressionError> {
+        match self.next()? {
+            Some((position, token)) => Err(ExpressionError::UnexpectedToken {
+                position,
+                found: token.to_string(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// sum := product (('+' | '-') product)*
+    fn parse_sum(&mut self) -> Result<Ast, ExpressionError> {
+        let mut lhs = self.parse_product()?;
+        loop {
+            let op = if self.next_if_symbol('+')? {
+                BinaryOp::Add
+            } else if self.next_if_symbol('-')? {
+                BinaryOp::Subtract
+            } else {
+                return Ok(lhs);
+            };
+            let rhs = self.parse_product()?;
+            lhs = Ast::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    /// product := unary (('*' | '/') unary)*
+    fn parse_product(&mut self) -> Result<Ast, ExpressionError> {
+        let mut lhs = self.parse_unary()?;
+        loop {
+            let op = if self.next_if_symbol('*')? {
+                BinaryOp::Multiply
+            } else if self.next_if_symbol('/')? {
+                BinaryOp::Divide
+            } else {
+                return Ok(lhs);
+            };
+            let rhs = self.parse_unary()?;
+            lhs = Ast::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+    }
+
+    /// unary := ('-' | '+') unary | power
+    fn parse_unary(&mut self) -> Result<Ast, ExpressionError> {
+        if self.next_if_symbol('-')? {
+            Ok(Ast::Negate(Box::new(self.parse_unary()?)))
+        } else if self.next_if_symbol('+')? {
+            self.parse_unary()
+        } else {
+            self.parse_power()
+        }
+    }
+
+    /// power := primary ('^' unary)?
+    ///
+    /// Powers are right-associative and bind tighter than negation on their
+    /// left, so `-z^2` is `-(z^2)` and `z^-2` is `z^(-2)`.
+    fn parse_power(&mut self) -> Result<Ast, ExpressionError> {
+        let base = self.parse_primary()?;
+        if self.next_if_symbol('^')? {
+            let exponent = self.parse_unary()?;
+            Ok(Ast::Binary(
+                BinaryOp::Power,
+                Box::new(base),
+                Box::new(exponent),
+            ))
+        } else {
+            Ok(base)
+        }
+    }
+
+    /// primary := number | identifier | identifier '(' args ')' | '(' sum ')'
+    fn parse_primary(&mut self) -> Result<Ast, ExpressionError> {
+        match self.next()? {
+            Some((position, Token::Number(number))) => match number.parse::<f32>() {
+                Ok(value) if value.is_finite() => Ok(Ast::Number(value)),
+                _ => Err(ExpressionError::InvalidNumber { position, number }),
+            },
+            Some((position, Token::Identifier(name))) => {
+                if self.next_if_symbol('(')? {
+                    let mut args = vec![self.parse_sum()?];
+                    while self.next_if_symbol(',')? {
+                        args.push(self.parse_sum()?);
+                    }
+                    self.expect_symbol(')')?;
+                    Ok(Ast::Call {
+                        position,
+                        name,
+                        args,
+                    })
+                } else {
+                    Ok(Ast::Variable { position, name })
+                }
+            },
+            Some((_, Token::Symbol('('))) => {
+                let inner = self.parse_sum()?;
+                self.expect_symbol(')')?;
+                Ok(inner)
+            },
+            Some((position, token)) => Err(ExpressionError::UnexpectedToken {
+                position,
+                found: token.to_string(),
+            }),
+            None => Err(ExpressionError::UnexpectedEnd),
+        }
+    }
+}
+
+//
+// Checking
+//
+
+impl Ast {
+    /// Resolves the names in this syntax tree and picks the kind of each
+    /// power, producing a checked [`Node`].
+    fn check(self) -> Result<Node, ExpressionError> {
+        Ok(match self {
+            Ast::Number(value) => Node::Constant(Complex32::new(value, 0.0)),
+            Ast::Variable { position, name } => match name.as_str() {
+                "z" => Node::Z,
+                "c" => Node::C,
+                "i" => Node::Constant(Complex32::new(0.0, 1.0)),
+                "pi" => Node::Constant(Complex32::new(PI, 0.0)),
+                "e" => Node::Constant(Complex32::new(E, 0.0)),
+                _ => return Err(ExpressionError::UnknownVariable { position, name }),
+            },
+            Ast::Call {
+                position,
+                name,
+                mut args,
+            } => {
+                let function =
+                    Function::from_name(&name).ok_or_else(|| ExpressionError::UnknownFunction {
+                        position,
+                        name: name.clone(),
+                    })?;
+                if args.len() != 1 {
+                    return Err(ExpressionError::WrongArgumentCount {
+                        position,
+                        name,
+                        found: args.len(),
+                    });
+                }
+                Node::Function(function, Box::new(args.remove(0).check()?))
+            },
+            Ast::Negate(a) => match a.check()? {
+                Node::Constant(value) => Node::Constant(-value),
+                a => Node::Negate(Box::new(a)),
+            },
+            Ast::Binary(op, a, b) => {
+                let a = Box::new(a.check()?);
+                let b = b.check()?;
+                match op {
+                    BinaryOp::Add => Node::Add(a, Box::new(b)),
+                    BinaryOp::Subtract => Node::Subtract(a, Box::new(b)),
+                    BinaryOp::Multiply => Node::Multiply(a, Box::new(b)),
+                    BinaryOp::Divide => Node::Divide(a, Box::new(b)),
+                    BinaryOp::Power => match b {
+                        Node::Constant(e) if e.im == 0.0 => {
+                            if e.re.fract() == 0.0 && e.re.abs() <= i32::MAX as f32 {
+                                Node::IntegerPower(a, e.re as i32)
+                            } else {
+                                Node::RealPower(a, e.re)
+                            }
+                        },
+                        b => Node::ComplexPower(a, Box::new(b)),
+                    },
+                }
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Result<Node, ExpressionError> {
+        s.parse::<Expression>().map(|e| e.root().clone())
+    }
+
+    fn constant(re: f32, im: f32) -> Box<Node> {
+        Box::new(Node::Constant(Complex32::new(re, im)))
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(
+            parse("z^3 - 0.5*z + c").unwrap(),
+            Node::Add(
+                Box::new(Node::Subtract(
+                    Box::new(Node::IntegerPower(Box::new(Node::Z), 3)),
+                    Box::new(Node::Multiply(constant(0.5, 0.0), Box::new(Node::Z))),
+                )),
+                Box::new(Node::C),
+            )
+        );
+        assert_eq!(
+            parse("-z^2").unwrap(),
+            Node::Negate(Box::new(Node::IntegerPower(Box::new(Node::Z), 2)))
+        );
+        assert_eq!(
+            parse("z^-1.5").unwrap(),
+            Node::RealPower(Box::new(Node::Z), -1.5)
+        );
+        assert_eq!(
+            parse("z^2^3").unwrap(),
+            Node::ComplexPower(
+                Box::new(Node::Z),
+                Box::new(Node::IntegerPower(constant(2.0, 0.0), 3))
+            )
+        );
+    }
+
+    #[test]
+    fn functions_and_constants() {
+        assert_eq!(
+            parse("SIN(z) * c").unwrap(),
+            Node::Multiply(
+                Box::new(Node::Function(Function::Sin, Box::new(Node::Z))),
+                Box::new(Node::C),
+            )
+        );
+        assert_eq!(
+            parse("z^i").unwrap(),
+            Node::ComplexPower(Box::new(Node::Z), constant(0.0, 1.0))
+        );
+        assert_eq!(parse("1e-3").unwrap(), *constant(1e-3, 0.0));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse("z +"), Err(ExpressionError::UnexpectedEnd));
+        assert_eq!(
+            parse("z $ c"),
+            Err(ExpressionError::UnexpectedCharacter {
+                position: 2,
+                character: '$'
+            })
+        );
+        assert_eq!(parse("(z + c"), Err(ExpressionError::UnexpectedEnd));
+        assert_eq!(
+            parse("z c"),
+            Err(ExpressionError::UnexpectedToken {
+                position: 2,
+                found: "c".to_string()
+            })
+        );
+        assert_eq!(
+            parse("x^2 + c"),
+            Err(ExpressionError::UnknownVariable {
+                position: 0,
+                name: "x".to_string()
+            })
+        );
+        assert_eq!(
+            parse("foo(z)"),
+            Err(ExpressionError::UnknownFunction {
+                position: 0,
+                name: "foo".to_string()
+            })
+        );
+        assert_eq!(
+            parse("sin(z, c)"),
+            Err(ExpressionError::WrongArgumentCount {
+                position: 0,
+                name: "sin".to_string(),
+                found: 2
+            })
+        );
+        assert!(matches!(
+            parse("1e99"),
+            Err(ExpressionError::InvalidNumber { .. })
+        ));
+    }
+
+    #[test]
+    fn degree() {
+        let degree = |s: &str| s.parse::<Expression>().unwrap().degree();
+        assert_eq!(degree("z^3 - 0.5*z + c"), Some(3.0));
+        assert_eq!(degree("z*z*c + 1"), Some(2.0));
+        assert_eq!(degree("1/z^2 + c"), Some(-2.0));
+        assert_eq!(degree("sin(c) * z^2"), Some(2.0));
+        assert_eq!(degree("sin(z)*c"), None);
+        assert_eq!(degree("z^c"), None);
+    }
+
+    #[test]
+    fn serde_round_trip() {
+        let expression: Expression = "z^3 - 0.5*z + c".parse().unwrap();
+        let str = ron::to_string(&expression).unwrap();
+        assert_eq!(str, "\"z^3 - 0.5*z + c\"");
+        let loaded: Expression = ron::from_str(&str).unwrap();
+        assert_eq!(loaded.root(), expression.root());
+        assert!(ron::from_str::<Expression>("\"z +\"").is_err());
+    }
+}
@@ -1,13 +1,15 @@
 use crate::{
     generator::{
+        args::Precision,
         gpu::{
-            shader::load_shaders,
+            shader::{load_shaders, opts::GpuFormula},
             uniforms::{GpuView, Uniforms},
         },
-        util::{copy_region, smallest_multiple_containing},
+        trap::OrbitTrap,
+        util::smallest_multiple_containing,
         view::View,
         FractalGenerator, FractalGeneratorFactory, FractalGeneratorInstance, FractalOpts,
-        PixelBlock, BYTES_PER_PIXEL,
+        PixelValue, ValueBlock, BYTES_PER_VALUE,
     },
     gpu::{
         buffer::{BufferWrapper, Encodable},
@@ -17,6 +19,7 @@ use crate::{
     util::{display_duration, result::ResultExt, running_guard::RunningGuard},
 };
 use anyhow::Context;
+use bytemuck::cast_slice;
 use chrono::{DateTime, Utc};
 use futures::{
     future::{ready, BoxFuture},
@@ -24,6 +27,7 @@ use futures::{
 };
 use std::{
     collections::HashMap,
+    mem::size_of,
     num::NonZeroU64,
     sync::{
         atomic::{AtomicBool, AtomicUsize, Ordering},
@@ -33,16 +37,19 @@ use std::{
 use tokio::sync::mpsc::Sender;
 use wgpu::{
     BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
-    BindGroupLayoutEntry, BindingResource, BindingType, BlendState, Buffer, BufferAddress,
-    BufferBinding, BufferBindingType, BufferUsages, Color, ColorTargetState, ColorWrites,
-    CommandBuffer, CommandEncoder, CommandEncoderDescriptor, Device, Extent3d, Face, FragmentState,
-    FrontFace, ImageCopyBuffer, ImageCopyTexture, ImageDataLayout, LoadOp, MapMode,
-    MultisampleState, Operations, Origin3d, PipelineLayout, PipelineLayoutDescriptor, PolygonMode,
-    PrimitiveState, PrimitiveTopology, RenderPassColorAttachment, RenderPassDescriptor,
-    RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor, ShaderStages, StoreOp,
-    Texture, TextureAspect, TextureFormat, TextureUsages, TextureView, VertexState,
+    BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferAddress, BufferBinding,
+    BufferBindingType, BufferUsages, Color, ColorTargetState, ColorWrites, CommandBuffer,
+    CommandEncoder, CommandEncoderDescriptor, Device, Extent3d, Face, FragmentState, FrontFace,
+    ImageCopyBuffer, ImageCopyTexture, ImageDataLayout, LoadOp, MapMode, MultisampleState,
+    Operations, Origin3d, PipelineLayout, PipelineLayoutDescriptor, PolygonMode, PrimitiveState,
+    PrimitiveTopology, RenderPassColorAttachment, RenderPassDescriptor, RenderPipeline,
+    RenderPipelineDescriptor, ShaderModule, ShaderModuleDescriptor, ShaderStages, StoreOp, Texture,
+    TextureAspect, TextureFormat, TextureSampleType, TextureUsages, TextureView,
+    TextureViewDimension, VertexState,
 };
 
+pub mod readback;
+pub mod recolor;
 mod shader;
 mod uniforms;
 
@@ -50,6 +57,8 @@ pub struct GpuFractalGeneratorFactory {
     gpu: GPUContext,
     uniform_bind_group_layout: Arc<BindGroupLayout>,
     render_pipeline_layout: Arc<PipelineLayout>,
+    texture_bind_group_layout: Arc<BindGroupLayout>,
+    adaptive_pipeline_layout: Arc<PipelineLayout>,
 }
 
 impl GpuFractalGeneratorFactory {
@@ -58,16 +67,31 @@ impl GpuFractalGeneratorFactory {
         let uniform_bind_group_layout = Arc::new(gpu.device.create_bind_group_layout(
             &BindGroupLayoutDescriptor {
                 label: Some("Uniform Bind Group Layout"),
-                entries: &[BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::FRAGMENT,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: Some(NonZeroU64::new(Uniforms::size() as u64).unwrap()),
+                entries: &[
+                    BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Buffer {
+                            ty: BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: Some(
+                                NonZeroU64::new(Uniforms::size() as u64).unwrap(),
+                            ),
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    // the orbit trap image
+                    BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: ShaderStages::FRAGMENT,
+                        ty: BindingType::Texture {
+                            sample_type: TextureSampleType::Float { filterable: false },
+                            view_dimension: TextureViewDimension::D2,
+                            multisampled: false,
+                        },
+                        count: None,
+                    },
+                ],
             },
         ));
 
@@ -80,10 +104,36 @@ impl GpuFractalGeneratorFactory {
             },
         ));
 
+        info!("Creating adaptive multisampling pipeline layout...");
+        let texture_bind_group_layout = Arc::new(gpu.device.create_bind_group_layout(
+            &BindGroupLayoutDescriptor {
+                label: Some("Texture Bind Group Layout"),
+                entries: &[BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                }],
+            },
+        ));
+        let adaptive_pipeline_layout = Arc::new(gpu.device.create_pipeline_layout(
+            &PipelineLayoutDescriptor {
+                label: Some("Adaptive Multisampling Pipeline Layout"),
+                bind_group_layouts: &[&uniform_bind_group_layout, &texture_bind_group_layout],
+                push_constant_ranges: &[],
+            },
+        ));
+
         GpuFractalGeneratorFactory {
             gpu,
             uniform_bind_group_layout,
             render_pipeline_layout,
+            texture_bind_group_layout,
+            adaptive_pipeline_layout,
         }
     }
 }
@@ -96,6 +146,8 @@ impl FractalGeneratorFactory for GpuFractalGeneratorFactory {
         let gpu = self.gpu.clone();
         let uniform_bind_group_layout = self.uniform_bind_group_layout.clone();
         let render_pipeline_layout = self.render_pipeline_layout.clone();
+        let texture_bind_group_layout = self.texture_bind_group_layout.clone();
+        let adaptive_pipeline_layout = self.adaptive_pipeline_layout.clone();
 
         async move {
             let boxed: Box<dyn FractalGenerator + Send> = Box::new(
@@ -104,6 +156,8 @@ impl FractalGeneratorFactory for GpuFractalGeneratorFactory {
                     gpu,
                     uniform_bind_group_layout,
                     render_pipeline_layout,
+                    texture_bind_group_layout,
+                    adaptive_pipeline_layout,
                 )
                 .await?,
             );
@@ -111,13 +165,19 @@ impl FractalGeneratorFactory for GpuFractalGeneratorFactory {
         }
         .boxed()
     }
+
+    fn supports_precision(&self, opts: &FractalOpts) -> bool {
+        opts.precision == Precision::Single || opts.formula.supports_double()
+    }
 }
 
 pub struct GpuFractalGenerator {
     opts: FractalOpts,
     gpu: GPUContext,
     uniform_bind_group_layout: Arc<BindGroupLayout>,
+    trap_image: Arc<TrapImageTexture>,
     render_pipeline: Arc<RenderPipeline>,
+    adaptive: Option<Arc<AdaptivePasses>>,
 }
 
 impl GpuFractalGenerator {
@@ -126,9 +186,13 @@ impl GpuFractalGenerator {
         gpu: GPUContext,
         uniform_bind_group_layout: Arc<BindGroupLayout>,
         render_pipeline_layout: Arc<PipelineLayout>,
+        texture_bind_group_layout: Arc<BindGroupLayout>,
+        adaptive_pipeline_layout: Arc<PipelineLayout>,
     ) -> anyhow::Result<GpuFractalGenerator> {
         info!("Creating shader modules...");
-        let shaders = load_shaders(opts).await.context("Error loading shaders")?;
+        let shaders = load_shaders(opts.clone())
+            .await
+            .context("Error loading shaders")?;
         let frag_module = gpu.device.create_shader_module(ShaderModuleDescriptor {
             label: Some("Fragment Shader"),
             source: shaders.fragment,
@@ -140,48 +204,53 @@ impl GpuFractalGenerator {
         });
 
         info!("Creating render pipeline...");
-        let render_pipeline = Arc::new(gpu.device.create_render_pipeline(
-            &RenderPipelineDescriptor {
-                label: Some("Render Pipeline"),
-                layout: Some(&render_pipeline_layout),
-                vertex: VertexState {
-                    module: &vert_module,
-                    entry_point: "vert_main",
-                    buffers: &[],
-                },
-                fragment: Some(FragmentState {
-                    module: &frag_module,
-                    entry_point: "frag_main",
-                    targets: &[Some(ColorTargetState {
-                        format: TextureFormat::Rgba8Unorm,
-                        blend: Some(BlendState::REPLACE),
-                        write_mask: ColorWrites::ALL,
-                    })],
-                }),
-                primitive: PrimitiveState {
-                    topology: PrimitiveTopology::TriangleList,
-                    strip_index_format: None,
-                    front_face: FrontFace::Ccw,
-                    cull_mode: Some(Face::Back),
-                    polygon_mode: PolygonMode::Fill,
-                    conservative: false,
-                    unclipped_depth: false,
-                },
-                depth_stencil: None,
-                multisample: MultisampleState {
-                    count: 1,
-                    mask: !0,
-                    alpha_to_coverage_enabled: false,
-                },
-                multiview: None,
-            },
+        let render_pipeline = Arc::new(create_render_pipeline(
+            &gpu.device,
+            "Render Pipeline",
+            &render_pipeline_layout,
+            &vert_module,
+            &frag_module,
+            "frag_main",
+            TextureFormat::Rgba32Float,
         ));
 
+        let adaptive = if opts.multisampling.adaptive_threshold().is_some() {
+            info!("Creating adaptive multisampling pipelines...");
+            Some(Arc::new(AdaptivePasses {
+                texture_bind_group_layout,
+                mask_pipeline: create_render_pipeline(
+                    &gpu.device,
+                    "Mask Pipeline",
+                    &adaptive_pipeline_layout,
+                    &vert_module,
+                    &frag_module,
+                    "mask_main",
+                    MASK_TEXTURE_FORMAT,
+                ),
+                refine_pipeline: create_render_pipeline(
+                    &gpu.device,
+                    "Refine Pipeline",
+                    &adaptive_pipeline_layout,
+                    &vert_module,
+                    &frag_module,
+                    "refine_main",
+                    TextureFormat::Rgba32Float,
+                ),
+            }))
+        } else {
+            None
+        };
+
+        info!("Creating orbit trap image texture...");
+        let trap_image = Arc::new(TrapImageTexture::new(&gpu, &opts));
+
         Ok(GpuFractalGenerator {
             opts,
             gpu,
             uniform_bind_group_layout,
+            trap_image,
             render_pipeline,
+            adaptive,
         })
     }
 }
@@ -194,14 +263,16 @@ impl FractalGenerator for GpuFractalGenerator {
     fn start_generation_to_cpu(
         &self,
         views: &[View],
-        sender: Sender<anyhow::Result<PixelBlock>>,
+        sender: Sender<anyhow::Result<ValueBlock>>,
     ) -> BoxFuture<'static, anyhow::Result<Box<dyn FractalGeneratorInstance + Send + 'static>>>
     {
         // This future must be 'static so we need to copy everything or use Arcs.
-        let opts = self.opts;
+        let opts = self.opts.clone();
         let gpu = self.gpu.clone();
         let uniform_bind_group_layout = self.uniform_bind_group_layout.clone();
+        let trap_image = self.trap_image.clone();
         let render_pipeline = self.render_pipeline.clone();
+        let adaptive = self.adaptive.clone();
         let views = views.to_vec();
 
         async move {
@@ -210,7 +281,9 @@ impl FractalGenerator for GpuFractalGenerator {
                     opts,
                     gpu,
                     uniform_bind_group_layout,
+                    trap_image,
                     render_pipeline,
+                    adaptive,
                     views,
                     sender,
                 ));
@@ -233,10 +306,12 @@ impl FractalGenerator for GpuFractalGenerator {
             "To-GPU GPUContext.ty must be GPUContextType::Presentable (this is a bug)"
         );
 
-        let opts = self.opts;
+        let opts = self.opts.clone();
         let gpu = self.gpu.clone();
         let uniform_bind_group_layout = self.uniform_bind_group_layout.clone();
+        let trap_image = self.trap_image.clone();
         let render_pipeline = self.render_pipeline.clone();
+        let adaptive = self.adaptive.clone();
         let views = views.to_vec();
 
         async move {
@@ -246,7 +321,9 @@ impl FractalGenerator for GpuFractalGenerator {
                     gpu,
                     present,
                     uniform_bind_group_layout,
+                    trap_image,
                     render_pipeline,
+                    adaptive,
                     views,
                     texture,
                 ));
@@ -268,9 +345,11 @@ impl GpuFractalGeneratorInstance {
         _opts: FractalOpts,
         gpu: GPUContext,
         uniform_bind_group_layout: Arc<BindGroupLayout>,
+        trap_image: Arc<TrapImageTexture>,
         render_pipeline: Arc<RenderPipeline>,
+        adaptive: Option<Arc<AdaptivePasses>>,
         views: Vec<View>,
-        sender: Sender<anyhow::Result<PixelBlock>>,
+        sender: Sender<anyhow::Result<ValueBlock>>,
     ) -> GpuFractalGeneratorInstance {
         let start_time = Utc::now();
         let view_count = views.len();
@@ -282,7 +361,7 @@ impl GpuFractalGeneratorInstance {
         let spawn_canceled = canceled.clone();
 
         let (mut uniforms_buffer, uniform_bind_group) =
-            setup_uniforms(&gpu.device, &uniform_bind_group_layout);
+            setup_uniforms(&gpu.device, &uniform_bind_group_layout, &trap_image);
 
         info!("Spawning gpu manager task...");
 
@@ -291,6 +370,7 @@ impl GpuFractalGeneratorInstance {
             let _running_guard = RunningGuard::new(spawn_running);
 
             let mut buffers = HashMap::new();
+            let mut adaptive_textures = HashMap::new();
 
             for view in views {
                 if spawn_canceled.load(Ordering::Acquire) {
@@ -320,6 +400,16 @@ impl GpuFractalGeneratorInstance {
                         texture_view,
                         &mut encoder,
                     );
+                    if let Some(adaptive) = &adaptive {
+                        adaptive.encode(
+                            &gpu.device,
+                            &mut adaptive_textures,
+                            texture,
+                            texture_view,
+                            &uniform_bind_group,
+                            &mut encoder,
+                        );
+                    }
 
                     encoder.copy_texture_to_buffer(
                         ImageCopyTexture {
@@ -332,7 +422,7 @@ impl GpuFractalGeneratorInstance {
                             buffer,
                             layout: ImageDataLayout {
                                 offset: 0,
-                                bytes_per_row: Some(BYTES_PER_PIXEL as u32 * texture_width),
+                                bytes_per_row: Some(BYTES_PER_VALUE as u32 * texture_width),
                                 rows_per_image: Some(texture_height),
                             },
                         },
@@ -350,8 +440,7 @@ impl GpuFractalGeneratorInstance {
                     gpu.queue.submit([uniforms_cb, encoder.finish()]);
                 }
 
-                let mut image_data =
-                    vec![0u8; view.image_width * view.image_height * BYTES_PER_PIXEL];
+                let mut values = Vec::with_capacity(view.image_width * view.image_height);
                 {
                     info!(
                         "Reading framebuffer for ({}, {})...",
@@ -368,19 +457,14 @@ impl GpuFractalGeneratorInstance {
 
                     let data = buffer_slice.get_mapped_range();
 
-                    info!("Copying image for ({}, {})...", view.image_x, view.image_y);
-                    copy_region(
-                        data.as_ref(),
-                        texture_width as usize,
-                        0,
-                        0,
-                        &mut image_data,
-                        view.image_width,
-                        0,
-                        0,
-                        view.image_width,
-                        view.image_height,
-                    );
+                    info!("Copying values for ({}, {})...", view.image_x, view.image_y);
+                    let data: &[PixelValue] = cast_slice(data.as_ref());
+                    for row in data
+                        .chunks_exact(texture_width as usize)
+                        .take(view.image_height)
+                    {
+                        values.extend_from_slice(&row[..view.image_width]);
+                    }
                 }
 
                 info!(
@@ -396,17 +480,17 @@ impl GpuFractalGeneratorInstance {
                 }
 
                 info!(
-                    "Sending pixel block for ({}, {})...",
+                    "Sending value block for ({}, {})...",
                     view.image_x, view.image_y
                 );
                 if let Err(e) = sender
-                    .send(Ok(PixelBlock {
+                    .send(Ok(ValueBlock {
                         view,
-                        image: image_data.into_boxed_slice(),
+                        values: values.into_boxed_slice(),
                     }))
                     .await
                 {
-                    warn!("Unable to send pixel block! Error: {:?}", e);
+                    warn!("Unable to send value block! Error: {:?}", e);
                     return;
                 }
             }
@@ -425,7 +509,9 @@ impl GpuFractalGeneratorInstance {
         gpu: GPUContext,
         present: GPUContext,
         uniform_bind_group_layout: Arc<BindGroupLayout>,
+        trap_image: Arc<TrapImageTexture>,
         render_pipeline: Arc<RenderPipeline>,
+        adaptive: Option<Arc<AdaptivePasses>>,
         views: Vec<View>,
         out_texture: Arc<Texture>,
     ) -> GpuFractalGeneratorInstance {
@@ -439,7 +525,7 @@ impl GpuFractalGeneratorInstance {
         let spawn_canceled = canceled.clone();
 
         let (uniforms_buffer, uniform_bind_group) =
-            setup_uniforms(&gpu.device, &uniform_bind_group_layout);
+            setup_uniforms(&gpu.device, &uniform_bind_group_layout, &trap_image);
 
         info!("Spawning gpu manager task...");
 
@@ -451,6 +537,7 @@ impl GpuFractalGeneratorInstance {
                 Self::generate_to_same_device(
                     gpu,
                     render_pipeline,
+                    adaptive,
                     views,
                     out_texture,
                     start_time,
@@ -466,6 +553,7 @@ impl GpuFractalGeneratorInstance {
                     gpu,
                     present,
                     render_pipeline,
+                    adaptive,
                     views,
                     out_texture,
                     start_time,
@@ -490,6 +578,7 @@ impl GpuFractalGeneratorInstance {
     async fn generate_to_same_device(
         gpu: GPUContext,
         render_pipeline: Arc<RenderPipeline>,
+        adaptive: Option<Arc<AdaptivePasses>>,
         views: Vec<View>,
         out_texture: Arc<Texture>,
         start_time: DateTime<Utc>,
@@ -500,6 +589,7 @@ impl GpuFractalGeneratorInstance {
         uniform_bind_group: BindGroup,
     ) {
         let mut buffers = HashMap::new();
+        let mut adaptive_textures = HashMap::new();
 
         for view in views {
             if spawn_canceled.load(Ordering::Acquire) {
@@ -528,6 +618,16 @@ impl GpuFractalGeneratorInstance {
                     texture_view,
                     &mut encoder,
                 );
+                if let Some(adaptive) = &adaptive {
+                    adaptive.encode(
+                        &gpu.device,
+                        &mut adaptive_textures,
+                        texture,
+                        texture_view,
+                        &uniform_bind_group,
+                        &mut encoder,
+                    );
+                }
 
                 encoder.copy_texture_to_texture(
                     ImageCopyTexture {
@@ -568,6 +668,7 @@ impl GpuFractalGeneratorInstance {
         gpu: GPUContext,
         present: GPUContext,
         render_pipeline: Arc<RenderPipeline>,
+        adaptive: Option<Arc<AdaptivePasses>>,
         views: Vec<View>,
         out_texture: Arc<Texture>,
         start_time: DateTime<Utc>,
@@ -578,6 +679,7 @@ impl GpuFractalGeneratorInstance {
         uniform_bind_group: BindGroup,
     ) {
         let mut buffers = HashMap::new();
+        let mut adaptive_textures = HashMap::new();
 
         for view in views {
             if spawn_canceled.load(Ordering::Acquire) {
@@ -607,6 +709,16 @@ impl GpuFractalGeneratorInstance {
                     texture_view,
                     &mut encoder,
                 );
+                if let Some(adaptive) = &adaptive {
+                    adaptive.encode(
+                        &gpu.device,
+                        &mut adaptive_textures,
+                        texture,
+                        texture_view,
+                        &uniform_bind_group,
+                        &mut encoder,
+                    );
+                }
 
                 encoder.copy_texture_to_buffer(
                     ImageCopyTexture {
@@ -619,7 +731,7 @@ impl GpuFractalGeneratorInstance {
                         buffer,
                         layout: ImageDataLayout {
                             offset: 0,
-                            bytes_per_row: Some(BYTES_PER_PIXEL as u32 * texture_width),
+                            bytes_per_row: Some(BYTES_PER_VALUE as u32 * texture_width),
                             rows_per_image: Some(texture_height),
                         },
                     },
@@ -667,7 +779,7 @@ impl GpuFractalGeneratorInstance {
                     data.as_ref(),
                     ImageDataLayout {
                         offset: 0,
-                        bytes_per_row: Some((view.image_width * BYTES_PER_PIXEL) as u32),
+                        bytes_per_row: Some((view.image_width * BYTES_PER_VALUE) as u32),
                         rows_per_image: None,
                     },
                     Extent3d {
@@ -692,6 +804,7 @@ impl GpuFractalGeneratorInstance {
 fn setup_uniforms(
     device: &Device,
     uniform_bind_group_layout: &BindGroupLayout,
+    trap_image: &TrapImageTexture,
 ) -> (BufferWrapper<Uniforms>, BindGroup) {
     info!("Creating uniform buffer...");
     let uniforms_buffer = BufferWrapper::<Uniforms>::new(
@@ -703,18 +816,78 @@ fn setup_uniforms(
     let uniform_bind_group = device.create_bind_group(&BindGroupDescriptor {
         label: Some("Uniform Bind Group"),
         layout: uniform_bind_group_layout,
-        entries: &[BindGroupEntry {
-            binding: 0,
-            resource: BindingResource::Buffer(BufferBinding {
-                buffer: uniforms_buffer.buffer(),
-                offset: 0,
-                size: None,
-            }),
-        }],
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::Buffer(BufferBinding {
+                    buffer: uniforms_buffer.buffer(),
+                    offset: 0,
+                    size: None,
+                }),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(&trap_image.view),
+            },
+        ],
     });
     (uniforms_buffer, uniform_bind_group)
 }
 
+/// The coverage of the orbit trap image as an `R32Float` texture, bound next
+/// to the uniforms. Generators without a trap image get a single pixel
+/// without coverage, as every binding of the layout must be filled.
+struct TrapImageTexture {
+    _texture: Texture,
+    view: TextureView,
+}
+
+impl TrapImageTexture {
+    fn new(gpu: &GPUContext, opts: &FractalOpts) -> TrapImageTexture {
+        let (width, height, coverage) = match &opts.orbit_trap {
+            Some(OrbitTrap::Image { image, .. })
+                if !image.coverage.is_empty()
+                    && image.coverage.len() == image.width * image.height =>
+            {
+                (image.width, image.height, image.coverage.as_slice())
+            },
+            _ => (1, 1, [0.0f32].as_slice()),
+        };
+
+        let (texture, view) = create_texture(
+            &gpu.device,
+            width as u32,
+            height as u32,
+            TextureFormat::R32Float,
+            TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+        );
+        gpu.queue.write_texture(
+            ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            cast_slice(coverage),
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some((width * size_of::<f32>()) as u32),
+                rows_per_image: None,
+            },
+            Extent3d {
+                width: width as u32,
+                height: height as u32,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        TrapImageTexture {
+            _texture: texture,
+            view,
+        }
+    }
+}
+
 fn find_texture_buffer_for_view<'a>(
     device: &Device,
     buffers: &'a mut HashMap<(usize, usize), (Texture, TextureView, Buffer)>,
@@ -743,13 +916,16 @@ fn find_texture_buffer_for_view<'a>(
             device,
             width as u32,
             height as u32,
-            TextureFormat::Rgba8Unorm,
-            TextureUsages::COPY_SRC | TextureUsages::RENDER_ATTACHMENT,
+            TextureFormat::Rgba32Float,
+            TextureUsages::COPY_SRC
+                | TextureUsages::RENDER_ATTACHMENT
+                | TextureUsages::TEXTURE_BINDING,
         );
         let buffer = create_texture_buffer(
             device,
             width as u32,
             height as u32,
+            BYTES_PER_VALUE as u32,
             BufferUsages::COPY_DST | BufferUsages::MAP_READ,
         );
         (texture, texture_view, buffer)
@@ -777,8 +953,10 @@ fn find_texture_for_view<'a>(
             device,
             width as u32,
             height as u32,
-            TextureFormat::Rgba8Unorm,
-            TextureUsages::COPY_SRC | TextureUsages::RENDER_ATTACHMENT,
+            TextureFormat::Rgba32Float,
+            TextureUsages::COPY_SRC
+                | TextureUsages::RENDER_ATTACHMENT
+                | TextureUsages::TEXTURE_BINDING,
         );
         (texture, texture_view)
     });
@@ -811,19 +989,37 @@ fn encode_render_pass(
     uniform_bind_group: &BindGroup,
     texture_view: &TextureView,
     encoder: &mut CommandEncoder,
+) {
+    encode_pass(
+        "Render Pass",
+        render_pipeline,
+        &[uniform_bind_group],
+        texture_view,
+        LoadOp::Clear(Color {
+            r: 0.0,
+            g: 0.0,
+            b: 0.0,
+            a: 1.0,
+        }),
+        encoder,
+    );
+}
+
+fn encode_pass(
+    label: &str,
+    pipeline: &RenderPipeline,
+    bind_groups: &[&BindGroup],
+    texture_view: &TextureView,
+    load: LoadOp<Color>,
+    encoder: &mut CommandEncoder,
 ) {
     let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-        label: Some("Render Pass"),
+        label: Some(label),
         color_attachments: &[Some(RenderPassColorAttachment {
             view: texture_view,
             resolve_target: None,
             ops: Operations {
-                load: LoadOp::Clear(Color {
-                    r: 0.0,
-                    g: 0.0,
-                    b: 0.0,
-                    a: 1.0,
-                }),
+                load,
                 store: StoreOp::Store,
             },
         })],
@@ -832,11 +1028,148 @@ fn encode_render_pass(
         occlusion_query_set: None,
     });
 
-    render_pass.set_pipeline(render_pipeline);
-    render_pass.set_bind_group(0, uniform_bind_group, &[]);
+    render_pass.set_pipeline(pipeline);
+    for (index, bind_group) in bind_groups.iter().enumerate() {
+        render_pass.set_bind_group(index as u32, bind_group, &[]);
+    }
     render_pass.draw(0..6, 0..1);
 }
 
+fn create_render_pipeline(
+    device: &Device,
+    label: &str,
+    layout: &PipelineLayout,
+    vert_module: &ShaderModule,
+    frag_module: &ShaderModule,
+    entry_point: &str,
+    format: TextureFormat,
+) -> RenderPipeline {
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(layout),
+        vertex: VertexState {
+            module: vert_module,
+            entry_point: "vert_main",
+            buffers: &[],
+        },
+        fragment: Some(FragmentState {
+            module: frag_module,
+            entry_point,
+            targets: &[Some(ColorTargetState {
+                format,
+                blend: None,
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: FrontFace::Ccw,
+            cull_mode: Some(Face::Back),
+            polygon_mode: PolygonMode::Fill,
+            conservative: false,
+            unclipped_depth: false,
+        },
+        depth_stencil: None,
+        multisample: MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+/// The format of the texture marking which pixels adaptive multisampling
+/// samples again.
+const MASK_TEXTURE_FORMAT: TextureFormat = TextureFormat::R32Float;
+
+/// The pipelines of the two passes that follow the render pass when using
+/// adaptive multisampling. The mask pass finds the pixels that differ from
+/// their neighbors, then the refine pass samples those pixels again.
+struct AdaptivePasses {
+    texture_bind_group_layout: Arc<BindGroupLayout>,
+    mask_pipeline: RenderPipeline,
+    refine_pipeline: RenderPipeline,
+}
+
+/// The mask texture and bind groups used by the adaptive multisampling passes
+/// for a single framebuffer.
+struct AdaptiveTextures {
+    _mask_texture: Texture,
+    mask_texture_view: TextureView,
+    values_bind_group: BindGroup,
+    mask_bind_group: BindGroup,
+}
+
+impl AdaptivePasses {
+    /// Encodes the mask and refine passes for the values just rendered to
+    /// `texture`. The textures used by these passes are cached in `textures`,
+    /// keyed by the size of the framebuffer they belong to.
+    fn encode(
+        &self,
+        device: &Device,
+        textures: &mut HashMap<(u32, u32), AdaptiveTextures>,
+        texture: &Texture,
+        texture_view: &TextureView,
+        uniform_bind_group: &BindGroup,
+        encoder: &mut CommandEncoder,
+    ) {
+        let textures = textures
+            .entry((texture.width(), texture.height()))
+            .or_insert_with(|| {
+                info!(
+                    "Creating new mask texture with dimensions ({}x{})...",
+                    texture.width(),
+                    texture.height()
+                );
+                let (mask_texture, mask_texture_view) = create_texture(
+                    device,
+                    texture.width(),
+                    texture.height(),
+                    MASK_TEXTURE_FORMAT,
+                    TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                );
+                let values_bind_group = self.create_texture_bind_group(device, texture_view);
+                let mask_bind_group = self.create_texture_bind_group(device, &mask_texture_view);
+                AdaptiveTextures {
+                    _mask_texture: mask_texture,
+                    mask_texture_view,
+                    values_bind_group,
+                    mask_bind_group,
+                }
+            });
+
+        encode_pass(
+            "Mask Pass",
+            &self.mask_pipeline,
+            &[uniform_bind_group, &textures.values_bind_group],
+            &textures.mask_texture_view,
+            LoadOp::Clear(Color::BLACK),
+            encoder,
+        );
+        encode_pass(
+            "Refine Pass",
+            &self.refine_pipeline,
+            &[uniform_bind_group, &textures.mask_bind_group],
+            texture_view,
+            LoadOp::Load,
+            encoder,
+        );
+    }
+
+    fn create_texture_bind_group(&self, device: &Device, texture_view: &TextureView) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Texture Bind Group"),
+            layout: &self.texture_bind_group_layout,
+            entries: &[BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(texture_view),
+            }],
+        })
+    }
+}
+
 impl FractalGeneratorInstance for GpuFractalGeneratorInstance {
     fn cancel(&self) {
         self.canceled.store(true, Ordering::Release);
@@ -853,3 +1186,79 @@ impl FractalGeneratorInstance for GpuFractalGeneratorInstance {
         ready(Ok(self.running.load(Ordering::Acquire))).boxed()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cli::create_headless_gpu_context, generator::cpu::CpuFractalGeneratorFactory};
+    use tokio::sync::mpsc;
+
+    async fn generate(
+        factory: &dyn FractalGeneratorFactory,
+        opts: FractalOpts,
+        view: View,
+    ) -> Box<[PixelValue]> {
+        let generator = factory.create_generator(opts).await.unwrap();
+        let (sender, mut receiver) = mpsc::channel(1);
+        let _instance = generator
+            .start_generation_to_cpu(&[view], sender)
+            .await
+            .unwrap();
+        receiver.recv().await.unwrap().unwrap().values
+    }
+
+    #[test]
+    fn double_precision_matches_cpu() {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let (gpu, _guard) = match create_headless_gpu_context().await {
+                Ok(context) => context,
+                Err(e) => {
+                    eprintln!("Skipping GPU double precision test: {}", e);
+                    return;
+                },
+            };
+
+            // neighboring pixels here are closer together than f32 can tell
+            // apart, so an image generated in f32 collapses into a few values
+            let view = View::new_uniform(16, 16, 1e-9, -0.743643887037151, 0.131825904205330);
+            let opts = FractalOpts {
+                iterations: 10000,
+                precision: Precision::Double,
+                ..FractalOpts::test_base()
+            };
+
+            let gpu_values =
+                generate(&GpuFractalGeneratorFactory::new(gpu), opts.clone(), view).await;
+            let cpu_values = generate(&CpuFractalGeneratorFactory::new(1), opts, view).await;
+
+            let mut distinct: Vec<_> = gpu_values.iter().map(|value| value.value).collect();
+            distinct.sort_by(f32::total_cmp);
+            distinct.dedup();
+            assert!(
+                distinct.len() > gpu_values.len() / 2,
+                "only {} distinct values",
+                distinct.len()
+            );
+
+            // df64 carries a few bits less than f64, so pixels whose orbits
+            // pass close to the escape radius may land an iteration apart
+            let matching = gpu_values
+                .iter()
+                .zip(cpu_values.iter())
+                .filter(|(gpu, cpu)| {
+                    gpu.coverage == cpu.coverage && (gpu.value - cpu.value).abs() < 1.0
+                })
+                .count();
+            assert!(
+                matching >= gpu_values.len() * 9 / 10,
+                "only {} of {} pixels match the CPU",
+                matching,
+                gpu_values.len()
+            );
+        });
+    }
+}
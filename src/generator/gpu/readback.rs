@@ -0,0 +1,84 @@
+//! This module contains reading the values of `Rgba32Float` value textures back
+//! from the GPU, for when the CPU needs to look at all of them, like when
+//! collecting their distribution for histogram equalization.
+
+use crate::{
+    generator::{util::smallest_multiple_containing, PixelValue, BYTES_PER_VALUE},
+    gpu::{util::create_texture_buffer, GPUContext},
+    util::result::ResultExt,
+};
+use bytemuck::cast_slice;
+use std::{future::Future, sync::Arc};
+use wgpu::{
+    BufferUsages, CommandEncoderDescriptor, ImageCopyBuffer, ImageCopyTexture, ImageDataLayout,
+    MapMode, Origin3d, Texture, COPY_BYTES_PER_ROW_ALIGNMENT,
+};
+
+/// Reads every value of a value texture, row by row from the top left.
+///
+/// The copy is submitted immediately, so values written to the texture after
+/// this is called are not read.
+pub fn read_values(
+    gpu: &GPUContext,
+    texture: Arc<Texture>,
+) -> impl Future<Output = anyhow::Result<Vec<PixelValue>>> + Send + 'static {
+    let size = texture.size();
+    let values_per_row = smallest_multiple_containing(
+        size.width,
+        COPY_BYTES_PER_ROW_ALIGNMENT / BYTES_PER_VALUE as u32,
+    );
+    let buffer = create_texture_buffer(
+        &gpu.device,
+        values_per_row,
+        size.height,
+        BYTES_PER_VALUE as u32,
+        BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+    );
+
+    let mut encoder = gpu
+        .device
+        .create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Readback Command Encoder"),
+        });
+    encoder.copy_texture_to_buffer(
+        ImageCopyTexture {
+            texture: &texture,
+            mip_level: 0,
+            origin: Origin3d::ZERO,
+            aspect: Default::default(),
+        },
+        ImageCopyBuffer {
+            buffer: &buffer,
+            layout: ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(BYTES_PER_VALUE as u32 * values_per_row),
+                rows_per_image: Some(size.height),
+            },
+        },
+        size,
+    );
+    gpu.queue.submit([encoder.finish()]);
+
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    buffer.slice(..).map_async(MapMode::Read, move |res| {
+        tx.send(res)
+            .on_err(|_| error!("Failed to send map_async completion!"));
+    });
+
+    async move {
+        rx.await??;
+
+        let values = {
+            let data = buffer.slice(..).get_mapped_range();
+            let data: &[PixelValue] = cast_slice(data.as_ref());
+            let mut values = Vec::with_capacity((size.width * size.height) as usize);
+            for row in data.chunks_exact(values_per_row as usize) {
+                values.extend_from_slice(&row[..size.width as usize]);
+            }
+            values
+        };
+        buffer.unmap();
+
+        Ok(values)
+    }
+}
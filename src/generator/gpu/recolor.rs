@@ -0,0 +1,203 @@
+//! This module contains the coloring pass, which turns the values written by a
+//! generator into colors. This lets palette and shading changes be shown
+//! without generating the fractal again.
+
+use crate::generator::{
+    color::Shading,
+    equalization::Equalization,
+    gpu::{
+        shader::load_recolor_shaders,
+        uniforms::{ColoringUniforms, GpuEqualization, GpuPalette},
+    },
+    palette::Palette,
+};
+use anyhow::Context;
+use std::{mem::size_of, num::NonZeroU64};
+use wgpu::{
+    BindGroup, BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBindingType,
+    BufferDescriptor, BufferUsages, Color, ColorTargetState, ColorWrites, CommandEncoder, Device,
+    Face, FragmentState, FrontFace, LoadOp, MultisampleState, Operations, PipelineLayoutDescriptor,
+    PolygonMode, PrimitiveState, PrimitiveTopology, Queue, RenderPassColorAttachment,
+    RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, ShaderModuleDescriptor,
+    ShaderStages, StoreOp, TextureFormat, TextureSampleType, TextureView, TextureViewDimension,
+    VertexState,
+};
+
+/// Colors `Rgba32Float` value textures into color textures using a palette and
+/// shading.
+pub struct Recolorer {
+    bind_group_layout: BindGroupLayout,
+    pipeline: RenderPipeline,
+    uniforms_buffer: Buffer,
+}
+
+impl Recolorer {
+    /// Creates a recolorer that colors into textures of the given format,
+    /// usually one of [`PixelFormat::texture_format()`].
+    ///
+    /// [`PixelFormat::texture_format()`]:
+    ///   crate::generator::PixelFormat::texture_format
+    pub fn new(device: &Device, format: TextureFormat) -> anyhow::Result<Recolorer> {
+        info!("Creating recolor shader modules...");
+        let shaders = load_recolor_shaders().context("Error loading recolor shaders")?;
+        let frag_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Recolor Fragment Shader"),
+            source: shaders.fragment,
+        });
+        let vert_module = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("Recolor Vertex Shader"),
+            source: shaders.vertex,
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Recolor Bind Group Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: Some(
+                            NonZeroU64::new(size_of::<ColoringUniforms>() as u64).unwrap(),
+                        ),
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Recolor Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+
+        info!("Creating recolor pipeline...");
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Recolor Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &vert_module,
+                entry_point: "vert_main",
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &frag_module,
+                entry_point: "frag_main",
+                targets: &[Some(ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: FrontFace::Ccw,
+                cull_mode: Some(Face::Back),
+                polygon_mode: PolygonMode::Fill,
+                conservative: false,
+                unclipped_depth: false,
+            },
+            depth_stencil: None,
+            multisample: MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        let uniforms_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Recolor Uniform Buffer"),
+            size: size_of::<ColoringUniforms>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Ok(Recolorer {
+            bind_group_layout,
+            pipeline,
+            uniforms_buffer,
+        })
+    }
+
+    /// Sets the palettes, shading and equalization used by the next coloring
+    /// passes, where a palette of `None` means the classic hue-cycling colors
+    /// are used and an equalization of `None` means values aren't equalized.
+    pub fn set_coloring(
+        &self,
+        queue: &Queue,
+        palette: Option<&Palette>,
+        interior_palette: Option<&Palette>,
+        shading: &Shading,
+        equalization: Option<&Equalization>,
+    ) {
+        let uniforms = ColoringUniforms {
+            palette: GpuPalette::from_palette(palette),
+            interior_palette: GpuPalette::from_palette(interior_palette),
+            shading: shading.into(),
+            equalization: GpuEqualization::from_equalization(equalization),
+        };
+        queue.write_buffer(&self.uniforms_buffer, 0, bytemuck::bytes_of(&uniforms));
+    }
+
+    /// Creates the bind group used to color the given `Rgba32Float` value
+    /// texture.
+    pub fn create_bind_group(&self, device: &Device, values: &TextureView) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Recolor Bind Group"),
+            layout: &self.bind_group_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniforms_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(values),
+                },
+            ],
+        })
+    }
+
+    /// Encodes a coloring pass from the value texture in `bind_group` into
+    /// `target`, which must be the same size.
+    pub fn encode(
+        &self,
+        encoder: &mut CommandEncoder,
+        bind_group: &BindGroup,
+        target: &TextureView,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Recolor Render Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::BLACK),
+                    store: StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, bind_group, &[]);
+        render_pass.draw(0..6, 0..1);
+    }
+}
@@ -3,6 +3,7 @@ pub mod source;
 
 use crate::{
     generator::{
+        args::Formula,
         gpu::shader::{opts::GpuFractalOpts, source::ShaderTemplateOpts},
         FractalOpts,
     },
@@ -19,6 +20,7 @@ use wgpu::ShaderSource;
 
 const VERTEX_SHADER_PATH: &str = "screen_rect_vertex_shader.wgsl.liquid";
 const FRAGMENT_SHADER_PATH: &str = "fragment_shader_main.wgsl.liquid";
+const RECOLOR_FRAGMENT_SHADER_PATH: &str = "recolor_fragment_shader.wgsl.liquid";
 
 /// Both
 pub struct LoadedShaders {
@@ -85,6 +87,40 @@ pub async fn load_shaders(opts: FractalOpts) -> anyhow::Result<LoadedShaders> {
     })
 }
 
+/// Loads the shaders for the coloring pass. These do not depend on any fractal
+/// options, so they are not written to debug files.
+pub fn load_recolor_shaders() -> anyhow::Result<LoadedShaders> {
+    info!("Getting shader loader...");
+    let loader = source::obtain_loader().context("Error obtaining shader loader")?;
+
+    info!("Loading recolor shader templates...");
+    let frag_str = loader
+        .compile_template(ShaderTemplateOpts {
+            path: Cow::Borrowed(RECOLOR_FRAGMENT_SHADER_PATH),
+            ..Default::default()
+        })
+        .context("Error loading recolor fragment shader template")?;
+    let vert_str = loader
+        .compile_template(ShaderTemplateOpts {
+            path: Cow::Borrowed(VERTEX_SHADER_PATH),
+            ..Default::default()
+        })
+        .context("Error loading vertex shader template")?;
+
+    info!("Validating recolor fragment shader...");
+    let module = front::wgsl::parse_str(&frag_str)
+        .map_err(|e| anyhow!("{}", e.emit_to_string(&frag_str)))
+        .context("Error parsing recolor fragment shader")?;
+    let _ = Validator::new(ValidationFlags::all(), Default::default())
+        .validate(&module)
+        .context("Error validating recolor fragment shader")?;
+
+    Ok(LoadedShaders {
+        vertex: ShaderSource::Wgsl(Cow::Owned(vert_str)),
+        fragment: ShaderSource::Wgsl(Cow::Owned(frag_str)),
+    })
+}
+
 async fn validate(source: &str, source_file: &Path, shader_name: &str) -> anyhow::Result<()> {
     info!("Validating {} source...", shader_name);
     let module = front::wgsl::parse_str(source)
@@ -116,4 +152,274 @@ async fn validate(source: &str, source_file: &Path, shader_name: &str) -> anyhow
 }
 
 #[derive(Error, Debug)]
-pub enum ShaderError {}
+pub enum ShaderError {
+    #[error("Formula {0:?} cannot be generated in double precision on the GPU")]
+    UnsupportedDoubleFormula(Formula),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{
+        args::{Averaging, InteriorColoring, Multisampling, Precision, Smoothing},
+        trap::{OrbitTrap, TrapImage},
+    };
+    use num_complex::Complex64;
+
+    fn check_fragment_shader(opts: FractalOpts) {
+        let loader = source::obtain_loader().unwrap();
+        let frag_str = loader
+            .compile_template(ShaderTemplateOpts {
+                path: Cow::Borrowed(FRAGMENT_SHADER_PATH),
+                globals: &opts.globals().unwrap(),
+            })
+            .unwrap();
+
+        let module = front::wgsl::parse_str(&frag_str)
+            .unwrap_or_else(|e| panic!("{:?}:\n{}", opts, e.emit_to_string(&frag_str)));
+        Validator::new(ValidationFlags::all(), Default::default())
+            .validate(&module)
+            .unwrap_or_else(|e| panic!("{:?}: {:?}", opts, e));
+    }
+
+    #[test]
+    fn formulas_compile() {
+        let opts = FractalOpts::test_base();
+
+        for formula in [
+            Formula::IntegerPower { exponent: 2 },
+            Formula::IntegerPower { exponent: 5 },
+            Formula::IntegerPower { exponent: -3 },
+            Formula::RealPower { exponent: 2.5 },
+            Formula::RealPower { exponent: -1.0 },
+            Formula::BurningShip,
+            Formula::Tricorn,
+            Formula::PerpendicularBurningShip,
+            Formula::Celtic,
+            "z^3 - 0.5*z + c".parse().unwrap(),
+            "sin(z)*c".parse().unwrap(),
+            "1e-5*exp(z) + tan(z) + tanh(z) + cosh(sqrt(z)) + sinh(ln(z)) + cos(z)"
+                .parse()
+                .unwrap(),
+            "abs(z) - conj(z) / re(z) + im(c)^-2 + z^-1.5 + z^(1 + i)"
+                .parse()
+                .unwrap(),
+        ] {
+            check_fragment_shader(FractalOpts {
+                formula,
+                ..opts.clone()
+            });
+        }
+    }
+
+    #[test]
+    fn double_precision_formulas_compile() {
+        let opts = FractalOpts {
+            smoothing: Smoothing::LinearIntersection,
+            c: Complex64 {
+                re: -0.743643887037151,
+                im: 0.131825904205330,
+            },
+            precision: Precision::Double,
+            ..FractalOpts::test_base()
+        };
+
+        for formula in [
+            Formula::IntegerPower { exponent: 2 },
+            Formula::IntegerPower { exponent: 5 },
+            Formula::IntegerPower { exponent: -3 },
+            Formula::BurningShip,
+            Formula::Tricorn,
+            Formula::PerpendicularBurningShip,
+            Formula::Celtic,
+        ] {
+            check_fragment_shader(FractalOpts {
+                formula,
+                ..opts.clone()
+            });
+        }
+    }
+
+    #[test]
+    fn adaptive_multisampling_compiles() {
+        for precision in [Precision::Single, Precision::Double] {
+            check_fragment_shader(FractalOpts {
+                smoothing: Smoothing::LinearIntersection,
+                multisampling: Multisampling::Adaptive {
+                    max_samples: 16,
+                    threshold: 0.5,
+                },
+                precision,
+                ..FractalOpts::test_base()
+            });
+        }
+    }
+
+    #[test]
+    fn distance_estimation_compiles() {
+        let opts = FractalOpts {
+            smoothing: Smoothing::LinearIntersection,
+            multisampling: Multisampling::Adaptive {
+                max_samples: 16,
+                threshold: 0.5,
+            },
+            distance_estimation: true,
+            ..FractalOpts::test_base()
+        };
+
+        for formula in [
+            Formula::IntegerPower { exponent: 2 },
+            Formula::IntegerPower { exponent: 5 },
+            Formula::IntegerPower { exponent: -3 },
+            Formula::BurningShip,
+            Formula::Tricorn,
+            Formula::PerpendicularBurningShip,
+            Formula::Celtic,
+        ] {
+            for precision in [Precision::Single, Precision::Double] {
+                check_fragment_shader(FractalOpts {
+                    formula: formula.clone(),
+                    precision,
+                    ..opts.clone()
+                });
+            }
+        }
+
+        // formulas without a derivative are generated without distances
+        for formula in [
+            Formula::RealPower { exponent: 2.5 },
+            "sin(z)*c".parse().unwrap(),
+        ] {
+            check_fragment_shader(FractalOpts {
+                formula,
+                ..opts.clone()
+            });
+        }
+    }
+
+    #[test]
+    fn orbit_traps_compile() {
+        let opts = |trap: OrbitTrap, precision| FractalOpts {
+            smoothing: Smoothing::LinearIntersection,
+            precision,
+            orbit_trap: Some(trap),
+            ..FractalOpts::test_base()
+        };
+        let image_trap = |image| OrbitTrap::Image {
+            center: Complex64 { re: 0.0, im: 0.0 },
+            size: 2.0,
+            image,
+        };
+
+        let image = TrapImage::from_coverage(2, 2, &[0.0, 0.5, 1.0, 0.25]);
+        for trap in [
+            "point(0, 0)".parse().unwrap(),
+            "line(0, 0, 30)".parse().unwrap(),
+            "cross(0.5, -0.5, 45)".parse().unwrap(),
+            "circle(0, 0, 1.5)".parse().unwrap(),
+            "image(0, 0, 2)".parse().unwrap(),
+            image_trap(image.clone()),
+        ] {
+            for precision in [Precision::Single, Precision::Double] {
+                check_fragment_shader(opts(trap.clone(), precision));
+            }
+        }
+
+        // the image is bound as a texture, so changing it leaves the shader as is
+        let other_image = TrapImage::from_coverage(1, 3, &[1.0, 0.0, 1.0]);
+        assert_eq!(
+            opts(image_trap(image), Precision::Single)
+                .globals()
+                .unwrap(),
+            opts(image_trap(other_image), Precision::Single)
+                .globals()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn interior_colorings_compile() {
+        for interior_coloring in [
+            InteriorColoring::FinalMagnitude,
+            InteriorColoring::MinMagnitudeIteration,
+            InteriorColoring::Period,
+            InteriorColoring::Distance,
+            InteriorColoring::AtomDomain,
+        ] {
+            for (formula, precision) in [
+                (Formula::IntegerPower { exponent: 2 }, Precision::Single),
+                (Formula::IntegerPower { exponent: 3 }, Precision::Double),
+                (Formula::BurningShip, Precision::Single),
+            ] {
+                check_fragment_shader(FractalOpts {
+                    formula,
+                    smoothing: Smoothing::LinearIntersection,
+                    multisampling: Multisampling::Adaptive {
+                        max_samples: 16,
+                        threshold: 0.5,
+                    },
+                    precision,
+                    interior_coloring,
+                    ..FractalOpts::test_base()
+                });
+            }
+        }
+    }
+
+    #[test]
+    fn averages_compile() {
+        for averaging in [
+            Averaging::Stripe { density: 5.0 },
+            Averaging::TriangleInequality,
+            Averaging::Curvature,
+        ] {
+            for (smoothing, precision) in [
+                (Smoothing::None, Precision::Single),
+                (Smoothing::LinearIntersection, Precision::Double),
+                (
+                    Smoothing::from_logarithmic_distance(4.0, 2.0),
+                    Precision::Single,
+                ),
+                (
+                    Smoothing::from_logarithmic_distance(4.0, 2.0),
+                    Precision::Double,
+                ),
+            ] {
+                check_fragment_shader(FractalOpts {
+                    smoothing,
+                    precision,
+                    averaging,
+                    ..FractalOpts::test_base()
+                });
+            }
+        }
+    }
+
+    #[test]
+    fn recolor_shader_compiles() {
+        load_recolor_shaders().unwrap();
+    }
+
+    #[test]
+    fn double_precision_rejects_unsupported_formulas() {
+        let opts = FractalOpts {
+            formula: Formula::RealPower { exponent: 2.5 },
+            smoothing: Smoothing::None,
+            precision: Precision::Double,
+            ..FractalOpts::test_base()
+        };
+
+        assert!(matches!(
+            opts.globals(),
+            Err(ShaderError::UnsupportedDoubleFormula(_))
+        ));
+        assert!(matches!(
+            FractalOpts {
+                formula: "sin(z) + c".parse().unwrap(),
+                ..opts
+            }
+            .globals(),
+            Err(ShaderError::UnsupportedDoubleFormula(_))
+        ));
+    }
+}
@@ -1,8 +1,14 @@
 use liquid_core::{object, Object};
 
 use crate::generator::{
-    args::{Multisampling, Smoothing},
+    args::{
+        Averaging, Formula, InteriorColoring, Multisampling, Precision, Smoothing,
+        AVERAGE_VALUE_SCALE, INTERIOR_MAGNITUDE_SCALE, INTERIOR_PERIOD_TOLERANCE,
+    },
+    expression::{Function, Node},
     gpu::shader::ShaderError,
+    trap::{OrbitTrap, TRAP_VALUE_SCALE},
+    util::split_f64,
     FractalOpts,
 };
 
@@ -14,20 +20,216 @@ pub trait GpuFractalOpts {
 
 impl GpuFractalOpts for FractalOpts {
     fn globals(&self) -> Result<Object, ShaderError> {
+        if self.precision == Precision::Double && !self.formula.supports_double() {
+            return Err(ShaderError::UnsupportedDoubleFormula(self.formula.clone()));
+        }
+
+        let (c_real, c_real_lo) = split_f64(self.c.re);
+        let (c_imag, c_imag_lo) = split_f64(self.c.im);
+
         let opts_obj = object!({
-            "c_real": self.c.re,
-            "c_imag": self.c.im,
+            "c_real": c_real,
+            "c_real_lo": c_real_lo,
+            "c_imag": c_imag,
+            "c_imag_lo": c_imag_lo,
+            "precision": self.precision.opts(),
             "iterations": self.iterations,
             "mandelbrot": self.mandelbrot,
+            "formula": self.formula.opts()?,
             "radius_squared": self.radius_squared,
             "smoothing": self.smoothing.opts()?,
             "multisampling": self.multisampling.opts()?,
+            "interior": object!({
+                "bulbs": self.checks_bulbs(),
+                "periodicity": self.checks_periodicity(),
+                "periodicity_tolerance": self.precision.periodicity_tolerance(),
+            }),
+            "distance_estimation": self.distance_estimation && self.formula.has_derivative(),
+            "trap": match &self.orbit_trap {
+                Some(trap) => trap.opts(),
+                None => object!({ "kind": "none" }),
+            },
+            // orbit traps take precedence over averages
+            "averaging": if self.averages_orbits() {
+                self.averaging.opts()
+            } else {
+                Averaging::None.opts()
+            },
+            "interior_coloring": object!({
+                // interior distances are only supported for some formulas
+                "kind": if self.interior_coloring == InteriorColoring::Distance
+                    && !self.estimates_interior_distance()
+                {
+                    "none"
+                } else {
+                    self.interior_coloring.opts()
+                },
+                "magnitude_scale": INTERIOR_MAGNITUDE_SCALE,
+                "period_tolerance": INTERIOR_PERIOD_TOLERANCE,
+            }),
         });
 
         Ok(object!({ "opts": opts_obj }))
     }
 }
 
+/// Structs implementing this trait can be used as the iterative function when
+/// generating fractals on the GPU.
+pub trait GpuFormula {
+    fn opts(&self) -> Result<Object, ShaderError>;
+
+    /// Checks whether this formula can be iterated in emulated double
+    /// precision on the GPU.
+    fn supports_double(&self) -> bool;
+}
+
+impl GpuFormula for Formula {
+    fn opts(&self) -> Result<Object, ShaderError> {
+        Ok(match self {
+            Formula::IntegerPower { exponent } => object!({
+                "kind": "integer_power",
+                "exponent": exponent,
+                "starts_at_c": self.starts_at_c(),
+            }),
+            Formula::RealPower { exponent } => object!({
+                "kind": "real_power",
+                "exponent": exponent,
+                "starts_at_c": self.starts_at_c(),
+            }),
+            Formula::BurningShip => object!({ "kind": "burning_ship", "starts_at_c": false }),
+            Formula::Tricorn => object!({ "kind": "tricorn", "starts_at_c": false }),
+            Formula::PerpendicularBurningShip => object!({
+                "kind": "perpendicular_burning_ship",
+                "starts_at_c": false,
+            }),
+            Formula::Celtic => object!({ "kind": "celtic", "starts_at_c": false }),
+            Formula::Expression(expression) => object!({
+                "kind": "expression",
+                "wgsl": expression.root().wgsl(),
+                "starts_at_c": self.starts_at_c(),
+            }),
+        })
+    }
+
+    fn supports_double(&self) -> bool {
+        !matches!(self, Formula::RealPower { .. } | Formula::Expression(_))
+    }
+}
+
+/// Structs implementing this trait can be used to select the arithmetic used
+/// for generating fractals on the GPU.
+pub trait GpuPrecision {
+    fn opts(&self) -> &'static str;
+
+    /// Gets how close two values of `z` have to be to be considered the same
+    /// by periodicity checking. This matches the tolerance used on the CPU.
+    fn periodicity_tolerance(&self) -> f64;
+}
+
+impl GpuPrecision for Precision {
+    fn opts(&self) -> &'static str {
+        match self {
+            Precision::Single => "single",
+            Precision::Double => "double",
+        }
+    }
+
+    fn periodicity_tolerance(&self) -> f64 {
+        match self {
+            Precision::Single => (f32::EPSILON * f32::EPSILON) as f64,
+            Precision::Double => f64::EPSILON * f64::EPSILON,
+        }
+    }
+}
+
+/// Structs implementing this trait can be used to select how the inside of the
+/// set is colored when generating fractals on the GPU.
+pub trait GpuInteriorColoring {
+    fn opts(&self) -> &'static str;
+}
+
+impl GpuInteriorColoring for InteriorColoring {
+    fn opts(&self) -> &'static str {
+        match self {
+            InteriorColoring::None => "none",
+            InteriorColoring::FinalMagnitude => "final_magnitude",
+            InteriorColoring::MinMagnitudeIteration => "min_magnitude",
+            InteriorColoring::Period => "period",
+            InteriorColoring::Distance => "distance",
+            InteriorColoring::AtomDomain => "atom_domain",
+        }
+    }
+}
+
+/// Structs implementing this trait are expression nodes that can be emitted as
+/// WGSL for generating fractals on the GPU.
+pub trait GpuExpression {
+    /// Gets the WGSL expression for this node, in terms of the `z` and `c`
+    /// parameters of `t_f`.
+    fn wgsl(&self) -> String;
+}
+
+impl GpuExpression for Node {
+    fn wgsl(&self) -> String {
+        match self {
+            Node::Z => "z".to_string(),
+            Node::C => "c".to_string(),
+            Node::Constant(value) => format!("vec2<f32>({:?}, {:?})", value.re, value.im),
+            Node::Negate(a) => format!("(-{})", a.wgsl()),
+            Node::Add(a, b) => format!("complex_add({}, {})", a.wgsl(), b.wgsl()),
+            Node::Subtract(a, b) => format!("({} - {})", a.wgsl(), b.wgsl()),
+            Node::Multiply(a, b) => format!("complex_multiply({}, {})", a.wgsl(), b.wgsl()),
+            Node::Divide(a, b) => format!("complex_divide({}, {})", a.wgsl(), b.wgsl()),
+            Node::IntegerPower(a, 2) => format!("complex_sqr({})", a.wgsl()),
+            Node::IntegerPower(a, e) => format!("complex_powi({}, {})", a.wgsl(), e),
+            Node::RealPower(a, e) => format!("complex_powf({}, {:?})", a.wgsl(), e),
+            Node::ComplexPower(a, b) => format!("complex_pow({}, {})", a.wgsl(), b.wgsl()),
+            Node::Function(function, a) => {
+                let name = match function {
+                    Function::Ln => "log",
+                    Function::Abs => "abs_value",
+                    _ => function.name(),
+                };
+                format!("complex_{}({})", name, a.wgsl())
+            },
+        }
+    }
+}
+
+/// Structs implementing this trait can be used as orbit traps when generating
+/// fractals on the GPU.
+pub trait GpuOrbitTrap {
+    fn opts(&self) -> Object;
+}
+
+impl GpuOrbitTrap for OrbitTrap {
+    fn opts(&self) -> Object {
+        // these are rounded to f32 the same way the CPU generator rounds them
+        let center = self.center();
+        let direction = self.direction();
+        let (kind, radius) = match self {
+            OrbitTrap::Point { .. } => ("point", 0.0),
+            OrbitTrap::Line { .. } => ("line", 0.0),
+            OrbitTrap::Cross { .. } => ("cross", 0.0),
+            OrbitTrap::Circle { radius, .. } => ("circle", *radius),
+            OrbitTrap::Image { size, .. } => ("image", *size),
+        };
+
+        // The image itself is bound as a texture, so that changing it doesn't
+        // change the shader.
+
+        object!({
+            "kind": kind,
+            "center_re": center.re as f32 as f64,
+            "center_im": center.im as f32 as f64,
+            "direction_re": direction.re as f64,
+            "direction_im": direction.im as f64,
+            "radius": radius as f64,
+            "value_scale": TRAP_VALUE_SCALE as f64,
+        })
+    }
+}
+
 /// Structs implementing this trait can be used as smoothing options for
 /// generating fractals on the GPU.
 pub trait GpuSmoothing {
@@ -48,6 +250,29 @@ impl GpuSmoothing for Smoothing {
     }
 }
 
+/// Structs implementing this trait can be used to average orbits when
+/// generating fractals on the GPU.
+pub trait GpuAveraging {
+    fn opts(&self) -> Object;
+}
+
+impl GpuAveraging for Averaging {
+    fn opts(&self) -> Object {
+        let (kind, density) = match self {
+            Averaging::None => ("none", 0.0),
+            Averaging::Stripe { density } => ("stripe", *density),
+            Averaging::TriangleInequality => ("triangle_inequality", 0.0),
+            Averaging::Curvature => ("curvature", 0.0),
+        };
+
+        object!({
+            "kind": kind,
+            "density": density as f64,
+            "value_scale": AVERAGE_VALUE_SCALE as f64,
+        })
+    }
+}
+
 /// Structs implementing this trait can be used as multisampling options for
 /// generating on the GPU.
 pub trait GpuMultisampling {
@@ -59,6 +284,8 @@ impl GpuMultisampling for Multisampling {
         Ok(object!({
             "sample_count": self.sample_count(),
             "sample_offsets": self.offsets(),
+            "adaptive": self.adaptive_threshold().is_some(),
+            "threshold": self.adaptive_threshold().unwrap_or(0.0),
         }))
     }
 }
@@ -1,7 +1,14 @@
-use crate::generator::view::View;
+use crate::generator::{
+    color::{ReliefSource, Shading},
+    equalization::{Equalization, EQUALIZATION_QUANTILES},
+    palette::{Interpolation, Palette, RepeatMode, MAX_PALETTE_STOPS},
+    util::split_f64,
+    view::View,
+};
 use bytemuck::{Pod, Zeroable};
-use cgmath::Vector2;
+use cgmath::{Vector2, Vector4};
 
+#[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct Uniforms {
     pub view: GpuView,
@@ -10,27 +17,38 @@ pub struct Uniforms {
 unsafe impl Zeroable for Uniforms {}
 unsafe impl Pod for Uniforms {}
 
+#[repr(C)]
 #[derive(Copy, Clone, Debug)]
 pub struct GpuView {
     pub image_size: Vector2<f32>,
     pub image_scale: Vector2<f32>,
     pub plane_start: Vector2<f32>,
+    /// The low parts of the plane start coordinates, used when generating in
+    /// emulated double precision.
+    pub plane_start_lo: Vector2<f32>,
 }
 
 impl GpuView {
     pub fn from_view(view: View) -> GpuView {
+        let (start_x, start_x_lo) = split_f64(view.plane_start_x);
+        let (start_y, start_y_lo) = split_f64(view.plane_start_y);
+
         GpuView {
             image_size: Vector2 {
                 x: view.image_width as f32,
                 y: view.image_height as f32,
             },
             image_scale: Vector2 {
-                x: view.image_scale_x,
-                y: view.image_scale_y,
+                x: view.image_scale_x as f32,
+                y: view.image_scale_y as f32,
             },
             plane_start: Vector2 {
-                x: view.plane_start_x,
-                y: view.plane_start_y,
+                x: start_x,
+                y: start_y,
+            },
+            plane_start_lo: Vector2 {
+                x: start_x_lo,
+                y: start_y_lo,
             },
         }
     }
@@ -41,3 +59,152 @@ impl From<View> for GpuView {
         GpuView::from_view(view)
     }
 }
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct GpuPaletteStop {
+    pub color: Vector4<f32>,
+    pub position: f32,
+    _padding: [f32; 3],
+}
+
+unsafe impl Zeroable for GpuPaletteStop {}
+unsafe impl Pod for GpuPaletteStop {}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct GpuPalette {
+    pub stops: [GpuPaletteStop; MAX_PALETTE_STOPS],
+    pub stop_count: u32,
+    pub interpolation: u32,
+    pub repeat_mode: u32,
+    pub offset: f32,
+    pub density: f32,
+    pub enabled: u32,
+    _padding: [u32; 2],
+}
+
+unsafe impl Zeroable for GpuPalette {}
+unsafe impl Pod for GpuPalette {}
+
+impl GpuPalette {
+    /// Creates a GPU palette from a [`Palette`]. Only the first
+    /// [`MAX_PALETTE_STOPS`] stops are used. If there is no palette, the
+    /// resulting GPU palette is disabled and the classic colors are used.
+    pub fn from_palette(palette: Option<&Palette>) -> GpuPalette {
+        let mut gpu_palette = GpuPalette::zeroed();
+
+        if let Some(palette) = palette {
+            let stops = palette.sorted_stops();
+            let count = stops.len().min(MAX_PALETTE_STOPS);
+            for (gpu_stop, stop) in gpu_palette.stops.iter_mut().zip(stops) {
+                gpu_stop.color = Vector4::new(stop.color[0], stop.color[1], stop.color[2], 1.0);
+                gpu_stop.position = stop.position;
+            }
+
+            gpu_palette.stop_count = count as u32;
+            gpu_palette.interpolation = match palette.interpolation {
+                Interpolation::Linear => 0,
+                Interpolation::Smooth => 1,
+                Interpolation::Step => 2,
+            };
+            gpu_palette.repeat_mode = match palette.repeat {
+                RepeatMode::Repeat => 0,
+                RepeatMode::Mirror => 1,
+                RepeatMode::Clamp => 2,
+            };
+            gpu_palette.offset = palette.offset;
+            gpu_palette.density = palette.density;
+            gpu_palette.enabled = 1;
+        }
+
+        gpu_palette
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct GpuShading {
+    pub boundary_thickness: f32,
+    pub distance_fade: f32,
+    pub relief_enabled: u32,
+    pub relief_source: u32,
+    /// The direction towards the light of the relief, in the first three
+    /// components.
+    pub light_direction: Vector4<f32>,
+    pub relief_depth: f32,
+    pub relief_specular: f32,
+    _padding: [f32; 2],
+}
+
+unsafe impl Zeroable for GpuShading {}
+unsafe impl Pod for GpuShading {}
+
+impl From<&Shading> for GpuShading {
+    fn from(shading: &Shading) -> Self {
+        let mut gpu_shading = GpuShading::zeroed();
+        gpu_shading.boundary_thickness = shading.boundary_thickness;
+        gpu_shading.distance_fade = shading.distance_fade;
+
+        if let Some(relief) = &shading.relief {
+            gpu_shading.relief_enabled = 1;
+            gpu_shading.relief_source = match relief.source {
+                ReliefSource::Value => 0,
+                ReliefSource::Distance => 1,
+            };
+            gpu_shading.light_direction = relief.light_direction().extend(0.0);
+            gpu_shading.relief_depth = relief.depth;
+            gpu_shading.relief_specular = relief.specular;
+        }
+
+        gpu_shading
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct GpuEqualization {
+    /// The quantiles of the distribution, packed four to an element.
+    pub quantiles: [Vector4<f32>; EQUALIZATION_QUANTILES / 4],
+    pub enabled: u32,
+    _padding: [u32; 3],
+}
+
+unsafe impl Zeroable for GpuEqualization {}
+unsafe impl Pod for GpuEqualization {}
+
+impl GpuEqualization {
+    /// Creates a GPU equalization from an [`Equalization`]. If there is no
+    /// equalization, the resulting GPU equalization is disabled and values are
+    /// colored as they are.
+    pub fn from_equalization(equalization: Option<&Equalization>) -> GpuEqualization {
+        let mut gpu_equalization = GpuEqualization::zeroed();
+
+        if let Some(equalization) = equalization {
+            for (gpu_quantiles, quantiles) in gpu_equalization
+                .quantiles
+                .iter_mut()
+                .zip(equalization.quantiles().chunks_exact(4))
+            {
+                *gpu_quantiles =
+                    Vector4::new(quantiles[0], quantiles[1], quantiles[2], quantiles[3]);
+            }
+            gpu_equalization.enabled = 1;
+        }
+
+        gpu_equalization
+    }
+}
+
+/// The uniforms of the coloring pass.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct ColoringUniforms {
+    pub palette: GpuPalette,
+    pub interior_palette: GpuPalette,
+    pub shading: GpuShading,
+    pub equalization: GpuEqualization,
+}
+
+unsafe impl Zeroable for ColoringUniforms {}
+unsafe impl Pod for ColoringUniforms {}
@@ -0,0 +1,241 @@
+//! This module contains the [`RenderJournal`], which lets an interrupted
+//! render to an image be resumed without generating the views it had already
+//! completed.
+//!
+//! A journal is a directory next to the output image, named after it with
+//! [`JOURNAL_EXTENSION`] appended. It holds a description of the render job
+//! along with a file of uncolored values for every completed view. Because the
+//! values are stored before being colored, a job can even be resumed with
+//! different palettes, shading, or histogram equalization.
+
+use crate::generator::{view::View, FractalOpts, PixelValue, ValueBlock, BYTES_PER_VALUE};
+use ron::ser::PrettyConfig;
+use std::{
+    ffi::OsString,
+    fs::{create_dir_all, read_to_string, remove_dir_all, write},
+    io,
+    path::{Path, PathBuf},
+};
+
+/// The extension appended to an output image's file name to get the name of
+/// its journal directory.
+pub const JOURNAL_EXTENSION: &str = "journal";
+
+const JOB_FILE_NAME: &str = "job.ron";
+const VALUES_FILE_EXTENSION: &str = "values";
+const TEMP_FILE_EXTENSION: &str = "tmp";
+
+/// Everything about a render job that has to match for its journal to be
+/// reused.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct JournalJob {
+    /// These options never include the palettes, shading, or histogram
+    /// equalization, as those are applied after values are journaled.
+    opts: FractalOpts,
+    parent_view: View,
+    child_views: Vec<View>,
+}
+
+/// Records the blocks completed by a render to an image so the render can be
+/// resumed later.
+pub struct RenderJournal {
+    dir: PathBuf,
+}
+
+impl RenderJournal {
+    /// Gets the path of the journal directory for the given output image.
+    pub fn dir_for(output: &Path) -> PathBuf {
+        let mut name = OsString::from(output.as_os_str());
+        name.push(".");
+        name.push(JOURNAL_EXTENSION);
+        PathBuf::from(name)
+    }
+
+    /// Opens the journal for rendering `child_views` of `parent_view` to
+    /// `output`, creating it if it doesn't exist yet.
+    ///
+    /// This returns the journal along with the child views that a previous
+    /// render of the same job already completed. A journal left by a different
+    /// job is started over.
+    pub fn open(
+        output: &Path,
+        opts: &FractalOpts,
+        parent_view: View,
+        child_views: &[View],
+    ) -> Result<(RenderJournal, Vec<View>), JournalError> {
+        // only the options that change the generated values are part of the job
+        let job_opts = FractalOpts {
+            palette: None,
+            interior_palette: None,
+            shading: Default::default(),
+            histogram_equalization: false,
+            ..opts.clone()
+        };
+        let job = JournalJob {
+            opts: job_opts,
+            parent_view,
+            child_views: child_views.to_vec(),
+        };
+
+        let journal = RenderJournal {
+            dir: RenderJournal::dir_for(output),
+        };
+        let job_path = journal.dir.join(JOB_FILE_NAME);
+
+        if journal.dir.exists() {
+            let existing: Option<JournalJob> = read_to_string(&job_path)
+                .ok()
+                .and_then(|str| ron::from_str(&str).ok());
+            if existing.as_ref() == Some(&job) {
+                let completed: Vec<_> = child_views
+                    .iter()
+                    .copied()
+                    .filter(|view| journal.is_complete(view))
+                    .collect();
+                info!(
+                    "Resuming render from journal {:?} with {} of {} views completed",
+                    &journal.dir,
+                    completed.len(),
+                    child_views.len()
+                );
+                return Ok((journal, completed));
+            }
+
+            warn!(
+                "Journal {:?} belongs to a different render job, starting over",
+                &journal.dir
+            );
+            remove_dir_all(&journal.dir)?;
+        }
+
+        create_dir_all(&journal.dir)?;
+        write(
+            &job_path,
+            ron::ser::to_string_pretty(&job, PrettyConfig::new())?,
+        )?;
+
+        Ok((journal, vec![]))
+    }
+
+    /// Records a completed block.
+    ///
+    /// The block is written to a temporary file first, so that an interrupted
+    /// write never leaves a partial block behind.
+    pub async fn record(&self, block: &ValueBlock) -> Result<(), JournalError> {
+        let path = self.values_path(&block.view);
+        let temp_path = path.with_extension(TEMP_FILE_EXTENSION);
+        tokio::fs::write(&temp_path, bytemuck::cast_slice(&block.values)).await?;
+        tokio::fs::rename(&temp_path, &path).await?;
+        Ok(())
+    }
+
+    /// Loads a block recorded for the given view.
+    pub async fn load(&self, view: View) -> Result<ValueBlock, JournalError> {
+        let bytes = tokio::fs::read(self.values_path(&view)).await?;
+        if bytes.len() != value_file_len(&view) {
+            return Err(JournalError::CorruptBlock(view));
+        }
+
+        let mut values = vec![PixelValue::default(); view.image_width * view.image_height];
+        bytemuck::cast_slice_mut(&mut values).copy_from_slice(&bytes);
+
+        Ok(ValueBlock {
+            view,
+            values: values.into_boxed_slice(),
+        })
+    }
+
+    /// Deletes this journal, once the render it belongs to has finished.
+    pub async fn remove(self) -> Result<(), JournalError> {
+        tokio::fs::remove_dir_all(&self.dir).await?;
+        Ok(())
+    }
+
+    fn is_complete(&self, view: &View) -> bool {
+        self.values_path(view).metadata().map_or(false, |metadata| {
+            metadata.len() == value_file_len(view) as u64
+        })
+    }
+
+    fn values_path(&self, view: &View) -> PathBuf {
+        self.dir.join(format!(
+            "{}_{}.{}",
+            view.image_x, view.image_y, VALUES_FILE_EXTENSION
+        ))
+    }
+}
+
+fn value_file_len(view: &View) -> usize {
+    view.image_width * view.image_height * BYTES_PER_VALUE
+}
+
+#[derive(Debug, Error)]
+pub enum JournalError {
+    #[error("IO Error while accessing render journal")]
+    IOError(#[from] io::Error),
+    #[error("Ron Error while writing render journal job")]
+    RonError(#[from] ron::Error),
+    #[error("Journaled block for {0:?} is corrupt")]
+    CorruptBlock(View),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_opts(iterations: u32) -> FractalOpts {
+        FractalOpts {
+            iterations,
+            ..FractalOpts::test_base()
+        }
+    }
+
+    #[test]
+    fn resumes_matching_jobs_only() {
+        let output = std::env::temp_dir().join("fractal-rs-2-journal-resume.png");
+        remove_dir_all(RenderJournal::dir_for(&output)).ok();
+
+        let parent = View::new_centered_uniform(32, 32, 3.0);
+        let views: Vec<_> = parent.subdivide_rectangles(16, 16).collect();
+        let block = ValueBlock {
+            view: views[1],
+            values: vec![
+                PixelValue {
+                    value: 12.5,
+                    coverage: 0.75,
+                    ..Default::default()
+                };
+                16 * 16
+            ]
+            .into_boxed_slice(),
+        };
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let (journal, completed) =
+                RenderJournal::open(&output, &test_opts(100), parent, &views).unwrap();
+            assert!(completed.is_empty());
+            journal.record(&block).await.unwrap();
+
+            // the coloring isn't part of the job
+            let mut opts = test_opts(100);
+            opts.palette = Some(Default::default());
+            opts.interior_palette = Some(Default::default());
+            opts.shading.boundary_thickness = 2.0;
+            opts.histogram_equalization = true;
+            let (journal, completed) = RenderJournal::open(&output, &opts, parent, &views).unwrap();
+            assert_eq!(completed, vec![views[1]]);
+            assert_eq!(journal.load(views[1]).await.unwrap().values, block.values);
+
+            // different options start over
+            let (journal, completed) =
+                RenderJournal::open(&output, &test_opts(200), parent, &views).unwrap();
+            assert!(completed.is_empty());
+
+            journal.remove().await.unwrap();
+            assert!(!RenderJournal::dir_for(&output).exists());
+        });
+    }
+}
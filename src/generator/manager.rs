@@ -4,14 +4,26 @@
 
 use crate::{
     generator::{
-        row_stitcher::RowStitcher, view::View, FractalGenerator, FractalGeneratorFactory,
-        FractalGeneratorInstance, FractalOpts, PixelBlock,
+        color::Shading,
+        cpu::CpuFractalGeneratorFactory,
+        equalization::{Equalization, ValueDistribution},
+        gpu::readback::read_values,
+        journal::{JournalError, RenderJournal},
+        palette::Palette,
+        progressive,
+        progressive::{start_downscaled_generation_to_gpu, Pass},
+        row_stitcher::{RowColorer, RowStitcher},
+        view::View,
+        FractalGenerator, FractalGeneratorFactory, FractalGeneratorInstance, FractalOpts,
+        PixelFormat, ValueBlock,
     },
     gpu::GPUContext,
     util::future::{future_wrapper::FutureWrapper, poll_join_result, poll_optional, RunningState},
 };
+use futures::future::BoxFuture;
 use mtpng::{encoder, ColorType, Header};
 use std::{
+    collections::{HashSet, VecDeque},
     fmt::Debug,
     fs::File,
     io::BufWriter,
@@ -31,6 +43,10 @@ use wgpu::{Texture, TextureView};
 
 const MAX_CHUNK_BACKLOG: usize = 32;
 
+/// How many generators with different options are kept around. Progressive
+/// generations use two: one for the preview passes and one for the last pass.
+const MAX_CACHED_GENERATORS: usize = 2;
+
 /// Handles the gritty details of polling generator & instance futures.
 pub struct GeneratorManager {
     // runtime handle
@@ -41,11 +57,21 @@ pub struct GeneratorManager {
 
     // stuff for use when creating new generators and managing generators
     factory: Arc<dyn FractalGeneratorFactory + Send + Sync + 'static>,
+    fallback_factory: Arc<dyn FractalGeneratorFactory + Send + Sync + 'static>,
     generator_future: Option<(
         StartArgs,
         JoinHandle<anyhow::Result<Box<dyn FractalGenerator + Send + 'static>>>,
     )>,
-    current_generator: Option<(FractalOpts, Box<dyn FractalGenerator + Send + 'static>)>,
+    /// The most recently used generators, from least to most recent.
+    generators: Vec<(FractalOpts, Box<dyn FractalGenerator + Send + 'static>)>,
+
+    // stuff for multi-pass generations to the GUI
+    gui_passes: VecDeque<Pass>,
+    gui_target: Option<GuiTarget>,
+    pass_index: usize,
+    pass_count: usize,
+    equalization_future: Option<JoinHandle<anyhow::Result<Option<Equalization>>>>,
+    equalization: Option<Equalization>,
 
     // stuff for managing a running instance
     current_instance: RunningState<
@@ -72,8 +98,15 @@ impl GeneratorManager {
         GeneratorManager {
             handle,
             factory,
+            fallback_factory: Arc::new(CpuFractalGeneratorFactory::new(num_cpus::get())),
             generator_future: None,
-            current_generator: None,
+            generators: vec![],
+            gui_passes: VecDeque::new(),
+            gui_target: None,
+            pass_index: 0,
+            pass_count: 0,
+            equalization_future: None,
+            equalization: None,
             current_instance: RunningState::NotStarted,
             progress_future: None,
             running_future: None,
@@ -91,12 +124,21 @@ impl GeneratorManager {
         self.current_instance.is_started()
             || self.generator_future.is_some()
             || self.current_image_writer.contains_future()
+            || !self.gui_passes.is_empty()
+            || self.equalization_future.is_some()
     }
 
     /// Gets this InstanceManager's FractalGeneratorInstance's current
-    /// generation progress.
+    /// generation progress. Each pass of a multi-pass generation counts for
+    /// the same share of the progress.
     pub fn progress(&self) -> f32 {
-        self.progress
+        ((self.pass_index as f32 + self.progress) / self.pass_count.max(1) as f32).min(1.0)
+    }
+
+    /// Gets the equalization of the values of the last generation to the GUI,
+    /// if it was equalized and has finished.
+    pub fn equalization(&self) -> Option<&Equalization> {
+        self.equalization.as_ref()
     }
 
     /// Gets this manager's writer's current writing progress.
@@ -116,7 +158,26 @@ impl GeneratorManager {
         factory: Arc<dyn FractalGeneratorFactory + Send + Sync + 'static>,
     ) {
         self.factory = factory;
-        self.current_generator = None;
+        self.generators.clear();
+    }
+
+    /// Checks whether fractals with the given options will be generated by
+    /// this manager's [`FractalGeneratorFactory`] in their precision.
+    ///
+    /// If not, a CPU generator, which supports every precision, is used
+    /// instead.
+    ///
+    /// [`FractalGeneratorFactory`]: crate::generator::FractalGeneratorFactory
+    pub fn factory_supports_precision(&self, opts: &FractalOpts) -> bool {
+        self.factory.supports_precision(opts)
+    }
+
+    /// Leaves out the options that the generator for them would ignore, so
+    /// that they don't keep cached generators from being reused.
+    fn strip_unused_opts(&self, opts: &mut FractalOpts) {
+        if !self.factory.supports_precision(opts) || !self.factory.uses_plane_start() {
+            opts.plane_start = None;
+        }
     }
 
     /// Cancels any running fractal generator associated with this manager.
@@ -130,7 +191,20 @@ impl GeneratorManager {
     ///
     /// First this `InstanceManager` checks to make sure it has a
     /// [`FractalGenerator`] with the correct [`FractalOpts`], creating a new
-    /// one if needed.
+    /// one if needed. The generated values are colored with the palettes and
+    /// shading in `opts` into pixels of the given `format` as they are
+    /// written, so changes to those alone never require a new generator.
+    ///
+    /// If `resumable` is set, completed blocks are recorded in a
+    /// [`RenderJournal`] next to `output` until the image has been written. If
+    /// a journal of the same job already exists, the views it completed are
+    /// loaded from it instead of being generated again.
+    ///
+    /// If histogram equalization is enabled, no rows are written until every
+    /// block has been generated, because coloring them needs the distribution
+    /// of all of the values of the image. The blocks are always journaled in
+    /// that case, so that they can be read back one row at a time once the
+    /// distribution is known instead of all being held in memory.
     ///
     /// [`FractalGenerator`]: crate::generator::FractalGenerator
     /// [`FractalOpts`]: crate::generator::FractalOpts
@@ -138,11 +212,13 @@ impl GeneratorManager {
     ///   crate::generator::FractalGenerator::start_generation_to_cpu
     pub fn start_to_image(
         &mut self,
-        opts: FractalOpts,
+        mut opts: FractalOpts,
         parent_view: View,
         child_views: Vec<View>,
         cache_generators: bool,
         output: PathBuf,
+        format: PixelFormat,
+        resumable: bool,
     ) -> Result<(), ImageStartError> {
         // make sure we're not currently running
         if self.running() {
@@ -154,55 +230,95 @@ impl GeneratorManager {
             return Err(ImageStartError::PathIsEmpty);
         }
 
+        let palette = opts.palette.take();
+        let interior_palette = opts.interior_palette.take();
+        let shading = std::mem::take(&mut opts.shading);
+        let histogram_equalization = std::mem::take(&mut opts.histogram_equalization);
+        self.strip_unused_opts(&mut opts);
+
+        let (journal, journaled) = if resumable || histogram_equalization {
+            let (journal, journaled) =
+                RenderJournal::open(&output, &opts, parent_view, &child_views)?;
+            (Some(journal), journaled)
+        } else {
+            (None, vec![])
+        };
+        let journaled_positions: HashSet<_> = journaled
+            .iter()
+            .map(|view| (view.image_x, view.image_y))
+            .collect();
+        let generate_views = child_views
+            .iter()
+            .copied()
+            .filter(|view| !journaled_positions.contains(&(view.image_x, view.image_y)))
+            .collect();
+
+        let job = ImageJob {
+            palette,
+            interior_palette,
+            shading,
+            histogram_equalization,
+            iterations: opts.iterations,
+            parent_view,
+            child_views,
+            generate_views,
+            journal,
+            journaled,
+            output,
+            format,
+        };
+
         self.cancel.store(false, Ordering::Release);
         self.instance_canceled = false;
+        self.pass_index = 0;
+        self.pass_count = 1;
+        self.gui_target = None;
 
         // check to see if we need to create a new generator
-        if self.current_generator.is_none()
-            || !cache_generators
-            || self.current_generator.as_ref().unwrap().0 != opts
-        {
-            // we need to create a new generator
-            self.start_with_new_generator(StartArgs::CPU {
-                opts,
-                parent_view,
-                child_views,
-                output,
-            });
-        } else {
-            self.image_max_y = parent_view.image_height;
-            self.image_writer_progress.store(0, Ordering::Release);
+        match self.cached_generator_index(&opts, cache_generators) {
+            Some(index) => {
+                // we can start the generator now
+                let (opts, generator) = self.generators.remove(index);
+                self.start_image_job(generator.as_ref(), job);
+                self.cache_generator(opts, generator);
+            },
+            None => {
+                // we need to create a new generator
+                self.start_with_new_generator(StartArgs::CPU { opts, job });
+            },
+        }
+
+        Ok(())
+    }
+
+    /// Starts generating the views of `job` that aren't journaled yet using
+    /// `generator`, along with the image writer.
+    fn start_image_job(&mut self, generator: &(dyn FractalGenerator + Send), job: ImageJob) {
+        self.image_max_y = job.parent_view.image_height;
+        self.image_writer_progress.store(0, Ordering::Release);
 
-            let (sender, receiver) = mpsc::channel(MAX_CHUNK_BACKLOG);
+        let (sender, receiver) = mpsc::channel(MAX_CHUNK_BACKLOG);
 
-            // we can start the generator now
+        // A job that was completed entirely by its journal only needs to be
+        // written, which happens once the sender is dropped here.
+        if !job.generate_views.is_empty() {
             self.current_instance = RunningState::Starting(
-                self.handle.spawn(
-                    self.current_generator
-                        .as_ref()
-                        .unwrap()
-                        .1
-                        .start_generation_to_cpu(&child_views, sender),
-                ),
+                self.handle
+                    .spawn(generator.start_generation_to_cpu(&job.generate_views, sender)),
             );
-
-            // start the image writer too
-            self.current_image_writer
-                .insert_spawn(
-                    &self.handle,
-                    write_to_image(
-                        self.cancel.clone(),
-                        self.image_writer_progress.clone(),
-                        receiver,
-                        parent_view,
-                        child_views,
-                        output,
-                    ),
-                )
-                .unwrap();
         }
 
-        Ok(())
+        self.current_image_writer
+            .insert_spawn(
+                &self.handle,
+                write_to_image(
+                    self.cancel.clone(),
+                    self.image_writer_progress.clone(),
+                    receiver,
+                    job,
+                ),
+            )
+            .unwrap();
     }
 
     /// Starts this `InstanceManager` managing an instance if it is not already
@@ -211,7 +327,18 @@ impl GeneratorManager {
     ///
     /// First this `InstanceManager` checks to make sure it has a
     /// [`FractalGenerator`] with the correct [`FractalOpts`], creating a new
-    /// one if needed.
+    /// one if needed. The generated values are written to `texture`, which the
+    /// caller is responsible for coloring, so the palettes and shading in
+    /// `opts` are ignored.
+    ///
+    /// If histogram equalization is enabled, the values are read back once
+    /// the last pass has finished to collect their distribution, which is
+    /// then available from [`equalization`](Self::equalization).
+    ///
+    /// If `progressive` is set, the fractal is first generated in several
+    /// [`progressive::passes`] of increasing resolution, each of which is
+    /// upscaled into `texture` so the image refines in place. Canceling stops
+    /// the generation before its next pass.
     ///
     /// [`FractalGenerator`]: crate::generator::FractalGenerator
     /// [`FractalOpts`]: crate::generator::FractalOpts
@@ -219,9 +346,10 @@ impl GeneratorManager {
     ///   crate::generator::FractalGenerator::start_generation_to_gpu()
     pub fn start_to_gui(
         &mut self,
-        opts: FractalOpts,
+        mut opts: FractalOpts,
         views: Vec<View>,
         cache_generators: bool,
+        progressive: bool,
         present: GPUContext,
         texture: Arc<Texture>,
         texture_view: Arc<TextureView>,
@@ -231,47 +359,131 @@ impl GeneratorManager {
             return Err(ViewerStartError::AlreadyRunning { opts });
         }
 
+        opts.palette = None;
+        opts.interior_palette = None;
+        opts.shading = Default::default();
+        let histogram_equalization = std::mem::take(&mut opts.histogram_equalization);
+        let iterations = opts.iterations;
+        self.strip_unused_opts(&mut opts);
+
         self.cancel.store(false, Ordering::Release);
         self.instance_canceled = false;
 
+        let passes = if progressive {
+            progressive::passes(&opts)
+        } else {
+            vec![Pass { opts, downscale: 1 }]
+        };
+        self.pass_index = 0;
+        self.pass_count = passes.len();
+        self.gui_passes = passes.into();
+        self.gui_target = Some(GuiTarget {
+            views,
+            cache_generators,
+            histogram_equalization,
+            iterations,
+            present,
+            texture,
+            texture_view,
+        });
+        self.equalization = None;
+
+        self.start_next_gui_pass();
+
+        Ok(())
+    }
+
+    /// Starts the next pass of the current generation to the GUI, if there is
+    /// one.
+    fn start_next_gui_pass(&mut self) {
+        let (pass, target) = match (self.gui_passes.pop_front(), &self.gui_target) {
+            (Some(pass), Some(target)) => (pass, target.clone()),
+            _ => return,
+        };
+
         // check to see if we need to create a new generator
-        if self.current_generator.is_none()
-            || !cache_generators
-            || self.current_generator.as_ref().unwrap().0 != opts
-        {
-            // we need to create a new generator
-            self.start_with_new_generator(StartArgs::GPU {
-                opts,
-                views,
-                present,
-                texture,
-                texture_view,
-            });
+        match self.cached_generator_index(&pass.opts, target.cache_generators) {
+            Some(index) => {
+                // we can start the generator now
+                self.current_instance = RunningState::Starting(self.handle.spawn(start_gui_pass(
+                    self.generators[index].1.as_ref(),
+                    &pass,
+                    &target,
+                )));
+            },
+            None => {
+                // we need to create a new generator
+                self.start_with_new_generator(StartArgs::GPU { pass, target });
+            },
+        }
+    }
+
+    /// Starts collecting the distribution of the values of a finished
+    /// generation to the GUI, if it is equalized.
+    fn start_equalization(&mut self) {
+        let target = match self.gui_target.take() {
+            Some(target) if target.histogram_equalization => target,
+            _ => return,
+        };
+
+        info!("Reading back values for equalization...");
+        let values = read_values(&target.present, target.texture);
+        self.equalization_future = Some(self.handle.spawn(async move {
+            let values = values.await?;
+            Ok(tokio::task::spawn_blocking(move || {
+                let mut distribution = ValueDistribution::new(target.iterations as f32);
+                distribution.add(&values);
+                distribution.equalization()
+            })
+            .await?)
+        }));
+    }
+
+    /// Finds the cached generator created with the given options, if caching
+    /// is enabled.
+    fn cached_generator_index(&self, opts: &FractalOpts, cache_generators: bool) -> Option<usize> {
+        if cache_generators {
+            self.generators
+                .iter()
+                .position(|(generator_opts, _)| generator_opts == opts)
         } else {
-            // we can start the generator now
-            self.current_instance = RunningState::Starting(
-                self.handle.spawn(
-                    self.current_generator
-                        .as_ref()
-                        .unwrap()
-                        .1
-                        .start_generation_to_gpu(&views, present, texture, texture_view),
-                ),
-            );
+            None
         }
+    }
 
-        Ok(())
+    /// Makes `generator` the most recently used cached generator, dropping the
+    /// least recently used one if there are too many.
+    fn cache_generator(
+        &mut self,
+        opts: FractalOpts,
+        generator: Box<dyn FractalGenerator + Send + 'static>,
+    ) {
+        self.generators
+            .retain(|(generator_opts, _)| *generator_opts != opts);
+        self.generators.push((opts, generator));
+        if self.generators.len() > MAX_CACHED_GENERATORS {
+            self.generators.remove(0);
+        }
     }
 
     fn start_with_new_generator(&mut self, args: StartArgs) {
         let opts = match &args {
             StartArgs::CPU { opts, .. } => opts.clone(),
-            StartArgs::GPU { opts, .. } => opts.clone(),
+            StartArgs::GPU { pass, .. } => pass.opts.clone(),
+        };
+
+        let factory = if self.factory.supports_precision(&opts) {
+            &self.factory
+        } else {
+            info!(
+                "Current factory does not support {:?} precision, falling back to CPU",
+                opts.precision
+            );
+            &self.fallback_factory
         };
 
         info!("Creating new Fractal Generator...");
-        self.generator_future =
-            Some((args, self.handle.spawn(self.factory.create_generator(opts))));
+        self.generator_future = Some((args, self.handle.spawn(factory.create_generator(opts))));
     }
 
     /// Polls the instance and futures currently being managed by this
@@ -285,63 +497,28 @@ impl GeneratorManager {
                 // ever Some(...), it's safe to assume that we're
                 // starting a fractal generator.
                 let opts = match args {
-                    StartArgs::CPU {
-                        opts,
-                        parent_view,
-                        child_views,
-                        output,
-                    } => {
+                    StartArgs::CPU { opts, job } => {
                         if !self.cancel.load(Ordering::Acquire) {
-                            self.image_max_y = parent_view.image_height;
-                            self.image_writer_progress.store(0, Ordering::Release);
-
-                            let (sender, receiver) = mpsc::channel(MAX_CHUNK_BACKLOG);
-
-                            self.current_instance = RunningState::Starting(
-                                self.handle
-                                    .spawn(generator.start_generation_to_cpu(&child_views, sender)),
-                            );
-
-                            self.current_image_writer
-                                .insert_spawn(
-                                    &self.handle,
-                                    write_to_image(
-                                        self.cancel.clone(),
-                                        self.image_writer_progress.clone(),
-                                        receiver,
-                                        parent_view,
-                                        child_views,
-                                        output,
-                                    ),
-                                )
-                                .unwrap();
+                            self.start_image_job(generator.as_ref(), job);
                         }
 
                         opts
                     },
-                    StartArgs::GPU {
-                        opts,
-                        views,
-                        present,
-                        texture,
-                        texture_view,
-                    } => {
+                    StartArgs::GPU { pass, target } => {
                         if !self.cancel.load(Ordering::Acquire) {
-                            self.current_instance = RunningState::Starting(self.handle.spawn(
-                                generator.start_generation_to_gpu(
-                                    &views,
-                                    present,
-                                    texture,
-                                    texture_view,
-                                ),
-                            ));
+                            self.current_instance =
+                                RunningState::Starting(self.handle.spawn(start_gui_pass(
+                                    generator.as_ref(),
+                                    &pass,
+                                    &target,
+                                )));
                         }
 
-                        opts
+                        pass.opts
                     },
                 };
 
-                self.current_generator = Some((opts, generator));
+                self.cache_generator(opts, generator);
             } else {
                 // put the args and the future back in the option
                 self.generator_future = Some((args, future));
@@ -357,6 +534,9 @@ impl GeneratorManager {
         }
 
         // check if we're canceled
+        if self.cancel.load(Ordering::Acquire) {
+            self.gui_passes.clear();
+        }
         if let RunningState::Running(instance) = &self.current_instance {
             if self.cancel.load(Ordering::Acquire) && !self.instance_canceled {
                 instance.cancel();
@@ -384,17 +564,36 @@ impl GeneratorManager {
             }
         });
 
+        // apply progress value
+        if let Some(progress) = progress {
+            self.progress = progress?;
+        }
+
         // apply running value
         if let Some(running) = running {
             let running = running?;
             if !running {
                 self.current_instance = RunningState::NotStarted;
+                self.pass_index += 1;
+                self.progress = 0.0;
+
+                // passes are only started after the previous one has finished
+                if !self.cancel.load(Ordering::Acquire) {
+                    if self.gui_passes.is_empty() {
+                        self.start_equalization();
+                    } else {
+                        self.start_next_gui_pass();
+                    }
+                }
             }
         }
 
-        // apply progress value
-        if let Some(progress) = progress {
-            self.progress = progress?;
+        // poll the equalization join handle
+        if let Some(mut future) = self.equalization_future.take() {
+            match poll_join_result(&self.handle, &mut future) {
+                Some(equalization) => self.equalization = equalization?,
+                None => self.equalization_future = Some(future),
+            }
         }
 
         // poll image writer join handle
@@ -418,6 +617,8 @@ pub enum ImageStartError {
     AlreadyRunning { opts: FractalOpts },
     #[error("output file path is empty")]
     PathIsEmpty,
+    #[error("error opening render journal")]
+    JournalError(#[from] JournalError),
 }
 
 #[derive(Debug, Error)]
@@ -438,20 +639,50 @@ pub enum WriteError {
     Anyhow(#[from] anyhow::Error),
     #[error("JoinError while writing image to file")]
     JoinError(#[from] JoinError),
+    #[error("error accessing render journal")]
+    JournalError(#[from] JournalError),
 }
 
 async fn write_to_image(
     canceled: Arc<AtomicBool>,
     progress: Arc<AtomicUsize>,
-    mut receiver: Receiver<anyhow::Result<PixelBlock>>,
-    parent_view: View,
-    child_views: Vec<View>,
-    output: PathBuf,
+    mut receiver: Receiver<anyhow::Result<ValueBlock>>,
+    job: ImageJob,
 ) -> Result<(), WriteError> {
+    let ImageJob {
+        palette,
+        interior_palette,
+        shading,
+        histogram_equalization,
+        iterations,
+        parent_view,
+        child_views,
+        journal,
+        mut journaled,
+        output,
+        format,
+        ..
+    } = job;
+
     if canceled.load(Ordering::Acquire) {
         return Err(WriteError::Canceled);
     }
 
+    // This happens before the output file is created, so that errors don't
+    // have to shut down the encoder. Every block ends up in the journal, so
+    // they are all loaded from it afterwards.
+    let equalization = if histogram_equalization {
+        let journal = journal
+            .as_ref()
+            .expect("Equalized images are always journaled");
+        let equalization =
+            gather_equalization(&canceled, &mut receiver, journal, &journaled, iterations).await?;
+        journaled = child_views.clone();
+        equalization
+    } else {
+        None
+    };
+
     info!("Creating output file...");
     let stream_writer: Result<_, WriteError> = tokio::task::spawn_blocking(move || {
         // we have to do blocking file operations because MTPNG doesn't like
@@ -465,7 +696,7 @@ async fn write_to_image(
             parent_view.image_width as u32,
             parent_view.image_height as u32,
         )?;
-        header.set_color(ColorType::TruecolorAlpha, 8)?;
+        header.set_color(ColorType::TruecolorAlpha, format.bit_depth())?;
         encoder.write_header(&header)?;
         Ok(encoder)
     })
@@ -474,11 +705,94 @@ async fn write_to_image(
 
     let mut stream_writer = Some(stream_writer?);
 
+    // Blocks are stitched before they are colored, so that the relief slopes
+    // along their edges can see the blocks next to them.
     let mut row_stitcher = RowStitcher::new(parent_view, &child_views);
+    let mut row_colorer = RowColorer::new(palette, interior_palette, shading, format);
+
+    // Journaled blocks are only loaded once their row is the next one to be
+    // written, so that they don't all have to be held in memory.
+    let mut journaled: VecDeque<View> = journaled.into();
+    let mut next_row_y = parent_view.image_y;
 
     info!("Starting image writer loop...");
-    // while let Some(block) = receiver.recv().await
     loop {
+        // write every row that is ready
+        loop {
+            while journaled
+                .front()
+                .map_or(false, |view| view.image_y == next_row_y)
+            {
+                let view = journaled.pop_front().unwrap();
+                let mut block = match journal.as_ref().unwrap().load(view).await {
+                    Ok(b) => b,
+                    Err(e) => {
+                        // This will probably return an error since image writing could be
+                        // incomplete. We'll just ignore it
+                        tokio::task::spawn_blocking(move || stream_writer.unwrap().flush())
+                            .await
+                            .expect("Something panicked while flushing the encoder")
+                            .ok();
+                        return Err(e.into());
+                    },
+                };
+
+                info!(
+                    "Loaded journaled block at ({}, {})",
+                    block.view.image_x, block.view.image_y
+                );
+                if let Some(equalization) = &equalization {
+                    equalization.apply(&mut block);
+                }
+                row_stitcher.insert(block);
+            }
+
+            let row = match row_stitcher.stitch() {
+                Poll::Ready(Some(row)) => {
+                    next_row_y = row.view.image_y + row.view.image_height;
+                    match row_colorer.push(row) {
+                        Some(row) => row,
+                        None => continue,
+                    }
+                },
+                // the last row is colored once nothing else can be stitched
+                Poll::Ready(None) => match row_colorer.finish() {
+                    Some(row) => row,
+                    None => break,
+                },
+                Poll::Pending => break,
+            };
+
+            let image_y = row.view.image_y;
+            let image_height = row.view.image_height;
+            let mut moved_writer = stream_writer.take().unwrap();
+            info!("Writing row at y={}", image_y);
+
+            let moved_writer: Result<_, WriteError> = tokio::task::spawn_blocking(move || {
+                moved_writer.write_image_rows(&row.image)?;
+                Ok(moved_writer)
+            })
+            .await
+            .expect("Something panicked while writing a row of the output PNG");
+
+            // we're using a match here because mtpng does not handle being dropped in the
+            // middle of encoding very well, so we need to shut it down first
+            stream_writer = Some(match moved_writer {
+                Ok(b) => b,
+                Err(e) => {
+                    // This will probably return an error since image writing could be incomplete.
+                    // We'll just ignore it
+                    tokio::task::spawn_blocking(move || stream_writer.unwrap().flush())
+                        .await
+                        .expect("Something panicked while flushing the encoder")
+                        .ok();
+                    return Err(e.into());
+                },
+            });
+
+            progress.store(image_y + image_height, Ordering::Release);
+        }
+
         tokio::select! {
             biased;
             poll_block = receiver.recv() => {
@@ -492,7 +806,7 @@ async fn write_to_image(
                     return Err(WriteError::Canceled);
                 }
 
-                let block: anyhow::Result<PixelBlock> = if let Some(block) = poll_block {
+                let block: anyhow::Result<ValueBlock> = if let Some(block) = poll_block {
                     block
                 } else {
                     break;
@@ -517,38 +831,20 @@ async fn write_to_image(
                     "Received block at ({}, {})",
                     block.view.image_x, block.view.image_y
                 );
-                row_stitcher.insert(block);
-
-                while let Poll::Ready(Some(row)) = row_stitcher.stitch() {
-                    let image_y = row.view.image_y;
-                    let image_height = row.view.image_height;
-                    let mut moved_writer = stream_writer.take().unwrap();
-                    info!("Writing row at y={}", image_y);
-
-                    let moved_writer: Result<_, WriteError> = tokio::task::spawn_blocking(move || {
-                        moved_writer.write_image_rows(&row.image)?;
-                        Ok(moved_writer)
-                    })
-                    .await
-                    .expect("Something panicked while writing a row of the output PNG");
-
-                    // we're using a match here because mtpng does not handle being dropped in the
-                    // middle of encoding very well, so we need to shut it down first
-                    stream_writer = Some(match moved_writer {
-                        Ok(b) => b,
-                        Err(e) => {
-                            // This will probably return an error since image writing could be incomplete.
-                            // We'll just ignore it
-                            tokio::task::spawn_blocking(move || stream_writer.unwrap().flush())
-                                .await
-                                .expect("Something panicked while flushing the encoder")
-                                .ok();
-                            return Err(e.into());
-                        }
-                    });
 
-                    progress.store(image_y + image_height, Ordering::Release);
+                if let Some(journal) = &journal {
+                    if let Err(e) = journal.record(&block).await {
+                        // This will probably return an error since image writing could be incomplete.
+                        // We'll just ignore it
+                        tokio::task::spawn_blocking(move || stream_writer.unwrap().flush())
+                            .await
+                            .expect("Something panicked while flushing the encoder")
+                            .ok();
+                        return Err(e.into());
+                    }
                 }
+
+                row_stitcher.insert(block);
             },
             else => {
                 if canceled.load(Ordering::Acquire) {
@@ -581,21 +877,253 @@ async fn write_to_image(
 
     info!("Finished writing PNG");
 
+    if let Some(journal) = journal {
+        info!("Removing render journal...");
+        journal.remove().await?;
+    }
+
     Ok(())
 }
 
+/// Collects the distribution of the values of every block of an image, both
+/// from its journal and from its generator. The generated blocks are recorded
+/// in the journal, so that only one block is held in memory at a time.
+async fn gather_equalization(
+    canceled: &AtomicBool,
+    receiver: &mut Receiver<anyhow::Result<ValueBlock>>,
+    journal: &RenderJournal,
+    journaled: &[View],
+    iterations: u32,
+) -> Result<Option<Equalization>, WriteError> {
+    let mut distribution = ValueDistribution::new(iterations as f32);
+
+    for &view in journaled {
+        let block = journal.load(view).await?;
+        info!(
+            "Loaded journaled block at ({}, {})",
+            block.view.image_x, block.view.image_y
+        );
+        distribution.add_block(&block);
+    }
+
+    while let Some(block) = receiver.recv().await {
+        if canceled.load(Ordering::Acquire) {
+            return Err(WriteError::Canceled);
+        }
+
+        let block = block?;
+        info!(
+            "Received block at ({}, {})",
+            block.view.image_x, block.view.image_y
+        );
+
+        journal.record(&block).await?;
+        distribution.add_block(&block);
+    }
+
+    if canceled.load(Ordering::Acquire) {
+        return Err(WriteError::Canceled);
+    }
+
+    info!("Equalizing values...");
+    Ok(distribution.equalization())
+}
+
+/// Everything the image writer needs to know about a render to an image.
+struct ImageJob {
+    palette: Option<Palette>,
+    interior_palette: Option<Palette>,
+    shading: Shading,
+    histogram_equalization: bool,
+    /// The iteration count of the generator, which the values are at most
+    /// about.
+    iterations: u32,
+    parent_view: View,
+    child_views: Vec<View>,
+    /// The child views that have to be generated, which leaves out the
+    /// journaled ones.
+    generate_views: Vec<View>,
+    journal: Option<RenderJournal>,
+    /// The child views that are loaded from the journal instead.
+    journaled: Vec<View>,
+    output: PathBuf,
+    format: PixelFormat,
+}
+
 enum StartArgs {
-    CPU {
-        opts: FractalOpts,
+    CPU { opts: FractalOpts, job: ImageJob },
+    GPU { pass: Pass, target: GuiTarget },
+}
+
+/// Where the passes of a generation to the GUI are generated.
+#[derive(Clone)]
+struct GuiTarget {
+    views: Vec<View>,
+    cache_generators: bool,
+    histogram_equalization: bool,
+    /// The iteration count of the last pass, which the values are at most
+    /// about.
+    iterations: u32,
+    present: GPUContext,
+    texture: Arc<Texture>,
+    texture_view: Arc<TextureView>,
+}
+
+/// Starts generating a single pass of a generation to the GUI.
+fn start_gui_pass(
+    generator: &(dyn FractalGenerator + Send),
+    pass: &Pass,
+    target: &GuiTarget,
+) -> BoxFuture<'static, anyhow::Result<Box<dyn FractalGeneratorInstance + Send + 'static>>> {
+    if pass.downscale == 1 {
+        generator.start_generation_to_gpu(
+            &target.views,
+            target.present.clone(),
+            target.texture.clone(),
+            target.texture_view.clone(),
+        )
+    } else {
+        start_downscaled_generation_to_gpu(
+            generator,
+            &target.views,
+            pass.downscale,
+            target.present.clone(),
+            target.texture.clone(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{color::Relief, cpu::opts::CpuFractalOpts, PixelValue};
+    use cgmath::Vector2;
+    use std::fs::{read, remove_dir_all, remove_file};
+    use tokio::runtime::Runtime;
+
+    fn gen_block(opts: &FractalOpts, view: View) -> ValueBlock {
+        let offsets = [Vector2::new(0.5, 0.5)];
+        let values: Vec<PixelValue> = (0..view.image_height)
+            .flat_map(|y| (0..view.image_width).map(move |x| (x, y)))
+            .map(|(x, y)| opts.gen_pixel(view, x, y, &offsets))
+            .collect();
+        ValueBlock {
+            view,
+            values: values.into_boxed_slice(),
+        }
+    }
+
+    fn image_job(
         parent_view: View,
-        child_views: Vec<View>,
+        child_views: &[View],
+        journal: Option<RenderJournal>,
+        journaled: Vec<View>,
         output: PathBuf,
-    },
-    GPU {
-        opts: FractalOpts,
-        views: Vec<View>,
-        present: GPUContext,
-        texture: Arc<Texture>,
-        texture_view: Arc<TextureView>,
-    },
+    ) -> ImageJob {
+        ImageJob {
+            palette: Some(Default::default()),
+            interior_palette: None,
+            // relief slopes cross the edges between journaled and generated rows
+            shading: Shading {
+                relief: Some(Relief::default()),
+                ..Default::default()
+            },
+            histogram_equalization: false,
+            iterations: 200,
+            parent_view,
+            child_views: child_views.to_vec(),
+            generate_views: vec![],
+            journal,
+            journaled,
+            output,
+            format: PixelFormat::Rgba8,
+        }
+    }
+
+    #[test]
+    fn resumed_images_match_uninterrupted_ones() {
+        let opts = FractalOpts::test_base();
+        let parent = View::new_centered_uniform(32, 32, 3.0);
+        let views: Vec<_> = parent.subdivide_rectangles(16, 8).collect();
+        let blocks: Vec<_> = views.iter().map(|&view| gen_block(&opts, view)).collect();
+
+        let dir = std::env::temp_dir();
+        let whole_output = dir.join("fractal-rs-2-manager-whole.png");
+        let resumed_output = dir.join("fractal-rs-2-manager-resumed.png");
+        remove_dir_all(RenderJournal::dir_for(&resumed_output)).ok();
+
+        let runtime = Runtime::new().unwrap();
+        runtime.block_on(async {
+            // the whole image, without a journal
+            let (sender, receiver) = mpsc::channel(blocks.len());
+            for block in &blocks {
+                sender.send(Ok(block.clone())).await.unwrap();
+            }
+            drop(sender);
+            let job = image_job(parent, &views, None, vec![], whole_output.clone());
+            write_to_image(Default::default(), Default::default(), receiver, job)
+                .await
+                .unwrap();
+
+            // The first row is only colored once the second row arrives, for
+            // its relief, so the first two rows have been journaled once the
+            // first one is written. The render is canceled then.
+            let (journal, journaled) =
+                RenderJournal::open(&resumed_output, &opts, parent, &views).unwrap();
+            assert!(journaled.is_empty());
+            let canceled = Arc::new(AtomicBool::new(false));
+            let progress = Arc::new(AtomicUsize::new(0));
+            let (sender, receiver) = mpsc::channel(blocks.len());
+            let job = image_job(
+                parent,
+                &views,
+                Some(journal),
+                vec![],
+                resumed_output.clone(),
+            );
+            let writer = tokio::spawn(write_to_image(
+                canceled.clone(),
+                progress.clone(),
+                receiver,
+                job,
+            ));
+            for block in &blocks[..4] {
+                sender.send(Ok(block.clone())).await.unwrap();
+            }
+            while progress.load(Ordering::Acquire) < views[0].image_height {
+                tokio::task::yield_now().await;
+            }
+            canceled.store(true, Ordering::Release);
+            drop(sender);
+            assert!(matches!(writer.await.unwrap(), Err(WriteError::Canceled)));
+
+            // resuming only generates the rest
+            let (journal, journaled) =
+                RenderJournal::open(&resumed_output, &opts, parent, &views).unwrap();
+            assert_eq!(journaled, views[..4]);
+            let (sender, receiver) = mpsc::channel(blocks.len());
+            for block in &blocks[4..] {
+                sender.send(Ok(block.clone())).await.unwrap();
+            }
+            drop(sender);
+            let job = image_job(
+                parent,
+                &views,
+                Some(journal),
+                journaled,
+                resumed_output.clone(),
+            );
+            write_to_image(Default::default(), Default::default(), receiver, job)
+                .await
+                .unwrap();
+        });
+
+        let whole = read(&whole_output).unwrap();
+        let resumed = read(&resumed_output).unwrap();
+        remove_file(&whole_output).ok();
+        remove_file(&resumed_output).ok();
+
+        assert!(!RenderJournal::dir_for(&resumed_output).exists());
+        assert!(whole == resumed, "resumed image differs");
+    }
 }
@@ -17,57 +17,213 @@
 //!    starts. This is where the [`View`] is specified. This phase is started by
 //!    calling either [`FractalGenerator::start_generation_to_cpu()`] to
 //!    generate the fractal into a series of CPU-side blocks of memory
-//!    ([`PixelBlock`]s), or [`FractalGenerator::start_generation_to_gpu()`] to
+//!    ([`ValueBlock`]s), or [`FractalGenerator::start_generation_to_gpu()`] to
 //!    generate the fractal into a GPU-side texture. These methods return a
 //!    [`FractalGeneratorInstance`] which represents a running instance of a
 //!    fractal generator, which can be used to track the progress of generation.
 //!
+//! Generators only produce smoothed iteration counts ([`PixelValue`]s). These
+//! are turned into colors by a separate, much cheaper, recolor pass, either on
+//! the CPU by [`ValueBlock::color_with_neighbors()`] or on the GPU by a
+//! [`Recolorer`], so that changing the palette does not require generating the
+//! fractal again.
+//!
 //! [`View`]: view::View
+//! [`Recolorer`]: gpu::recolor::Recolorer
 
 pub mod args;
 pub mod color;
 pub mod composite;
 pub mod cpu;
+pub mod equalization;
+pub mod expression;
 pub mod gpu;
+pub mod journal;
 pub mod manager;
+pub mod palette;
+pub mod perturbation;
+pub mod progressive;
+pub mod remote;
 pub mod row_stitcher;
+pub mod trap;
 pub mod util;
 pub mod view;
 
 use crate::{
     generator::{
-        args::{Multisampling, Smoothing},
+        args::{
+            Averaging, BoundaryTracing, CpuKernel, Formula, InteriorChecks, InteriorColoring,
+            Multisampling, Precision, Smoothing,
+        },
+        color::{color_value, RGBA8Color, Shading},
+        palette::Palette,
+        perturbation::big_float::PlanePoint,
+        trap::OrbitTrap,
         view::View,
     },
     gpu::GPUContext,
 };
+use bytemuck::{Pod, Zeroable};
+use cgmath::{Vector2, Vector4, Zero};
 use futures::future::BoxFuture;
 use num_complex::Complex;
 use std::{
     fmt::{Debug, Formatter},
     mem::size_of,
+    str::FromStr,
     sync::Arc,
 };
 use tokio::sync::mpsc::Sender;
-use wgpu::{Texture, TextureView};
+use wgpu::{Texture, TextureFormat, TextureView};
 
-pub const BYTES_PER_PIXEL: usize = size_of::<u32>();
+pub const BYTES_PER_VALUE: usize = size_of::<PixelValue>();
 
 /// Represents a set of options passed to a fractal generator at initialization.
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct FractalOpts {
     pub mandelbrot: bool,
+    #[serde(default)]
+    pub formula: Formula,
     pub iterations: u32,
     pub smoothing: Smoothing,
     pub multisampling: Multisampling,
-    pub c: Complex<f32>,
+    pub c: Complex<f64>,
     pub radius_squared: f32,
+    /// The precision to iterate in. Generators that don't support the
+    /// requested precision fall back to the highest one they do support.
+    #[serde(default)]
+    pub precision: Precision,
+    /// The top left corner of the image on the complex plane in arbitrary
+    /// precision, or `None` to use the views' `f64` plane starts. This is only
+    /// needed once the image is so small that `f64` can't place it exactly,
+    /// and is only used by perturbation, which builds its reference orbit
+    /// from it.
+    #[serde(default)]
+    pub plane_start: Option<PlanePoint>,
+    /// The shortcuts used to find points inside the set early. These never
+    /// change which points are considered inside the set, only how quickly
+    /// they are found. Interior coloring needs the whole orbit of each point,
+    /// so these are skipped while it is used.
+    #[serde(default)]
+    pub interior_checks: InteriorChecks,
+    /// Whether the generators that iterate on the CPU use boundary tracing.
+    /// Other generators ignore this.
+    #[serde(default)]
+    pub boundary_tracing: BoundaryTracing,
+    /// The kernel the CPU generator iterates with. Every kernel generates the
+    /// same values. Other generators ignore this.
+    #[serde(default)]
+    pub cpu_kernel: CpuKernel,
+    /// Whether generators estimate the distance from each pixel to the set,
+    /// by tracking the derivative of `z` along with `z`. Pixels are given a
+    /// distance of [`NO_DISTANCE`] otherwise.
+    #[serde(default)]
+    pub distance_estimation: bool,
+    /// The orbit trap whose distance escaped pixels are given as their value
+    /// instead of their smoothed iteration count, if any.
+    #[serde(default)]
+    pub orbit_trap: Option<OrbitTrap>,
+    /// The statistic averaged over the orbit of each escaped pixel to get its
+    /// value instead of its smoothed iteration count. This is ignored while an
+    /// orbit trap is used.
+    #[serde(default)]
+    pub averaging: Averaging,
+    /// How pixels inside the set are given interior values. Pixels are given
+    /// an interior value of [`NO_INTERIOR`] and colored black otherwise.
+    #[serde(default)]
+    pub interior_coloring: InteriorColoring,
+    /// The palette used to color the fractal, or `None` for the classic
+    /// hue-cycling colors. This is only used by the recolor pass, so
+    /// generators ignore it.
+    #[serde(default)]
+    pub palette: Option<Palette>,
+    /// The palette used to color interior values, or `None` for the classic
+    /// hue-cycling colors. Like the palette, this is only used by the recolor
+    /// pass.
+    #[serde(default)]
+    pub interior_palette: Option<Palette>,
+    /// How the estimated distances shade the fractal. Like the palette, this
+    /// is only used by the recolor pass.
+    #[serde(default)]
+    pub shading: Shading,
+    /// Whether values are equalized by the distribution of all the values of
+    /// the view before being colored. Like the palette, this is only used by
+    /// the recolor pass.
+    #[serde(default)]
+    pub histogram_equalization: bool,
+}
+
+#[cfg(test)]
+impl FractalOpts {
+    /// Gets the options tests build on with `..FractalOpts::test_base()`: a
+    /// single precision Mandelbrot set with 200 iterations and logarithmic
+    /// smoothing, without multisampling, coloring, or any of the optional
+    /// features.
+    pub fn test_base() -> FractalOpts {
+        FractalOpts {
+            mandelbrot: true,
+            formula: Default::default(),
+            iterations: 200,
+            smoothing: Smoothing::from_logarithmic_distance(4.0, 2.0),
+            multisampling: Multisampling::None,
+            c: Complex::new(0.0, 0.0),
+            radius_squared: args::DEFAULT_RADIUS_SQUARED,
+            precision: Precision::Single,
+            plane_start: None,
+            palette: None,
+            interior_palette: None,
+            shading: Default::default(),
+            histogram_equalization: false,
+            interior_checks: Default::default(),
+            boundary_tracing: Default::default(),
+            cpu_kernel: Default::default(),
+            distance_estimation: false,
+            orbit_trap: None,
+            averaging: Default::default(),
+            interior_coloring: Default::default(),
+        }
+    }
+}
+
+impl FractalOpts {
+    /// Checks whether the main cardioid and period-2 bulb can be skipped. These
+    /// only have known shapes in the Mandelbrot set of `z^2 + c`.
+    pub fn checks_bulbs(&self) -> bool {
+        self.interior_checks.bulbs
+            && self.interior_coloring == InteriorColoring::None
+            && self.mandelbrot
+            && self.formula == Formula::IntegerPower { exponent: 2 }
+    }
+
+    /// Checks whether points can stop being iterated once they are caught in a
+    /// cycle.
+    pub fn checks_periodicity(&self) -> bool {
+        self.interior_checks.periodicity && self.interior_coloring == InteriorColoring::None
+    }
+
+    /// Checks whether escaped points are given an orbit average as their value.
+    /// Orbit traps take precedence over averages.
+    pub fn averages_orbits(&self) -> bool {
+        self.averaging != Averaging::None && self.orbit_trap.is_none()
+    }
+
+    /// Checks whether points inside the set are colored by their estimated
+    /// distance to its boundary. This is only supported for Mandelbrot sets of
+    /// `z^n + c`.
+    pub fn estimates_interior_distance(&self) -> bool {
+        self.interior_coloring == InteriorColoring::Distance
+            && self.mandelbrot
+            && matches!(self.formula, Formula::IntegerPower { exponent } if exponent >= 2)
+    }
 }
 
 /// Represents a block of pixels, likely generated by a fractal generator.
 #[derive(Clone)]
 pub struct PixelBlock {
     pub view: View,
+    pub format: PixelFormat,
+    /// The pixels of this block, row by row from the top left, laid out as
+    /// they are in a PNG image of this block's format.
     pub image: Box<[u8]>,
 }
 
@@ -76,11 +232,222 @@ impl Debug for PixelBlock {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("PixelBlock")
             .field("view", &self.view)
+            .field("format", &self.format)
             .field("image", &format!("[{} bytes]", self.image.len()))
             .finish()
     }
 }
 
+/// How the colors of a [`PixelBlock`] are stored.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum PixelFormat {
+    /// 8 bits per channel RGBA.
+    Rgba8,
+    /// 16 bits per channel RGBA, with each channel stored big-endian like in
+    /// PNG images.
+    Rgba16,
+}
+
+impl PixelFormat {
+    /// Gets the name of this format as displayed to the user.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PixelFormat::Rgba8 => "8-bit",
+            PixelFormat::Rgba16 => "16-bit",
+        }
+    }
+
+    /// Gets how many bits each channel of this format has.
+    pub fn bit_depth(&self) -> u8 {
+        match self {
+            PixelFormat::Rgba8 => 8,
+            PixelFormat::Rgba16 => 16,
+        }
+    }
+
+    /// Gets how many bytes each pixel of this format takes up.
+    pub fn bytes_per_pixel(&self) -> usize {
+        self.bit_depth() as usize / 2
+    }
+
+    /// Gets the format of the textures that colors of this format are
+    /// rendered into on the GPU, which have at least the same precision.
+    pub fn texture_format(&self) -> TextureFormat {
+        match self {
+            PixelFormat::Rgba8 => TextureFormat::Rgba8Unorm,
+            PixelFormat::Rgba16 => TextureFormat::Rgba16Float,
+        }
+    }
+
+    /// Appends a color with channels between 0 and 1 to an image in this
+    /// format.
+    pub fn push_color(&self, image: &mut Vec<u8>, color: Vector4<f32>) {
+        match self {
+            PixelFormat::Rgba8 => {
+                let color: RGBA8Color = color.into();
+                let color: [u8; 4] = color.into();
+                image.extend_from_slice(&color);
+            },
+            PixelFormat::Rgba16 => {
+                let color: [f32; 4] = color.into();
+                for channel in color {
+                    let channel = (channel.clamp(0.0, 1.0) * 65535.0 + 0.5) as u16;
+                    image.extend_from_slice(&channel.to_be_bytes());
+                }
+            },
+        }
+    }
+}
+
+impl Default for PixelFormat {
+    fn default() -> Self {
+        PixelFormat::Rgba8
+    }
+}
+
+impl FromStr for PixelFormat {
+    type Err = ();
+
+    /// Parses formats by their bit depth.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(PixelFormat::Rgba8),
+            "16" => Ok(PixelFormat::Rgba16),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The distance of pixels whose distance to the set was not estimated. This is
+/// far enough away that shading leaves them untouched.
+pub const NO_DISTANCE: f32 = f32::MAX;
+
+/// The interior value of pixels whose samples have no interior value, either
+/// because they all escaped or because interior coloring is not used. This
+/// leaves the inside of the set black.
+pub const NO_INTERIOR: f32 = -1.0;
+
+/// The uncolored result of generating a single pixel.
+///
+/// This is laid out the same as a texel of an `Rgba32Float` texture, which is
+/// what [`FractalGenerator::start_generation_to_gpu()`] generates into.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PixelValue {
+    /// The average smoothed iteration count of the pixel's samples that
+    /// escaped.
+    pub value: f32,
+    /// The fraction of the pixel's samples that escaped. Samples that never
+    /// escape are inside the set and are colored by their interior value.
+    pub coverage: f32,
+    /// The smallest estimated distance to the set of the pixel's samples that
+    /// escaped, in pixels, or [`NO_DISTANCE`] if distances were not estimated.
+    pub distance: f32,
+    /// The average interior value of the pixel's samples that never escaped,
+    /// or [`NO_INTERIOR`] if none of them have one.
+    pub interior: f32,
+}
+
+impl Default for PixelValue {
+    /// Gets the value of a pixel inside the set without an interior value.
+    fn default() -> Self {
+        PixelValue {
+            value: 0.0,
+            coverage: 0.0,
+            distance: 0.0,
+            interior: NO_INTERIOR,
+        }
+    }
+}
+
+unsafe impl Zeroable for PixelValue {}
+unsafe impl Pod for PixelValue {}
+
+/// Represents a block of uncolored pixel values, likely generated by a fractal
+/// generator.
+#[derive(Clone)]
+pub struct ValueBlock {
+    pub view: View,
+    pub values: Box<[PixelValue]>,
+}
+
+impl ValueBlock {
+    /// Colors this block's values with the given palettes, or with the classic
+    /// colors where there are none, and shading into pixels of the given
+    /// format. The line of values directly above the block and the one directly
+    /// below it, where there are any, are used as the neighbors of its top and
+    /// bottom lines.
+    ///
+    /// This keeps the relief slopes of blocks that are stitched on top of each
+    /// other the same as if the whole image had been colored at once.
+    pub fn color_with_neighbors(
+        &self,
+        above: Option<&[PixelValue]>,
+        below: Option<&[PixelValue]>,
+        palette: Option<&Palette>,
+        interior_palette: Option<&Palette>,
+        shading: &Shading,
+        format: PixelFormat,
+    ) -> PixelBlock {
+        // the stops are sorted once here rather than for every pixel
+        let palette = palette.map(Palette::sorted);
+        let interior_palette = interior_palette.map(Palette::sorted);
+
+        let width = self.view.image_width;
+        let first_line = above.map_or(0, |_| 1);
+        // the neighbors are only needed for relief slopes
+        let neighborhood: Vec<_> = match &shading.relief {
+            Some(_) => above
+                .into_iter()
+                .chain([&self.values[..]])
+                .chain(below)
+                .flatten()
+                .copied()
+                .collect(),
+            None => vec![],
+        };
+
+        let mut image = Vec::with_capacity(self.values.len() * format.bytes_per_pixel());
+        for (index, value) in self.values.iter().enumerate() {
+            let slope = match &shading.relief {
+                Some(relief) => relief.slope(
+                    &neighborhood,
+                    width,
+                    index % width,
+                    index / width + first_line,
+                ),
+                None => Vector2::zero(),
+            };
+            format.push_color(
+                &mut image,
+                color_value(
+                    value,
+                    slope,
+                    palette.as_ref(),
+                    interior_palette.as_ref(),
+                    shading,
+                ),
+            );
+        }
+
+        PixelBlock {
+            view: self.view,
+            format,
+            image: image.into_boxed_slice(),
+        }
+    }
+}
+
+// Special debug that doesn't look as ugly
+impl Debug for ValueBlock {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ValueBlock")
+            .field("view", &self.view)
+            .field("values", &format!("[{} values]", self.values.len()))
+            .finish()
+    }
+}
+
 /// Structs implementing this trait can be used to create [`FractalGenerator`]s.
 ///
 /// Structs implementing this trait generally describe the general structure of
@@ -97,6 +464,20 @@ pub trait FractalGeneratorFactory {
         &self,
         opts: FractalOpts,
     ) -> BoxFuture<'static, anyhow::Result<Box<dyn FractalGenerator + Send + 'static>>>;
+
+    /// Checks whether generators created by this factory can iterate the
+    /// given options' formula in their precision.
+    fn supports_precision(&self, opts: &FractalOpts) -> bool {
+        opts.precision == Precision::Single
+    }
+
+    /// Checks whether generators created by this factory place images by the
+    /// options' arbitrary-precision [`plane_start`](FractalOpts::plane_start).
+    /// Generators that don't are created without it, so that moving the view
+    /// doesn't create new ones.
+    fn uses_plane_start(&self) -> bool {
+        false
+    }
 }
 
 /// Structs implementing this trait can be used to generate fractals.
@@ -115,12 +496,12 @@ pub trait FractalGenerator {
     fn start_generation_to_cpu(
         &self,
         views: &[View],
-        sender: Sender<anyhow::Result<PixelBlock>>,
+        sender: Sender<anyhow::Result<ValueBlock>>,
     ) -> BoxFuture<'static, anyhow::Result<Box<dyn FractalGeneratorInstance + Send + 'static>>>;
 
-    /// Starts the generation of a fractal. This variant writes fractal image
-    /// data directly to a gpu-side image instead of sending it as cpu-side
-    /// pixel blocks.
+    /// Starts the generation of a fractal. This variant writes fractal values
+    /// directly to a gpu-side `Rgba32Float` texture instead of sending them as
+    /// cpu-side value blocks.
     ///
     /// # Panics
     /// This can panic if `present.ty != GPUContextType::Presentable`.
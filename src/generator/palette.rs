@@ -0,0 +1,399 @@
+//! This module contains gradient color palettes, used to turn smoothed
+//! iteration counts into colors, as well as the palette file format.
+
+use crate::util::{files::config_dir, result::ResultExt};
+use cgmath::Vector4;
+use ron::ser::PrettyConfig;
+use std::{
+    cmp::Ordering,
+    fs::{create_dir_all, read_dir, File},
+    io,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// The file extension used by palette files.
+pub const PALETTE_FILE_EXTENSION: &str = "ron";
+
+/// The maximum number of color stops a palette can have. This is limited by
+/// the size of the GPU generator's uniform buffer.
+pub const MAX_PALETTE_STOPS: usize = 32;
+
+const PALETTES_DIR_NAME: &str = "palettes";
+
+/// A gradient of colors that iteration counts are mapped onto.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Palette {
+    /// The colors making up this gradient. These do not need to be sorted.
+    pub stops: Vec<ColorStop>,
+    /// How colors are blended between stops.
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    /// The position in the gradient that an iteration count of zero maps to.
+    #[serde(default)]
+    pub offset: f32,
+    /// How far through the gradient each iteration moves.
+    #[serde(default = "default_density")]
+    pub density: f32,
+    /// What happens to positions outside the gradient.
+    #[serde(default)]
+    pub repeat: RepeatMode,
+}
+
+/// A single color in a [`Palette`].
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColorStop {
+    /// This color's position in the gradient, between 0 and 1.
+    pub position: f32,
+    /// This color's red, green, and blue components, between 0 and 1.
+    pub color: [f32; 3],
+}
+
+/// How colors are blended between the stops of a [`Palette`].
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Interpolation {
+    /// Colors are blended linearly.
+    Linear,
+    /// Colors are blended with an ease-in-ease-out curve.
+    Smooth,
+    /// Each stop's color is used until the next stop, without blending.
+    Step,
+}
+
+/// What happens to positions outside a [`Palette`]'s gradient.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum RepeatMode {
+    /// The gradient starts over from the beginning.
+    Repeat,
+    /// The gradient is run through backwards, then forwards again.
+    Mirror,
+    /// The colors at the ends of the gradient are extended.
+    Clamp,
+}
+
+impl Palette {
+    /// Gets this palette's stops sorted by position.
+    pub fn sorted_stops(&self) -> Vec<ColorStop> {
+        let mut stops = self.stops.clone();
+        stops.sort_by(|a, b| {
+            a.position
+                .partial_cmp(&b.position)
+                .unwrap_or(Ordering::Equal)
+        });
+        stops
+    }
+
+    /// Gets a copy of this palette with its stops sorted by position, ready to
+    /// be sampled with [`color_at`](Palette::color_at).
+    pub fn sorted(&self) -> Palette {
+        Palette {
+            stops: self.sorted_stops(),
+            ..self.clone()
+        }
+    }
+
+    /// Gets the position in the gradient, between 0 and 1, that a smoothed
+    /// iteration count maps to.
+    pub fn position(&self, value: f32) -> f32 {
+        let t = self.offset + value * self.density;
+        match self.repeat {
+            RepeatMode::Repeat => t - t.floor(),
+            RepeatMode::Mirror => {
+                let half = t * 0.5;
+                1.0 - ((half - half.floor()) * 2.0 - 1.0).abs()
+            },
+            RepeatMode::Clamp => t.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Gets the color at a position in the gradient.
+    ///
+    /// This palette's stops must already be sorted by position, like the ones
+    /// of [`sorted`](Palette::sorted), so that coloring every pixel doesn't
+    /// sort them again.
+    pub fn color_at(&self, t: f32) -> Vector4<f32> {
+        let stops = &self.stops;
+        let (first, last) = match (stops.first(), stops.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Vector4::new(0.0, 0.0, 0.0, 1.0),
+        };
+
+        if t <= first.position {
+            return first.vector();
+        }
+
+        for pair in stops.windows(2) {
+            let (a, b) = (&pair[0], &pair[1]);
+            if t < b.position {
+                let f = (t - a.position) / (b.position - a.position);
+                let f = match self.interpolation {
+                    Interpolation::Linear => f,
+                    Interpolation::Smooth => f * f * (3.0 - 2.0 * f),
+                    Interpolation::Step => 0.0,
+                };
+                return a.vector() + (b.vector() - a.vector()) * f;
+            }
+        }
+
+        last.vector()
+    }
+
+    /// Gets the color for a smoothed iteration count. Like
+    /// [`color_at`](Palette::color_at), this needs sorted stops.
+    pub fn color(&self, value: f32) -> Vector4<f32> {
+        self.color_at(self.position(value))
+    }
+
+    /// Loads a palette from a file, failing if it has more than
+    /// [`MAX_PALETTE_STOPS`] stops, because the GPU generator would only use
+    /// some of them.
+    pub fn load(path: impl AsRef<Path>) -> Result<Palette, PaletteError> {
+        let mut file = File::open(path)?;
+        let mut str = String::new();
+        file.read_to_string(&mut str)?;
+        let palette: Palette = ron::from_str(&str)?;
+
+        if palette.stops.len() > MAX_PALETTE_STOPS {
+            return Err(PaletteError::TooManyStops(palette.stops.len()));
+        }
+
+        Ok(palette)
+    }
+
+    /// Loads a palette saved in the palettes directory by name, or from a file
+    /// if `name` is a path to one.
+    pub fn find(name: &str) -> Result<Palette, PaletteError> {
+        let path = Path::new(name);
+        if path.is_file() {
+            Palette::load(path)
+        } else {
+            Palette::load(palette_path(name)?)
+        }
+    }
+
+    /// Stores this palette into a file.
+    pub fn store(&self, path: impl AsRef<Path>) -> Result<(), PaletteError> {
+        let str = ron::ser::to_string_pretty(self, PrettyConfig::new())?;
+        let mut file = File::create(path)?;
+        write!(file, "{}", str)?;
+        Ok(())
+    }
+}
+
+impl ColorStop {
+    fn vector(&self) -> Vector4<f32> {
+        Vector4::new(self.color[0], self.color[1], self.color[2], 1.0)
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        let stop = |position, r: u8, g: u8, b: u8| ColorStop {
+            position,
+            color: [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0],
+        };
+
+        Palette {
+            stops: vec![
+                stop(0.0, 0, 7, 100),
+                stop(0.16, 32, 107, 203),
+                stop(0.42, 237, 255, 255),
+                stop(0.6425, 255, 170, 0),
+                stop(0.8575, 0, 2, 0),
+                stop(1.0, 0, 7, 100),
+            ],
+            interpolation: Default::default(),
+            offset: 0.0,
+            density: default_density(),
+            repeat: Default::default(),
+        }
+    }
+}
+
+impl Default for Interpolation {
+    fn default() -> Self {
+        Interpolation::Linear
+    }
+}
+
+impl Default for RepeatMode {
+    fn default() -> Self {
+        RepeatMode::Repeat
+    }
+}
+
+fn default_density() -> f32 {
+    1.0 / 64.0
+}
+
+/// Gets the directory reusable palettes are saved in.
+pub fn palettes_dir() -> PathBuf {
+    let dir = config_dir().join(PALETTES_DIR_NAME);
+    create_dir_all(&dir).on_err(|e| error!("Error creating palettes dir: {:?}", e));
+    dir
+}
+
+/// Gets the path of the palette saved in the palettes directory with the given
+/// name, failing if the name isn't a valid palette name.
+pub fn palette_path(name: &str) -> Result<PathBuf, PaletteError> {
+    if !is_valid_palette_name(name) {
+        return Err(PaletteError::InvalidName(name.to_string()));
+    }
+    Ok(palettes_dir().join(format!("{}.{}", name, PALETTE_FILE_EXTENSION)))
+}
+
+/// Checks that a palette name is a plain file name, so that the palette is
+/// saved inside the palettes directory rather than anywhere else.
+pub fn is_valid_palette_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('.')
+        && !name.contains("..")
+        && !name.contains(|c| matches!(c, '/' | '\\' | ':' | '\0'))
+}
+
+/// Lists the names of the palettes saved in the palettes directory.
+pub fn saved_palettes() -> Vec<String> {
+    let mut names: Vec<_> = read_dir(palettes_dir())
+        .on_err(|e| error!("Error reading palettes dir: {:?}", e))
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .map_or(false, |ext| ext == PALETTE_FILE_EXTENSION)
+        })
+        .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+        .collect();
+    names.sort();
+    names
+}
+
+#[derive(Debug, Error)]
+pub enum PaletteError {
+    #[error("IO Error while accessing palette file")]
+    IOError(#[from] io::Error),
+    #[error("Ron Error while writing palette file")]
+    RonError(#[from] ron::Error),
+    #[error("Ron Error while parsing palette file")]
+    RonSpannedError(#[from] ron::error::SpannedError),
+    #[error("'{0}' is not a valid palette name")]
+    InvalidName(String),
+    #[error(
+        "Palette has {0} stops, but at most {} are supported",
+        MAX_PALETTE_STOPS
+    )]
+    TooManyStops(usize),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn two_color_palette(interpolation: Interpolation, repeat: RepeatMode) -> Palette {
+        Palette {
+            stops: vec![
+                ColorStop {
+                    position: 1.0,
+                    color: [1.0, 1.0, 1.0],
+                },
+                ColorStop {
+                    position: 0.0,
+                    color: [0.0, 0.0, 0.0],
+                },
+            ],
+            interpolation,
+            offset: 0.25,
+            density: 0.5,
+            repeat,
+        }
+    }
+
+    #[test]
+    fn interpolation() {
+        let linear = two_color_palette(Interpolation::Linear, RepeatMode::Clamp).sorted();
+        assert_eq!(linear.color_at(0.25).x, 0.25);
+        assert_eq!(linear.color_at(-1.0).x, 0.0);
+        assert_eq!(linear.color_at(2.0).x, 1.0);
+
+        let smooth = two_color_palette(Interpolation::Smooth, RepeatMode::Clamp).sorted();
+        assert_eq!(smooth.color_at(0.5).x, 0.5);
+        assert!(smooth.color_at(0.25).x < 0.25);
+
+        let step = two_color_palette(Interpolation::Step, RepeatMode::Clamp).sorted();
+        assert_eq!(step.color_at(0.75).x, 0.0);
+        assert_eq!(step.color_at(1.0).x, 1.0);
+    }
+
+    #[test]
+    fn repeat_modes() {
+        let repeat = two_color_palette(Interpolation::Linear, RepeatMode::Repeat);
+        assert_eq!(repeat.position(0.0), 0.25);
+        assert_eq!(repeat.position(2.0), 0.25);
+        assert_eq!(repeat.position(-1.0), 0.75);
+
+        let mirror = two_color_palette(Interpolation::Linear, RepeatMode::Mirror);
+        assert_eq!(mirror.position(1.0), 0.75);
+        assert_eq!(mirror.position(3.0), 0.25);
+        assert_eq!(mirror.position(5.0), 0.75);
+
+        let clamp = two_color_palette(Interpolation::Linear, RepeatMode::Clamp);
+        assert_eq!(clamp.position(1.0), 0.75);
+        assert_eq!(clamp.position(4.0), 1.0);
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = std::env::temp_dir().join("fractal-rs-2-palette-round-trip.ron");
+        let palette = two_color_palette(Interpolation::Smooth, RepeatMode::Mirror);
+
+        palette.store(&path).unwrap();
+        let loaded = Palette::load(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(loaded, palette);
+    }
+
+    #[test]
+    fn rejects_too_many_stops() {
+        let path = std::env::temp_dir().join("fractal-rs-2-palette-too-many-stops.ron");
+        let mut palette = two_color_palette(Interpolation::Linear, RepeatMode::Repeat);
+        palette.stops = (0..=MAX_PALETTE_STOPS)
+            .map(|index| ColorStop {
+                position: index as f32 / MAX_PALETTE_STOPS as f32,
+                color: [0.0, 0.0, 0.0],
+            })
+            .collect();
+
+        palette.store(&path).unwrap();
+        let loaded = Palette::load(&path);
+        std::fs::remove_file(&path).ok();
+
+        assert!(matches!(
+            loaded,
+            Err(PaletteError::TooManyStops(count)) if count == MAX_PALETTE_STOPS + 1
+        ));
+    }
+
+    #[test]
+    fn palette_names() {
+        for name in ["sunset", "Deep Blue", "fire-2"] {
+            assert!(is_valid_palette_name(name), "{}", name);
+        }
+        for name in [
+            "",
+            "../../general",
+            "a/b",
+            "a\\b",
+            ".hidden",
+            "..",
+            "c:palette",
+        ] {
+            assert!(!is_valid_palette_name(name), "{}", name);
+            assert!(matches!(
+                palette_path(name),
+                Err(PaletteError::InvalidName(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,455 @@
+//! This module contains a simple arbitrary-precision binary floating point
+//! number used for computing reference orbits, along with the points of the
+//! complex plane they are computed at.
+
+use num_complex::Complex;
+use std::{
+    cmp::Ordering,
+    ops::{Add, Mul, Neg, Sub},
+};
+
+const LIMB_BITS: i64 = u32::BITS as i64;
+
+/// Extra bits of precision beyond what is needed to tell neighboring pixels
+/// apart.
+const EXTRA_PRECISION_BITS: f64 = 64.0;
+
+/// An arbitrary-precision binary floating point number.
+///
+/// The value of a `BigFloat` is `mantissa * 2^exponent`, where the mantissa is
+/// an unsigned integer stored as little-endian 32-bit limbs. Non-zero numbers
+/// are kept normalized so that the highest bit of the highest limb is set.
+/// Both operands of an arithmetic operation must have the same precision, and
+/// results are truncated towards zero to that precision.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BigFloat {
+    negative: bool,
+    exponent: i64,
+    mantissa: Vec<u32>,
+}
+
+impl BigFloat {
+    /// Creates a zero with the given number of 32-bit limbs of precision.
+    pub fn zero(limbs: usize) -> BigFloat {
+        assert!(limbs >= 2, "A BigFloat needs at least 2 limbs of precision");
+        BigFloat {
+            negative: false,
+            exponent: 0,
+            mantissa: vec![0; limbs],
+        }
+    }
+
+    /// Gets the number of 32-bit limbs needed to hold `bits` bits of mantissa.
+    pub fn limbs_for_bits(bits: u32) -> usize {
+        ((bits as usize + LIMB_BITS as usize - 1) / LIMB_BITS as usize).max(2)
+    }
+
+    /// Gets the number of 32-bit limbs needed to tell apart points `scale`
+    /// apart, such as neighboring pixels, anywhere fractals are drawn.
+    pub fn limbs_for_scale(scale: f64) -> usize {
+        let bits = (EXTRA_PRECISION_BITS - scale.abs().log2()).max(EXTRA_PRECISION_BITS);
+        BigFloat::limbs_for_bits(bits.ceil() as u32)
+    }
+
+    /// Creates a `BigFloat` with the given number of 32-bit limbs of precision
+    /// holding exactly the given `f64`.
+    ///
+    /// # Panics
+    /// This panics if `value` is not finite.
+    pub fn from_f64(value: f64, limbs: usize) -> BigFloat {
+        assert!(value.is_finite(), "Cannot create a BigFloat from {}", value);
+
+        let mut res = BigFloat::zero(limbs);
+        if value == 0.0 {
+            return res;
+        }
+
+        let bits = value.to_bits();
+        let biased_exponent = ((bits >> 52) & 0x7ff) as i64;
+        let fraction = bits & 0xf_ffff_ffff_ffff;
+        let (integer, exponent) = if biased_exponent == 0 {
+            (fraction, -1074)
+        } else {
+            (fraction | (1 << 52), biased_exponent - 1075)
+        };
+
+        res.negative = value < 0.0;
+        res.exponent = exponent;
+        res.mantissa[0] = integer as u32;
+        res.mantissa[1] = (integer >> 32) as u32;
+        res.normalize();
+        res
+    }
+
+    /// Gets an `f64` approximation of this number.
+    pub fn to_f64(&self) -> f64 {
+        if self.is_zero() {
+            return 0.0;
+        }
+
+        let len = self.mantissa.len();
+        let top = ((self.mantissa[len - 1] as u64) << 32) | self.mantissa[len - 2] as u64;
+        let exponent = self.exponent + (len as i64 - 2) * LIMB_BITS;
+
+        // Split the scaling so that neither factor overflows on its own.
+        let half = (exponent / 2).clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+        let rest = (exponent - half as i64).clamp(i32::MIN as i64, i32::MAX as i64) as i32;
+        let value = top as f64 * 2f64.powi(half) * 2f64.powi(rest);
+
+        if self.negative {
+            -value
+        } else {
+            value
+        }
+    }
+
+    /// Checks whether this number is zero.
+    pub fn is_zero(&self) -> bool {
+        self.mantissa.iter().all(|&limb| limb == 0)
+    }
+
+    /// Gets the number of 32-bit limbs of precision this number has.
+    pub fn limbs(&self) -> usize {
+        self.mantissa.len()
+    }
+
+    /// Gets this number with the given number of 32-bit limbs of precision,
+    /// truncating it towards zero if that is less precision than it has.
+    pub fn with_limbs(&self, limbs: usize) -> BigFloat {
+        let mut res = BigFloat::zero(limbs);
+        res.negative = self.negative;
+        res.exponent = self.exponent + (self.limbs() as i64 - limbs as i64) * LIMB_BITS;
+        for (limb, &value) in res
+            .mantissa
+            .iter_mut()
+            .rev()
+            .zip(self.mantissa.iter().rev())
+        {
+            *limb = value;
+        }
+
+        // deserialized numbers might not be normalized yet
+        res.normalize();
+        res
+    }
+
+    /// Shifts the mantissa so that its highest bit is set.
+    fn normalize(&mut self) {
+        let leading_zeros = leading_zeros(&self.mantissa);
+        if leading_zeros == self.mantissa.len() as i64 * LIMB_BITS {
+            self.negative = false;
+            self.exponent = 0;
+        } else {
+            shift_left(&mut self.mantissa, leading_zeros);
+            self.exponent -= leading_zeros;
+        }
+    }
+
+    /// Compares the absolute values of two normalized numbers.
+    fn cmp_magnitude(&self, other: &BigFloat) -> Ordering {
+        match (self.is_zero(), other.is_zero()) {
+            (true, true) => Ordering::Equal,
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            (false, false) => self
+                .exponent
+                .cmp(&other.exponent)
+                .then_with(|| self.mantissa.iter().rev().cmp(other.mantissa.iter().rev())),
+        }
+    }
+
+    /// Adds or subtracts the magnitudes of two numbers with the same precision.
+    fn add_signed(&self, other: &BigFloat, other_negative: bool) -> BigFloat {
+        assert_eq!(
+            self.limbs(),
+            other.limbs(),
+            "BigFloat operands must have the same precision"
+        );
+
+        let (larger, larger_negative, smaller) = if self.cmp_magnitude(other) == Ordering::Less {
+            (other, other_negative, self)
+        } else {
+            (self, self.negative, other)
+        };
+
+        if smaller.is_zero() {
+            return BigFloat {
+                negative: larger_negative && !larger.is_zero(),
+                ..larger.clone()
+            };
+        }
+
+        let mut aligned = smaller.mantissa.clone();
+        shift_right(&mut aligned, larger.exponent - smaller.exponent);
+
+        let mut res = BigFloat {
+            negative: larger_negative,
+            exponent: larger.exponent,
+            mantissa: larger.mantissa.clone(),
+        };
+
+        if self.negative == other_negative {
+            let mut carry = 0u64;
+            for (limb, add) in res.mantissa.iter_mut().zip(aligned) {
+                let sum = *limb as u64 + add as u64 + carry;
+                *limb = sum as u32;
+                carry = sum >> 32;
+            }
+
+            if carry != 0 {
+                shift_right(&mut res.mantissa, 1);
+                let top = res.mantissa.len() - 1;
+                res.mantissa[top] |= 1 << 31;
+                res.exponent += 1;
+            }
+        } else {
+            let mut borrow = 0i64;
+            for (limb, sub) in res.mantissa.iter_mut().zip(aligned) {
+                let diff = *limb as i64 - sub as i64 - borrow;
+                *limb = diff as u32;
+                borrow = (diff < 0) as i64;
+            }
+
+            res.normalize();
+        }
+
+        res
+    }
+}
+
+impl Add for &BigFloat {
+    type Output = BigFloat;
+
+    fn add(self, rhs: &BigFloat) -> BigFloat {
+        self.add_signed(rhs, rhs.negative)
+    }
+}
+
+impl Sub for &BigFloat {
+    type Output = BigFloat;
+
+    fn sub(self, rhs: &BigFloat) -> BigFloat {
+        self.add_signed(rhs, !rhs.negative)
+    }
+}
+
+impl Mul for &BigFloat {
+    type Output = BigFloat;
+
+    fn mul(self, rhs: &BigFloat) -> BigFloat {
+        assert_eq!(
+            self.limbs(),
+            rhs.limbs(),
+            "BigFloat operands must have the same precision"
+        );
+
+        let len = self.limbs();
+        if self.is_zero() || rhs.is_zero() {
+            return BigFloat::zero(len);
+        }
+
+        let mut product = vec![0u32; len + rhs.limbs()];
+        for (i, &a) in self.mantissa.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, &b) in rhs.mantissa.iter().enumerate() {
+                let sum = product[i + j] as u64 + a as u64 * b as u64 + carry;
+                product[i + j] = sum as u32;
+                carry = sum >> 32;
+            }
+            product[i + rhs.limbs()] = carry as u32;
+        }
+
+        let leading_zeros = leading_zeros(&product);
+        shift_left(&mut product, leading_zeros);
+        let dropped = product.len() - len;
+
+        BigFloat {
+            negative: self.negative != rhs.negative,
+            exponent: self.exponent + rhs.exponent + dropped as i64 * LIMB_BITS - leading_zeros,
+            mantissa: product[dropped..].to_vec(),
+        }
+    }
+}
+
+impl Neg for &BigFloat {
+    type Output = BigFloat;
+
+    fn neg(self) -> BigFloat {
+        BigFloat {
+            negative: !self.negative && !self.is_zero(),
+            ..self.clone()
+        }
+    }
+}
+
+/// A point on the complex plane in arbitrary precision.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlanePoint {
+    pub re: BigFloat,
+    pub im: BigFloat,
+}
+
+impl PlanePoint {
+    /// Creates a point with the given number of 32-bit limbs of precision
+    /// holding exactly the given `f64` point.
+    pub fn from_f64(point: Complex<f64>, limbs: usize) -> PlanePoint {
+        PlanePoint {
+            re: BigFloat::from_f64(point.re, limbs),
+            im: BigFloat::from_f64(point.im, limbs),
+        }
+    }
+
+    /// Gets an `f64` approximation of this point.
+    pub fn to_f64(&self) -> Complex<f64> {
+        Complex::new(self.re.to_f64(), self.im.to_f64())
+    }
+
+    /// Gets this point moved by `offset`, with the given number of 32-bit limbs
+    /// of precision.
+    pub fn offset(&self, offset: Complex<f64>, limbs: usize) -> PlanePoint {
+        PlanePoint {
+            re: &self.re.with_limbs(limbs) + &BigFloat::from_f64(offset.re, limbs),
+            im: &self.im.with_limbs(limbs) + &BigFloat::from_f64(offset.im, limbs),
+        }
+    }
+}
+
+/// Counts the leading zero bits of a little-endian mantissa.
+fn leading_zeros(mantissa: &[u32]) -> i64 {
+    let mut count = 0;
+    for &limb in mantissa.iter().rev() {
+        count += limb.leading_zeros() as i64;
+        if limb != 0 {
+            break;
+        }
+    }
+    count
+}
+
+/// Shifts a little-endian mantissa left, dropping the bits shifted out.
+fn shift_left(mantissa: &mut [u32], bits: i64) {
+    let len = mantissa.len();
+    let limbs = (bits / LIMB_BITS) as usize;
+    let bits = (bits % LIMB_BITS) as u32;
+
+    for index in (0..len).rev() {
+        let high = index
+            .checked_sub(limbs)
+            .map_or(0, |source| mantissa[source]);
+        let low = index
+            .checked_sub(limbs + 1)
+            .map_or(0, |source| mantissa[source]);
+        mantissa[index] = if bits == 0 {
+            high
+        } else {
+            (high << bits) | (low >> (32 - bits))
+        };
+    }
+}
+
+/// Shifts a little-endian mantissa right, dropping the bits shifted out.
+fn shift_right(mantissa: &mut [u32], bits: i64) {
+    let len = mantissa.len();
+    if bits >= len as i64 * LIMB_BITS {
+        mantissa.fill(0);
+        return;
+    }
+
+    let limbs = (bits / LIMB_BITS) as usize;
+    let bits = (bits % LIMB_BITS) as u32;
+
+    for index in 0..len {
+        let low = mantissa.get(index + limbs).copied().unwrap_or(0);
+        let high = mantissa.get(index + limbs + 1).copied().unwrap_or(0);
+        mantissa[index] = if bits == 0 {
+            low
+        } else {
+            (low >> bits) | (high << (32 - bits))
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn big(value: f64) -> BigFloat {
+        BigFloat::from_f64(value, 4)
+    }
+
+    #[test]
+    fn f64_round_trip() {
+        for value in [
+            0.0,
+            1.0,
+            -1.0,
+            0.1,
+            -0.743643887037151,
+            1e-300,
+            5e-324,
+            1e300,
+        ] {
+            assert_eq!(big(value).to_f64(), value);
+        }
+    }
+
+    #[test]
+    fn arithmetic_matches_f64() {
+        let values = [0.0, 1.0, -2.5, 0.1, 3.75, -1e-10, 1e10];
+        for &a in values.iter() {
+            for &b in values.iter() {
+                assert_eq!((&big(a) + &big(b)).to_f64(), a + b, "{} + {}", a, b);
+                assert_eq!((&big(a) - &big(b)).to_f64(), a - b, "{} - {}", a, b);
+                assert_eq!((&big(a) * &big(b)).to_f64(), a * b, "{} * {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_extra_precision() {
+        let tiny = big(2f64.powi(-100));
+        let one = big(1.0);
+
+        // 1 + 2^-100 can't be represented as an f64, but the difference can
+        let sum = &one + &tiny;
+        assert_eq!(sum.to_f64(), 1.0);
+        assert_eq!((&sum - &one).to_f64(), 2f64.powi(-100));
+
+        // (1 + 2^-100)^2 - 1 = 2^-99 + 2^-200
+        let square = &sum * &sum;
+        assert_eq!((&square - &one).to_f64(), 2f64.powi(-99));
+    }
+
+    #[test]
+    fn changes_precision() {
+        let sum = &big(1.0) + &big(2f64.powi(-100));
+
+        // more limbs keep the value exactly
+        let wider = sum.with_limbs(8);
+        assert_eq!(wider.limbs(), 8);
+        assert_eq!(
+            (&wider - &BigFloat::from_f64(1.0, 8)).to_f64(),
+            2f64.powi(-100)
+        );
+
+        // and fewer limbs truncate it
+        let narrower = sum.with_limbs(2);
+        assert_eq!(narrower, BigFloat::from_f64(1.0, 2));
+        assert_eq!(BigFloat::zero(2).with_limbs(4), BigFloat::zero(4));
+    }
+
+    #[test]
+    fn plane_points_keep_small_offsets() {
+        let center = Complex::new(-0.743643887037151, 0.131825904205330);
+        let point = PlanePoint::from_f64(center, 2);
+        let limbs = BigFloat::limbs_for_scale(1e-30);
+        let moved = point.offset(Complex::new(1e-25, -1e-25), limbs);
+        assert_eq!((moved.re.limbs(), moved.im.limbs()), (limbs, limbs));
+        assert_eq!(moved.to_f64(), center);
+
+        let difference = &moved.re - &point.re.with_limbs(limbs);
+        assert!((difference.to_f64() - 1e-25).abs() < 1e-40);
+        let difference = &moved.im - &point.im.with_limbs(limbs);
+        assert!((difference.to_f64() + 1e-25).abs() < 1e-40);
+    }
+}
@@ -0,0 +1,195 @@
+//! This module contains a fractal generator for deep zooms using perturbation
+//! theory.
+//!
+//! A single reference orbit is computed at the center of the image in
+//! arbitrary precision, and each pixel is then iterated on the CPU as a small
+//! delta from that orbit, in the precision requested by the [`FractalOpts`].
+//!
+//! With an arbitrary-precision [`plane_start`](FractalOpts::plane_start) in
+//! the options, views can be zoomed in until their pixel size reaches the
+//! limits of the exponent of the precision pixels are iterated in. Without
+//! one, the reference is built from the views' `f64` plane starts, which stop
+//! telling images apart once they are smaller than about `1e-16` times their
+//! distance from the origin.
+
+use crate::{
+    generator::{
+        cpu::{
+            BoundaryFill, CpuFractalGenerator, CpuFractalGeneratorInstance, CpuGenError,
+            GpuValueBlockSink,
+        },
+        perturbation::opts::PerturbationOpts,
+        view::View,
+        FractalGenerator, FractalGeneratorFactory, FractalGeneratorInstance, FractalOpts,
+        ValueBlock,
+    },
+    gpu::{GPUContext, GPUContextType},
+};
+use futures::{
+    future::{ready, BoxFuture},
+    FutureExt,
+};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::sync::Arc;
+use tokio::{sync::mpsc::Sender, task};
+use wgpu::{Texture, TextureView};
+
+pub mod big_float;
+pub mod opts;
+
+pub struct PerturbationFractalGeneratorFactory {
+    thread_count: usize,
+}
+
+impl PerturbationFractalGeneratorFactory {
+    pub fn new(thread_count: usize) -> PerturbationFractalGeneratorFactory {
+        PerturbationFractalGeneratorFactory { thread_count }
+    }
+}
+
+impl FractalGeneratorFactory for PerturbationFractalGeneratorFactory {
+    fn create_generator(
+        &self,
+        opts: FractalOpts,
+    ) -> BoxFuture<'static, anyhow::Result<Box<dyn FractalGenerator + Send + 'static>>> {
+        let res: Result<Box<dyn FractalGenerator + Send>, CpuGenError> =
+            if PerturbationOpts::supports_formula(&opts.formula) {
+                PerturbationFractalGenerator::new(opts, self.thread_count)
+                    .map(|gen| Box::new(gen) as Box<dyn FractalGenerator + Send>)
+            } else {
+                warn!(
+                    "Perturbation does not support {:?}, iterating directly instead",
+                    opts.formula
+                );
+                CpuFractalGenerator::new(opts, self.thread_count)
+                    .map(|gen| Box::new(gen) as Box<dyn FractalGenerator + Send>)
+            };
+
+        ready(res.map_err(|e| e.into())).boxed()
+    }
+
+    fn supports_precision(&self, opts: &FractalOpts) -> bool {
+        // Formulas that can't be perturbed are iterated directly, which is
+        // reported like any other precision fallback so deep zooms of them
+        // aren't mistaken for perturbed ones.
+        PerturbationOpts::supports_formula(&opts.formula)
+    }
+
+    fn uses_plane_start(&self) -> bool {
+        true
+    }
+}
+
+pub struct PerturbationFractalGenerator {
+    opts: FractalOpts,
+    thread_pool: Arc<ThreadPool>,
+    thread_count: usize,
+}
+
+impl PerturbationFractalGenerator {
+    pub fn new(
+        opts: FractalOpts,
+        thread_count: usize,
+    ) -> Result<PerturbationFractalGenerator, CpuGenError> {
+        Ok(PerturbationFractalGenerator {
+            opts,
+            thread_pool: Arc::new(ThreadPoolBuilder::new().num_threads(thread_count).build()?),
+            thread_count,
+        })
+    }
+}
+
+/// Computes the reference orbit for `views` off of the async runtime.
+async fn compute_reference(
+    opts: FractalOpts,
+    views: Vec<View>,
+) -> anyhow::Result<(PerturbationOpts, Vec<View>)> {
+    ensure!(!views.is_empty(), "No views to generate");
+
+    Ok(task::spawn_blocking(move || {
+        info!("Computing perturbation reference orbit...");
+        let perturbation = PerturbationOpts::new(opts, &views);
+        info!(
+            "Computed reference orbit with {} points.",
+            perturbation.reference_len()
+        );
+        (perturbation, views)
+    })
+    .await?)
+}
+
+impl FractalGenerator for PerturbationFractalGenerator {
+    fn min_views_hint(&self) -> BoxFuture<'static, anyhow::Result<usize>> {
+        ready(Ok(self.thread_count)).boxed()
+    }
+
+    fn start_generation_to_cpu(
+        &self,
+        views: &[View],
+        sender: Sender<anyhow::Result<ValueBlock>>,
+    ) -> BoxFuture<'static, anyhow::Result<Box<dyn FractalGeneratorInstance + Send + 'static>>>
+    {
+        let thread_pool = self.thread_pool.clone();
+        let views = views.to_vec();
+        let opts = self.opts.clone();
+        async move {
+            let multisampling = opts.multisampling;
+            let fill = BoundaryFill::for_opts(&opts);
+            let (perturbation, views) = compute_reference(opts, views).await?;
+            let boxed: Box<dyn FractalGeneratorInstance + Send> = Box::new(
+                CpuFractalGeneratorInstance::start(
+                    thread_pool,
+                    views,
+                    sender,
+                    perturbation,
+                    multisampling,
+                    fill,
+                )
+                .await,
+            );
+            Ok(boxed)
+        }
+        .boxed()
+    }
+
+    fn start_generation_to_gpu(
+        &self,
+        views: &[View],
+        present: GPUContext,
+        texture: Arc<Texture>,
+        _texture_view: Arc<TextureView>,
+    ) -> BoxFuture<'static, anyhow::Result<Box<dyn FractalGeneratorInstance + Send + 'static>>>
+    {
+        assert_eq!(
+            present.ty,
+            GPUContextType::Presentable,
+            "To-GPU GPUContext.ty must be GPUContextType::Presentable (this is a bug)"
+        );
+
+        let thread_pool = self.thread_pool.clone();
+        let views = views.to_vec();
+        let opts = self.opts.clone();
+        async move {
+            let sink = GpuValueBlockSink {
+                queue: present.queue,
+                texture,
+            };
+            let multisampling = opts.multisampling;
+            let fill = BoundaryFill::for_opts(&opts);
+            let (perturbation, views) = compute_reference(opts, views).await?;
+            let boxed: Box<dyn FractalGeneratorInstance + Send> = Box::new(
+                CpuFractalGeneratorInstance::start(
+                    thread_pool,
+                    views,
+                    sink,
+                    perturbation,
+                    multisampling,
+                    fill,
+                )
+                .await,
+            );
+            Ok(boxed)
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,359 @@
+use crate::generator::{
+    args::{Formula, Precision},
+    cpu::opts::{
+        cast, cast_complex, estimate_distance, AverageMeasure, CpuFractalOpts, CpuSmoothing,
+        InteriorMeasure, Sample, TrapMeasure,
+    },
+    perturbation::big_float::{BigFloat, PlanePoint},
+    view::View,
+    FractalOpts,
+};
+use num_complex::Complex;
+use num_traits::Float;
+
+/// Fractal options combined with a high-precision reference orbit that every
+/// pixel is iterated relative to.
+///
+/// Each pixel only tracks its difference (delta) from the reference orbit,
+/// which stays small enough to be represented by an `f32` or `f64` even when
+/// the pixels themselves are too close together to be told apart.
+pub struct PerturbationOpts {
+    opts: FractalOpts,
+    orbit: Vec<Complex<f64>>,
+    reference_pixel: (f64, f64),
+    reference_location: Complex<f64>,
+}
+
+impl PerturbationOpts {
+    /// Checks whether the given formula can be iterated using perturbation.
+    pub fn supports_formula(formula: &Formula) -> bool {
+        *formula == Formula::IntegerPower { exponent: 2 }
+    }
+
+    /// Computes the reference orbit for the image made up of `views`, placing
+    /// the reference at the center of the image.
+    ///
+    /// The image is placed on the plane by the options' arbitrary-precision
+    /// plane start if they have one, and by the views' `f64` plane starts
+    /// otherwise, which can't place images smaller than about `1e-16` times
+    /// their distance from the origin.
+    ///
+    /// # Panics
+    /// This panics if `views` is empty.
+    pub fn new(opts: FractalOpts, views: &[View]) -> PerturbationOpts {
+        let first = views[0];
+        let min_x = views.iter().map(|view| view.image_x).min().unwrap();
+        let min_y = views.iter().map(|view| view.image_y).min().unwrap();
+        let max_x = views
+            .iter()
+            .map(|view| view.image_x + view.image_width)
+            .max()
+            .unwrap();
+        let max_y = views
+            .iter()
+            .map(|view| view.image_y + view.image_height)
+            .max()
+            .unwrap();
+        let reference_pixel = ((min_x + max_x) as f64 / 2.0, (min_y + max_y) as f64 / 2.0);
+
+        let scale = first.image_scale_x.abs().min(first.image_scale_y.abs());
+        let limbs = BigFloat::limbs_for_scale(scale);
+
+        // The location of the image's top-left pixel is shared by every view so
+        // that all pixels use the same reference, even when the views' own
+        // plane starts can't be told apart.
+        let origin = match &opts.plane_start {
+            Some(plane_start) => PlanePoint {
+                re: plane_start.re.with_limbs(limbs),
+                im: plane_start.im.with_limbs(limbs),
+            },
+            None => PlanePoint::from_f64(
+                Complex::new(
+                    first.plane_start_x - first.image_x as f64 * first.image_scale_x,
+                    first.plane_start_y - first.image_y as f64 * first.image_scale_y,
+                ),
+                limbs,
+            ),
+        };
+
+        let big = |value: f64| BigFloat::from_f64(value, limbs);
+        let reference_re = &origin.re + &(&big(reference_pixel.0) * &big(first.image_scale_x));
+        let reference_im = &origin.im + &(&big(reference_pixel.1) * &big(first.image_scale_y));
+        let reference_location = Complex::new(reference_re.to_f64(), reference_im.to_f64());
+
+        let orbit = reference_orbit(&opts, reference_re, reference_im);
+
+        PerturbationOpts {
+            opts,
+            orbit,
+            reference_pixel,
+            reference_location,
+        }
+    }
+
+    /// Gets the number of points in the reference orbit.
+    pub fn reference_len(&self) -> usize {
+        self.orbit.len()
+    }
+
+    fn gen_delta_value(&self, delta: Complex<f64>) -> Sample {
+        match self.opts.precision {
+            Precision::Single => self.gen_delta_value_in::<f32>(cast_complex(delta)),
+            Precision::Double => self.gen_delta_value_in::<f64>(delta),
+        }
+    }
+
+    /// Iterates the pixel `delta` away from the reference using `T` for all
+    /// per-pixel arithmetic.
+    fn gen_delta_value_in<T: Float>(&self, delta: Complex<T>) -> Sample {
+        let zero = Complex::<T>::new(T::zero(), T::zero());
+        let (mut dz, dc) = if self.opts.mandelbrot {
+            (zero, delta)
+        } else {
+            (delta, zero)
+        };
+        let two: T = cast(2.0);
+        let radius_squared: T = cast(self.opts.radius_squared);
+        let start: Complex<T> = cast_complex(self.orbit[0]);
+
+        let mut index = 0;
+        let mut z = start + dz;
+        let mut z_prev = z;
+
+        // the derivative of the full value of z, if distances are being estimated
+        let estimates_distance = self.opts.distance_estimation;
+        let one = Complex::<T>::new(T::one(), T::zero());
+        let (mut der, der_c) = if self.opts.mandelbrot {
+            (zero, one)
+        } else {
+            (one, zero)
+        };
+
+        let trap = self.opts.orbit_trap.as_ref().map(TrapMeasure::new);
+        let mut trap_distance = f32::INFINITY;
+
+        // the full value of c is only needed by the few averages and interior
+        // colorings that use it, which don't need its precision
+        let c = if self.opts.mandelbrot {
+            cast_complex::<f64, T>(self.reference_location) + delta
+        } else {
+            cast_complex(self.opts.c)
+        };
+        let mut average = AverageMeasure::new(&self.opts, z, c);
+
+        let mut interior = InteriorMeasure::new(&self.opts);
+
+        let mut n = 0;
+        while n < self.opts.iterations {
+            if z.norm_sqr() > radius_squared {
+                break;
+            }
+
+            // A pixel is glitched when it gets closer to the start of the reference
+            // orbit than to the reference itself, because its delta is then too
+            // large compared to its value to keep enough precision. Glitched
+            // pixels, as well as pixels that outlive an escaped reference, are
+            // rebased onto the start of the reference orbit.
+            if index + 1 >= self.orbit.len() || (z - start).norm_sqr() < dz.norm_sqr() {
+                dz = z - start;
+                index = 0;
+            }
+
+            z_prev = z;
+
+            if estimates_distance {
+                der = z * der * two + der_c;
+            }
+
+            let reference: Complex<T> = cast_complex(self.orbit[index]);
+            dz = reference * dz * two + dz * dz + dc;
+            index += 1;
+            z = cast_complex::<f64, T>(self.orbit[index]) + dz;
+
+            if let Some(trap) = &trap {
+                trap_distance = trap_distance.min(trap.distance(z));
+            }
+
+            if let Some(average) = &mut average {
+                average.record(z);
+            }
+
+            n += 1;
+
+            if let Some(interior) = &mut interior {
+                interior.record(n, z);
+            }
+        }
+
+        if n < self.opts.iterations {
+            let value = match (&trap, &average) {
+                (Some(trap), _) => trap.value(n, z, trap_distance),
+                (None, Some(average)) => {
+                    average.value(&self.opts.smoothing, n, z, z_prev, radius_squared)
+                },
+                (None, None) => self.opts.smoothing.smooth(n, z, z_prev, radius_squared),
+            };
+
+            Sample {
+                value,
+                distance: estimates_distance.then(|| estimate_distance(z, der)),
+                interior: None,
+            }
+        } else if let Some(interior) = &interior {
+            interior.sample(z, c)
+        } else {
+            Sample::new(n as f32)
+        }
+    }
+}
+
+impl CpuFractalOpts for PerturbationOpts {
+    fn gen_value(&self, loc: Complex<f64>) -> Sample {
+        self.gen_delta_value(loc - self.reference_location)
+    }
+
+    fn iterations(&self) -> u32 {
+        self.opts.iterations
+    }
+
+    fn gen_pixel_value(&self, view: View, x: f64, y: f64) -> Sample {
+        // Deltas are computed from pixel positions rather than plane coordinates
+        // so that they keep their precision.
+        self.gen_delta_value(Complex::new(
+            (view.image_x as f64 + x - self.reference_pixel.0) * view.image_scale_x,
+            (view.image_y as f64 + y - self.reference_pixel.1) * view.image_scale_y,
+        ))
+    }
+}
+
+/// Iterates the reference point at (`re` + `im`i) in high precision, stopping
+/// once it escapes or reaches the iteration limit.
+fn reference_orbit(opts: &FractalOpts, re: BigFloat, im: BigFloat) -> Vec<Complex<f64>> {
+    let limbs = re.limbs();
+    let ((mut z_re, mut z_im), (c_re, c_im)) = if opts.mandelbrot {
+        ((BigFloat::zero(limbs), BigFloat::zero(limbs)), (re, im))
+    } else {
+        (
+            (re, im),
+            (
+                BigFloat::from_f64(opts.c.re, limbs),
+                BigFloat::from_f64(opts.c.im, limbs),
+            ),
+        )
+    };
+    let radius_squared = opts.radius_squared as f64;
+
+    let mut orbit = Vec::with_capacity(opts.iterations as usize + 1);
+    loop {
+        let z = Complex::new(z_re.to_f64(), z_im.to_f64());
+        orbit.push(z);
+
+        if orbit.len() > opts.iterations as usize || z.norm_sqr() > radius_squared {
+            break;
+        }
+
+        let re_im = &z_re * &z_im;
+        let new_re = &(&(&z_re * &z_re) - &(&z_im * &z_im)) + &c_re;
+        z_im = &(&re_im + &re_im) + &c_im;
+        z_re = new_re;
+    }
+
+    orbit
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num_complex::Complex64;
+
+    fn test_opts(mandelbrot: bool, iterations: u32) -> FractalOpts {
+        FractalOpts {
+            mandelbrot,
+            formula: Formula::IntegerPower { exponent: 2 },
+            iterations,
+            c: Complex64 {
+                re: -0.8,
+                im: 0.156,
+            },
+            precision: Precision::Double,
+            ..FractalOpts::test_base()
+        }
+    }
+
+    #[test]
+    fn matches_direct_iteration() {
+        for mandelbrot in [true, false] {
+            let opts = test_opts(mandelbrot, 200);
+            let view = View::new_uniform(32, 32, 1e-3, -0.7436, 0.1318);
+            let views: Vec<_> = view.subdivide_rectangles(16, 16).collect();
+            let perturbation = PerturbationOpts::new(opts.clone(), &views);
+
+            for sub_view in views {
+                for y in 0..sub_view.image_height {
+                    for x in 0..sub_view.image_width {
+                        let (x, y) = (x as f64 + 0.5, y as f64 + 0.5);
+                        let expected = opts.gen_pixel_value(sub_view, x, y).value;
+                        let actual = perturbation.gen_pixel_value(sub_view, x, y).value;
+                        assert!(
+                            (expected - actual).abs() < 1e-2,
+                            "mandelbrot: {}, ({}, {}) in {:?}: expected {}, got {}",
+                            mandelbrot,
+                            x,
+                            y,
+                            sub_view,
+                            expected,
+                            actual
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn resolves_zooms_beyond_double_precision() {
+        let opts = test_opts(true, 10000);
+        let view = View::new_uniform(16, 16, 1e-20, -0.743643887037151, 0.131825904205330);
+        let perturbation = PerturbationOpts::new(opts.clone(), &[view]);
+
+        let direct: Vec<_> = (0..16)
+            .map(|x| opts.gen_pixel_value(view, x as f64 + 0.5, 8.5).value)
+            .collect();
+        let perturbed: Vec<_> = (0..16)
+            .map(|x| {
+                perturbation
+                    .gen_pixel_value(view, x as f64 + 0.5, 8.5)
+                    .value
+            })
+            .collect();
+
+        assert!(direct.windows(2).all(|pair| pair[0] == pair[1]));
+        assert!(perturbed.windows(2).any(|pair| pair[0] != pair[1]));
+    }
+
+    #[test]
+    fn separates_views_closer_than_double_precision() {
+        let view = View::new_uniform(16, 16, 1e-20, -0.743643887037151, 0.131825904205330);
+        let limbs = BigFloat::limbs_for_scale(view.image_scale_x);
+        let start =
+            PlanePoint::from_f64(Complex::new(view.plane_start_x, view.plane_start_y), limbs);
+        let moved = start.offset(Complex::new(1e-20, 0.0), limbs);
+        assert_eq!(start.to_f64(), moved.to_f64());
+
+        let render = |plane_start: PlanePoint| {
+            let perturbation = PerturbationOpts::new(
+                FractalOpts {
+                    plane_start: Some(plane_start),
+                    ..test_opts(true, 10000)
+                },
+                &[view],
+            );
+            (0..16)
+                .flat_map(|y| (0..16).map(move |x| (x as f64 + 0.5, y as f64 + 0.5)))
+                .map(|(x, y)| perturbation.gen_pixel_value(view, x, y).value)
+                .collect::<Vec<_>>()
+        };
+
+        assert_ne!(render(start), render(moved));
+    }
+}
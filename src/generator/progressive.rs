@@ -0,0 +1,264 @@
+//! This module contains progressive previews, which generate a fractal at a
+//! fraction of its resolution and upscale the result into the viewer's
+//! texture. A viewer generation made of several such passes, each at a higher
+//! resolution than the last, refines the image in place instead of leaving it
+//! blank until full-resolution chunks are ready.
+
+use crate::{
+    generator::{
+        args::Multisampling,
+        cpu::{GpuValueBlockSink, ValueBlockSink},
+        view::View,
+        FractalGenerator, FractalGeneratorInstance, FractalOpts, PixelValue, ValueBlock,
+        NO_DISTANCE,
+    },
+    gpu::GPUContext,
+    util::running_guard::RunningGuard,
+};
+use futures::{future::BoxFuture, FutureExt};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::mpsc;
+use wgpu::Texture;
+
+/// The resolution divisors of the preview passes, from coarsest to finest.
+pub const PREVIEW_DOWNSCALES: [usize; 3] = [8, 4, 2];
+
+const MAX_PREVIEW_BACKLOG: usize = 32;
+
+/// A single pass of a progressive generation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pass {
+    pub opts: FractalOpts,
+    /// How many pixels along each axis each generated pixel covers. Passes
+    /// with a `downscale` of 1 are generated at full resolution.
+    pub downscale: usize,
+}
+
+/// Gets the passes that make up a progressive generation with the given
+/// options.
+///
+/// Every pass but the last is generated without multisampling. The last pass
+/// is the only one using `opts` as they are, so that a generator created for
+/// it is the same as one created for a non-progressive generation.
+pub fn passes(opts: &FractalOpts) -> Vec<Pass> {
+    let mut preview_opts = opts.clone();
+    preview_opts.multisampling = Multisampling::None;
+
+    let mut passes: Vec<_> = PREVIEW_DOWNSCALES
+        .iter()
+        .map(|&downscale| Pass {
+            opts: preview_opts.clone(),
+            downscale,
+        })
+        .collect();
+
+    if opts.multisampling != Multisampling::None {
+        passes.push(Pass {
+            opts: preview_opts,
+            downscale: 1,
+        });
+    }
+
+    passes.push(Pass {
+        opts: opts.clone(),
+        downscale: 1,
+    });
+
+    passes
+}
+
+/// Gets the view covering the same part of the complex plane as `view`, but
+/// with each pixel covering `downscale` by `downscale` pixels of `view`'s
+/// image.
+pub fn downscale_view(view: &View, downscale: usize) -> View {
+    let image_x = view.image_x / downscale;
+    let image_y = view.image_y / downscale;
+    let end_x = (view.image_x + view.image_width + downscale - 1) / downscale;
+    let end_y = (view.image_y + view.image_height + downscale - 1) / downscale;
+
+    View {
+        image_width: end_x - image_x,
+        image_height: end_y - image_y,
+        image_x,
+        image_y,
+        image_scale_x: view.image_scale_x * downscale as f64,
+        image_scale_y: view.image_scale_y * downscale as f64,
+        // views that don't start on a multiple of `downscale` start a little earlier
+        plane_start_x: view.plane_start_x
+            - (view.image_x - image_x * downscale) as f64 * view.image_scale_x,
+        plane_start_y: view.plane_start_y
+            - (view.image_y - image_y * downscale) as f64 * view.image_scale_y,
+    }
+}
+
+/// Upscales a block generated for `downscale_view(view, downscale)` back to
+/// the full resolution of `view`. Distances are scaled to full-resolution
+/// pixels along with the block.
+pub fn upscale_block(block: &ValueBlock, downscale: usize, view: View) -> ValueBlock {
+    let downscaled = block.view;
+
+    let mut values = Vec::with_capacity(view.image_width * view.image_height);
+    for y in 0..view.image_height {
+        let row_y = (view.image_y + y) / downscale - downscaled.image_y;
+        let row = &block.values[row_y * downscaled.image_width..];
+        values.extend((0..view.image_width).map(|x| {
+            let value = row[(view.image_x + x) / downscale - downscaled.image_x];
+            PixelValue {
+                distance: (value.distance * downscale as f32).min(NO_DISTANCE),
+                ..value
+            }
+        }));
+    }
+
+    ValueBlock {
+        view,
+        values: values.into_boxed_slice(),
+    }
+}
+
+/// Starts generating `views` at 1/`downscale` of their resolution, upscaling
+/// each generated block into `texture`.
+pub fn start_downscaled_generation_to_gpu(
+    generator: &(dyn FractalGenerator + Send),
+    views: &[View],
+    downscale: usize,
+    present: GPUContext,
+    texture: Arc<Texture>,
+) -> BoxFuture<'static, anyhow::Result<Box<dyn FractalGeneratorInstance + Send + 'static>>> {
+    let downscaled: Vec<_> = views
+        .iter()
+        .map(|view| downscale_view(view, downscale))
+        .collect();
+    // the downscaled views start where their full-resolution views start
+    let full_views: HashMap<_, _> = downscaled
+        .iter()
+        .zip(views.iter())
+        .map(|(downscaled, view)| ((downscaled.image_x, downscaled.image_y), *view))
+        .collect();
+    let (sender, mut receiver) = mpsc::channel::<anyhow::Result<ValueBlock>>(MAX_PREVIEW_BACKLOG);
+    let start = generator.start_generation_to_cpu(&downscaled, sender);
+
+    async move {
+        let inner = start.await?;
+
+        let upscaling = Arc::new(AtomicBool::new(true));
+        let async_upscaling = upscaling.clone();
+        let sink = GpuValueBlockSink {
+            queue: present.queue,
+            texture,
+        };
+
+        tokio::spawn(async move {
+            let _running_guard = RunningGuard::new(async_upscaling);
+
+            // the channel closes once the generator has finished or been canceled
+            while let Some(block) = receiver.recv().await {
+                match block {
+                    Ok(block) => {
+                        let view = full_views[&(block.view.image_x, block.view.image_y)];
+                        let upscaled = upscale_block(&block, downscale, view);
+                        sink.accept(upscaled).await.ok();
+                    },
+                    Err(e) => error!("Error generating preview: {:?}", e),
+                }
+            }
+        });
+
+        let boxed: Box<dyn FractalGeneratorInstance + Send> =
+            Box::new(UpscalingInstance { inner, upscaling });
+        Ok(boxed)
+    }
+    .boxed()
+}
+
+/// A downscaled generation, which keeps running until its last block has been
+/// upscaled.
+struct UpscalingInstance {
+    inner: Box<dyn FractalGeneratorInstance + Send>,
+    upscaling: Arc<AtomicBool>,
+}
+
+impl FractalGeneratorInstance for UpscalingInstance {
+    fn cancel(&self) {
+        self.inner.cancel();
+    }
+
+    fn progress(&self) -> BoxFuture<'static, anyhow::Result<f32>> {
+        self.inner.progress()
+    }
+
+    fn running(&self) -> BoxFuture<'static, anyhow::Result<bool>> {
+        let running = self.inner.running();
+        let upscaling = self.upscaling.clone();
+        async move { Ok(running.await? || upscaling.load(Ordering::Acquire)) }.boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::args::Smoothing;
+
+    #[test]
+    fn downscaled_views_cover_the_image() {
+        let parent = View::new_centered_uniform(100, 60, 3.0);
+        for view in parent.subdivide_rectangles(32, 32) {
+            let downscaled = downscale_view(&view, 8);
+            let block = ValueBlock {
+                view: downscaled,
+                values: (0..downscaled.image_width * downscaled.image_height)
+                    .map(|i| PixelValue {
+                        value: i as f32,
+                        coverage: 1.0,
+                        ..Default::default()
+                    })
+                    .collect(),
+            };
+
+            // the upscaled block covers exactly the view it was generated for
+            let upscaled = upscale_block(&block, 8, view);
+            assert_eq!(upscaled.view, view);
+            assert_eq!(upscaled.values.len(), view.image_width * view.image_height);
+            assert_eq!(upscaled.values.last(), block.values.last());
+
+            // each generated pixel is at the center of the pixels it covers
+            let center = downscaled.get_local_plane_coordinates((0, 0));
+            let covered = parent.get_local_subpixel_plane_coordinates((
+                (downscaled.image_x * 8) as f64 + 4.0,
+                (downscaled.image_y * 8) as f64 + 4.0,
+            ));
+            assert!((center - covered).norm() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn last_pass_uses_original_options() {
+        let opts = FractalOpts {
+            iterations: 100,
+            smoothing: Smoothing::None,
+            multisampling: Multisampling::Linear { axial_points: 4 },
+            c: Default::default(),
+            radius_squared: 4.0,
+            precision: Default::default(),
+            ..FractalOpts::test_base()
+        };
+
+        let progressive = passes(&opts);
+        let downscales: Vec<_> = progressive.iter().map(|pass| pass.downscale).collect();
+        assert_eq!(downscales, vec![8, 4, 2, 1, 1]);
+        assert_eq!(progressive.last().unwrap().opts, opts);
+        assert!(progressive[..4]
+            .iter()
+            .all(|pass| pass.opts.multisampling == Multisampling::None));
+
+        let mut opts = opts;
+        opts.multisampling = Multisampling::None;
+        assert_eq!(passes(&opts).len(), 4);
+    }
+}
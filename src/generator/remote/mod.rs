@@ -0,0 +1,465 @@
+//! This module contains a fractal generator that runs on a render node, which
+//! is another fractal-rs-2 process started with the `node` subcommand that is
+//! reachable over TCP.
+//!
+//! Each generator created by a [`RemoteFractalGeneratorFactory`] has its own
+//! connection to the node, which hosts a local generator with the same
+//! options. Generated values are always sent back as [`ValueBlock`]s, which
+//! are written to the texture when generating to the GPU.
+
+use crate::{
+    generator::{
+        cpu::{GpuValueBlockSink, ValueBlockSink},
+        remote::protocol::{
+            read_handshake, read_message, write_handshake, write_message, Request, Response,
+            MAX_REQUEST_SIZE, MAX_RESPONSE_SIZE,
+        },
+        view::View,
+        FractalGenerator, FractalGeneratorFactory, FractalGeneratorInstance, FractalOpts,
+        ValueBlock,
+    },
+    gpu::{GPUContext, GPUContextType},
+    util::{result::ResultExt, running_guard::RunningGuard},
+};
+use anyhow::Context;
+use futures::{
+    future::{ready, BoxFuture},
+    FutureExt,
+};
+use std::sync::{
+    atomic::{AtomicBool, AtomicUsize, Ordering},
+    Arc,
+};
+use tokio::{
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    sync::{mpsc::Sender, oneshot, Mutex, Notify},
+};
+use wgpu::{Texture, TextureView};
+
+pub mod node;
+pub mod protocol;
+
+/// The port render nodes listen on by default.
+pub const DEFAULT_NODE_PORT: u16 = 7117;
+
+pub struct RemoteFractalGeneratorFactory {
+    address: String,
+}
+
+impl RemoteFractalGeneratorFactory {
+    /// Creates a factory for generators running on the render node at
+    /// `address`, which is a `host:port` pair.
+    pub fn new(address: impl Into<String>) -> RemoteFractalGeneratorFactory {
+        RemoteFractalGeneratorFactory {
+            address: address.into(),
+        }
+    }
+}
+
+impl FractalGeneratorFactory for RemoteFractalGeneratorFactory {
+    fn create_generator(
+        &self,
+        opts: FractalOpts,
+    ) -> BoxFuture<'static, anyhow::Result<Box<dyn FractalGenerator + Send + 'static>>> {
+        let address = self.address.clone();
+        async move {
+            info!("Connecting to render node {}...", address);
+            let stream = TcpStream::connect(&address)
+                .await
+                .with_context(|| format!("Error connecting to render node {}", address))?;
+            stream.set_nodelay(true)?;
+            let (mut reader, mut writer) = stream.into_split();
+            write_handshake(&mut writer).await?;
+            read_handshake(&mut reader).await?;
+
+            info!("Creating generator on render node {}...", address);
+            write_message(
+                &mut writer,
+                &Request::CreateGenerator { opts },
+                MAX_REQUEST_SIZE,
+            )
+            .await?;
+            let min_views_hint = match read_message(&mut reader, MAX_RESPONSE_SIZE).await? {
+                Some(Response::GeneratorCreated { min_views_hint }) => min_views_hint,
+                response => return Err(unexpected_response(response)),
+            };
+
+            let boxed: Box<dyn FractalGenerator + Send> = Box::new(RemoteFractalGenerator {
+                connection: Arc::new(Connection {
+                    reader: Mutex::new(reader),
+                    writer: Mutex::new(writer),
+                }),
+                min_views_hint,
+            });
+            Ok(boxed)
+        }
+        .boxed()
+    }
+
+    fn supports_precision(&self, _opts: &FractalOpts) -> bool {
+        // render nodes fall back to the CPU for precisions their own generator
+        // doesn't support
+        true
+    }
+
+    fn uses_plane_start(&self) -> bool {
+        // the render node's own generator may be placing images by it
+        true
+    }
+}
+
+/// The connection between a remote generator and its render node.
+struct Connection {
+    /// This is held by the running generation until it has read all of its
+    /// responses.
+    reader: Mutex<OwnedReadHalf>,
+    writer: Mutex<OwnedWriteHalf>,
+}
+
+impl Connection {
+    async fn send(&self, request: &Request) -> anyhow::Result<()> {
+        let mut writer = self.writer.lock().await;
+        write_message(&mut *writer, request, MAX_REQUEST_SIZE).await?;
+        Ok(())
+    }
+}
+
+pub struct RemoteFractalGenerator {
+    connection: Arc<Connection>,
+    min_views_hint: usize,
+}
+
+impl RemoteFractalGenerator {
+    fn start<S: ValueBlockSink + Send + Sync + 'static>(
+        &self,
+        views: &[View],
+        sink: S,
+        errors: Option<Sender<anyhow::Result<ValueBlock>>>,
+    ) -> BoxFuture<'static, anyhow::Result<Box<dyn FractalGeneratorInstance + Send + 'static>>>
+    {
+        let connection = self.connection.clone();
+        let views = views.to_vec();
+        async move {
+            info!("Starting new remote fractal generator...");
+            let view_count = views.len();
+            connection.send(&Request::Start { views }).await?;
+
+            let completed = Arc::new(AtomicUsize::new(0));
+            let running = Arc::new(AtomicBool::new(true));
+            let cancel = Arc::new(Notify::new());
+            let async_completed = completed.clone();
+            let async_running = running.clone();
+            let async_cancel = cancel.clone();
+
+            tokio::spawn(async move {
+                let _running_guard = RunningGuard::new(async_running);
+
+                // cancel requests are sent separately, because the connection's
+                // reader is busy until the node has finished
+                let (done, done_receiver) = oneshot::channel::<()>();
+                let cancel_connection = connection.clone();
+                let cancel_notify = async_cancel.clone();
+                let canceler = tokio::spawn(async move {
+                    tokio::select! {
+                        biased;
+                        _ = done_receiver => {},
+                        _ = cancel_notify.notified() => {
+                            info!("Received cancel signal.");
+                            cancel_connection
+                                .send(&Request::Cancel)
+                                .await
+                                .on_err(|e| warn!("Error canceling remote generator: {:?}", e));
+                        },
+                    }
+                });
+
+                let res = receive_blocks(&connection, &sink, &async_completed, &async_cancel).await;
+
+                // make sure any cancel request is sent before the next generation starts
+                done.send(()).ok();
+                canceler
+                    .await
+                    .on_err(|e| warn!("JoinError in remote generator: {:?}", e));
+
+                if let Err(e) = res {
+                    error!("Error in remote generator: {:?}", e);
+                    if let Some(errors) = errors {
+                        errors.send(Err(e)).await.ok();
+                    }
+                }
+            });
+
+            let boxed: Box<dyn FractalGeneratorInstance + Send> =
+                Box::new(RemoteFractalGeneratorInstance {
+                    view_count,
+                    completed,
+                    running,
+                    cancel,
+                });
+            Ok(boxed)
+        }
+        .boxed()
+    }
+}
+
+impl FractalGenerator for RemoteFractalGenerator {
+    fn min_views_hint(&self) -> BoxFuture<'static, anyhow::Result<usize>> {
+        ready(Ok(self.min_views_hint)).boxed()
+    }
+
+    fn start_generation_to_cpu(
+        &self,
+        views: &[View],
+        sender: Sender<anyhow::Result<ValueBlock>>,
+    ) -> BoxFuture<'static, anyhow::Result<Box<dyn FractalGeneratorInstance + Send + 'static>>>
+    {
+        self.start(views, sender.clone(), Some(sender))
+    }
+
+    fn start_generation_to_gpu(
+        &self,
+        views: &[View],
+        present: GPUContext,
+        texture: Arc<Texture>,
+        _texture_view: Arc<TextureView>,
+    ) -> BoxFuture<'static, anyhow::Result<Box<dyn FractalGeneratorInstance + Send + 'static>>>
+    {
+        assert_eq!(
+            present.ty,
+            GPUContextType::Presentable,
+            "To-GPU GPUContext.ty must be GPUContextType::Presentable (this is a bug)"
+        );
+
+        let sink = GpuValueBlockSink {
+            queue: present.queue,
+            texture,
+        };
+        self.start(views, sink, None)
+    }
+}
+
+struct RemoteFractalGeneratorInstance {
+    view_count: usize,
+    completed: Arc<AtomicUsize>,
+    running: Arc<AtomicBool>,
+    cancel: Arc<Notify>,
+}
+
+impl FractalGeneratorInstance for RemoteFractalGeneratorInstance {
+    fn cancel(&self) {
+        self.cancel.notify_one();
+    }
+
+    fn progress(&self) -> BoxFuture<'static, anyhow::Result<f32>> {
+        ready(Ok(
+            self.completed.load(Ordering::Acquire) as f32 / self.view_count as f32
+        ))
+        .boxed()
+    }
+
+    fn running(&self) -> BoxFuture<'static, anyhow::Result<bool>> {
+        ready(Ok(self.running.load(Ordering::Acquire))).boxed()
+    }
+}
+
+/// Reads the node's responses to a `Start` request until it has finished,
+/// passing each block on to `sink`.
+async fn receive_blocks<S: ValueBlockSink>(
+    connection: &Connection,
+    sink: &S,
+    completed: &AtomicUsize,
+    cancel: &Notify,
+) -> anyhow::Result<()> {
+    let mut reader = connection.reader.lock().await;
+    let mut sink_open = true;
+
+    loop {
+        match read_message(&mut *reader, MAX_RESPONSE_SIZE).await? {
+            Some(Response::Block { view, values }) => {
+                completed.fetch_add(1, Ordering::AcqRel);
+
+                // Blocks still have to be read after the sink has closed, so that
+                // the next generation doesn't receive them.
+                if sink_open {
+                    if let Err(e) = sink.accept(ValueBlock { view, values }).await {
+                        warn!(
+                            "Error while submitting value block in remote generator: {:?}",
+                            e
+                        );
+                        sink_open = false;
+                        cancel.notify_one();
+                    }
+                }
+            },
+            Some(Response::Finished) => return Ok(()),
+            response => return Err(unexpected_response(response)),
+        }
+    }
+}
+
+fn unexpected_response(response: Option<Response>) -> anyhow::Error {
+    match response {
+        Some(Response::Error { message }) => anyhow!("Render node error: {}", message),
+        Some(response) => anyhow!("Unexpected response from render node: {:?}", response),
+        None => anyhow!("Render node closed the connection"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{cpu::CpuFractalGeneratorFactory, PixelValue};
+    use std::net::SocketAddr;
+    use tokio::{net::TcpListener, runtime::Runtime, sync::mpsc, task::yield_now};
+
+    fn test_opts(iterations: u32) -> FractalOpts {
+        FractalOpts {
+            iterations,
+            ..FractalOpts::test_base()
+        }
+    }
+
+    /// Starts a render node generating on the CPU on a free localhost port,
+    /// returning its address.
+    async fn start_node_at() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(node::serve(
+            listener,
+            Arc::new(CpuFractalGeneratorFactory::new(2)),
+            64 * 64,
+        ));
+        address
+    }
+
+    async fn start_node() -> RemoteFractalGeneratorFactory {
+        RemoteFractalGeneratorFactory::new(start_node_at().await.to_string())
+    }
+
+    async fn generate(
+        generator: &(dyn FractalGenerator + Send),
+        views: &[View],
+    ) -> Vec<(View, Box<[PixelValue]>)> {
+        let (sender, mut receiver) = mpsc::channel(views.len());
+        let _instance = generator
+            .start_generation_to_cpu(views, sender)
+            .await
+            .unwrap();
+
+        // the channel closes once generation has finished
+        let mut blocks = vec![];
+        while let Some(block) = receiver.recv().await {
+            let block = block.unwrap();
+            blocks.push((block.view, block.values));
+        }
+        blocks.sort_by_key(|(view, _)| (view.image_y, view.image_x));
+        blocks
+    }
+
+    fn runtime() -> Runtime {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn matches_local_generation() {
+        runtime().block_on(async {
+            let remote = start_node().await;
+            let local = CpuFractalGeneratorFactory::new(2);
+            let views: Vec<_> = View::new_centered_uniform(32, 32, 3.0)
+                .subdivide_rectangles(16, 16)
+                .collect();
+
+            let remote_generator = remote.create_generator(test_opts(100)).await.unwrap();
+            assert_eq!(remote_generator.min_views_hint().await.unwrap(), 2);
+            let local_generator = local.create_generator(test_opts(100)).await.unwrap();
+
+            let expected = generate(local_generator.as_ref(), &views).await;
+            assert_eq!(expected.len(), views.len());
+            assert_eq!(generate(remote_generator.as_ref(), &views).await, expected);
+        });
+    }
+
+    #[test]
+    fn generates_again_after_cancel() {
+        runtime().block_on(async {
+            let remote = start_node().await;
+            let generator = remote.create_generator(test_opts(10000)).await.unwrap();
+            let views: Vec<_> = View::new_centered_uniform(256, 256, 3.0)
+                .subdivide_rectangles(16, 16)
+                .collect();
+
+            let (sender, _receiver) = mpsc::channel(views.len());
+            let instance = generator
+                .start_generation_to_cpu(&views, sender)
+                .await
+                .unwrap();
+            instance.cancel();
+            while instance.running().await.unwrap() {
+                yield_now().await;
+            }
+            assert!(instance.progress().await.unwrap() < 1.0);
+
+            // the canceled generation's blocks must not be mixed into this one's
+            let small_views: Vec<_> = View::new_centered_uniform(16, 16, 3.0)
+                .subdivide_rectangles(16, 16)
+                .collect();
+            let blocks = generate(generator.as_ref(), &small_views).await;
+            assert_eq!(blocks.len(), 1);
+            assert_eq!(blocks[0].0, small_views[0]);
+        });
+    }
+
+    #[test]
+    fn rejects_oversized_views() {
+        runtime().block_on(async {
+            let stream = TcpStream::connect(start_node_at().await).await.unwrap();
+            let (mut reader, mut writer) = stream.into_split();
+            write_handshake(&mut writer).await.unwrap();
+            read_handshake(&mut reader).await.unwrap();
+
+            let request = Request::CreateGenerator {
+                opts: test_opts(100),
+            };
+            write_message(&mut writer, &request, MAX_REQUEST_SIZE)
+                .await
+                .unwrap();
+            assert!(matches!(
+                read_message(&mut reader, MAX_RESPONSE_SIZE).await.unwrap(),
+                Some(Response::GeneratorCreated { .. })
+            ));
+
+            // a view far larger than the node allows is refused without being
+            // generated
+            let huge = View::new_centered_uniform(1 << 30, 1 << 30, 3.0);
+            let request = Request::Start { views: vec![huge] };
+            write_message(&mut writer, &request, MAX_REQUEST_SIZE)
+                .await
+                .unwrap();
+            assert!(matches!(
+                read_message(&mut reader, MAX_RESPONSE_SIZE).await.unwrap(),
+                Some(Response::Error { .. })
+            ));
+
+            // and the node keeps serving views within its limit
+            let view = View::new_centered_uniform(64, 64, 3.0);
+            let request = Request::Start { views: vec![view] };
+            write_message(&mut writer, &request, MAX_REQUEST_SIZE)
+                .await
+                .unwrap();
+            assert!(matches!(
+                read_message(&mut reader, MAX_RESPONSE_SIZE).await.unwrap(),
+                Some(Response::Block { view: block_view, .. }) if block_view == view
+            ));
+            assert_eq!(
+                read_message(&mut reader, MAX_RESPONSE_SIZE).await.unwrap(),
+                Some(Response::Finished)
+            );
+        });
+    }
+}
@@ -0,0 +1,253 @@
+//! This module contains the render node, which hosts a local fractal generator
+//! for [`RemoteFractalGeneratorFactory`] clients connecting over TCP.
+//!
+//! [`RemoteFractalGeneratorFactory`]: super::RemoteFractalGeneratorFactory
+
+use crate::{
+    generator::{
+        cpu::CpuFractalGeneratorFactory,
+        remote::protocol::{
+            read_handshake, read_message, write_handshake, write_message, ProtocolError, Request,
+            Response, MAX_REQUEST_SIZE, MAX_RESPONSE_SIZE, MAX_VIEW_DIMENSION,
+        },
+        view::View,
+        FractalGenerator, FractalGeneratorFactory, FractalGeneratorInstance,
+    },
+    util::result::ResultExt,
+};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    task::JoinHandle,
+};
+
+/// How many generated blocks can be waiting to be sent to a client before the
+/// generator is made to wait.
+const BLOCK_BACKLOG: usize = 32;
+
+/// Accepts clients on `listener` forever, generating their fractals with
+/// generators created by `factory`.
+///
+/// Options that `factory` doesn't support the precision of are generated on
+/// the CPU instead. Views with more than `max_view_pixels` pixels, or wider or
+/// taller than [`MAX_VIEW_DIMENSION`], are rejected.
+pub async fn serve(
+    listener: TcpListener,
+    factory: Arc<dyn FractalGeneratorFactory + Send + Sync>,
+    max_view_pixels: usize,
+) -> Result<!, ProtocolError> {
+    let fallback: Arc<dyn FractalGeneratorFactory + Send + Sync> =
+        Arc::new(CpuFractalGeneratorFactory::new(num_cpus::get()));
+
+    loop {
+        let (stream, address) = listener.accept().await?;
+        info!("Accepted render node client {}", address);
+
+        let factory = factory.clone();
+        let fallback = fallback.clone();
+        tokio::spawn(async move {
+            match handle_client(stream, factory, fallback, max_view_pixels).await {
+                Ok(_) => info!("Render node client {} disconnected", address),
+                Err(e) => warn!("Error serving render node client {}: {:?}", address, e),
+            }
+        });
+    }
+}
+
+/// The generation a client most recently started.
+struct Generation {
+    instance: Box<dyn FractalGeneratorInstance + Send>,
+    /// Sends the generated blocks to the client, followed by `Finished`.
+    forwarder: JoinHandle<()>,
+}
+
+async fn handle_client(
+    stream: TcpStream,
+    factory: Arc<dyn FractalGeneratorFactory + Send + Sync>,
+    fallback: Arc<dyn FractalGeneratorFactory + Send + Sync>,
+    max_view_pixels: usize,
+) -> Result<(), ProtocolError> {
+    stream.set_nodelay(true)?;
+    let (mut reader, mut writer) = stream.into_split();
+    write_handshake(&mut writer).await?;
+    read_handshake(&mut reader).await?;
+
+    // Responses are written by their own task, so that requests like `Cancel`
+    // can still be read while blocks are being sent.
+    let (responses, mut response_receiver) = mpsc::channel::<Response>(BLOCK_BACKLOG);
+    let writer_task = tokio::spawn(async move {
+        while let Some(response) = response_receiver.recv().await {
+            write_message(&mut writer, &response, MAX_RESPONSE_SIZE).await?;
+        }
+        Ok::<_, ProtocolError>(())
+    });
+
+    let mut generator: Option<Box<dyn FractalGenerator + Send>> = None;
+    let mut generation: Option<Generation> = None;
+
+    let res = async {
+        while let Some(request) = read_message(&mut reader, MAX_REQUEST_SIZE).await? {
+            match request {
+                Request::CreateGenerator { opts } => {
+                    let factory = if factory.supports_precision(&opts) {
+                        &factory
+                    } else {
+                        &fallback
+                    };
+
+                    let res = async {
+                        let new_generator = factory.create_generator(opts).await?;
+                        let min_views_hint = new_generator.min_views_hint().await?;
+                        Ok::<_, anyhow::Error>((new_generator, min_views_hint))
+                    }
+                    .await;
+
+                    let response = match res {
+                        Ok((new_generator, min_views_hint)) => {
+                            generator = Some(new_generator);
+                            Response::GeneratorCreated { min_views_hint }
+                        },
+                        Err(e) => Response::Error {
+                            message: format!("Error creating generator: {:#}", e),
+                        },
+                    };
+                    if responses.send(response).await.is_err() {
+                        break;
+                    }
+                },
+                Request::Start { views } => {
+                    // The previous generation's blocks must all be sent before this one's.
+                    // Its client has moved on, so it is canceled rather than waited out.
+                    if let Some(previous) = generation.take() {
+                        previous.instance.cancel();
+                        previous.forwarder.await.ok();
+                    }
+
+                    let generator = match &generator {
+                        Some(generator) => generator,
+                        None => {
+                            let response = Response::Error {
+                                message: "No generator has been created".to_string(),
+                            };
+                            if responses.send(response).await.is_err() {
+                                break;
+                            }
+                            continue;
+                        },
+                    };
+
+                    if let Err(message) = check_views(&views, max_view_pixels) {
+                        if responses.send(Response::Error { message }).await.is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+
+                    let (sender, mut receiver) = mpsc::channel(BLOCK_BACKLOG);
+                    match generator.start_generation_to_cpu(&views, sender).await {
+                        Ok(instance) => {
+                            let responses = responses.clone();
+                            let forwarder = tokio::spawn(async move {
+                                // the channel closes once the generator has finished or been
+                                // canceled
+                                while let Some(block) = receiver.recv().await {
+                                    let response = match block {
+                                        Ok(block) => Response::Block {
+                                            view: block.view,
+                                            values: block.values,
+                                        },
+                                        Err(e) => {
+                                            let response = Response::Error {
+                                                message: format!(
+                                                    "Error generating fractal: {:#}",
+                                                    e
+                                                ),
+                                            };
+                                            responses.send(response).await.ok();
+                                            return;
+                                        },
+                                    };
+                                    if responses.send(response).await.is_err() {
+                                        return;
+                                    }
+                                }
+                                responses.send(Response::Finished).await.ok();
+                            });
+
+                            generation = Some(Generation {
+                                instance,
+                                forwarder,
+                            });
+                        },
+                        Err(e) => {
+                            let response = Response::Error {
+                                message: format!("Error starting generator: {:#}", e),
+                            };
+                            if responses.send(response).await.is_err() {
+                                break;
+                            }
+                        },
+                    }
+                },
+                Request::Cancel => {
+                    if let Some(generation) = &generation {
+                        generation.instance.cancel();
+                    }
+                },
+            }
+        }
+        Ok::<_, ProtocolError>(())
+    }
+    .await;
+
+    // nobody is left to receive the rest of the blocks
+    if let Some(generation) = generation {
+        generation.instance.cancel();
+        generation.forwarder.await.ok();
+    }
+
+    drop(responses);
+    res?;
+    writer_task
+        .await
+        .on_err(|e| warn!("JoinError in render node writer: {:?}", e))
+        .unwrap_or(Ok(()))
+}
+
+/// Makes sure every view is small enough to generate, so that a client can't
+/// make the node allocate more than it is willing to.
+fn check_views(views: &[View], max_view_pixels: usize) -> Result<(), String> {
+    for view in views {
+        let pixels = view.image_width.checked_mul(view.image_height);
+        if view.image_width > MAX_VIEW_DIMENSION
+            || view.image_height > MAX_VIEW_DIMENSION
+            || pixels.map_or(true, |pixels| pixels > max_view_pixels)
+        {
+            return Err(format!(
+                "View of {}x{} pixels is larger than this node's limit of {} pixels and {} pixels \
+                 on each side",
+                view.image_width, view.image_height, max_view_pixels, MAX_VIEW_DIMENSION
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Binds a listener to `address` and serves clients on it forever.
+pub async fn bind_and_serve(
+    address: SocketAddr,
+    factory: Arc<dyn FractalGeneratorFactory + Send + Sync>,
+    max_view_pixels: usize,
+) -> Result<!, ProtocolError> {
+    let listener = TcpListener::bind(address).await?;
+    info!("Render node listening on {}", listener.local_addr()?);
+    if !address.ip().is_loopback() {
+        warn!(
+            "Render node accepts connections from other machines, and does not authenticate \
+             them"
+        );
+    }
+    serve(listener, factory, max_view_pixels).await
+}
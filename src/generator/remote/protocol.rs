@@ -0,0 +1,296 @@
+//! This module contains the messages sent between a render node and its
+//! clients, as well as how they are framed.
+//!
+//! Every connection starts with each side sending [`MAGIC`] followed by its
+//! [`PROTOCOL_VERSION`]. After that, each message is sent as a big-endian
+//! `u32` length followed by that many bytes of bincode.
+//!
+//! The client sends [`Request`]s and the node answers with [`Response`]s:
+//! * [`Request::CreateGenerator`] is answered by either
+//!   [`Response::GeneratorCreated`] or [`Response::Error`].
+//! * [`Request::Start`] is answered by any number of [`Response::Block`]s,
+//!   followed by either [`Response::Finished`] or [`Response::Error`].
+//! * [`Request::Cancel`] is not answered, but makes the running generation
+//!   reach its [`Response::Finished`] sooner.
+
+use crate::generator::{view::View, FractalOpts, PixelValue, BYTES_PER_VALUE};
+use serde::{de::DeserializeOwned, Serialize};
+use std::io;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// The bytes each side of a connection starts with.
+pub const MAGIC: [u8; 8] = *b"FRACTRS2";
+
+/// The version of this protocol. Nodes and clients only talk to each other if
+/// their versions match exactly.
+pub const PROTOCOL_VERSION: u32 = 8;
+
+/// The most pixels a single view may have. Render nodes reject larger views
+/// before generating anything.
+pub const MAX_VIEW_PIXELS: usize = 4096 * 4096;
+
+/// The largest width or height a single view may have. This is the largest
+/// texture size every GPU supports.
+pub const MAX_VIEW_DIMENSION: usize = 8192;
+
+/// The largest request a render node will accept. Requests only carry options
+/// and views, so this is far below the size of a block.
+pub const MAX_REQUEST_SIZE: usize = 1024 * 1024;
+
+/// The largest response a client will accept, which is a block of the largest
+/// view plus room for the rest of the message.
+pub const MAX_RESPONSE_SIZE: usize = MAX_VIEW_PIXELS * BYTES_PER_VALUE + 64 * 1024;
+
+/// How much of a message is allocated for before any of it has been read.
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// A message sent from a client to a render node.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Request {
+    /// Creates the generator used by every following `Start`.
+    CreateGenerator { opts: FractalOpts },
+    /// Starts generating the given views with the current generator.
+    Start { views: Vec<View> },
+    /// Cancels the running generation, if any.
+    Cancel,
+}
+
+/// A message sent from a render node to a client.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Response {
+    GeneratorCreated {
+        min_views_hint: usize,
+    },
+    Block {
+        view: View,
+        values: Box<[PixelValue]>,
+    },
+    Finished,
+    Error {
+        message: String,
+    },
+}
+
+/// Writes this side's magic bytes and protocol version.
+pub async fn write_handshake<W: AsyncWrite + Unpin>(writer: &mut W) -> Result<(), ProtocolError> {
+    writer.write_all(&MAGIC).await?;
+    writer.write_u32(PROTOCOL_VERSION).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Reads the other side's magic bytes and protocol version, making sure they
+/// match this side's.
+pub async fn read_handshake<R: AsyncRead + Unpin>(reader: &mut R) -> Result<(), ProtocolError> {
+    let mut magic = [0u8; MAGIC.len()];
+    reader.read_exact(&mut magic).await?;
+    if magic != MAGIC {
+        return Err(ProtocolError::BadMagic);
+    }
+
+    let version = reader.read_u32().await?;
+    if version != PROTOCOL_VERSION {
+        return Err(ProtocolError::UnsupportedVersion(version));
+    }
+
+    Ok(())
+}
+
+/// Writes a single length-prefixed message, failing if it is larger than
+/// `max_size`, which should be what the other side accepts.
+pub async fn write_message<W: AsyncWrite + Unpin, M: Serialize>(
+    writer: &mut W,
+    message: &M,
+    max_size: usize,
+) -> Result<(), ProtocolError> {
+    let bytes = bincode::serialize(message)?;
+    if bytes.len() > max_size {
+        return Err(ProtocolError::MessageTooLarge(bytes.len()));
+    }
+
+    writer.write_u32(bytes.len() as u32).await?;
+    writer.write_all(&bytes).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Reads a single length-prefixed message, returning `None` if the connection
+/// was closed before the message started, or an error if the message is
+/// larger than `max_size`.
+///
+/// The message is read into a buffer that only grows as its bytes arrive, so
+/// a length that the other side never follows up on doesn't allocate anything.
+pub async fn read_message<R: AsyncRead + Unpin, M: DeserializeOwned>(
+    reader: &mut R,
+    max_size: usize,
+) -> Result<Option<M>, ProtocolError> {
+    let len = match reader.read_u32().await {
+        Ok(len) => len as usize,
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    if len > max_size {
+        return Err(ProtocolError::MessageTooLarge(len));
+    }
+
+    let mut bytes = Vec::with_capacity(len.min(READ_BUFFER_SIZE));
+    let read = (&mut *reader)
+        .take(len as u64)
+        .read_to_end(&mut bytes)
+        .await?;
+    if read < len {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+    Ok(Some(bincode::deserialize(&bytes)?))
+}
+
+#[derive(Debug, Error)]
+pub enum ProtocolError {
+    #[error("IO Error while communicating with render node")]
+    IOError(#[from] io::Error),
+    #[error("Bincode Error while encoding or decoding a message")]
+    BincodeError(#[from] bincode::Error),
+    #[error("Message of {0} bytes is too large")]
+    MessageTooLarge(usize),
+    #[error("Other side is not a fractal-rs-2 render node or client")]
+    BadMagic,
+    #[error("Other side uses unsupported protocol version {0}")]
+    UnsupportedVersion(u32),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{
+        args::{Multisampling, Precision},
+        trap::{OrbitTrap, TrapImage, MAX_TRAP_IMAGE_SIZE},
+    };
+    use num_complex::Complex64;
+
+    #[test]
+    fn messages_round_trip() {
+        let opts = FractalOpts {
+            mandelbrot: false,
+            iterations: 300,
+            multisampling: Multisampling::Linear { axial_points: 4 },
+            c: Complex64 {
+                re: -0.8,
+                im: 0.156,
+            },
+            precision: Precision::Double,
+            ..FractalOpts::test_base()
+        };
+        let view = View::new_centered_uniform(2, 2, 3.0);
+        let request = Request::CreateGenerator { opts };
+        let response = Response::Block {
+            view,
+            values: vec![
+                PixelValue {
+                    value: 1.5,
+                    coverage: 1.0,
+                    ..Default::default()
+                };
+                4
+            ]
+            .into_boxed_slice(),
+        };
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut bytes = vec![];
+            write_handshake(&mut bytes).await.unwrap();
+            write_message(&mut bytes, &request, MAX_REQUEST_SIZE)
+                .await
+                .unwrap();
+            write_message(&mut bytes, &response, MAX_RESPONSE_SIZE)
+                .await
+                .unwrap();
+
+            let mut reader = bytes.as_slice();
+            read_handshake(&mut reader).await.unwrap();
+            assert_eq!(
+                read_message(&mut reader, MAX_REQUEST_SIZE).await.unwrap(),
+                Some(request)
+            );
+            assert_eq!(
+                read_message(&mut reader, MAX_RESPONSE_SIZE).await.unwrap(),
+                Some(response)
+            );
+            assert_eq!(
+                read_message::<_, Request>(&mut reader, MAX_REQUEST_SIZE)
+                    .await
+                    .unwrap(),
+                None
+            );
+        });
+    }
+
+    #[test]
+    fn largest_trap_image_fits_in_a_request() {
+        let coverage = vec![0.5; MAX_TRAP_IMAGE_SIZE * MAX_TRAP_IMAGE_SIZE];
+        let request = Request::CreateGenerator {
+            opts: FractalOpts {
+                orbit_trap: Some(OrbitTrap::Image {
+                    center: Complex64::new(0.0, 0.0),
+                    size: 1.0,
+                    image: TrapImage::from_coverage(
+                        MAX_TRAP_IMAGE_SIZE,
+                        MAX_TRAP_IMAGE_SIZE,
+                        &coverage,
+                    ),
+                }),
+                ..FractalOpts::test_base()
+            },
+        };
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut bytes = vec![];
+            write_message(&mut bytes, &request, MAX_REQUEST_SIZE)
+                .await
+                .unwrap();
+        });
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut bytes = MAGIC.to_vec();
+            bytes.extend_from_slice(&(PROTOCOL_VERSION + 1).to_be_bytes());
+            assert!(matches!(
+                read_handshake(&mut bytes.as_slice()).await,
+                Err(ProtocolError::UnsupportedVersion(_))
+            ));
+        });
+    }
+
+    #[test]
+    fn rejects_oversized_messages() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let bytes = ((MAX_REQUEST_SIZE + 1) as u32).to_be_bytes();
+            assert!(matches!(
+                read_message::<_, Request>(&mut bytes.as_slice(), MAX_REQUEST_SIZE).await,
+                Err(ProtocolError::MessageTooLarge(_))
+            ));
+
+            // a length that isn't followed by that many bytes is an error rather
+            // than a buffer of that size
+            let mut bytes = (MAX_RESPONSE_SIZE as u32).to_be_bytes().to_vec();
+            bytes.extend_from_slice(&[0; 16]);
+            assert!(matches!(
+                read_message::<_, Response>(&mut bytes.as_slice(), MAX_RESPONSE_SIZE).await,
+                Err(ProtocolError::IOError(_))
+            ));
+        });
+    }
+}
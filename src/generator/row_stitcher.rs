@@ -7,13 +7,17 @@ use std::{
     task::Poll,
 };
 
-use crate::generator::{util::copy_region, view::View, PixelBlock, BYTES_PER_PIXEL};
+use crate::generator::{
+    color::Shading, palette::Palette, util::copy_region, view::View, PixelBlock, PixelFormat,
+    PixelValue, ValueBlock, BYTES_PER_VALUE,
+};
+use bytemuck::{cast_slice, cast_slice_mut};
 
-/// Stitches blocks of pixels together into complete rows.
+/// Stitches blocks of values together into complete rows.
 pub struct RowStitcher {
     parent: View,
     remaining_views: HashMap<ViewWrapper, usize>,
-    remaining_blocks: Vec<Option<PixelBlock>>,
+    remaining_blocks: Vec<Option<ValueBlock>>,
 }
 
 impl RowStitcher {
@@ -63,10 +67,10 @@ impl RowStitcher {
 
     /// Inserts a fractal generation message into this row stitcher at its
     /// specified location.
-    pub fn insert(&mut self, message: PixelBlock) {
+    pub fn insert(&mut self, message: ValueBlock) {
         // TODO: Add errors for this method.
 
-        // Note: this is using the reverse-order index, which means that the first pixel
+        // Note: this is using the reverse-order index, which means that the first value
         // blocks will be stored at the end of the array.
         let index = self.remaining_views.get(&ViewWrapper(message.view));
 
@@ -75,17 +79,17 @@ impl RowStitcher {
         }
     }
 
-    /// Stitches all the currently contiguous pixel blocks together into a
-    /// single row pixel block.
+    /// Stitches all the currently contiguous value blocks together into a
+    /// single row value block.
     ///
     /// This returns:
-    /// * `Poll::Pending` if there are not enough contiguous pixel blocks for a
+    /// * `Poll::Pending` if there are not enough contiguous value blocks for a
     ///   complete row.
-    /// * `Poll::Ready(Some(block))` if a complete row of pixel blocks is
+    /// * `Poll::Ready(Some(block))` if a complete row of value blocks is
     ///   available.
     /// * `Poll::Ready(None)` if this row stitcher has stitched all rows in its
     ///   view and cannot stitch any more.
-    pub fn stitch(&mut self) -> Poll<Option<PixelBlock>> {
+    pub fn stitch(&mut self) -> Poll<Option<ValueBlock>> {
         if self.remaining_blocks.is_empty() {
             return Poll::Ready(None);
         }
@@ -108,11 +112,10 @@ impl RowStitcher {
                 }
             }
 
-            let mut new_image =
-                vec![
-                    0u8;
-                    first_block.view.image_height * self.parent.image_width * BYTES_PER_PIXEL
-                ];
+            let mut new_values = vec![
+                PixelValue::default();
+                first_block.view.image_height * self.parent.image_width
+            ];
             let new_view = View {
                 image_width: self.parent.image_width,
                 image_height: first_block.view.image_height,
@@ -134,11 +137,12 @@ impl RowStitcher {
                 let block = self.remaining_blocks.pop().unwrap().unwrap();
 
                 copy_region(
-                    &block.image,
+                    BYTES_PER_VALUE,
+                    cast_slice(&block.values),
                     block.view.image_width,
                     0,
                     0,
-                    &mut new_image,
+                    cast_slice_mut(&mut new_values),
                     new_view.image_width,
                     block.view.image_x - self.parent.image_x,
                     0,
@@ -149,9 +153,9 @@ impl RowStitcher {
                 self.remaining_views.remove(&ViewWrapper(block.view));
             }
 
-            Poll::Ready(Some(PixelBlock {
+            Poll::Ready(Some(ValueBlock {
                 view: new_view,
-                image: new_image.into_boxed_slice(),
+                values: new_values.into_boxed_slice(),
             }))
         } else {
             Poll::Pending
@@ -159,6 +163,82 @@ impl RowStitcher {
     }
 }
 
+/// Colors the rows stitched by a [`RowStitcher`], in order, so that the relief
+/// slopes along the top and bottom of each row take the rows around it into
+/// account.
+///
+/// With relief shading, each row is only colored once the row below it
+/// arrives, or once [`RowColorer::finish`] is called for the last row.
+pub struct RowColorer {
+    palette: Option<Palette>,
+    interior_palette: Option<Palette>,
+    shading: Shading,
+    format: PixelFormat,
+    /// The last line of the previous row.
+    above: Option<Box<[PixelValue]>>,
+    /// The row waiting for the row below it.
+    pending: Option<ValueBlock>,
+}
+
+impl RowColorer {
+    pub fn new(
+        palette: Option<Palette>,
+        interior_palette: Option<Palette>,
+        shading: Shading,
+        format: PixelFormat,
+    ) -> RowColorer {
+        RowColorer {
+            palette,
+            interior_palette,
+            shading,
+            format,
+            above: None,
+            pending: None,
+        }
+    }
+
+    /// Adds the next row, returning the colored row before it, if that one is
+    /// no longer waiting on its neighbors.
+    pub fn push(&mut self, row: ValueBlock) -> Option<PixelBlock> {
+        if self.shading.relief.is_none() {
+            return Some(self.color(&row, None));
+        }
+
+        let width = row.view.image_width;
+        let colored = self
+            .pending
+            .take()
+            .map(|pending| self.color_pending(pending, Some(&row.values[..width])));
+        self.pending = Some(row);
+        colored
+    }
+
+    /// Colors the last row, which has nothing below it.
+    pub fn finish(&mut self) -> Option<PixelBlock> {
+        self.pending
+            .take()
+            .map(|pending| self.color_pending(pending, None))
+    }
+
+    fn color_pending(&mut self, pending: ValueBlock, below: Option<&[PixelValue]>) -> PixelBlock {
+        let colored = self.color(&pending, below);
+        let width = pending.view.image_width;
+        self.above = Some(pending.values[pending.values.len() - width..].into());
+        colored
+    }
+
+    fn color(&self, row: &ValueBlock, below: Option<&[PixelValue]>) -> PixelBlock {
+        row.color_with_neighbors(
+            self.above.as_deref(),
+            below,
+            self.palette.as_ref(),
+            self.interior_palette.as_ref(),
+            &self.shading,
+            self.format,
+        )
+    }
+}
+
 struct ViewWrapper(View);
 
 impl Hash for ViewWrapper {
@@ -175,3 +255,106 @@ impl PartialEq for ViewWrapper {
 }
 
 impl Eq for ViewWrapper {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{color::Relief, NO_INTERIOR};
+
+    fn value(value: f32) -> PixelValue {
+        PixelValue {
+            value,
+            coverage: 1.0,
+            distance: 0.0,
+            interior: NO_INTERIOR,
+        }
+    }
+
+    #[test]
+    fn stitches_values() {
+        let parent = View::new_uniform(4, 2, 4.0, 0.0, 0.0);
+        let children: Vec<_> = parent.subdivide_rectangles(2, 2).collect();
+        let mut stitcher = RowStitcher::new(parent, &children);
+
+        // each pixel is filled with the index of its block
+        for (index, child) in children.iter().enumerate().rev() {
+            stitcher.insert(ValueBlock {
+                view: *child,
+                values: vec![value(index as f32); child.image_width * child.image_height]
+                    .into_boxed_slice(),
+            });
+        }
+
+        let row = match stitcher.stitch() {
+            Poll::Ready(Some(row)) => row,
+            _ => panic!("Row was not stitched"),
+        };
+        assert_eq!(row.view.image_width, 4);
+        assert_eq!(row.values.len(), 4 * 2);
+        for (y, line) in row.values.chunks_exact(4).enumerate() {
+            assert_eq!(&line[..2], &[value(0.0); 2], "row {}", y);
+            assert_eq!(&line[2..], &[value(1.0); 2], "row {}", y);
+        }
+        assert!(matches!(stitcher.stitch(), Poll::Ready(None)));
+    }
+
+    #[test]
+    fn stitched_rows_color_like_the_whole_view() {
+        let parent = View::new_uniform(8, 6, 4.0, 0.0, 0.0);
+        let values: Vec<_> = (0..parent.image_width * parent.image_height)
+            .map(|index| {
+                let (x, y) = ((index % 8) as f32, (index / 8) as f32);
+                value(0.3 * x + 0.2 * y * y + 0.1 * ((index * 7) % 5) as f32)
+            })
+            .collect();
+        let shading = Shading {
+            relief: Some(Relief::default()),
+            ..Default::default()
+        };
+        let whole = ValueBlock {
+            view: parent,
+            values: values.clone().into_boxed_slice(),
+        }
+        .color_with_neighbors(None, None, None, None, &shading, PixelFormat::Rgba8);
+
+        // two blocks side by side in each of two rows of different heights
+        let children: Vec<_> = parent.subdivide_rectangles(4, 4).collect();
+        assert_eq!(children.len(), 4);
+        let mut stitcher = RowStitcher::new(parent, &children);
+        for child in children.iter() {
+            let mut block_values =
+                vec![PixelValue::default(); child.image_width * child.image_height];
+            copy_region(
+                BYTES_PER_VALUE,
+                cast_slice(&values),
+                parent.image_width,
+                child.image_x,
+                child.image_y,
+                cast_slice_mut(&mut block_values),
+                child.image_width,
+                0,
+                0,
+                child.image_width,
+                child.image_height,
+            );
+            stitcher.insert(ValueBlock {
+                view: *child,
+                values: block_values.into_boxed_slice(),
+            });
+        }
+
+        let mut colorer = RowColorer::new(None, None, shading, PixelFormat::Rgba8);
+        let mut stitched = vec![];
+        while let Poll::Ready(Some(row)) = stitcher.stitch() {
+            stitched.extend(colorer.push(row));
+        }
+        stitched.extend(colorer.finish());
+
+        assert_eq!(stitched.len(), 2);
+        let image: Vec<u8> = stitched
+            .iter()
+            .flat_map(|row| row.image.iter().copied())
+            .collect();
+        assert_eq!(&image[..], &whole.image[..]);
+    }
+}
@@ -0,0 +1,284 @@
+//! This module contains orbit traps, which color points by how close their
+//! orbits come to a shape instead of by how long they take to escape, as well
+//! as the images used by image traps.
+
+use num_complex::{Complex, Complex64};
+use regex::{Regex, RegexBuilder};
+use std::{fs::File, io, num::ParseFloatError, path::Path, str::FromStr};
+
+/// The largest width or height of a trap image. Larger images are scaled down
+/// to fit, because the image is part of the fractal options, which are copied
+/// for every generator and sent to render nodes. At this size it still fits
+/// well within a render node request.
+pub const MAX_TRAP_IMAGE_SIZE: usize = 256;
+
+/// Trap distances are multiplied by this to get pixel values, so that they are
+/// in the same range as iteration counts and look reasonable with the same
+/// palettes.
+pub const TRAP_VALUE_SCALE: f32 = 32.0;
+
+lazy_static::lazy_static! {
+static ref TRAP_REGEX: Regex = RegexBuilder::new(r"^(?P<kind>[a-z]+) *\((?P<args>[^()]*)\)$").case_insensitive(true).build().unwrap();
+}
+
+/// A shape that the orbit of each point is compared against. Escaped points
+/// get the smallest distance between their orbit and the trap as their value.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum OrbitTrap {
+    /// Traps orbits around a single point.
+    Point { center: Complex64 },
+    /// Traps orbits around a line through `center`, `angle` degrees
+    /// counter-clockwise from the real axis.
+    Line { center: Complex64, angle: f32 },
+    /// Traps orbits around two perpendicular lines crossing at `center`, the
+    /// first of which is `angle` degrees counter-clockwise from the real axis.
+    Cross { center: Complex64, angle: f32 },
+    /// Traps orbits around the outline of a circle.
+    Circle { center: Complex64, radius: f32 },
+    /// Traps orbits using an image covering a `size` by `size` square around
+    /// `center`. Brighter, more opaque pixels of the image are closer to the
+    /// trap, while orbits outside the image are 1 away from it.
+    Image {
+        center: Complex64,
+        size: f32,
+        image: TrapImage,
+    },
+}
+
+impl OrbitTrap {
+    /// Gets the name of this trap's kind as displayed to the user.
+    pub fn name(&self) -> &'static str {
+        match self {
+            OrbitTrap::Point { .. } => "Point",
+            OrbitTrap::Line { .. } => "Line",
+            OrbitTrap::Cross { .. } => "Cross",
+            OrbitTrap::Circle { .. } => "Circle",
+            OrbitTrap::Image { .. } => "Image",
+        }
+    }
+
+    /// Gets the point this trap is placed around.
+    pub fn center(&self) -> Complex64 {
+        match self {
+            OrbitTrap::Point { center }
+            | OrbitTrap::Line { center, .. }
+            | OrbitTrap::Cross { center, .. }
+            | OrbitTrap::Circle { center, .. }
+            | OrbitTrap::Image { center, .. } => *center,
+        }
+    }
+
+    /// Gets the unit vector pointing along this trap's line, or along the real
+    /// axis for traps without one.
+    pub fn direction(&self) -> Complex<f32> {
+        match self {
+            OrbitTrap::Line { angle, .. } | OrbitTrap::Cross { angle, .. } => {
+                let (sin, cos) = angle.to_radians().sin_cos();
+                Complex::new(cos, sin)
+            },
+            _ => Complex::new(1.0, 0.0),
+        }
+    }
+}
+
+impl FromStr for OrbitTrap {
+    type Err = ParseOrbitTrapError;
+
+    /// Parses traps of the form `point(<re>, <im>)`, `line(<re>, <im>,
+    /// <angle>)`, `cross(<re>, <im>, <angle>)`, `circle(<re>, <im>,
+    /// <radius>)` and `image(<re>, <im>, <size>)`. Image traps are parsed
+    /// without an image.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let captures = TRAP_REGEX
+            .captures(s.trim())
+            .ok_or(ParseOrbitTrapError::NotOrbitTrap)?;
+        let args = captures["args"]
+            .split(',')
+            .map(|arg| arg.trim().parse::<f64>())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let center = |args: &[f64]| Complex64::new(args[0], args[1]);
+        Ok(
+            match (captures["kind"].to_ascii_lowercase().as_str(), args.len()) {
+                ("point", 2) => OrbitTrap::Point {
+                    center: center(&args),
+                },
+                ("line", 3) => OrbitTrap::Line {
+                    center: center(&args),
+                    angle: args[2] as f32,
+                },
+                ("cross", 3) => OrbitTrap::Cross {
+                    center: center(&args),
+                    angle: args[2] as f32,
+                },
+                ("circle", 3) => OrbitTrap::Circle {
+                    center: center(&args),
+                    radius: args[2] as f32,
+                },
+                ("image", 3) => OrbitTrap::Image {
+                    center: center(&args),
+                    size: args[2] as f32,
+                    image: TrapImage::default(),
+                },
+                _ => return Err(ParseOrbitTrapError::NotOrbitTrap),
+            },
+        )
+    }
+}
+
+/// Returned if an error occurred while parsing an orbit trap from a string.
+#[derive(Debug, Clone)]
+pub enum ParseOrbitTrapError {
+    NotOrbitTrap,
+    ParseFloatError(ParseFloatError),
+}
+
+impl From<ParseFloatError> for ParseOrbitTrapError {
+    fn from(e: ParseFloatError) -> Self {
+        ParseOrbitTrapError::ParseFloatError(e)
+    }
+}
+
+/// A grayscale image used by an image trap.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TrapImage {
+    pub width: usize,
+    pub height: usize,
+    /// How strongly each pixel traps orbits, between 0 and 1, row by row from
+    /// the top left.
+    pub coverage: Vec<f32>,
+}
+
+impl TrapImage {
+    /// Loads a trap image from a PNG file, scaling it down to fit within
+    /// [`MAX_TRAP_IMAGE_SIZE`].
+    pub fn load(path: impl AsRef<Path>) -> Result<TrapImage, TrapImageError> {
+        let mut decoder = png::Decoder::new(File::open(path)?);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        let bytes = &buf[..info.buffer_size()];
+
+        let coverage: Vec<f32> = match info.color_type {
+            png::ColorType::Grayscale => bytes.iter().map(|&l| l as f32 / 255.0).collect(),
+            png::ColorType::GrayscaleAlpha => bytes
+                .chunks_exact(2)
+                .map(|p| p[0] as f32 * p[1] as f32 / (255.0 * 255.0))
+                .collect(),
+            png::ColorType::Rgb => bytes.chunks_exact(3).map(|p| luma(p) / 255.0).collect(),
+            png::ColorType::Rgba => bytes
+                .chunks_exact(4)
+                .map(|p| luma(p) * p[3] as f32 / (255.0 * 255.0))
+                .collect(),
+            png::ColorType::Indexed => return Err(TrapImageError::UnsupportedColorType),
+        };
+
+        Ok(TrapImage::from_coverage(
+            info.width as usize,
+            info.height as usize,
+            &coverage,
+        ))
+    }
+
+    /// Creates a trap image from full-size coverage values, averaging blocks
+    /// of pixels until it fits within [`MAX_TRAP_IMAGE_SIZE`].
+    pub fn from_coverage(width: usize, height: usize, coverage: &[f32]) -> TrapImage {
+        let block = ((width.max(height) + MAX_TRAP_IMAGE_SIZE - 1) / MAX_TRAP_IMAGE_SIZE).max(1);
+        let scaled_width = (width + block - 1) / block;
+        let scaled_height = (height + block - 1) / block;
+
+        let mut scaled = Vec::with_capacity(scaled_width * scaled_height);
+        for y in 0..scaled_height {
+            for x in 0..scaled_width {
+                let mut sum = 0.0;
+                let mut count = 0;
+                for source_y in y * block..((y + 1) * block).min(height) {
+                    for source_x in x * block..((x + 1) * block).min(width) {
+                        sum += coverage[source_y * width + source_x];
+                        count += 1;
+                    }
+                }
+                scaled.push(sum / count as f32);
+            }
+        }
+
+        TrapImage {
+            width: scaled_width,
+            height: scaled_height,
+            coverage: scaled,
+        }
+    }
+
+    /// Gets the coverage of the pixel at `(u, v)`, where `(0, 0)` is the top
+    /// left corner of the image and `(1, 1)` is its bottom right corner.
+    /// Locations outside the image have no coverage.
+    pub fn coverage_at(&self, u: f32, v: f32) -> f32 {
+        if self.coverage.is_empty() || !(0.0..1.0).contains(&u) || !(0.0..1.0).contains(&v) {
+            return 0.0;
+        }
+
+        let x = ((u * self.width as f32) as usize).min(self.width - 1);
+        let y = ((v * self.height as f32) as usize).min(self.height - 1);
+        self.coverage[y * self.width + x]
+    }
+}
+
+fn luma(pixel: &[u8]) -> f32 {
+    0.2126 * pixel[0] as f32 + 0.7152 * pixel[1] as f32 + 0.0722 * pixel[2] as f32
+}
+
+#[derive(Debug, Error)]
+pub enum TrapImageError {
+    #[error("IO Error while reading trap image")]
+    IOError(#[from] io::Error),
+    #[error("PNG Error while decoding trap image")]
+    DecodingError(#[from] png::DecodingError),
+    #[error("Trap image has an unsupported color type")]
+    UnsupportedColorType,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_traps() {
+        assert_eq!(
+            "point(0.5, -1)".parse::<OrbitTrap>().unwrap(),
+            OrbitTrap::Point {
+                center: Complex64::new(0.5, -1.0)
+            }
+        );
+        assert_eq!(
+            "Cross(0,0,45)".parse::<OrbitTrap>().unwrap(),
+            OrbitTrap::Cross {
+                center: Complex64::new(0.0, 0.0),
+                angle: 45.0,
+            }
+        );
+        assert!(matches!(
+            "image(0, 0, 2)".parse::<OrbitTrap>().unwrap(),
+            OrbitTrap::Image { size, .. } if size == 2.0
+        ));
+        assert!("circle(0, 0)".parse::<OrbitTrap>().is_err());
+        assert!("square(0, 0, 1)".parse::<OrbitTrap>().is_err());
+    }
+
+    #[test]
+    fn large_images_are_scaled_down() {
+        // a 700x400 image with a bright left half
+        let coverage: Vec<_> = (0..700 * 400)
+            .map(|i| if i % 700 < 350 { 1.0 } else { 0.0 })
+            .collect();
+        let image = TrapImage::from_coverage(700, 400, &coverage);
+        assert_eq!((image.width, image.height), (234, 134));
+        assert_eq!(image.coverage_at(0.1, 0.5), 1.0);
+        assert_eq!(image.coverage_at(0.9, 0.5), 0.0);
+        assert_eq!(image.coverage_at(-0.1, 0.5), 0.0);
+        assert_eq!(image.coverage_at(0.1, 1.0), 0.0);
+
+        // the block straddling the edge of the bright half is averaged
+        assert_eq!(image.coverage[116], 2.0 / 3.0);
+    }
+}
@@ -1,4 +1,3 @@
-use crate::generator::BYTES_PER_PIXEL;
 use cgmath::Vector2;
 use core::mem;
 use num_traits::One;
@@ -12,8 +11,10 @@ where
     (value + base - T::one()) / base * base
 }
 
-/// Copies a rectangle of pixels from one buffer to another.
+/// Copies a rectangle of pixels `bytes_per_pixel` bytes large from one buffer
+/// to another.
 pub fn copy_region(
+    bytes_per_pixel: usize,
     src: &[u8],
     src_width: usize,
     src_x: usize,
@@ -31,20 +32,20 @@ pub fn copy_region(
     if width > dest_width {
         panic!("Dest width is smaller than the region being copied");
     }
-    if src.len() < (src_width * src_y + src_x * height + width * height) * BYTES_PER_PIXEL {
+    if src.len() < (src_width * src_y + src_x * height + width * height) * bytes_per_pixel {
         panic!("Source buffer is too small to contain the source region");
     }
-    if dest.len() < (dest_width * dest_y + dest_x * height + width * height) * BYTES_PER_PIXEL {
+    if dest.len() < (dest_width * dest_y + dest_x * height + width * height) * bytes_per_pixel {
         panic!("Dest buffer is too small to contain the dest region")
     }
 
-    let strip_size = width * BYTES_PER_PIXEL;
+    let strip_size = width * bytes_per_pixel;
 
     for y in 0..height {
         let sy = y + src_y;
         let dy = y + dest_y;
-        let si = (sy * src_width + src_x) * BYTES_PER_PIXEL;
-        let di = (dy * dest_width + dest_x) * BYTES_PER_PIXEL;
+        let si = (sy * src_width + src_x) * bytes_per_pixel;
+        let di = (dy * dest_width + dest_x) * bytes_per_pixel;
         dest[di..di + strip_size].copy_from_slice(&src[si..si + strip_size]);
     }
 }
@@ -90,6 +91,14 @@ pub fn build_linear_offsets(axial_points: u32) -> Vec<Vector2<f32>> {
     vec
 }
 
+/// Splits an `f64` into a high and a low `f32` whose sum approximates the
+/// original value to roughly twice the precision of a single `f32`.
+pub fn split_f64(value: f64) -> (f32, f32) {
+    let hi = value as f32;
+    let lo = (value - hi as f64) as f32;
+    (hi, lo)
+}
+
 /// Designed to allow the use of `f32` and `f64` as map keys.
 #[derive(Debug, Copy, Clone, PartialOrd, PartialEq, Ord, Eq, Hash)]
 #[allow(unused)]
@@ -126,7 +135,7 @@ impl FloatKey {
 
 #[cfg(test)]
 mod tests {
-    use crate::generator::util::smallest_multiple_containing;
+    use crate::generator::util::{smallest_multiple_containing, split_f64};
 
     #[test]
     fn smallest_multiple_containing_below() {
@@ -142,4 +151,12 @@ mod tests {
     fn smallest_multiple_containing_above() {
         assert_eq!(smallest_multiple_containing(65, 64), 128);
     }
+
+    #[test]
+    fn split_f64_keeps_low_bits() {
+        let value = -0.743643887037151;
+        let (hi, lo) = split_f64(value);
+        assert_ne!(lo, 0.0);
+        assert!((hi as f64 + lo as f64 - value).abs() < 1e-14);
+    }
 }
@@ -13,24 +13,24 @@ use num_complex::Complex;
 
 /// A view represents an image's width, height, and mapping onto the complex
 /// plane.
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct View {
     pub image_width: usize,
     pub image_height: usize,
     pub image_x: usize,
     pub image_y: usize,
-    pub image_scale_x: f32,
-    pub image_scale_y: f32,
-    pub plane_start_x: f32,
-    pub plane_start_y: f32,
+    pub image_scale_x: f64,
+    pub image_scale_y: f64,
+    pub plane_start_x: f64,
+    pub plane_start_y: f64,
 }
 
 impl View {
     /// Creates a view centered at (0 + 0i) on the complex plane with the same
     /// scaling for both x and y axis.
-    pub fn new_centered_uniform(image_width: usize, image_height: usize, plane_width: f32) -> View {
-        let image_scale = plane_width / image_width as f32;
-        let plane_height = image_height as f32 * image_scale;
+    pub fn new_centered_uniform(image_width: usize, image_height: usize, plane_width: f64) -> View {
+        let image_scale = plane_width / image_width as f64;
+        let plane_height = image_height as f64 * image_scale;
 
         View {
             image_width,
@@ -39,8 +39,8 @@ impl View {
             image_y: 0,
             image_scale_x: image_scale,
             image_scale_y: image_scale,
-            plane_start_x: -plane_width / 2f32,
-            plane_start_y: -plane_height / 2f32,
+            plane_start_x: -plane_width / 2f64,
+            plane_start_y: -plane_height / 2f64,
         }
     }
 
@@ -49,12 +49,12 @@ impl View {
     pub fn new_uniform(
         image_width: usize,
         image_height: usize,
-        plane_width: f32,
-        center_x: f32,
-        center_y: f32,
+        plane_width: f64,
+        center_x: f64,
+        center_y: f64,
     ) -> View {
-        let image_scale = plane_width / image_width as f32;
-        let plane_height = image_height as f32 * image_scale;
+        let image_scale = plane_width / image_width as f64;
+        let plane_height = image_height as f64 * image_scale;
 
         View {
             image_width,
@@ -63,8 +63,8 @@ impl View {
             image_y: 0,
             image_scale_x: image_scale,
             image_scale_y: image_scale,
-            plane_start_x: center_x - plane_width / 2f32,
-            plane_start_y: center_y - plane_height / 2f32,
+            plane_start_x: center_x - plane_width / 2f64,
+            plane_start_y: center_y - plane_height / 2f64,
         }
     }
 
@@ -89,12 +89,12 @@ impl View {
     ///
     /// This method places the complex coordinate directly in the middle of the
     /// pixel instead of at the corner.
-    pub fn get_local_plane_coordinates(&self, (x, y): (usize, usize)) -> Complex<f32> {
+    pub fn get_local_plane_coordinates(&self, (x, y): (usize, usize)) -> Complex<f64> {
         // Note the `+ 0.5`. This means that a pixel's value is at its center instead of
         // its corner.
-        Complex::<f32>::new(
-            (x as f32 + 0.5) * self.image_scale_x + self.plane_start_x,
-            (y as f32 + 0.5) * self.image_scale_y + self.plane_start_y,
+        Complex::<f64>::new(
+            (x as f64 + 0.5) * self.image_scale_x + self.plane_start_x,
+            (y as f64 + 0.5) * self.image_scale_y + self.plane_start_y,
         )
     }
 
@@ -103,10 +103,10 @@ impl View {
     ///
     /// This method assumes that subpixel coordinates range from 0.0 to 1.0 with
     /// 0.5 being in the middle.
-    pub fn get_local_subpixel_plane_coordinates(&self, (x, y): (f32, f32)) -> Complex<f32> {
+    pub fn get_local_subpixel_plane_coordinates(&self, (x, y): (f64, f64)) -> Complex<f64> {
         // Note that there is no `+ 0.5` here because that is handled by what ever is
         // supplying the sub-pixel coordinates.
-        Complex::<f32>::new(
+        Complex::<f64>::new(
             x * self.image_scale_x + self.plane_start_x,
             y * self.image_scale_y + self.plane_start_y,
         )
@@ -116,7 +116,7 @@ impl View {
     /// plane.
     pub fn get_local_pixel_coordinates(
         &self,
-        plane_coordinates: Complex<f32>,
+        plane_coordinates: Complex<f64>,
     ) -> (ConstrainedValue<usize>, ConstrainedValue<usize>) {
         (
             if plane_coordinates.re >= self.plane_start_x {
@@ -149,7 +149,7 @@ impl View {
     /// negative.
     pub fn get_local_unconstrained_pixel_coordinates(
         &self,
-        plane_coordinates: Complex<f32>,
+        plane_coordinates: Complex<f64>,
     ) -> (isize, isize) {
         (
             ((plane_coordinates.re - self.plane_start_x) / self.image_scale_x) as isize,
@@ -334,7 +334,7 @@ impl Iterator for SubViewIter {
                         image_scale_x: view.image_scale_x,
                         image_scale_y: view.image_scale_y,
                         plane_start_x: view.plane_start_x,
-                        plane_start_y: view.plane_start_y + *image_y as f32 * view.image_scale_y,
+                        plane_start_y: view.plane_start_y + *image_y as f64 * view.image_scale_y,
                     });
 
                     *image_y += image_height;
@@ -370,8 +370,8 @@ impl Iterator for SubViewIter {
                         image_y: view.image_y + *image_y,
                         image_scale_x: view.image_scale_x,
                         image_scale_y: view.image_scale_y,
-                        plane_start_x: view.plane_start_x + *image_x as f32 * view.image_scale_x,
-                        plane_start_y: view.plane_start_y + *image_y as f32 * view.image_scale_y,
+                        plane_start_x: view.plane_start_x + *image_x as f64 * view.image_scale_x,
+                        plane_start_y: view.plane_start_y + *image_y as f64 * view.image_scale_y,
                     });
 
                     *image_x += image_width;
@@ -417,8 +417,8 @@ impl Iterator for SubViewIter {
                         image_y: view.image_y + *image_y,
                         image_scale_x: view.image_scale_x,
                         image_scale_y: view.image_scale_y,
-                        plane_start_x: view.plane_start_x + *image_x as f32 * view.image_scale_x,
-                        plane_start_y: view.plane_start_y + *image_y as f32 * view.image_scale_y,
+                        plane_start_x: view.plane_start_x + *image_x as f64 * view.image_scale_x,
+                        plane_start_y: view.plane_start_y + *image_y as f64 * view.image_scale_y,
                     });
 
                     *image_x += image_width;
@@ -797,8 +797,8 @@ mod tests {
                 let offsets = build_linear_offsets(16);
 
                 for offset in offsets {
-                    let subpixel_x = offset.x + pixel_x as f32;
-                    let subpixel_y = offset.y + pixel_y as f32;
+                    let subpixel_x = offset.x as f64 + pixel_x as f64;
+                    let subpixel_y = offset.y as f64 + pixel_y as f64;
 
                     let complex =
                         view.get_local_subpixel_plane_coordinates((subpixel_x, subpixel_y));
@@ -840,8 +840,10 @@ mod tests {
         let offsets = build_linear_offsets(16);
 
         for offset in offsets {
-            let complex =
-                view.get_local_subpixel_plane_coordinates((offset.x - 1.0, offset.y - 1.0));
+            let complex = view.get_local_subpixel_plane_coordinates((
+                offset.x as f64 - 1.0,
+                offset.y as f64 - 1.0,
+            ));
 
             let new_coord = view.get_local_pixel_coordinates(complex);
 
@@ -871,8 +873,10 @@ mod tests {
         let offsets = build_linear_offsets(16);
 
         for offset in offsets {
-            let complex =
-                view.get_local_subpixel_plane_coordinates((offset.x + 256.0, offset.y + 256.0));
+            let complex = view.get_local_subpixel_plane_coordinates((
+                offset.x as f64 + 256.0,
+                offset.y as f64 + 256.0,
+            ));
 
             let new_coord = view.get_local_pixel_coordinates(complex);
 
@@ -6,12 +6,9 @@ use wgpu::{
 };
 use winit::window::Window;
 
-pub fn initialize_wgpu(
-    window: &Window,
-    handle: &Handle,
-    power_preference: PowerPreference,
-) -> Result<(Arc<Instance>, Surface, Adapter), WgpuInitializationError> {
-    let backend = if cfg!(feature = "prefer-dx12") {
+/// Gets the wgpu backends selected by this build's `prefer-*` features.
+pub fn preferred_backends() -> Backends {
+    if cfg!(feature = "prefer-dx12") {
         info!("Preferred backend: dx12");
         Backends::DX12
     } else if cfg!(feature = "prefer-metal") {
@@ -23,7 +20,15 @@ pub fn initialize_wgpu(
     } else {
         info!("No preferred backend, using primary backend.");
         Backends::PRIMARY
-    };
+    }
+}
+
+pub fn initialize_wgpu(
+    window: &Window,
+    handle: &Handle,
+    power_preference: PowerPreference,
+) -> Result<(Arc<Instance>, Surface, Adapter), WgpuInitializationError> {
+    let backend = preferred_backends();
 
     info!("Creating instance...");
     let instance = Arc::new(Instance::new(InstanceDescriptor {
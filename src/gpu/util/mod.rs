@@ -1,6 +1,5 @@
 pub mod backend;
 
-use std::mem::size_of;
 use wgpu::{
     Adapter, Buffer, BufferAddress, BufferDescriptor, BufferUsages, Device, Extent3d, Features,
     Limits, Texture, TextureDescriptor, TextureDimension, TextureFormat, TextureUsages,
@@ -37,9 +36,10 @@ pub fn create_texture_buffer(
     device: &Device,
     width: u32,
     height: u32,
+    bytes_per_pixel: u32,
     usage: BufferUsages,
 ) -> Buffer {
-    let size = width * height * size_of::<u32>() as u32;
+    let size = width * height * bytes_per_pixel;
     let texture_buffer = device.create_buffer(&BufferDescriptor {
         label: Some("Framebuffer Buffer"),
         size: size as BufferAddress,
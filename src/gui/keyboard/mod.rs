@@ -28,6 +28,9 @@ pub const DEFAULT_SHORTCUT_LIST: &[(ShortcutName, Shortcut)] = &[
     (App_Fullscreen, shortcut!(F11)),
     (App_Quit, shortcut!(Cmd - Q)),
     (App_New, shortcut!(Cmd - N)),
+    (App_Open, shortcut!(Cmd - O)),
+    (App_Save, shortcut!(Cmd - S)),
+    (App_SaveAs, shortcut!(Shift - Cmd - S)),
     // Tab shortcuts
     (Tab_DeselectPosition, shortcut!(MacAlt - D)),
     (Tab_Generate, shortcut!(MacAlt - G)),
@@ -52,6 +55,9 @@ pub enum ShortcutName {
     App_CloseTab,
     App_Quit,
     App_New,
+    App_Open,
+    App_Save,
+    App_SaveAs,
 
     // Tab shortcuts
     Tab_DeselectPosition,
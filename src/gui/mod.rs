@@ -28,6 +28,7 @@ use winit::{
 mod flow;
 mod fonts;
 mod keyboard;
+mod project;
 mod storage;
 mod ui;
 mod util;
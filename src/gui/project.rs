@@ -0,0 +1,179 @@
+//! This module contains the project file format, used to save and load a set
+//! of tabs.
+
+use crate::generator::{perturbation::big_float::PlanePoint, view::View, FractalOpts, PixelFormat};
+use ron::ser::PrettyConfig;
+use std::{
+    fs::File,
+    io,
+    io::{Read, Write},
+    path::Path,
+};
+
+/// The file extension used by project files.
+pub const PROJECT_FILE_EXTENSION: &str = "ron";
+
+/// A set of tabs saved to a file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Project {
+    /// The tabs in this project, in the order they appear in the tab list.
+    pub tabs: Vec<ProjectTab>,
+}
+
+/// A single tab saved in a project file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProjectTab {
+    /// The name of this tab.
+    pub name: String,
+    /// The options used to generate this tab's fractal.
+    pub opts: FractalOpts,
+    /// The view rendered to this tab's viewer.
+    pub viewer_view: View,
+    /// The center of the viewer's view in arbitrary precision, if its
+    /// navigation placed it more precisely than the view's `f64` plane start
+    /// can.
+    #[serde(default)]
+    pub viewer_center: Option<PlanePoint>,
+    /// The view rendered when exporting this tab to an image.
+    pub image_view: View,
+    /// The file this tab's fractal gets exported to.
+    #[serde(default)]
+    pub output_location: String,
+    /// The format of the pixels of this tab's exported images.
+    #[serde(default)]
+    pub output_format: PixelFormat,
+    /// The index of the tab in this project that Julia/Fatou sets selected in
+    /// this tab are generated in, if any.
+    #[serde(default)]
+    pub julia_target: Option<usize>,
+}
+
+impl Project {
+    /// Loads a project from a file.
+    pub fn load(path: impl AsRef<Path>) -> Result<Project, ProjectError> {
+        let mut file = File::open(path)?;
+        let mut str = String::new();
+        file.read_to_string(&mut str)?;
+        let project: Project = ron::from_str(&str)?;
+
+        // make sure we don't end up with any links to tabs that don't exist
+        for (index, tab) in project.tabs.iter().enumerate() {
+            if let Some(target) = tab.julia_target {
+                if target >= project.tabs.len() || target == index {
+                    return Err(ProjectError::InvalidTarget { index, target });
+                }
+            }
+        }
+
+        Ok(project)
+    }
+
+    /// Stores this project into a file.
+    pub fn store(&self, path: impl AsRef<Path>) -> Result<(), ProjectError> {
+        let str = ron::ser::to_string_pretty(self, PrettyConfig::new())?;
+        let mut file = File::create(path)?;
+        write!(file, "{}", str)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ProjectError {
+    #[error("IO Error while accessing project file")]
+    IOError(#[from] io::Error),
+    #[error("Ron Error while writing project file")]
+    RonError(#[from] ron::Error),
+    #[error("Ron Error while parsing project file")]
+    RonSpannedError(#[from] ron::error::SpannedError),
+    #[error("Tab {index} targets tab {target}, which is not valid")]
+    InvalidTarget { index: usize, target: usize },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generator::{
+        args::{Formula, Multisampling, Precision},
+        palette::Palette,
+    };
+    use num_complex::Complex64;
+
+    /// A point that `f64` can't hold exactly.
+    fn deep_point(re: f64, im: f64) -> PlanePoint {
+        PlanePoint::from_f64(Complex64::new(re, im), 2).offset(Complex64::new(1e-30, -1e-30), 5)
+    }
+
+    fn test_project() -> Project {
+        let opts = FractalOpts {
+            formula: Formula::IntegerPower { exponent: 3 },
+            multisampling: Multisampling::Linear { axial_points: 16 },
+            ..FractalOpts::test_base()
+        };
+
+        Project {
+            tabs: vec![
+                ProjectTab {
+                    name: "Fractal 1".to_string(),
+                    opts: opts.clone(),
+                    viewer_view: View::new_centered_uniform(1024, 1024, 3.0),
+                    viewer_center: None,
+                    image_view: View::new_centered_uniform(4096, 4096, 3.0),
+                    output_location: "fractal.png".to_string(),
+                    output_format: PixelFormat::Rgba16,
+                    julia_target: Some(1),
+                },
+                ProjectTab {
+                    name: "Julia 2".to_string(),
+                    opts: FractalOpts {
+                        mandelbrot: false,
+                        c: Complex64 {
+                            re: 0.16611,
+                            im: 0.59419,
+                        },
+                        precision: Precision::Double,
+                        plane_start: Some(deep_point(-0.5, 0.35)),
+                        palette: Some(Palette::default()),
+                        ..opts
+                    },
+                    viewer_view: View::new_uniform(800, 600, 0.5, -0.25, 0.5),
+                    viewer_center: Some(deep_point(-0.25, 0.5)),
+                    image_view: View::new_uniform(1600, 1200, 0.5, -0.25, 0.5),
+                    output_location: "".to_string(),
+                    output_format: Default::default(),
+                    julia_target: None,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = std::env::temp_dir().join("fractal-rs-2-project-round-trip.ron");
+        let project = test_project();
+
+        project.store(&path).unwrap();
+        let loaded = Project::load(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(loaded, project);
+    }
+
+    #[test]
+    fn invalid_target() {
+        let path = std::env::temp_dir().join("fractal-rs-2-project-invalid-target.ron");
+        let mut project = test_project();
+        project.tabs[0].julia_target = Some(5);
+
+        project.store(&path).unwrap();
+        let loaded = Project::load(&path);
+        std::fs::remove_file(&path).ok();
+
+        assert!(matches!(
+            loaded,
+            Err(ProjectError::InvalidTarget {
+                index: 0,
+                target: 5
+            })
+        ));
+    }
+}
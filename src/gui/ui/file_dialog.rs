@@ -29,6 +29,17 @@ impl FileDialogWrapper {
         Ok(())
     }
 
+    /// Opens an open file dialog.
+    pub fn open_file(&mut self, dialog: AsyncFileDialog) -> Result<(), OpenError> {
+        if self.dialog.contains_future() {
+            return Err(OpenError::AlreadyOpen);
+        }
+
+        self.dialog.insert(Box::new(dialog.pick_file())).unwrap();
+
+        Ok(())
+    }
+
     /// Polls this wrapper to see if the dialog has been closed.
     ///
     /// Returns:
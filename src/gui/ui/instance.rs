@@ -1,16 +1,25 @@
 use crate::{
     generator::{
-        args::{Multisampling, Smoothing, DEFAULT_RADIUS_SQUARED},
-        manager::{GeneratorManager, PollError, WriteError},
+        args::{
+            Averaging, BoundaryTracing, CpuKernel, Formula, InteriorChecks, InteriorColoring,
+            Multisampling, Precision, Smoothing, DEFAULT_RADIUS_SQUARED,
+        },
+        color::{Relief, ReliefSource, Shading},
+        expression::Expression,
+        manager::{GeneratorManager, ImageStartError, PollError, WriteError},
+        palette::Palette,
+        perturbation::big_float::{BigFloat, PlanePoint},
+        trap::{OrbitTrap, TrapImage},
         view::View,
-        FractalGeneratorFactory, FractalOpts,
+        FractalGeneratorFactory, FractalOpts, PixelFormat,
     },
     gpu::GPUContext,
     gui::{
         keyboard::{ShortcutMap, ShortcutName},
+        project::ProjectTab,
         ui::{
-            file_dialog::FileDialogWrapper, widgets::viewer::FractalViewer, UIOperationRequest,
-            UIOperations,
+            file_dialog::FileDialogWrapper, palette_editor::PaletteEditor,
+            widgets::viewer::FractalViewer, UIOperationRequest, UIOperations,
         },
     },
     util::result::ResultExt,
@@ -20,10 +29,10 @@ use egui::{
     TextEdit, TextStyle, Ui,
 };
 use egui_wgpu_backend::RenderPass;
-use num_complex::Complex32;
+use num_complex::Complex64;
 use num_traits::Zero;
 use rfd::AsyncFileDialog;
-use std::{borrow::Cow, collections::HashMap, path::PathBuf, sync::Arc};
+use std::{borrow::Cow, collections::HashMap, mem::discriminant, path::PathBuf, sync::Arc};
 use tokio::runtime::Handle;
 
 const DEFAULT_GENERATION_MESSAGE: &str = "Not Generating";
@@ -37,6 +46,8 @@ pub struct UIInstance {
     pub name: String,
     /// Whether this instance has been changed since the last save.
     pub dirty: bool,
+    /// This instance's state and target instance as of the last save.
+    saved_state: Option<(ProjectTab, Option<u64>)>,
     id: u64,
     present: GPUContext,
     manager: GeneratorManager,
@@ -45,12 +56,14 @@ pub struct UIInstance {
     show_generator_controls: bool,
     show_viewer_controls: bool,
     show_project_settings: bool,
+    show_palette_editor: bool,
 
     // generator controls
     pub generate_fractal: Option<UIInstanceGenerationType>,
     pub generation_running: bool,
     generation_fraction: f32,
     generation_message: Cow<'static, str>,
+    generation_precision: Option<(Precision, bool)>,
     writer_fraction: f32,
     writer_message: Cow<'static, str>,
 
@@ -58,29 +71,67 @@ pub struct UIInstance {
     edit_viewer_width: usize,
     edit_viewer_height: usize,
     output_location: String,
+    output_format: PixelFormat,
+    resumable_image: bool,
     edit_image_width: usize,
     edit_image_height: usize,
     file_dialog_wrapper: FileDialogWrapper,
+    trap_image_dialog_wrapper: FileDialogWrapper,
 
     // complex plane controls
-    edit_fractal_plane_width: f32,
+    edit_fractal_plane_width: f64,
     edit_fractal_plane_centered: bool,
-    edit_fractal_plane_center_x: f32,
-    edit_fractal_plane_center_y: f32,
+    edit_fractal_plane_center_x: f64,
+    edit_fractal_plane_center_y: f64,
+    /// The center of the plane in arbitrary precision, which is only used
+    /// while the edited center is still its `f64` approximation.
+    precise_center: Option<PlanePoint>,
 
     // backup plane values for resets
-    init_fractal_plane_width: f32,
-    init_fractal_plane_center_x: f32,
-    init_fractal_plane_center_y: f32,
+    init_fractal_plane_width: f64,
+    init_fractal_plane_center_x: f64,
+    init_fractal_plane_center_y: f64,
+    init_precise_center: Option<PlanePoint>,
 
     // mandelbrot & julia/fatou set controls
     pub mandelbrot: bool,
-    pub c: Complex32,
+    pub c: Complex64,
     iterations: u32,
+    pub formula: Formula,
+    formula_expression: String,
+    formula_error: Option<String>,
+    multisampling: Multisampling,
+    radius_squared: f32,
+    /// The kind of smoothing. Logarithmic smoothing is always fitted to the
+    /// formula and escape radius when generating.
+    smoothing: Smoothing,
+    interior_checks: InteriorChecks,
+    boundary_tracing: BoundaryTracing,
+    cpu_kernel: CpuKernel,
+    distance_estimation: bool,
+    orbit_trap: Option<OrbitTrap>,
+    trap_image_error: Option<String>,
+    averaging: Averaging,
+    interior_coloring: InteriorColoring,
+
+    // coloring controls
+    palette: Option<Palette>,
+    interior_palette: Option<Palette>,
+    shading: Shading,
+    histogram_equalization: bool,
+    /// The relief settings, which are kept while the relief is disabled.
+    relief: Relief,
+    palette_editor: PaletteEditor,
+    interior_palette_editor: PaletteEditor,
+    editing_interior_palette: bool,
 
     // fractal viewers
     viewer: FractalViewer,
-    deselected_position: Complex32,
+    deselected_position: Complex64,
+    /// The offset of `deselected_position` from the center of the plane, if it
+    /// was selected by clicking a pixel of the viewer, along with the position
+    /// it was measured for.
+    deselected_offset: Option<(Complex64, Complex64)>,
 
     // julia target stuff
     generate_julia_from_point: bool,
@@ -122,9 +173,11 @@ pub struct UIInstanceInitialSettings {
     /// julia/fatou set.
     pub mandelbrot: bool,
     /// Complex value added to `z` on every iteration of the complex function.
-    pub c: Complex32,
+    pub c: Complex64,
     /// The number of times the complex iterative function should be run on `z`.
     pub iterations: u32,
+    /// The complex iterative function run on `z`.
+    pub formula: Formula,
 }
 
 /// Context passed to a UIInstance when updating.
@@ -135,6 +188,9 @@ pub struct UIInstanceUpdateContext<'a> {
     pub chunk_size: usize,
     /// Whether to cache pipelines if starting a new fractal.
     pub cache_generators: bool,
+    /// Whether to render viewer generations in several passes of increasing
+    /// resolution.
+    pub progressive_preview: bool,
     /// A vec into which operation requests are inserted.
     pub operations: &'a mut UIOperations,
 }
@@ -171,11 +227,12 @@ impl Default for UIInstanceInitialSettings {
         Self {
             view: View::new_centered_uniform(1024, 1024, 3.0),
             mandelbrot: true,
-            c: Complex32 {
+            c: Complex64 {
                 re: 0.16611,
                 im: 0.59419,
             },
             iterations: 200,
+            formula: Default::default(),
         }
     }
 }
@@ -187,6 +244,7 @@ impl UIInstanceInitialSettings {
             mandelbrot: instance.mandelbrot,
             c: instance.c,
             iterations: instance.iterations,
+            formula: instance.formula.clone(),
         }
     }
 }
@@ -195,9 +253,9 @@ impl UIInstance {
     pub fn new(ctx: UIInstanceCreationContext<impl ToString>) -> UIInstance {
         // obtain original values from view
         let plane_width =
-            ctx.initial_settings.view.image_width as f32 * ctx.initial_settings.view.image_scale_x;
+            ctx.initial_settings.view.image_width as f64 * ctx.initial_settings.view.image_scale_x;
         let plane_height =
-            ctx.initial_settings.view.image_height as f32 * ctx.initial_settings.view.image_scale_y;
+            ctx.initial_settings.view.image_height as f64 * ctx.initial_settings.view.image_scale_y;
         let center_x = ctx.initial_settings.view.plane_start_x + plane_width / 2.0;
         let center_y = ctx.initial_settings.view.plane_start_y + plane_height / 2.0;
 
@@ -209,39 +267,70 @@ impl UIInstance {
             ctx.initial_settings.view,
         );
 
-        UIInstance {
+        let mut instance = UIInstance {
             name: ctx.name.to_string(),
             dirty: false,
+            saved_state: None,
             id: ctx.id,
             present: ctx.present,
             manager,
             show_generator_controls: true,
             show_viewer_controls: true,
             show_project_settings: true,
+            show_palette_editor: false,
             generate_fractal: None,
             generation_running: false,
             generation_fraction: 0.0,
             generation_message: Cow::Borrowed(DEFAULT_GENERATION_MESSAGE),
+            generation_precision: None,
             writer_fraction: 0.0,
             writer_message: Cow::Borrowed(DEFAULT_WRITER_MESSAGE),
             edit_viewer_width: ctx.initial_settings.view.image_width,
             edit_viewer_height: ctx.initial_settings.view.image_height,
             output_location: "".to_string(),
+            output_format: PixelFormat::default(),
+            resumable_image: false,
             edit_image_width: 1024,
             edit_image_height: 1024,
-            file_dialog_wrapper: FileDialogWrapper::new(ctx.handle),
+            file_dialog_wrapper: FileDialogWrapper::new(ctx.handle.clone()),
+            trap_image_dialog_wrapper: FileDialogWrapper::new(ctx.handle),
             edit_fractal_plane_width: plane_width,
             edit_fractal_plane_centered: center_x == 0.0 && center_y == 0.0,
             edit_fractal_plane_center_x: center_x,
             edit_fractal_plane_center_y: center_y,
+            precise_center: None,
             init_fractal_plane_width: plane_width,
             init_fractal_plane_center_x: center_x,
             init_fractal_plane_center_y: center_y,
+            init_precise_center: None,
             mandelbrot: ctx.initial_settings.mandelbrot,
             c: ctx.initial_settings.c,
             iterations: ctx.initial_settings.iterations,
+            formula_expression: expression_source(&ctx.initial_settings.formula),
+            formula: ctx.initial_settings.formula,
+            formula_error: None,
+            multisampling: Multisampling::Linear { axial_points: 16 },
+            radius_squared: DEFAULT_RADIUS_SQUARED,
+            smoothing: Smoothing::from_logarithmic_distance(4.0, 2.0),
+            interior_checks: InteriorChecks::default(),
+            boundary_tracing: BoundaryTracing::default(),
+            cpu_kernel: CpuKernel::default(),
+            distance_estimation: false,
+            orbit_trap: None,
+            trap_image_error: None,
+            averaging: Averaging::default(),
+            interior_coloring: InteriorColoring::default(),
+            palette: None,
+            interior_palette: None,
+            shading: Default::default(),
+            histogram_equalization: false,
+            relief: Relief::default(),
+            palette_editor: PaletteEditor::new(),
+            interior_palette_editor: PaletteEditor::new(),
+            editing_interior_palette: false,
             viewer,
             deselected_position: Default::default(),
+            deselected_offset: None,
             generate_julia_from_point: false,
             switch_to_target: false,
             switch_to_parent: false,
@@ -251,9 +340,90 @@ impl UIInstance {
             parent_instance: None,
             generate_fractal_with_zoom: false,
             generate_reset_fractal: false,
+        };
+
+        // A freshly created instance has nothing worth saving yet.
+        instance.mark_saved();
+
+        instance
+    }
+
+    /// Sets the formula iterated by this instance, along with the expression
+    /// shown in its expression editor.
+    pub fn set_formula(&mut self, formula: Formula) {
+        self.formula_expression = expression_source(&formula);
+        self.formula_error = None;
+        self.formula = formula;
+    }
+
+    /// Applies the settings from a project file's tab that are not already
+    /// covered by [`UIInstanceInitialSettings`] and marks this instance as
+    /// saved.
+    ///
+    /// Target links are not applied here, as they depend on the other tabs in
+    /// the project.
+    pub fn load_project_tab(&mut self, tab: &ProjectTab) {
+        self.formula_expression = expression_source(&tab.opts.formula);
+        self.formula_error = None;
+        self.multisampling = tab.opts.multisampling;
+        self.radius_squared = tab.opts.radius_squared;
+        self.smoothing = tab.opts.smoothing;
+        self.interior_checks = tab.opts.interior_checks;
+        self.boundary_tracing = tab.opts.boundary_tracing;
+        self.cpu_kernel = tab.opts.cpu_kernel;
+        self.distance_estimation = tab.opts.distance_estimation;
+        self.orbit_trap = tab.opts.orbit_trap.clone();
+        self.averaging = tab.opts.averaging;
+        self.trap_image_error = None;
+        self.interior_coloring = tab.opts.interior_coloring;
+        self.palette = tab.opts.palette.clone();
+        self.interior_palette = tab.opts.interior_palette.clone();
+        self.shading = tab.opts.shading;
+        if let Some(relief) = tab.opts.shading.relief {
+            self.relief = relief;
+        }
+        self.histogram_equalization = tab.opts.histogram_equalization;
+        self.precise_center = tab.viewer_center.clone();
+        self.init_precise_center = tab.viewer_center.clone();
+        self.edit_image_width = tab.image_view.image_width;
+        self.edit_image_height = tab.image_view.image_height;
+        self.output_location = tab.output_location.clone();
+        self.output_format = tab.output_format;
+        self.generate_fractal = Some(UIInstanceGenerationType::Viewer);
+
+        self.mark_saved();
+    }
+
+    /// Gets the state of this instance as it would be saved to a project
+    /// file.
+    ///
+    /// The returned tab's `julia_target` is always `None`, because this
+    /// instance only knows its target's id and not its index in the project.
+    pub fn project_tab(&self) -> ProjectTab {
+        ProjectTab {
+            name: self.name.clone(),
+            opts: self.fractal_opts(&self.viewer_view()),
+            viewer_view: self.viewer_view(),
+            viewer_center: self.precise_center().cloned(),
+            image_view: self.image_view(),
+            output_location: self.output_location.clone(),
+            output_format: self.output_format,
+            julia_target: None,
         }
     }
 
+    /// Gets the id of the instance Julia/Fatou sets selected in this instance
+    /// are generated in, if any.
+    pub fn target_instance(&self) -> Option<u64> {
+        self.target_instance
+    }
+
+    /// Records this instance's current state as saved, clearing `dirty`.
+    pub fn mark_saved(&mut self) {
+        self.saved_state = Some((self.project_tab(), self.target_instance));
+        self.dirty = false;
+    }
+
     /// Sets this `UIInstance`'s [`FractalGeneratorFactory`].
     ///
     /// [`FractalGeneratorFactory`]: crate::generator::FractalGeneratorFactory
@@ -280,14 +450,11 @@ impl UIInstance {
                 };
 
                 // construct the FractalOpts from UI settings
-                let opts = FractalOpts {
-                    mandelbrot: self.mandelbrot,
-                    iterations: self.iterations,
-                    smoothing: Smoothing::from_logarithmic_distance(4.0, 2.0),
-                    multisampling: Multisampling::Linear { axial_points: 16 },
-                    c: self.c,
-                    radius_squared: DEFAULT_RADIUS_SQUARED,
-                };
+                let opts = self.fractal_opts(&view);
+                self.generation_precision = Some((
+                    opts.precision,
+                    !self.manager.factory_supports_precision(&opts),
+                ));
 
                 // subdivide the view
                 let views: Vec<_> = view
@@ -302,9 +469,10 @@ impl UIInstance {
                                 opts,
                                 views,
                                 ctx.cache_generators,
+                                ctx.progressive_preview,
                                 self.present.clone(),
-                                self.viewer.get_texture(),
-                                self.viewer.get_texture_view(),
+                                self.viewer.get_value_texture(),
+                                self.viewer.get_value_texture_view(),
                             )
                             .expect(
                                 "Attempted to start new fractal generator while one was \
@@ -312,23 +480,39 @@ impl UIInstance {
                             );
                     },
                     UIInstanceGenerationType::Image => {
-                        self.manager
-                            .start_to_image(
-                                opts,
-                                view,
-                                views,
-                                ctx.cache_generators,
-                                PathBuf::from(&self.output_location),
-                            )
-                            .expect(
+                        let res = self.manager.start_to_image(
+                            opts,
+                            view,
+                            views,
+                            ctx.cache_generators,
+                            PathBuf::from(&self.output_location),
+                            self.output_format,
+                            self.resumable_image,
+                        );
+
+                        match res {
+                            Err(ImageStartError::JournalError(e)) => {
+                                error!("Error opening render journal: {:?}", e);
+                            },
+                            res => res.expect(
                                 "Attempted to start a new gractal generator while one was \
                                 already running! (This is a bug)",
-                            );
+                            ),
+                        }
                     },
                 }
             }
         }
 
+        // the viewer previews exports with the same precision
+        if self.viewer.pixel_format() != self.output_format {
+            self.viewer
+                .set_pixel_format(&self.present.device, ctx.render_pass, self.output_format)
+                .on_err(|e| error!("Error changing the fractal image format: {:?}", e));
+        }
+
+        let was_running = self.generation_running;
+
         if let Err(e) = self.manager.poll() {
             match e {
                 PollError::WriteError(WriteError::Canceled) => {
@@ -341,6 +525,18 @@ impl UIInstance {
         }
 
         self.generation_running = self.manager.running();
+
+        // values keep arriving until the poll after the generator finishes
+        self.viewer.recolor(
+            &self.present,
+            self.palette.as_ref(),
+            self.interior_palette.as_ref(),
+            &self.shading,
+            self.histogram_equalization
+                .then(|| self.manager.equalization())
+                .flatten(),
+            was_running || self.generation_running,
+        );
         let gen_progress = self.manager.progress();
         self.generation_fraction = gen_progress;
         self.generation_message = Cow::Owned(format!("{:.1}%", gen_progress * 100.0));
@@ -355,10 +551,29 @@ impl UIInstance {
             self.output_location = file.path().to_string_lossy().to_string();
         }
 
+        if let Some(file) = self.trap_image_dialog_wrapper.poll().flatten() {
+            match TrapImage::load(file.path()) {
+                Ok(loaded) => {
+                    if let Some(OrbitTrap::Image { image, .. }) = &mut self.orbit_trap {
+                        *image = loaded;
+                    }
+                    self.trap_image_error = None;
+                },
+                Err(e) => {
+                    error!("Error loading trap image: {:?}", e);
+                    self.trap_image_error = Some(e.to_string());
+                },
+            }
+        }
+
         // If something's selected, let's update the deselected position for when it
         // gets deselected.
         if let Some(selected_position) = self.viewer.selection_pos {
             self.deselected_position = selected_position;
+            self.deselected_offset = self
+                .viewer
+                .selection_offset()
+                .map(|offset| (selected_position, offset));
         }
 
         // If we're wanting to start a julia set, then we need to request that.
@@ -366,6 +581,7 @@ impl UIInstance {
             ctx.operations.push(UIOperationRequest::StartJuliaSet {
                 instance_id: self.target_instance,
                 c: self.deselected_position,
+                formula: self.formula.clone(),
             });
         }
         self.generate_julia_from_point = false;
@@ -377,14 +593,28 @@ impl UIInstance {
                 self.edit_fractal_plane_width = new_plane_width;
             }
 
-            if self.deselected_position != Complex32::zero() {
+            if self.deselected_position != Complex64::zero() {
+                // The new center is moved from the old one in arbitrary precision,
+                // so that zooming in keeps going past what f64 can place.
+                let limbs = BigFloat::limbs_for_scale(
+                    self.edit_fractal_plane_width / self.edit_viewer_width as f64,
+                );
+                let center = match self.deselected_offset {
+                    Some((position, offset)) if position == self.deselected_position => {
+                        self.plane_center().offset(offset, limbs)
+                    },
+                    _ => PlanePoint::from_f64(self.deselected_position, limbs),
+                };
+
                 self.edit_fractal_plane_centered = false;
-                self.edit_fractal_plane_center_x = self.deselected_position.re;
-                self.edit_fractal_plane_center_y = self.deselected_position.im;
+                self.edit_fractal_plane_center_x = center.re.to_f64();
+                self.edit_fractal_plane_center_y = center.im.to_f64();
+                self.precise_center = Some(center);
             } else {
                 self.edit_fractal_plane_centered = true;
                 self.edit_fractal_plane_center_x = 0.0;
                 self.edit_fractal_plane_center_y = 0.0;
+                self.precise_center = None;
             }
 
             self.generate_fractal = Some(UIInstanceGenerationType::Viewer);
@@ -402,6 +632,7 @@ impl UIInstance {
             self.edit_fractal_plane_center_y = self.init_fractal_plane_center_y;
             self.edit_fractal_plane_centered =
                 self.init_fractal_plane_center_y == 0.0 && self.init_fractal_plane_center_x == 0.0;
+            self.precise_center = self.init_precise_center.clone();
 
             self.generate_fractal = Some(UIInstanceGenerationType::Viewer);
 
@@ -438,12 +669,19 @@ impl UIInstance {
             });
         }
         self.switch_to_parent = false;
+
+        self.dirty = self
+            .saved_state
+            .as_ref()
+            .map(|(tab, target)| *tab != self.project_tab() || *target != self.target_instance)
+            .unwrap_or(true);
     }
 
     pub fn draw_window_options(&mut self, ui: &mut Ui) {
         ui.checkbox(&mut self.show_generator_controls, "Generator Controls");
         ui.checkbox(&mut self.show_viewer_controls, "Viewer Controls");
         ui.checkbox(&mut self.show_project_settings, "Project Settings");
+        ui.checkbox(&mut self.show_palette_editor, "Palette Editor");
     }
 
     pub fn handle_keyboard_shortcuts(&mut self, ctx: &UIInstanceRenderContext) {
@@ -516,6 +754,7 @@ impl UIInstance {
         self.draw_generator_controls(ctx);
         self.draw_viewer_controls(ctx);
         self.draw_project_settings(ctx);
+        self.draw_palette_editor(ctx);
     }
 
     fn draw_fractal_viewers(&mut self, ctx: &UIInstanceRenderContext) {
@@ -544,6 +783,27 @@ impl UIInstance {
                     }
                 });
 
+                if let Some((precision, fallback)) = self.generation_precision {
+                    let text = if fallback {
+                        format!("Precision: {} on CPU", precision.name())
+                    } else {
+                        format!("Precision: {}", precision.name())
+                    };
+                    let text = RichText::new(text);
+                    ui.label(if precision == Precision::Single {
+                        text
+                    } else {
+                        text.color(Color32::YELLOW)
+                    })
+                    .on_hover_text(
+                        "Double precision is used automatically when the view is too small \
+                        for single precision. Views are generated on the CPU when the current \
+                        generator can't iterate the formula in that precision, which for \
+                        perturbation means formulas other than z^2 + c are iterated directly \
+                        and can't be zoomed in as far.",
+                    );
+                }
+
                 ui.separator();
 
                 egui::CollapsingHeader::new("Generate to Viewer")
@@ -618,6 +878,12 @@ impl UIInstance {
                                     .ok();
                             }
 
+                            ui.checkbox(&mut self.resumable_image, "Resumable")
+                                .on_hover_text(
+                                    "Journal completed chunks next to the output image, so an \
+                                interrupted render resumes where it stopped when started again.",
+                                );
+
                             egui::Grid::new("generate_to_image.image_settings.grid").show(
                                 ui,
                                 |ui| {
@@ -638,6 +904,26 @@ impl UIInstance {
                                             .clamp_range(2..=65536),
                                     );
                                     ui.end_row();
+
+                                    ui.label("Bit Depth:");
+                                    ComboBox::from_id_source("generate_to_image.bit_depth")
+                                        .selected_text(self.output_format.name())
+                                        .show_ui(ui, |ui| {
+                                            for format in [PixelFormat::Rgba8, PixelFormat::Rgba16]
+                                            {
+                                                ui.selectable_value(
+                                                    &mut self.output_format,
+                                                    format,
+                                                    format.name(),
+                                                );
+                                            }
+                                        })
+                                        .response
+                                        .on_hover_text(
+                                            "Bits per channel of the exported image. 16 bits \
+                                        avoids visible banding in smooth gradients.",
+                                        );
+                                    ui.end_row();
                                 },
                             );
                         });
@@ -735,6 +1021,385 @@ impl UIInstance {
                                 DragValue::new(&mut self.iterations).clamp_range(1..=1000),
                             );
                             ui.end_row();
+
+                            ui.label("Smoothing:");
+                            ComboBox::from_id_source("fractal_options.smoothing")
+                                .selected_text(self.smoothing.name())
+                                .show_ui(ui, |ui| {
+                                    for smoothing in [
+                                        Smoothing::from_logarithmic_distance(4.0, 2.0),
+                                        Smoothing::LinearIntersection,
+                                        Smoothing::None,
+                                    ] {
+                                        // logarithmic smoothings differ in their parameters
+                                        let selected = discriminant(&self.smoothing)
+                                            == discriminant(&smoothing);
+                                        if ui.selectable_label(selected, smoothing.name()).clicked()
+                                        {
+                                            self.smoothing = smoothing;
+                                        }
+                                    }
+                                })
+                                .response
+                                .on_hover_text(
+                                    "How iteration counts are smoothed into continuous values. \
+                                Logarithmic smoothing is fitted to the formula and escape radius.",
+                                );
+                            ui.end_row();
+
+                            ui.label("Interior Checks:");
+                            ui.checkbox(&mut self.interior_checks.bulbs, "Main Bulbs")
+                                .on_hover_text(
+                                    "Skip the points in the main cardioid and period-2 bulb. This \
+                                only applies to the Mandelbrot set of z^2 + c.",
+                                );
+                            ui.end_row();
+                            ui.label("");
+                            ui.checkbox(&mut self.interior_checks.periodicity, "Periodicity")
+                                .on_hover_text(
+                                    "Stop iterating points once they start repeating themselves.",
+                                );
+                            ui.end_row();
+
+                            ui.label("Boundary Tracing:");
+                            ComboBox::from_id_source("fractal_options.boundary_tracing")
+                                .selected_text(self.boundary_tracing.name())
+                                .show_ui(ui, |ui| {
+                                    for mode in [
+                                        BoundaryTracing::Off,
+                                        BoundaryTracing::Exact,
+                                        BoundaryTracing::Always,
+                                    ] {
+                                        ui.selectable_value(
+                                            &mut self.boundary_tracing,
+                                            mode,
+                                            mode.name(),
+                                        );
+                                    }
+                                })
+                                .response
+                                .on_hover_text(
+                                    "Only generate the borders of rectangles, filling in the ones \
+                                whose borders are uniform. This only applies to generating on the \
+                                CPU.",
+                                );
+                            ui.end_row();
+
+                            ui.label("CPU Kernel:");
+                            ComboBox::from_id_source("fractal_options.cpu_kernel")
+                                .selected_text(self.cpu_kernel.name())
+                                .show_ui(ui, |ui| {
+                                    for kernel in
+                                        [CpuKernel::Scalar, CpuKernel::Simd4, CpuKernel::Simd8]
+                                    {
+                                        ui.selectable_value(
+                                            &mut self.cpu_kernel,
+                                            kernel,
+                                            kernel.name(),
+                                        );
+                                    }
+                                })
+                                .response
+                                .on_hover_text(
+                                    "How many pixels the CPU generator iterates at once. Every \
+                                kernel generates the same image.",
+                                );
+                            ui.end_row();
+
+                            ui.label("Distance Estimation:");
+                            ui.checkbox(&mut self.distance_estimation, "Enabled")
+                                .on_hover_text(
+                                    "Estimate the distance from each pixel to the set, which the \
+                                boundary lines and distance fade are drawn with. This doesn't \
+                                apply to custom formulas.",
+                                );
+                            ui.end_row();
+
+                            ui.label("Boundary Lines:");
+                            ui.add_sized(
+                                vec2(80.0, ui.spacing().interact_size.y),
+                                DragValue::new(&mut self.shading.boundary_thickness)
+                                    .clamp_range(0.0..=16.0)
+                                    .speed(0.05)
+                                    .suffix(" px"),
+                            )
+                            .on_hover_text(
+                                "Draws the boundary of the set this many pixels thick. 0 disables \
+                            the lines. Changing this doesn't require generating the fractal again.",
+                            );
+                            ui.end_row();
+
+                            ui.label("Distance Fade:");
+                            ui.add_sized(
+                                vec2(80.0, ui.spacing().interact_size.y),
+                                DragValue::new(&mut self.shading.distance_fade)
+                                    .clamp_range(0.0..=256.0)
+                                    .speed(0.5)
+                                    .suffix(" px"),
+                            )
+                            .on_hover_text(
+                                "Darkens the pixels close to the set over about this many pixels. \
+                            0 disables the fading.",
+                            );
+                            ui.end_row();
+
+                            ui.label("Histogram Equalization:");
+                            let equalization_response = ui
+                                .checkbox(&mut self.histogram_equalization, "Enabled")
+                                .on_hover_text(
+                                    "Spreads the colors of the palette evenly over the values of \
+                                the whole view instead of using the palette's density. Collecting \
+                                the values requires generating the fractal again.",
+                                );
+                            if equalization_response.changed()
+                                && self.histogram_equalization
+                                && self.manager.equalization().is_none()
+                            {
+                                self.generate_fractal = Some(UIInstanceGenerationType::Viewer);
+                            }
+                            ui.end_row();
+
+                            ui.label("Relief Lighting:");
+                            let mut relief_enabled = self.shading.relief.is_some();
+                            ui.checkbox(&mut relief_enabled, "Enabled").on_hover_text(
+                                "Lights the fractal as a surface whose height comes from its \
+                            values. Changing the lighting doesn't require generating the fractal \
+                            again.",
+                            );
+                            ui.end_row();
+
+                            if relief_enabled {
+                                ui.label("Relief Source:");
+                                ComboBox::from_id_source("fractal_options.relief_source")
+                                    .selected_text(self.relief.source.name())
+                                    .show_ui(ui, |ui| {
+                                        for source in [ReliefSource::Value, ReliefSource::Distance]
+                                        {
+                                            ui.selectable_value(
+                                                &mut self.relief.source,
+                                                source,
+                                                source.name(),
+                                            );
+                                        }
+                                    })
+                                    .response
+                                    .on_hover_text(
+                                        "What the height of the surface comes from. Distance \
+                                    estimates are only available with distance estimation enabled.",
+                                    );
+                                ui.end_row();
+
+                                ui.label("Light Angle:");
+                                ui.add_sized(
+                                    vec2(80.0, ui.spacing().interact_size.y),
+                                    DragValue::new(&mut self.relief.light_angle)
+                                        .clamp_range(0.0..=360.0)
+                                        .speed(1.0)
+                                        .suffix("°"),
+                                )
+                                .on_hover_text(
+                                    "The direction the light comes from, counter-clockwise from \
+                                the right.",
+                                );
+                                ui.end_row();
+
+                                ui.label("Light Height:");
+                                ui.add_sized(
+                                    vec2(80.0, ui.spacing().interact_size.y),
+                                    DragValue::new(&mut self.relief.light_height)
+                                        .clamp_range(0.0..=90.0)
+                                        .speed(0.5)
+                                        .suffix("°"),
+                                )
+                                .on_hover_text(
+                                    "How high the light is above the image. 90° lights it from \
+                                straight above.",
+                                );
+                                ui.end_row();
+
+                                ui.label("Specular:");
+                                ui.add_sized(
+                                    vec2(80.0, ui.spacing().interact_size.y),
+                                    DragValue::new(&mut self.relief.specular)
+                                        .clamp_range(0.0..=2.0)
+                                        .speed(0.01),
+                                )
+                                .on_hover_text("How bright the highlights are. 0 disables them.");
+                                ui.end_row();
+
+                                ui.label("Relief Depth:");
+                                ui.add_sized(
+                                    vec2(80.0, ui.spacing().interact_size.y),
+                                    DragValue::new(&mut self.relief.depth)
+                                        .clamp_range(0.0..=64.0)
+                                        .speed(0.05),
+                                )
+                                .on_hover_text("How steep the surface is drawn.");
+                                ui.end_row();
+                            }
+                            self.shading.relief = relief_enabled.then_some(self.relief);
+
+                            ui.label("Formula:");
+                            ComboBox::from_id_source("fractal_options.formula")
+                                .selected_text(formula_name(&self.formula))
+                                .show_ui(ui, |ui| {
+                                    let exponent = self.formula.exponent();
+                                    ui.selectable_value(
+                                        &mut self.formula,
+                                        Formula::IntegerPower {
+                                            exponent: exponent.round() as i32,
+                                        },
+                                        "z^n + c (Integer n)",
+                                    );
+                                    ui.selectable_value(
+                                        &mut self.formula,
+                                        Formula::RealPower { exponent },
+                                        "z^n + c (Real n)",
+                                    );
+                                    for formula in [
+                                        Formula::BurningShip,
+                                        Formula::Tricorn,
+                                        Formula::PerpendicularBurningShip,
+                                        Formula::Celtic,
+                                    ] {
+                                        let name = formula_name(&formula);
+                                        ui.selectable_value(&mut self.formula, formula, name);
+                                    }
+                                    let selected = matches!(self.formula, Formula::Expression(_));
+                                    if ui.selectable_label(selected, "Custom Expression").clicked()
+                                        && !selected
+                                    {
+                                        let expression = self
+                                            .formula_expression
+                                            .parse()
+                                            .unwrap_or_else(|_| default_expression());
+                                        self.formula_expression = expression.source().to_string();
+                                        self.formula_error = None;
+                                        self.formula = Formula::Expression(expression);
+                                    }
+                                });
+                            ui.end_row();
+
+                            if matches!(self.formula, Formula::Expression(_)) {
+                                ui.label("Expression:");
+                            } else {
+                                ui.label("Exponent:");
+                            }
+                            match &mut self.formula {
+                                Formula::IntegerPower { exponent } => {
+                                    ui.add_sized(
+                                        vec2(80.0, ui.spacing().interact_size.y),
+                                        DragValue::new(exponent).clamp_range(-16..=16).speed(0.05),
+                                    );
+                                },
+                                Formula::RealPower { exponent } => {
+                                    ui.add_sized(
+                                        vec2(80.0, ui.spacing().interact_size.y),
+                                        DragValue::new(exponent)
+                                            .clamp_range(-16.0..=16.0)
+                                            .speed(0.001)
+                                            .min_decimals(3),
+                                    );
+                                },
+                                Formula::Expression(_) => {
+                                    let response = ui.add(
+                                        TextEdit::singleline(&mut self.formula_expression)
+                                            .font(TextStyle::Monospace)
+                                            .hint_text("z^2 + c"),
+                                    );
+                                    if response.changed() {
+                                        match self.formula_expression.parse::<Expression>() {
+                                            Ok(expression) => {
+                                                self.formula = Formula::Expression(expression);
+                                                self.formula_error = None;
+                                            },
+                                            Err(e) => self.formula_error = Some(e.to_string()),
+                                        }
+                                    }
+                                },
+                                _ => {
+                                    ui.label(format!("{}", self.formula.exponent()));
+                                },
+                            }
+                            ui.end_row();
+
+                            if let Some(error) = &self.formula_error {
+                                if matches!(self.formula, Formula::Expression(_)) {
+                                    ui.label(
+                                        RichText::new(format!("Error: {}", error))
+                                            .color(Color32::RED),
+                                    );
+                                    ui.end_row();
+                                }
+                            }
+
+                            ui.label("Averaging:");
+                            ComboBox::from_id_source("fractal_options.averaging")
+                                .selected_text(self.averaging.name())
+                                .show_ui(ui, |ui| {
+                                    for averaging in [
+                                        Averaging::None,
+                                        Averaging::Stripe { density: 5.0 },
+                                        Averaging::TriangleInequality,
+                                        Averaging::Curvature,
+                                    ] {
+                                        let selected = self.averaging.name() == averaging.name();
+                                        if ui.selectable_label(selected, averaging.name()).clicked()
+                                            && !selected
+                                        {
+                                            self.averaging = averaging;
+                                        }
+                                    }
+                                })
+                                .response
+                                .on_hover_text(
+                                    "Color escaped points by a statistic averaged over their \
+                                orbits instead of by their iteration count. Orbit traps take \
+                                precedence over averages.",
+                                );
+                            ui.end_row();
+
+                            if let Averaging::Stripe { density } = &mut self.averaging {
+                                ui.label("Stripe Density:");
+                                ui.add_sized(
+                                    vec2(80.0, ui.spacing().interact_size.y),
+                                    DragValue::new(density).clamp_range(0.0..=64.0).speed(0.05),
+                                );
+                                ui.end_row();
+                            }
+
+                            Self::draw_orbit_trap_options(
+                                ui,
+                                &mut self.orbit_trap,
+                                &mut self.trap_image_dialog_wrapper,
+                                &self.trap_image_error,
+                            );
+
+                            ui.label("Interior Coloring:");
+                            ComboBox::from_id_source("fractal_options.interior_coloring")
+                                .selected_text(self.interior_coloring.name())
+                                .show_ui(ui, |ui| {
+                                    for coloring in [
+                                        InteriorColoring::None,
+                                        InteriorColoring::FinalMagnitude,
+                                        InteriorColoring::MinMagnitudeIteration,
+                                        InteriorColoring::Period,
+                                        InteriorColoring::Distance,
+                                        InteriorColoring::AtomDomain,
+                                    ] {
+                                        ui.selectable_value(
+                                            &mut self.interior_coloring,
+                                            coloring,
+                                            coloring.name(),
+                                        );
+                                    }
+                                })
+                                .response
+                                .on_hover_text(
+                                    "How points inside the set are colored, using the interior \
+                                palette. Distance only applies to Mandelbrot sets of z^n + c.",
+                                );
+                            ui.end_row();
                         });
                     });
             });
@@ -998,6 +1663,224 @@ impl UIInstance {
             });
     }
 
+    fn draw_orbit_trap_options(
+        ui: &mut Ui,
+        orbit_trap: &mut Option<OrbitTrap>,
+        trap_image_dialog_wrapper: &mut FileDialogWrapper,
+        trap_image_error: &Option<String>,
+    ) {
+        ui.label("Orbit Trap:");
+        ComboBox::from_id_source("fractal_options.orbit_trap")
+            .selected_text(orbit_trap.as_ref().map_or("None", OrbitTrap::name))
+            .show_ui(ui, |ui| {
+                let center = orbit_trap
+                    .as_ref()
+                    .map_or(Complex64::zero(), OrbitTrap::center);
+                for trap in [
+                    None,
+                    Some(OrbitTrap::Point { center }),
+                    Some(OrbitTrap::Line { center, angle: 0.0 }),
+                    Some(OrbitTrap::Cross { center, angle: 0.0 }),
+                    Some(OrbitTrap::Circle {
+                        center,
+                        radius: 1.0,
+                    }),
+                    Some(OrbitTrap::Image {
+                        center,
+                        size: 2.0,
+                        image: TrapImage::default(),
+                    }),
+                ] {
+                    let name = trap.as_ref().map_or("None", OrbitTrap::name);
+                    let selected = orbit_trap.as_ref().map(OrbitTrap::name)
+                        == trap.as_ref().map(OrbitTrap::name);
+                    if ui.selectable_label(selected, name).clicked() && !selected {
+                        *orbit_trap = trap;
+                    }
+                }
+            })
+            .response
+            .on_hover_text(
+                "Color escaped points by how close their orbits come to a shape instead of by \
+            their iteration count.",
+            );
+        ui.end_row();
+
+        let trap = match orbit_trap {
+            Some(trap) => trap,
+            None => return,
+        };
+
+        ui.label("Trap Center:");
+        ui.horizontal(|ui| {
+            let center = match trap {
+                OrbitTrap::Point { center }
+                | OrbitTrap::Line { center, .. }
+                | OrbitTrap::Cross { center, .. }
+                | OrbitTrap::Circle { center, .. }
+                | OrbitTrap::Image { center, .. } => center,
+            };
+            for part in [&mut center.re, &mut center.im] {
+                ui.add_sized(
+                    vec2(80.0, ui.spacing().interact_size.y),
+                    DragValue::new(part)
+                        .clamp_range(-10.0..=10.0)
+                        .speed(0.001)
+                        .min_decimals(3),
+                );
+            }
+        });
+        ui.end_row();
+
+        match trap {
+            OrbitTrap::Point { .. } => {},
+            OrbitTrap::Line { angle, .. } | OrbitTrap::Cross { angle, .. } => {
+                ui.label("Trap Angle:");
+                ui.add_sized(
+                    vec2(80.0, ui.spacing().interact_size.y),
+                    DragValue::new(angle)
+                        .clamp_range(-180.0..=180.0)
+                        .speed(0.5)
+                        .suffix("°"),
+                );
+                ui.end_row();
+            },
+            OrbitTrap::Circle { radius, .. } => {
+                ui.label("Trap Radius:");
+                ui.add_sized(
+                    vec2(80.0, ui.spacing().interact_size.y),
+                    DragValue::new(radius).clamp_range(0.0..=10.0).speed(0.001),
+                );
+                ui.end_row();
+            },
+            OrbitTrap::Image { size, image, .. } => {
+                ui.label("Trap Size:");
+                ui.add_sized(
+                    vec2(80.0, ui.spacing().interact_size.y),
+                    DragValue::new(size).clamp_range(0.001..=20.0).speed(0.001),
+                );
+                ui.end_row();
+
+                ui.label("Trap Image:");
+                ui.horizontal(|ui| {
+                    if ui.button("Choose File").clicked() {
+                        trap_image_dialog_wrapper
+                            .open_file(AsyncFileDialog::new().add_filter("PNG Image", &["png"]))
+                            .ok();
+                    }
+                    if image.coverage.is_empty() {
+                        ui.label("None");
+                    } else {
+                        ui.label(format!("{}x{}", image.width, image.height));
+                    }
+                });
+                ui.end_row();
+
+                if let Some(error) = trap_image_error {
+                    ui.label(RichText::new(format!("Error: {}", error)).color(Color32::RED));
+                    ui.end_row();
+                }
+            },
+        }
+    }
+
+    fn draw_palette_editor(&mut self, ctx: &UIInstanceRenderContext) {
+        egui::Window::new("Palette Editor")
+            .default_size([340.0, 500.0])
+            .open(&mut self.show_palette_editor)
+            .show(ctx.ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.selectable_value(&mut self.editing_interior_palette, false, "Exterior");
+                    ui.selectable_value(&mut self.editing_interior_palette, true, "Interior");
+                });
+
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    if self.editing_interior_palette {
+                        self.interior_palette_editor
+                            .draw(ui, &mut self.interior_palette);
+                    } else {
+                        self.palette_editor.draw(ui, &mut self.palette);
+                    }
+                });
+            });
+    }
+
+    /// Gets the [`FractalOpts`] described by this instance's settings for
+    /// generating the given view.
+    pub fn fractal_opts(&self, view: &View) -> FractalOpts {
+        // Views that are small enough to need double precision are placed with
+        // the precise center, while leaving it out of shallower ones keeps
+        // navigating them from invalidating cached generators.
+        let precision = Precision::required_for(view);
+        let plane_start = (precision == Precision::Double).then(|| {
+            let scale = view.image_scale_x.abs().min(view.image_scale_y.abs());
+            self.plane_center().offset(
+                Complex64::new(
+                    -(view.image_width as f64) / 2.0 * view.image_scale_x,
+                    -(view.image_height as f64) / 2.0 * view.image_scale_y,
+                ),
+                BigFloat::limbs_for_scale(scale),
+            )
+        });
+
+        FractalOpts {
+            mandelbrot: self.mandelbrot,
+            iterations: self.iterations,
+            formula: self.formula.clone(),
+            smoothing: match self.smoothing {
+                Smoothing::LogarithmicDistance { .. } => self
+                    .formula
+                    .logarithmic_smoothing(self.radius_squared.sqrt()),
+                smoothing => smoothing,
+            },
+            multisampling: self.multisampling,
+            c: self.c,
+            radius_squared: self.radius_squared,
+            precision,
+            plane_start,
+            palette: self.palette.clone(),
+            interior_palette: self.interior_palette.clone(),
+            shading: self.shading,
+            histogram_equalization: self.histogram_equalization,
+            interior_checks: self.interior_checks,
+            boundary_tracing: self.boundary_tracing,
+            cpu_kernel: self.cpu_kernel,
+            distance_estimation: self.distance_estimation,
+            orbit_trap: self.orbit_trap.clone(),
+            averaging: self.averaging,
+            interior_coloring: self.interior_coloring,
+        }
+    }
+
+    /// Gets the center of the plane in arbitrary precision, if navigating
+    /// found one and the edited center hasn't been changed since.
+    fn precise_center(&self) -> Option<&PlanePoint> {
+        self.precise_center.as_ref().filter(|center| {
+            !self.edit_fractal_plane_centered
+                && center.to_f64()
+                    == Complex64::new(
+                        self.edit_fractal_plane_center_x,
+                        self.edit_fractal_plane_center_y,
+                    )
+        })
+    }
+
+    /// Gets the center of the plane, in arbitrary precision if there is a
+    /// precise center and in the precision of the edited center otherwise.
+    fn plane_center(&self) -> PlanePoint {
+        match self.precise_center() {
+            Some(center) => center.clone(),
+            None if self.edit_fractal_plane_centered => PlanePoint::from_f64(Complex64::zero(), 2),
+            None => PlanePoint::from_f64(
+                Complex64::new(
+                    self.edit_fractal_plane_center_x,
+                    self.edit_fractal_plane_center_y,
+                ),
+                2,
+            ),
+        }
+    }
+
     pub fn viewer_view(&self) -> View {
         if self.edit_fractal_plane_centered {
             View::new_centered_uniform(
@@ -1047,3 +1930,29 @@ impl UIInstance {
         }
     }
 }
+
+/// Gets the name of a formula as displayed in the formula selector.
+fn formula_name(formula: &Formula) -> &'static str {
+    match formula {
+        Formula::IntegerPower { .. } => "z^n + c (Integer n)",
+        Formula::RealPower { .. } => "z^n + c (Real n)",
+        Formula::BurningShip => "Burning Ship",
+        Formula::Tricorn => "Tricorn",
+        Formula::PerpendicularBurningShip => "Perpendicular Burning Ship",
+        Formula::Celtic => "Celtic",
+        Formula::Expression(_) => "Custom Expression",
+    }
+}
+
+/// Gets the expression shown in the expression editor when the given formula
+/// is selected.
+fn expression_source(formula: &Formula) -> String {
+    match formula {
+        Formula::Expression(expression) => expression.source().to_string(),
+        _ => default_expression().source().to_string(),
+    }
+}
+
+fn default_expression() -> Expression {
+    "z^2 + c".parse().unwrap()
+}
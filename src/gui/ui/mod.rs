@@ -1,10 +1,13 @@
 mod file_dialog;
 mod instance;
+mod palette_editor;
 mod widgets;
 
 use crate::{
     generator::{
-        cpu::CpuFractalGeneratorFactory, gpu::GpuFractalGeneratorFactory, FractalGeneratorFactory,
+        args::Formula, composite::CompositeFractalGeneratorFactory,
+        cpu::CpuFractalGeneratorFactory, gpu::GpuFractalGeneratorFactory,
+        perturbation::PerturbationFractalGeneratorFactory, FractalGeneratorFactory,
     },
     gpu::{
         util::{get_desired_limits, print_adapter_info},
@@ -14,8 +17,10 @@ use crate::{
         keyboard::{
             tracker::KeyboardTracker, tree::ShortcutTreeNode, Shortcut, ShortcutMap, ShortcutName,
         },
+        project::{Project, PROJECT_FILE_EXTENSION},
         storage::CfgUiSettings,
         ui::{
+            file_dialog::FileDialogWrapper,
             instance::{
                 UIInstance, UIInstanceCreationContext, UIInstanceGenerationType, UIInstanceInfo,
                 UIInstanceInitialSettings, UIInstanceRenderContext, UIInstanceUpdateContext,
@@ -27,15 +32,17 @@ use crate::{
         },
         util::get_trace_path,
     },
-    storage::{CfgFractalGeneratorType, CfgGeneral, CfgSingleton},
+    storage::{CfgFractalGeneratorType, CfgGeneral, CfgSingleton, CHUNK_SIZE_POWERS},
     util::{future::future_wrapper::FutureWrapper, result::ResultExt, running_guard::RunningGuard},
 };
 use egui::{vec2, Align, Align2, Button, Context, DragValue, Layout, RichText, TextStyle};
 use egui_wgpu_backend::RenderPass;
-use num_complex::Complex32;
+use num_complex::Complex64;
+use rfd::AsyncFileDialog;
 use std::{
     collections::HashMap,
     io,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
@@ -73,6 +80,7 @@ pub struct FractalRSUI {
     new_generator_type: GeneratorType,
     chunk_size_power: usize,
     cache_generators: bool,
+    progressive_preview: bool,
     start_fullscreen: bool,
     initial_window_width: u32,
     initial_window_height: u32,
@@ -111,6 +119,14 @@ pub struct FractalRSUI {
     next_instance_name_index: u64,
     tab_close_requested: Option<usize>,
     instance_operations: UIOperations,
+
+    // project files
+    project_path: Option<PathBuf>,
+    project_dialog: FileDialogWrapper,
+    project_dialog_type: ProjectDialogType,
+    open_project_requested: bool,
+    save_project_requested: bool,
+    save_project_as_requested: bool,
 }
 
 /// Struct containing context passed when creating UIState.
@@ -167,7 +183,9 @@ pub enum UIOperationRequest {
         /// the id of the one created.
         instance_id: Option<u64>,
         /// The C value of the julia set to generate.
-        c: Complex32,
+        c: Complex64,
+        /// The formula of the julia set to generate.
+        formula: Formula,
     },
     /// This instance wants the UI to stop having it be another instance's
     /// target.
@@ -208,6 +226,11 @@ impl FractalRSUI {
                     Arc::new(GpuFractalGeneratorFactory::new(ctx.present.clone())),
                     None,
                 ),
+                GeneratorType::Perturbation => (
+                    Arc::new(PerturbationFractalGeneratorFactory::new(num_cpus::get())),
+                    None,
+                ),
+                GeneratorType::Hybrid => (create_hybrid_factory(ctx.present.clone()), None),
                 GeneratorType::DedicatedGPU => {
                     let res = ctx
                         .handle
@@ -247,7 +270,7 @@ impl FractalRSUI {
         let ui_settings = CfgUiSettings::read_clone();
 
         FractalRSUI {
-            handle: ctx.handle,
+            handle: ctx.handle.clone(),
             present: ctx.present,
             close_requested: false,
             previous_fullscreen: false,
@@ -258,6 +281,7 @@ impl FractalRSUI {
             new_generator_type: generator_type,
             chunk_size_power: general.fractal_chunk_size_power,
             cache_generators: general.cache_generators,
+            progressive_preview: general.progressive_preview,
             start_fullscreen: ui_settings.start_fullscreen,
             initial_window_width: ui_settings.initial_window_width,
             initial_window_height: ui_settings.initial_window_height,
@@ -280,6 +304,12 @@ impl FractalRSUI {
             next_instance_name_index: 2,
             tab_close_requested: None,
             instance_operations: Default::default(),
+            project_path: None,
+            project_dialog: FileDialogWrapper::new(ctx.handle),
+            project_dialog_type: ProjectDialogType::Open,
+            open_project_requested: false,
+            save_project_requested: false,
+            save_project_as_requested: false,
         }
     }
 
@@ -309,6 +339,25 @@ impl FractalRSUI {
                         instance.set_factory(self.factory.clone());
                     }
                 },
+                GeneratorType::Perturbation => {
+                    self.factory =
+                        Arc::new(PerturbationFractalGeneratorFactory::new(num_cpus::get()));
+                    self.gpu_poll = None;
+
+                    // update the factories for all existing instances
+                    for instance in self.instances.values_mut() {
+                        instance.set_factory(self.factory.clone());
+                    }
+                },
+                GeneratorType::Hybrid => {
+                    self.factory = create_hybrid_factory(self.present.clone());
+                    self.gpu_poll = None;
+
+                    // update the factories for all existing instances
+                    for instance in self.instances.values_mut() {
+                        instance.set_factory(self.factory.clone());
+                    }
+                },
                 GeneratorType::DedicatedGPU => {
                     self.factory_future
                         .insert_spawn(&self.handle, create_gpu_factory(self.instance.clone()))
@@ -366,6 +415,7 @@ impl FractalRSUI {
                 render_pass: ctx.render_pass,
                 chunk_size: 1 << self.chunk_size_power,
                 cache_generators: self.cache_generators,
+                progressive_preview: self.progressive_preview,
                 operations: &mut self.instance_operations,
             });
         }
@@ -378,6 +428,8 @@ impl FractalRSUI {
 
         self.handle_instance_operations(ctx);
         self.handle_new_instance(ctx);
+        self.handle_save_project_requested();
+        self.handle_project_dialog(ctx);
     }
 
     /// Render the current UI state to the Egui context.
@@ -413,6 +465,7 @@ impl FractalRSUI {
         self.draw_misc_windows(ctx);
 
         self.handle_tab_close_requested(ctx);
+        self.handle_open_project_requested(ctx);
         self.handle_change_shortcut(ctx);
     }
 
@@ -427,6 +480,21 @@ impl FractalRSUI {
             self.new_instance_requested = true;
         }
 
+        // Open keyboard shortcut
+        if shortcuts.is_pressed(ShortcutName::App_Open) {
+            self.open_project_requested = true;
+        }
+
+        // Save keyboard shortcut
+        if shortcuts.is_pressed(ShortcutName::App_Save) {
+            self.save_project_requested = true;
+        }
+
+        // Save As keyboard shortcut
+        if shortcuts.is_pressed(ShortcutName::App_SaveAs) {
+            self.save_project_as_requested = true;
+        }
+
         // Close tab keyboard shortcut
         if shortcuts.is_pressed(ShortcutName::App_CloseTab) {
             self.tab_close_requested = Some(self.current_tab);
@@ -455,6 +523,21 @@ impl FractalRSUI {
 
                     ui.separator();
 
+                    if ui.add(shortcut_button!("Open...", ctx, App_Open)).clicked() {
+                        self.open_project_requested = true;
+                    }
+                    if ui.add(shortcut_button!("Save", ctx, App_Save)).clicked() {
+                        self.save_project_requested = true;
+                    }
+                    if ui
+                        .add(shortcut_button!("Save As...", ctx, App_SaveAs))
+                        .clicked()
+                    {
+                        self.save_project_as_requested = true;
+                    }
+
+                    ui.separator();
+
                     if ui.add(shortcut_button!("Quit", ctx, App_Quit)).clicked() {
                         self.close_requested = true;
                     }
@@ -529,6 +612,16 @@ impl FractalRSUI {
                                 GeneratorType::DedicatedGPU,
                                 "Dedicated GPU (Fastest)",
                             );
+                            ui.radio_value(
+                                &mut self.new_generator_type,
+                                GeneratorType::Perturbation,
+                                "Perturbation (Deep Zooms)",
+                            );
+                            ui.radio_value(
+                                &mut self.new_generator_type,
+                                GeneratorType::Hybrid,
+                                "CPU + Display GPU (Hybrid)",
+                            );
                         });
                         ui.label(
                             "Note 1: While the GPU generator is significantly faster on most \
@@ -547,7 +640,10 @@ impl FractalRSUI {
                         ui.label(RichText::new("Chunk Size:").heading());
                         ui.horizontal(|ui| {
                             ui.label(RichText::new("2^").monospace());
-                            ui.add(DragValue::new(&mut self.chunk_size_power).clamp_range(4..=13));
+                            ui.add(
+                                DragValue::new(&mut self.chunk_size_power)
+                                    .clamp_range(CHUNK_SIZE_POWERS),
+                            );
                         });
                         ui.label(
                             "Note that while larger values are generally faster, some drivers \
@@ -562,6 +658,13 @@ impl FractalRSUI {
                             "Note: you generally only want to disable this if you're \
                             doing shader development.",
                         );
+
+                        ui.label(RichText::new("Progressive Preview:").heading());
+                        ui.checkbox(&mut self.progressive_preview, "Progressive Preview");
+                        ui.label(
+                            "Renders the viewer at low resolution first, refining the image in \
+                            several passes.",
+                        );
                     });
 
                 egui::CollapsingHeader::new("Window Settings")
@@ -715,10 +818,194 @@ impl FractalRSUI {
         }
     }
 
+    fn handle_open_project_requested(&mut self, ctx: &UIRenderContext) {
+        if !self.open_project_requested {
+            return;
+        }
+
+        // Opening a project replaces all the open tabs, so make sure the user is ok
+        // with losing any unsaved changes first.
+        let mut open = true;
+        if self.instances.values().any(|instance| instance.dirty) {
+            open = false;
+
+            egui::Window::new("Are you sure?")
+                .resizable(false)
+                .collapsible(false)
+                .anchor(Align2::CENTER_CENTER, vec2(0.0, 0.0))
+                .show(ctx.ctx, |ui| {
+                    ui.label("Are you sure you want to open a project?");
+                    ui.label("Some open tabs have unsaved changes, which will be lost.");
+                    ui.add_space(20.0);
+                    ui.with_layout(Layout::right_to_left(Align::Min), |ui| {
+                        if ui.button("Open Project").clicked() {
+                            open = true;
+                        }
+                        if ui.button("Cancel").clicked() {
+                            self.open_project_requested = false;
+                        }
+                    });
+                });
+        }
+
+        if open {
+            self.open_project_requested = false;
+            self.open_project_dialog(ProjectDialogType::Open);
+        }
+    }
+
+    fn handle_save_project_requested(&mut self) {
+        if self.save_project_as_requested {
+            self.save_project_as_requested = false;
+            self.save_project_requested = false;
+            self.open_project_dialog(ProjectDialogType::SaveAs);
+        }
+
+        if self.save_project_requested {
+            self.save_project_requested = false;
+
+            if let Some(path) = self.project_path.clone() {
+                self.save_project(&path);
+            } else {
+                self.open_project_dialog(ProjectDialogType::SaveAs);
+            }
+        }
+    }
+
+    fn open_project_dialog(&mut self, ty: ProjectDialogType) {
+        let mut dialog =
+            AsyncFileDialog::new().add_filter("Fractal-RS Project", &[PROJECT_FILE_EXTENSION]);
+        if let Some(dir) = self.project_path.as_ref().and_then(|path| path.parent()) {
+            dialog = dialog.set_directory(dir);
+        }
+
+        let res = match ty {
+            ProjectDialogType::Open => self.project_dialog.open_file(dialog),
+            ProjectDialogType::SaveAs => self.project_dialog.save_file(dialog),
+        };
+
+        if res.is_ok() {
+            self.project_dialog_type = ty;
+        }
+    }
+
+    fn handle_project_dialog(&mut self, ctx: &mut UIUpdateContext) {
+        if let Some(file) = self.project_dialog.poll().flatten() {
+            let mut path = file.path().to_path_buf();
+            match self.project_dialog_type {
+                ProjectDialogType::Open => self.open_project(ctx, path),
+                ProjectDialogType::SaveAs => {
+                    if path.extension().is_none() {
+                        path.set_extension(PROJECT_FILE_EXTENSION);
+                    }
+                    self.save_project(&path);
+                },
+            }
+        }
+    }
+
+    fn open_project(&mut self, ctx: &mut UIUpdateContext, path: PathBuf) {
+        info!("Opening project {:?}...", &path);
+        let project = match Project::load(&path) {
+            Ok(project) => project,
+            Err(e) => {
+                error!("Error opening project {:?}: {:?}", &path, e);
+                return;
+            },
+        };
+
+        self.instances.clear();
+        self.tabs.clear();
+        self.current_tab = 0;
+        self.tab_close_requested = None;
+
+        // Create all the instances before linking any of them.
+        let mut ids = vec![];
+        for tab in project.tabs.iter() {
+            let mut instance = UIInstance::new(UIInstanceCreationContext {
+                name: &tab.name,
+                handle: self.handle.clone(),
+                present: self.present.clone(),
+                factory: self.factory.clone(),
+                render_pass: ctx.render_pass,
+                id: self.next_instance_id,
+                initial_settings: UIInstanceInitialSettings {
+                    view: tab.viewer_view,
+                    mandelbrot: tab.opts.mandelbrot,
+                    c: tab.opts.c,
+                    iterations: tab.opts.iterations,
+                    formula: tab.opts.formula.clone(),
+                },
+            });
+            instance.load_project_tab(tab);
+
+            ids.push(self.next_instance_id);
+            self.tabs.push(SimpleTab::new(self.next_instance_id));
+            self.instances.insert(self.next_instance_id, instance);
+            increment_instance_id(&mut self.next_instance_id, &self.instances);
+        }
+
+        // `Project::load` makes sure all targets are valid indices.
+        for (index, tab) in project.tabs.iter().enumerate() {
+            if let Some(target) = tab.julia_target {
+                let parent = self.instances.get_mut(&ids[index]).unwrap();
+                parent.set_target_instance(Some(ids[target]));
+                parent.mark_saved();
+
+                self.instances
+                    .get_mut(&ids[target])
+                    .unwrap()
+                    .parent_instance = Some(ids[index]);
+            }
+        }
+
+        self.project_path = Some(path);
+    }
+
+    fn save_project(&mut self, path: &Path) {
+        info!("Saving project {:?}...", path);
+        let indices: HashMap<_, _> = self
+            .tabs
+            .iter()
+            .enumerate()
+            .map(|(index, tab)| (tab.data, index))
+            .collect();
+
+        let project = Project {
+            tabs: self
+                .tabs
+                .iter()
+                .map(|tab| {
+                    let instance = &self.instances[&tab.data];
+                    let mut project_tab = instance.project_tab();
+                    project_tab.julia_target = instance
+                        .target_instance()
+                        .and_then(|id| indices.get(&id).copied());
+                    project_tab
+                })
+                .collect(),
+        };
+
+        if let Err(e) = project.store(path) {
+            error!("Error saving project {:?}: {:?}", path, e);
+            return;
+        }
+
+        for instance in self.instances.values_mut() {
+            instance.mark_saved();
+        }
+
+        self.project_path = Some(path.to_path_buf());
+    }
+
     fn handle_instance_operations(&mut self, ctx: &mut UIUpdateContext) {
         for (id, operation) in self.instance_operations.operations.drain(..) {
             match operation {
-                UIOperationRequest::StartJuliaSet { instance_id, c } => {
+                UIOperationRequest::StartJuliaSet {
+                    instance_id,
+                    c,
+                    formula,
+                } => {
                     if let Some(instance) = instance_id
                         .as_ref()
                         .and_then(|id| self.instances.get_mut(id))
@@ -726,6 +1013,7 @@ impl FractalRSUI {
                         let instance_id = instance_id.unwrap();
 
                         instance.c = c;
+                        instance.set_formula(formula);
                         instance.mandelbrot = false;
                         instance.parent_instance = Some(id);
                         if !instance.generation_running {
@@ -743,6 +1031,7 @@ impl FractalRSUI {
                         // Create a new instance for generating this julia set
                         let initial_settings = UIInstanceInitialSettings {
                             c,
+                            formula,
                             mandelbrot: false,
                             ..Default::default()
                         };
@@ -896,6 +1185,7 @@ impl FractalRSUI {
             cfg.fractal_generator_type = self.current_generator_type.into();
             cfg.fractal_chunk_size_power = self.chunk_size_power;
             cfg.cache_generators = self.cache_generators;
+            cfg.progressive_preview = self.progressive_preview;
         }
         {
             let mut cfg = CfgUiSettings::write();
@@ -913,11 +1203,20 @@ fn increment_instance_id(next_instance_id: &mut u64, instances: &HashMap<u64, UI
     }
 }
 
+/// What the project file dialog was opened for.
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum ProjectDialogType {
+    Open,
+    SaveAs,
+}
+
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
 enum GeneratorType {
     CPU,
     PresentGPU,
     DedicatedGPU,
+    Perturbation,
+    Hybrid,
 }
 
 impl From<CfgFractalGeneratorType> for GeneratorType {
@@ -926,6 +1225,8 @@ impl From<CfgFractalGeneratorType> for GeneratorType {
             CfgFractalGeneratorType::Cpu => Self::CPU,
             CfgFractalGeneratorType::Gpu => Self::PresentGPU,
             CfgFractalGeneratorType::GpuDedicated => Self::DedicatedGPU,
+            CfgFractalGeneratorType::Perturbation => Self::Perturbation,
+            CfgFractalGeneratorType::Hybrid => Self::Hybrid,
         }
     }
 }
@@ -936,10 +1237,23 @@ impl From<GeneratorType> for CfgFractalGeneratorType {
             GeneratorType::CPU => Self::Cpu,
             GeneratorType::PresentGPU => Self::Gpu,
             GeneratorType::DedicatedGPU => Self::GpuDedicated,
+            GeneratorType::Perturbation => Self::Perturbation,
+            GeneratorType::Hybrid => Self::Hybrid,
         }
     }
 }
 
+/// Creates a factory that splits generation between the CPU and the display
+/// GPU.
+fn create_hybrid_factory(
+    present: GPUContext,
+) -> Arc<dyn FractalGeneratorFactory + Send + Sync + 'static> {
+    Arc::new(CompositeFractalGeneratorFactory::new(vec![
+        Arc::new(CpuFractalGeneratorFactory::new(num_cpus::get())),
+        Arc::new(GpuFractalGeneratorFactory::new(present)),
+    ]))
+}
+
 async fn create_gpu_factory(
     instance: Arc<Instance>,
 ) -> Result<
@@ -0,0 +1,302 @@
+//! This module contains the palette editor, used to edit, save, and load the
+//! gradient palettes fractals are colored with.
+
+use crate::generator::palette::{
+    is_valid_palette_name, palette_path, saved_palettes, ColorStop, Interpolation, Palette,
+    RepeatMode, MAX_PALETTE_STOPS,
+};
+use egui::{vec2, Color32, ComboBox, DragValue, Sense, TextEdit, Ui};
+
+const PREVIEW_HEIGHT: f32 = 24.0;
+
+/// Editor for the palette of a single UIInstance.
+pub struct PaletteEditor {
+    /// The gradient restored when switching back from the classic colors.
+    stashed: Palette,
+    /// The name the palette is saved under.
+    palette_name: String,
+    /// The palettes in the palettes directory, loaded when first needed.
+    saved_palettes: Option<Vec<String>>,
+    /// The result of the last save or load.
+    message: Option<String>,
+    /// The name of the existing palette the user is being asked to confirm
+    /// overwriting.
+    confirm_overwrite: Option<String>,
+}
+
+impl PaletteEditor {
+    pub fn new() -> PaletteEditor {
+        PaletteEditor {
+            stashed: Default::default(),
+            palette_name: "".to_string(),
+            saved_palettes: None,
+            message: None,
+            confirm_overwrite: None,
+        }
+    }
+
+    /// Draws the editor for `palette`, where `None` means the classic
+    /// hue-cycling colors are used.
+    pub fn draw(&mut self, ui: &mut Ui, palette: &mut Option<Palette>) {
+        ui.horizontal(|ui| {
+            if ui.radio(palette.is_none(), "Classic").clicked() {
+                if let Some(gradient) = palette.take() {
+                    self.stashed = gradient;
+                }
+            }
+            if ui.radio(palette.is_some(), "Gradient").clicked() && palette.is_none() {
+                *palette = Some(self.stashed.clone());
+            }
+        });
+
+        ui.separator();
+
+        if let Some(palette) = palette {
+            draw_preview(ui, palette);
+
+            egui::CollapsingHeader::new("Mapping")
+                .default_open(true)
+                .show(ui, |ui| {
+                    draw_mapping(ui, palette);
+                });
+
+            egui::CollapsingHeader::new("Color Stops")
+                .default_open(true)
+                .show(ui, |ui| {
+                    draw_stops(ui, palette);
+                });
+        }
+
+        egui::CollapsingHeader::new("Saved Palettes").show(ui, |ui| {
+            self.draw_saved_palettes(ui, palette);
+        });
+    }
+
+    fn draw_saved_palettes(&mut self, ui: &mut Ui, palette: &mut Option<Palette>) {
+        let saved = self.saved_palettes.get_or_insert_with(saved_palettes);
+
+        ui.horizontal(|ui| {
+            ComboBox::from_id_source("palette_editor.load")
+                .selected_text("Load...")
+                .show_ui(ui, |ui| {
+                    for name in saved.iter() {
+                        if ui.button(name).clicked() {
+                            match palette_path(name).and_then(Palette::load) {
+                                Ok(loaded) => {
+                                    *palette = Some(loaded);
+                                    self.palette_name = name.clone();
+                                    self.message = Some(format!("Loaded '{}'", name));
+                                },
+                                Err(e) => {
+                                    error!("Error loading palette '{}': {:?}", name, e);
+                                    self.message = Some(format!("Error loading '{}': {}", name, e));
+                                },
+                            }
+                        }
+                    }
+                });
+
+            if ui.button("Refresh").clicked() {
+                *saved = saved_palettes();
+            }
+        });
+
+        ui.label("Palette name:");
+        ui.add(TextEdit::singleline(&mut self.palette_name).desired_width(ui.available_width()));
+
+        let name = self.palette_name.trim().to_string();
+        let valid_name = is_valid_palette_name(&name);
+        if !name.is_empty() && !valid_name {
+            ui.colored_label(
+                Color32::RED,
+                "Palette names can't contain path separators or '..', or start with '.'.",
+            );
+        }
+
+        // the confirmation is only for the name it was asked about
+        if self.confirm_overwrite.as_ref() != Some(&name) {
+            self.confirm_overwrite = None;
+        }
+
+        let mut save = false;
+        if self.confirm_overwrite.is_some() {
+            ui.label(format!("'{}' already exists. Overwrite it?", name));
+            ui.horizontal(|ui| {
+                if ui.button("Overwrite").clicked() {
+                    save = true;
+                }
+                if ui.button("Cancel").clicked() {
+                    self.confirm_overwrite = None;
+                }
+            });
+        } else {
+            ui.add_enabled_ui(palette.is_some() && valid_name, |ui| {
+                if ui.button("Save").clicked() {
+                    let exists = palette_path(&name).map_or(false, |path| path.exists());
+                    if exists {
+                        self.confirm_overwrite = Some(name.clone());
+                    } else {
+                        save = true;
+                    }
+                }
+            });
+        }
+
+        if let (true, Some(to_save)) = (save, palette.as_ref()) {
+            self.confirm_overwrite = None;
+            match palette_path(&name).and_then(|path| to_save.store(path)) {
+                Ok(_) => {
+                    self.message = Some(format!("Saved '{}'", name));
+                    self.saved_palettes = Some(saved_palettes());
+                },
+                Err(e) => {
+                    error!("Error saving palette '{}': {:?}", name, e);
+                    self.message = Some(format!("Error saving '{}': {}", name, e));
+                },
+            }
+        }
+
+        if let Some(message) = &self.message {
+            ui.label(message);
+        }
+    }
+}
+
+/// Paints the gradient across the width of the UI.
+fn draw_preview(ui: &mut Ui, palette: &Palette) {
+    let (rect, _) =
+        ui.allocate_exact_size(vec2(ui.available_width(), PREVIEW_HEIGHT), Sense::hover());
+    let painter = ui.painter_at(rect);
+
+    let palette = palette.sorted();
+    let columns = rect.width().max(1.0) as usize;
+    for column in 0..columns {
+        let t = (column as f32 + 0.5) / columns as f32;
+        let color = palette.color_at(t);
+        let mut column_rect = rect;
+        column_rect.set_left(rect.left() + column as f32);
+        column_rect.set_width(1.0);
+        painter.rect_filled(
+            column_rect,
+            0.0,
+            Color32::from_rgb(
+                (color.x * 255.0) as u8,
+                (color.y * 255.0) as u8,
+                (color.z * 255.0) as u8,
+            ),
+        );
+    }
+}
+
+fn draw_mapping(ui: &mut Ui, palette: &mut Palette) {
+    egui::Grid::new("palette_editor.mapping.grid").show(ui, |ui| {
+        ui.label("Interpolation:");
+        ComboBox::from_id_source("palette_editor.interpolation")
+            .selected_text(interpolation_name(palette.interpolation))
+            .show_ui(ui, |ui| {
+                for interpolation in [
+                    Interpolation::Linear,
+                    Interpolation::Smooth,
+                    Interpolation::Step,
+                ] {
+                    ui.selectable_value(
+                        &mut palette.interpolation,
+                        interpolation,
+                        interpolation_name(interpolation),
+                    );
+                }
+            });
+        ui.end_row();
+
+        ui.label("Repeat:");
+        ComboBox::from_id_source("palette_editor.repeat")
+            .selected_text(repeat_name(palette.repeat))
+            .show_ui(ui, |ui| {
+                for repeat in [RepeatMode::Repeat, RepeatMode::Mirror, RepeatMode::Clamp] {
+                    ui.selectable_value(&mut palette.repeat, repeat, repeat_name(repeat));
+                }
+            });
+        ui.end_row();
+
+        ui.label("Offset:");
+        ui.add(DragValue::new(&mut palette.offset).speed(0.01));
+        ui.end_row();
+
+        ui.label("Density:")
+            .on_hover_text("How far through the gradient each iteration moves.");
+        ui.add(
+            DragValue::new(&mut palette.density)
+                .speed(0.001)
+                .clamp_range(0.0..=f32::INFINITY),
+        );
+        ui.end_row();
+    });
+}
+
+fn draw_stops(ui: &mut Ui, palette: &mut Palette) {
+    let can_remove = palette.stops.len() > 1;
+    let mut remove = None;
+
+    egui::Grid::new("palette_editor.stops.grid").show(ui, |ui| {
+        ui.label("Position");
+        ui.label("Color");
+        ui.end_row();
+
+        for (index, stop) in palette.stops.iter_mut().enumerate() {
+            ui.add(
+                DragValue::new(&mut stop.position)
+                    .speed(0.005)
+                    .clamp_range(0.0..=1.0),
+            );
+            ui.color_edit_button_rgb(&mut stop.color);
+            if ui
+                .add_enabled(can_remove, egui::Button::new("Remove"))
+                .clicked()
+            {
+                remove = Some(index);
+            }
+            ui.end_row();
+        }
+    });
+
+    if let Some(index) = remove {
+        palette.stops.remove(index);
+    }
+
+    ui.add_enabled_ui(palette.stops.len() < MAX_PALETTE_STOPS, |ui| {
+        if ui.button("Add Stop").clicked() {
+            // New stops go halfway between the last two stops so they are
+            // immediately visible.
+            let sorted = palette.sorted();
+            let position = match sorted.stops.as_slice() {
+                [.., a, b] => (a.position + b.position) / 2.0,
+                _ => 0.5,
+            };
+            let color = sorted.color_at(position);
+            palette.stops.push(ColorStop {
+                position,
+                color: [color.x, color.y, color.z],
+            });
+        }
+    });
+
+    if ui.button("Sort Stops").clicked() {
+        palette.stops = palette.sorted_stops();
+    }
+}
+
+fn interpolation_name(interpolation: Interpolation) -> &'static str {
+    match interpolation {
+        Interpolation::Linear => "Linear",
+        Interpolation::Smooth => "Smooth",
+        Interpolation::Step => "Step",
+    }
+}
+
+fn repeat_name(repeat: RepeatMode) -> &'static str {
+    match repeat {
+        RepeatMode::Repeat => "Repeat",
+        RepeatMode::Mirror => "Mirror",
+        RepeatMode::Clamp => "Clamp",
+    }
+}
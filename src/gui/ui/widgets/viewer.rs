@@ -1,16 +1,24 @@
 //! viewer.rs - This file holds the systems for the fractal image viewer. This
 //! means both image managing and rendering.
 
-use crate::{generator::view::View, gpu::util::create_texture, gui::util::conversion::IntoVec2};
+use crate::{
+    generator::{
+        color::Shading, equalization::Equalization, gpu::recolor::Recolorer, palette::Palette,
+        view::View, PixelFormat,
+    },
+    gpu::{util::create_texture, GPUContext},
+    gui::util::conversion::IntoVec2,
+};
 use egui::{
     Align2, Color32, Mesh, PointerButton, Pos2, Rect, Response, Sense, Shape, Stroke, TextStyle,
     TextureId, Ui, Vec2, Widget,
 };
 use egui_wgpu_backend::RenderPass;
-use num_complex::Complex32;
+use num_complex::Complex64;
 use std::sync::Arc;
 use wgpu::{
-    Device, FilterMode, SamplerDescriptor, Texture, TextureFormat, TextureUsages, TextureView,
+    BindGroup, CommandEncoderDescriptor, Device, FilterMode, SamplerDescriptor, Texture,
+    TextureFormat, TextureUsages, TextureView,
 };
 
 const IMAGE_UV_RECT: Rect = Rect::from_min_max(Pos2 { x: 0.0, y: 0.0 }, Pos2 { x: 1.0, y: 1.0 });
@@ -23,38 +31,49 @@ const ZOOM_BORDER: Stroke = Stroke {
 pub struct FractalViewer {
     // Static Components
     texture_id: TextureId,
+    recolorer: Recolorer,
 
     // Dynamic Components
     fractal_view: View,
+    pixel_format: PixelFormat,
     fractal_size_f: Vec2,
+    value_texture: Arc<Texture>,
+    value_texture_view: Arc<TextureView>,
     image_texture: Arc<Texture>,
     image_texture_view: Arc<TextureView>,
+    recolor_bind_group: BindGroup,
     previous_size: Option<Vec2>,
 
+    // Coloring Components
+    colored_palette: Option<Palette>,
+    colored_interior_palette: Option<Palette>,
+    colored_shading: Shading,
+    colored_equalization: Option<Equalization>,
+    needs_recolor: bool,
+
     // View components
     pub fractal_offset: Vec2,
     pub fractal_scale: f32,
 
     // Selection Components
-    pub selection_pos: Option<Complex32>,
+    pub selection_pos: Option<Complex64>,
+    /// The pixel of the view that was clicked to select `selection_pos`.
+    selected_pixel: Option<(usize, usize)>,
 
     // Zoom Components
     scroll_mode: ScrollMode,
-    pub new_plane_width: Option<f32>,
+    pub new_plane_width: Option<f64>,
 }
 
 impl FractalViewer {
     pub fn new(device: &Device, render_pass: &mut RenderPass, fractal_view: View) -> FractalViewer {
-        let (image_texture, image_texture_view) = create_texture(
-            device,
-            fractal_view.image_width as u32,
-            fractal_view.image_height as u32,
-            TextureFormat::Rgba8Unorm,
-            TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
-        );
+        let pixel_format = PixelFormat::default();
+        let recolorer = create_recolorer(device, pixel_format);
 
-        let image_texture = Arc::new(image_texture);
-        let image_texture_view = Arc::new(image_texture_view);
+        let (value_texture, value_texture_view) = create_value_texture(device, fractal_view);
+        let (image_texture, image_texture_view) =
+            create_image_texture(device, fractal_view, pixel_format);
+        let recolor_bind_group = recolorer.create_bind_group(device, &value_texture_view);
 
         let texture_id = render_pass.egui_texture_from_wgpu_texture_with_sampler_options(
             device,
@@ -69,28 +88,90 @@ impl FractalViewer {
 
         FractalViewer {
             texture_id,
+            recolorer,
             fractal_view,
+            pixel_format,
             fractal_size_f: Vec2::new(
                 fractal_view.image_width as f32,
                 fractal_view.image_height as f32,
             ),
+            value_texture,
+            value_texture_view,
             image_texture,
             image_texture_view,
+            recolor_bind_group,
             previous_size: None,
+            colored_palette: None,
+            colored_interior_palette: None,
+            colored_shading: Default::default(),
+            colored_equalization: None,
+            needs_recolor: true,
             fractal_offset: Vec2::new(0.0, 0.0),
             fractal_scale: 1.0,
             selection_pos: None,
+            selected_pixel: None,
             scroll_mode: ScrollMode::Image,
             new_plane_width: None,
         }
     }
 
-    pub fn get_texture(&self) -> Arc<Texture> {
-        self.image_texture.clone()
+    /// Gets the `Rgba32Float` texture that generators write this viewer's
+    /// values into.
+    pub fn get_value_texture(&self) -> Arc<Texture> {
+        self.value_texture.clone()
+    }
+
+    pub fn get_value_texture_view(&self) -> Arc<TextureView> {
+        self.value_texture_view.clone()
     }
 
-    pub fn get_texture_view(&self) -> Arc<TextureView> {
-        self.image_texture_view.clone()
+    /// Colors this viewer's values into the displayed image if either the
+    /// values, the palettes, the shading or the equalization have changed
+    /// since the last time it was colored.
+    pub fn recolor(
+        &mut self,
+        present: &GPUContext,
+        palette: Option<&Palette>,
+        interior_palette: Option<&Palette>,
+        shading: &Shading,
+        equalization: Option<&Equalization>,
+        values_changed: bool,
+    ) {
+        if !values_changed
+            && !self.needs_recolor
+            && self.colored_palette.as_ref() == palette
+            && self.colored_interior_palette.as_ref() == interior_palette
+            && self.colored_shading == *shading
+            && self.colored_equalization.as_ref() == equalization
+        {
+            return;
+        }
+
+        self.recolorer.set_coloring(
+            &present.queue,
+            palette,
+            interior_palette,
+            shading,
+            equalization,
+        );
+
+        let mut encoder = present
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Recolor Command Encoder"),
+            });
+        self.recolorer.encode(
+            &mut encoder,
+            &self.recolor_bind_group,
+            &self.image_texture_view,
+        );
+        present.queue.submit([encoder.finish()]);
+
+        self.colored_palette = palette.cloned();
+        self.colored_interior_palette = interior_palette.cloned();
+        self.colored_shading = *shading;
+        self.colored_equalization = equalization.cloned();
+        self.needs_recolor = false;
     }
 
     pub fn set_fractal_view(
@@ -101,21 +182,26 @@ impl FractalViewer {
     ) -> Result<(), FractalViewerError> {
         let old_view = self.fractal_view;
         self.fractal_view = fractal_view;
+        if fractal_view != old_view {
+            self.selected_pixel = None;
+        }
 
         // only update everything if the fractal size has changed
         if fractal_view.image_width != old_view.image_width
             || fractal_view.image_height != old_view.image_height
         {
-            let (image_texture, image_texture_view) = create_texture(
-                device,
-                fractal_view.image_width as u32,
-                fractal_view.image_height as u32,
-                TextureFormat::Rgba8Unorm,
-                TextureUsages::COPY_DST | TextureUsages::TEXTURE_BINDING,
-            );
-
-            self.image_texture = Arc::new(image_texture);
-            self.image_texture_view = Arc::new(image_texture_view);
+            let (value_texture, value_texture_view) = create_value_texture(device, fractal_view);
+            let (image_texture, image_texture_view) =
+                create_image_texture(device, fractal_view, self.pixel_format);
+
+            self.recolor_bind_group = self
+                .recolorer
+                .create_bind_group(device, &value_texture_view);
+            self.value_texture = value_texture;
+            self.value_texture_view = value_texture_view;
+            self.image_texture = image_texture;
+            self.image_texture_view = image_texture_view;
+            self.needs_recolor = true;
             self.fractal_size_f = Vec2::new(
                 fractal_view.image_width as f32,
                 fractal_view.image_height as f32,
@@ -137,6 +223,50 @@ impl FractalViewer {
         Ok(())
     }
 
+    /// Gets the format whose precision this viewer's image is colored with.
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+    }
+
+    /// Sets the format whose precision this viewer's image is colored with,
+    /// so that it previews exports in that format.
+    pub fn set_pixel_format(
+        &mut self,
+        device: &Device,
+        render_pass: &mut RenderPass,
+        pixel_format: PixelFormat,
+    ) -> Result<(), FractalViewerError> {
+        if pixel_format == self.pixel_format {
+            return Ok(());
+        }
+
+        let (image_texture, image_texture_view) =
+            create_image_texture(device, self.fractal_view, pixel_format);
+
+        self.pixel_format = pixel_format;
+        self.recolorer = create_recolorer(device, pixel_format);
+        self.recolor_bind_group = self
+            .recolorer
+            .create_bind_group(device, &self.value_texture_view);
+        self.image_texture = image_texture;
+        self.image_texture_view = image_texture_view;
+        self.needs_recolor = true;
+
+        render_pass.update_egui_texture_from_wgpu_texture_with_sampler_options(
+            device,
+            &self.image_texture_view,
+            SamplerDescriptor {
+                label: Some("viewer image sampler"),
+                mag_filter: FilterMode::Nearest,
+                min_filter: FilterMode::Linear,
+                ..Default::default()
+            },
+            self.texture_id,
+        )?;
+
+        Ok(())
+    }
+
     pub fn zoom_1_to_1(&mut self) {
         let previous_scale = self.fractal_scale;
         self.fractal_scale = 1.0;
@@ -161,6 +291,23 @@ impl FractalViewer {
         }
     }
 
+    /// Gets how far the selected position is from the center of the view, if
+    /// it is still the center of the pixel that was clicked to select it.
+    ///
+    /// This is computed from the pixel rather than from the selected position,
+    /// so that it stays precise even when the view is too small for `f64` to
+    /// tell its pixels' positions apart.
+    pub fn selection_offset(&self) -> Option<Complex64> {
+        let (x, y) = self.selected_pixel?;
+        let view = &self.fractal_view;
+        (self.selection_pos? == view.get_local_plane_coordinates((x, y))).then(|| {
+            Complex64::new(
+                (x as f64 + 0.5 - view.image_width as f64 / 2.0) * view.image_scale_x,
+                (y as f64 + 0.5 - view.image_height as f64 / 2.0) * view.image_scale_y,
+            )
+        })
+    }
+
     pub fn is_plane_scrolling(&self) -> bool {
         self.scroll_mode == ScrollMode::Plane
     }
@@ -171,7 +318,7 @@ impl FractalViewer {
         self.scroll_mode = ScrollMode::Plane;
         if self.new_plane_width.is_none() {
             self.new_plane_width =
-                Some(self.fractal_view.image_width as f32 * self.fractal_view.image_scale_x);
+                Some(self.fractal_view.image_width as f64 * self.fractal_view.image_scale_x);
         }
     }
 
@@ -191,7 +338,7 @@ impl FractalViewer {
     /// image.
     pub fn reset_potential_plane_scale(&mut self) {
         self.new_plane_width =
-            Some(self.fractal_view.image_width as f32 * self.fractal_view.image_scale_x);
+            Some(self.fractal_view.image_width as f64 * self.fractal_view.image_scale_x);
     }
 
     pub fn draw(&mut self, ui: &mut egui::Ui, opts: &FractalViewerDrawOptions) -> Response {
@@ -211,7 +358,7 @@ impl FractalViewer {
         // image scale, but none has been set yet.
         if self.scroll_mode == ScrollMode::Plane && self.new_plane_width.is_none() {
             self.new_plane_width =
-                Some(self.fractal_view.image_width as f32 * self.fractal_view.image_scale_x);
+                Some(self.fractal_view.image_width as f64 * self.fractal_view.image_scale_x);
         }
 
         // handle scroll events, but only if we're being hovered over
@@ -264,6 +411,8 @@ impl FractalViewer {
                     pixel_selection.y as usize,
                 ));
                 self.selection_pos = Some(complex_selection);
+                self.selected_pixel =
+                    Some((pixel_selection.x as usize, pixel_selection.y as usize));
             }
         }
 
@@ -513,10 +662,10 @@ impl FractalViewer {
                 // Draw the zoom selection thingy
                 if let Some(new_plane_width) = self.new_plane_width {
                     // This is so unoptimised
-                    let new_image_scale = new_plane_width / self.fractal_view.image_width as f32;
-                    let new_plane_height = self.fractal_view.image_height as f32 * new_image_scale;
+                    let new_image_scale = new_plane_width / self.fractal_view.image_width as f64;
+                    let new_plane_height = self.fractal_view.image_height as f64 * new_image_scale;
                     let plane_offset =
-                        Complex32::new(new_plane_width / 2.0, new_plane_height / 2.0);
+                        Complex64::new(new_plane_width / 2.0, new_plane_height / 2.0);
                     let plane_min = complex_selection - plane_offset;
                     let plane_max = complex_selection + plane_offset;
                     let image_min = self
@@ -593,3 +742,37 @@ enum ScrollMode {
     /// current image size stays the same.
     Plane,
 }
+
+/// Creates the texture generators write a viewer's values into.
+fn create_value_texture(device: &Device, fractal_view: View) -> (Arc<Texture>, Arc<TextureView>) {
+    let (texture, texture_view) = create_texture(
+        device,
+        fractal_view.image_width as u32,
+        fractal_view.image_height as u32,
+        TextureFormat::Rgba32Float,
+        TextureUsages::COPY_DST | TextureUsages::COPY_SRC | TextureUsages::TEXTURE_BINDING,
+    );
+    (Arc::new(texture), Arc::new(texture_view))
+}
+
+/// Creates the recolorer that colors a viewer's values into its image.
+fn create_recolorer(device: &Device, pixel_format: PixelFormat) -> Recolorer {
+    Recolorer::new(device, pixel_format.texture_format())
+        .expect("Error creating the viewer's recolorer (this is a bug)")
+}
+
+/// Creates the texture a viewer's values are colored into for display.
+fn create_image_texture(
+    device: &Device,
+    fractal_view: View,
+    pixel_format: PixelFormat,
+) -> (Arc<Texture>, Arc<TextureView>) {
+    let (texture, texture_view) = create_texture(
+        device,
+        fractal_view.image_width as u32,
+        fractal_view.image_height as u32,
+        pixel_format.texture_format(),
+        TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+    );
+    (Arc::new(texture), Arc::new(texture_view))
+}
@@ -1,10 +1,10 @@
 //! main.rs - This file contains the `main()` function. This method delegates to
-//! `gui` module for gui-based core application logic.
-//!
-//! This system will hopefully eventually determine based on application
-//! arguments whether to start a gui or to perform some other function.
+//! the `cli` module when started with a subcommand like `render` or `node`, and
+//! to the `gui` module for gui-based core application logic otherwise.
 
 #![feature(never_type)]
+#![feature(portable_simd)]
+#![cfg_attr(test, feature(test))]
 
 #[macro_use]
 extern crate anyhow;
@@ -23,6 +23,7 @@ extern crate thiserror;
 
 use crate::storage::{CfgGeneral, CfgSingleton};
 
+mod cli;
 mod generator;
 mod gpu;
 mod gui;
@@ -42,5 +43,10 @@ fn main() {
     info!("Loading general settings...");
     CfgGeneral::load().expect("Error loading general config");
 
-    gui::start_gui_application();
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("render") => cli::start_render_application(args.collect()),
+        Some("node") => cli::start_node_application(args.collect()),
+        _ => gui::start_gui_application(),
+    }
 }
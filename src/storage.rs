@@ -13,10 +13,15 @@ use std::{
     fs::File,
     io,
     io::{Read, Write},
+    ops::RangeInclusive,
 };
 
 const FILE_NAME: &str = "general.ron";
 
+/// The chunk size powers fractals can be generated with. Smaller chunks are
+/// needlessly slow, and larger ones are beyond what GPUs can allocate.
+pub const CHUNK_SIZE_POWERS: RangeInclusive<usize> = 4..=13;
+
 lazy_static! {
     static ref SINGLETON: RwLock<Option<CfgGeneral>> = RwLock::new(None);
 }
@@ -39,6 +44,11 @@ pub struct CfgGeneral {
     /// only want this off if you're doing shader development.
     #[serde(default = "default_cache_generators")]
     pub cache_generators: bool,
+
+    /// Whether to generate fractals in the viewer at low resolution first,
+    /// refining them in several passes.
+    #[serde(default = "default_progressive_preview")]
+    pub progressive_preview: bool,
 }
 
 /// Represents a selection of which type of generator backend should be used to
@@ -53,6 +63,12 @@ pub enum CfgFractalGeneratorType {
     /// from the ones used for the GUI if any. This is the same as `Gpu` when
     /// not using the GUI.
     GpuDedicated,
+    /// Generate fractals on the CPU using perturbation theory, allowing zooms
+    /// deeper than double precision alone can reach.
+    Perturbation,
+    /// Generate fractals on the CPU and the GPU at the same time, splitting
+    /// the work between them based on how fast each one is.
+    Hybrid,
 }
 
 impl CfgSingleton for CfgGeneral {
@@ -75,6 +91,7 @@ impl Default for CfgGeneral {
             fractal_generator_type: Default::default(),
             fractal_chunk_size_power: default_fractal_chunk_size_power(),
             cache_generators: true,
+            progressive_preview: default_progressive_preview(),
         }
     }
 }
@@ -93,6 +110,10 @@ fn default_cache_generators() -> bool {
     true
 }
 
+fn default_progressive_preview() -> bool {
+    true
+}
+
 /// Implemented by any struct that is loaded as a singleton from a config file.
 pub trait CfgSingleton: Serialize + DeserializeOwned + Default + Sized + 'static {
     /// This config-singleton's singleton.